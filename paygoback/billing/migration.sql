@@ -110,3 +110,300 @@ CREATE TRIGGER update_sessions_updated_at BEFORE UPDATE ON streaming_sessions FO
 CREATE TRIGGER update_permissions_updated_at BEFORE UPDATE ON spending_permissions FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
 CREATE TRIGGER update_transactions_updated_at BEFORE UPDATE ON billing_transactions FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
 
+
+-- Nonce tracking and gas replacement history for on-chain billing
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS nonce BIGINT;
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS gas_price_wei DECIMAL(40,0);
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS submission_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS replaced_tx_hashes TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS idx_transactions_pending_nonce ON billing_transactions(nonce) WHERE status = 'pending';
+
+-- Batched on-chain settlement of accrued interval charges
+CREATE TABLE IF NOT EXISTS settlement_batches (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    total_amount DECIMAL(20,8) NOT NULL,
+    charge_count INTEGER NOT NULL,
+    pair_count INTEGER NOT NULL,
+    tx_hash VARCHAR(255),
+    nonce BIGINT,
+    gas_price_wei DECIMAL(40,0),
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    settled_at TIMESTAMP WITH TIME ZONE
+);
+
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS batch_id UUID REFERENCES settlement_batches(id);
+
+CREATE INDEX IF NOT EXISTS idx_transactions_batch ON billing_transactions(batch_id);
+CREATE INDEX IF NOT EXISTS idx_transactions_accrued ON billing_transactions(created_at) WHERE status = 'accrued';
+
+-- ERC-20 token billing: the token each session (and charge) settles in
+ALTER TABLE streaming_sessions ADD COLUMN IF NOT EXISTS token_address VARCHAR(42);
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS token_address VARCHAR(42);
+
+-- EIP-712 signed spending permits for Ethereum users
+CREATE TABLE IF NOT EXISTS ethereum_permits (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_wallet_address VARCHAR(42) NOT NULL,
+    vendor_wallet_address VARCHAR(42),
+    token_address VARCHAR(42),
+    approved_amount DECIMAL(20,8) NOT NULL,
+    remaining_amount DECIMAL(20,8) NOT NULL,
+    max_rate_per_hour DECIMAL(20,8) NOT NULL,
+    nonce BIGINT NOT NULL,
+    signature TEXT NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'active',
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (user_wallet_address, nonce)
+);
+
+CREATE INDEX IF NOT EXISTS idx_eth_permits_user ON ethereum_permits(user_wallet_address, status);
+
+-- Permit a charge reserved from, returned if the charge is cancelled or failed
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS permit_id UUID REFERENCES ethereum_permits(id);
+
+-- Chain indexer: billing contract events, per-user balances and block cursors
+CREATE TABLE IF NOT EXISTS indexer_cursors (
+    name VARCHAR(255) PRIMARY KEY,
+    last_block BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS contract_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    chain_id BIGINT NOT NULL,
+    contract_address VARCHAR(42) NOT NULL,
+    event_type VARCHAR(20) NOT NULL,
+    user_address VARCHAR(42) NOT NULL,
+    vendor_address VARCHAR(42),
+    amount DECIMAL(38,18) NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash VARCHAR(66) NOT NULL,
+    tx_hash VARCHAR(66) NOT NULL,
+    log_index BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (chain_id, tx_hash, log_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_contract_events_user ON contract_events(user_address, block_number);
+
+CREATE TABLE IF NOT EXISTS indexed_balances (
+    chain_id BIGINT NOT NULL,
+    contract_address VARCHAR(42) NOT NULL,
+    user_address VARCHAR(42) NOT NULL,
+    balance DECIMAL(38,18) NOT NULL DEFAULT 0,
+    last_block BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (chain_id, contract_address, user_address)
+);
+
+-- Multi-chain deployments: the chain each session, charge, batch and permit settles on.
+-- Existing rows are attributed to the default CHAIN_ID at service startup.
+ALTER TABLE streaming_sessions ADD COLUMN IF NOT EXISTS chain_id BIGINT;
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS chain_id BIGINT;
+ALTER TABLE settlement_batches ADD COLUMN IF NOT EXISTS chain_id BIGINT;
+ALTER TABLE ethereum_permits ADD COLUMN IF NOT EXISTS chain_id BIGINT;
+
+ALTER TABLE ethereum_permits DROP CONSTRAINT IF EXISTS ethereum_permits_user_wallet_address_nonce_key;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_eth_permits_chain_nonce ON ethereum_permits(chain_id, user_wallet_address, nonce);
+
+-- Wallet ownership challenges: single use, bound to the caller that requested them
+CREATE TABLE IF NOT EXISTS wallet_challenges (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    subject VARCHAR(255) NOT NULL,
+    wallet_address VARCHAR(255) NOT NULL,
+    message TEXT NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_wallet_challenges_expires ON wallet_challenges(expires_at);
+
+-- Outbound webhooks: endpoints per vendor (vendor_id NULL for system-wide
+-- endpoints), one delivery row per endpoint and event, and the deliveries
+-- that ran out of retries
+CREATE TABLE IF NOT EXISTS webhook_endpoints (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    vendor_id VARCHAR(255),
+    url TEXT NOT NULL,
+    secret VARCHAR(255) NOT NULL,
+    event_types TEXT[] NOT NULL DEFAULT '{}', -- empty means every event
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_endpoints_vendor ON webhook_endpoints(vendor_id) WHERE active;
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    endpoint_id UUID NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    event_id UUID NOT NULL,
+    event_type VARCHAR(64) NOT NULL,
+    payload TEXT NOT NULL, -- the exact body that is signed and sent
+    status VARCHAR(20) NOT NULL DEFAULT 'pending', -- pending, delivered, dead
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_status_code INTEGER,
+    last_error TEXT,
+    delivered_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(next_attempt_at) WHERE status = 'pending';
+
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    delivery_id UUID NOT NULL REFERENCES webhook_deliveries(id) ON DELETE CASCADE,
+    endpoint_id UUID NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    event_id UUID NOT NULL,
+    event_type VARCHAR(64) NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_status_code INTEGER,
+    last_error TEXT,
+    replayed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Transactional outbox: written in the same transaction as the state change
+-- it announces, then relayed to webhooks and Redis. The id is the event id
+-- consumers deduplicate on.
+CREATE TABLE IF NOT EXISTS outbox_events (
+    id UUID PRIMARY KEY,
+    seq BIGSERIAL,
+    event_type VARCHAR(64) NOT NULL,
+    vendor_id VARCHAR(255),
+    payload TEXT NOT NULL, -- webhook body
+    live_event TEXT, -- session event for Server-Sent Events clients, if any
+    published_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_outbox_events_unpublished ON outbox_events(seq) WHERE published_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_outbox_events_published ON outbox_events(published_at) WHERE published_at IS NOT NULL;
+
+-- A relayed event is queued at most once per endpoint
+CREATE UNIQUE INDEX IF NOT EXISTS idx_webhook_deliveries_event ON webhook_deliveries(endpoint_id, event_id);
+
+-- Keyset pages of active sessions for the billing scheduler
+CREATE INDEX IF NOT EXISTS idx_sessions_active_id ON streaming_sessions(id) WHERE status = 'active';
+
+-- Pricing plan each session was created under, and the active time billed
+-- so far against it. Older sessions were billed at a flat rate since
+-- start_time.
+ALTER TABLE streaming_sessions ADD COLUMN IF NOT EXISTS pricing_plan JSONB;
+UPDATE streaming_sessions SET pricing_plan = jsonb_build_object('rate_per_hour', rate_per_hour) WHERE pricing_plan IS NULL;
+ALTER TABLE streaming_sessions ALTER COLUMN pricing_plan SET NOT NULL;
+ALTER TABLE streaming_sessions ADD COLUMN IF NOT EXISTS billed_micros BIGINT NOT NULL DEFAULT 0;
+UPDATE streaming_sessions
+SET billed_micros = (EXTRACT(EPOCH FROM last_billed_time - start_time) * 1000000)::BIGINT
+WHERE billed_micros = 0 AND last_billed_time > start_time;
+
+-- Metered usage reported by vendors, billed from spending permissions once
+-- per period as one charge per permission, vendor and meter
+CREATE TABLE IF NOT EXISTS usage_charges (
+    id UUID PRIMARY KEY,
+    permission_id UUID NOT NULL REFERENCES spending_permissions(id),
+    vendor_id VARCHAR(255) NOT NULL,
+    meter VARCHAR(64) NOT NULL,
+    quantity DECIMAL(30,8) NOT NULL,
+    amount DECIMAL(20,8) NOT NULL,
+    status VARCHAR(16) NOT NULL, -- charged or failed
+    period_end TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS usage_events (
+    id UUID PRIMARY KEY,
+    vendor_id VARCHAR(255) NOT NULL,
+    event_id VARCHAR(255) NOT NULL, -- the vendor's id, for idempotent reposts
+    permission_id UUID NOT NULL REFERENCES spending_permissions(id),
+    session_id UUID REFERENCES streaming_sessions(id),
+    meter VARCHAR(64) NOT NULL,
+    unit VARCHAR(32) NOT NULL,
+    quantity DECIMAL(30,8) NOT NULL,
+    unit_price DECIMAL(20,8) NOT NULL,
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    charge_id UUID REFERENCES usage_charges(id),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (vendor_id, event_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_usage_events_unbilled ON usage_events(created_at) WHERE charge_id IS NULL;
+CREATE INDEX IF NOT EXISTS idx_usage_charges_permission ON usage_charges(permission_id, created_at);
+
+-- Exchange rate each fiat-priced charge was converted at
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS fiat_currency VARCHAR(3);
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS fiat_amount DECIMAL(20,8);
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS exchange_rate DECIMAL(30,12);
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS exchange_rate_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE usage_events ADD COLUMN IF NOT EXISTS currency VARCHAR(3);
+ALTER TABLE usage_charges ADD COLUMN IF NOT EXISTS fiat_currency VARCHAR(3);
+ALTER TABLE usage_charges ADD COLUMN IF NOT EXISTS fiat_amount DECIMAL(20,8);
+ALTER TABLE usage_charges ADD COLUMN IF NOT EXISTS exchange_rate DECIMAL(30,12);
+
+-- Platform fee and the vendor's net per charge; earlier charges had no fee
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS fee_amount DECIMAL(20,8) NOT NULL DEFAULT 0;
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS net_amount DECIMAL(20,8);
+UPDATE billing_transactions SET net_amount = amount WHERE net_amount IS NULL;
+ALTER TABLE billing_transactions ALTER COLUMN net_amount SET NOT NULL;
+ALTER TABLE usage_charges ADD COLUMN IF NOT EXISTS fee_amount DECIMAL(20,8) NOT NULL DEFAULT 0;
+ALTER TABLE usage_charges ADD COLUMN IF NOT EXISTS net_amount DECIMAL(20,8);
+UPDATE usage_charges SET net_amount = amount WHERE net_amount IS NULL;
+ALTER TABLE usage_charges ALTER COLUMN net_amount SET NOT NULL;
+
+-- How each charge divides between the platform, the vendor and its payees
+CREATE TABLE IF NOT EXISTS revenue_splits (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    transaction_id UUID REFERENCES billing_transactions(id),
+    usage_charge_id UUID REFERENCES usage_charges(id),
+    role VARCHAR(16) NOT NULL, -- platform, vendor or payee
+    recipient VARCHAR(255) NOT NULL,
+    wallet_address VARCHAR(255),
+    amount DECIMAL(20,8) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    CHECK ((transaction_id IS NULL) <> (usage_charge_id IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS idx_revenue_splits_transaction ON revenue_splits(transaction_id);
+CREATE INDEX IF NOT EXISTS idx_revenue_splits_usage_charge ON revenue_splits(usage_charge_id);
+CREATE INDEX IF NOT EXISTS idx_revenue_splits_recipient ON revenue_splits(recipient, created_at);
+
+-- Invoices and receipts, numbered per year without gaps
+CREATE TABLE IF NOT EXISTS invoice_sequences (
+    year INTEGER PRIMARY KEY,
+    last_number BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS invoices (
+    id UUID PRIMARY KEY,
+    number VARCHAR(64) NOT NULL UNIQUE,
+    kind VARCHAR(16) NOT NULL, -- session or period
+    vendor_id VARCHAR(255) NOT NULL,
+    user_wallet_address VARCHAR(255) NOT NULL,
+    session_id UUID REFERENCES streaming_sessions(id),
+    period_start TIMESTAMP WITH TIME ZONE NOT NULL,
+    period_end TIMESTAMP WITH TIME ZONE NOT NULL,
+    issued_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_invoices_session ON invoices(session_id) WHERE kind = 'session';
+CREATE UNIQUE INDEX IF NOT EXISTS idx_invoices_period
+    ON invoices(vendor_id, user_wallet_address, period_start, period_end) WHERE kind = 'period';
+CREATE INDEX IF NOT EXISTS idx_transactions_wallet_created ON billing_transactions(user_wallet_address, created_at);
+
+-- The Zcash spending permission a permission-billed session draws from
+CREATE TABLE IF NOT EXISTS session_permissions (
+    session_id UUID PRIMARY KEY REFERENCES streaming_sessions(id),
+    permission_id UUID NOT NULL REFERENCES spending_permissions(id),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_session_permissions_permission ON session_permissions(permission_id);
+
+-- The exact span a charge billed, so a cancelled charge can hand it back
+ALTER TABLE billing_transactions ADD COLUMN IF NOT EXISTS duration_micros BIGINT NOT NULL DEFAULT 0;
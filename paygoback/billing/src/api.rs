@@ -1,17 +1,29 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{error::Error as ActixError, web, HttpRequest, HttpResponse, Responder};
+use sqlx::PgPool;
 use std::sync::Arc;
+use crate::auth::{Access, Principal};
 use crate::billing::BillingEngine;
+use crate::zcash::IntegratedBillingEngine;
+use crate::db;
 use crate::models::*;
 use crate::validation::Validator;
 use crate::error::BillingError;
+use crate::metrics::SchedulerMetrics;
 
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+    // Malformed bodies, paths and queries get the same problem responses
+    cfg.app_data(web::JsonConfig::default().error_handler(|e, _| reject_input(e)))
+        .app_data(web::PathConfig::default().error_handler(|e, _| reject_input(e)))
+        .app_data(web::QueryConfig::default().error_handler(|e, _| reject_input(e)));
+
     cfg.service(
         web::scope("/api/v1")
             .route("/sessions", web::post().to(create_session))
             .route("/sessions/activate", web::post().to(activate_session))
             .route("/sessions/end", web::post().to(end_session))
             .route("/health", web::get().to(health_check))
+            .route("/scheduler/metrics", web::get().to(scheduler_metrics))
+            .route("/openapi.json", web::get().to(crate::openapi::openapi_json))
             .route("/zcash/test", web::get().to(zcash_test_endpoint))
             .route("/zcash/permissions", web::post().to(crate::zcash::zcash_api::create_permission))
             .route("/zcash/permissions/{id}/verify", web::post().to(crate::zcash::zcash_api::verify_permission))
@@ -19,9 +31,43 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig) {
             .route("/zcash/permissions/{id}/revoke", web::post().to(crate::zcash::zcash_api::revoke_permission))
             .route("/zcash/balance/{address}", web::get().to(crate::zcash::zcash_api::get_wallet_balance))
             .route("/zcash/permissions/wallet/{address}", web::get().to(crate::zcash::zcash_api::get_active_permission))
+            .route("/eth/permits/domain", web::get().to(crate::permit_api::get_permit_domain))
+            .route("/eth/permits", web::post().to(crate::permit_api::submit_permit))
+            .route("/eth/permits/{id}", web::get().to(crate::permit_api::get_permit))
+            .route("/eth/permits/{id}/revoke", web::post().to(crate::permit_api::revoke_permit))
+            .route("/wallets/challenges", web::post().to(crate::ownership_api::create_challenge))
+            .route("/wallets/challenges/{id}/verify", web::post().to(crate::ownership_api::verify_challenge))
+            .route("/events/sessions/{id}", web::get().to(crate::events_api::stream_session_events))
+            .route("/events/permissions/{id}", web::get().to(crate::events_api::stream_permission_events))
+            .route("/webhooks/endpoints", web::post().to(crate::webhooks_api::create_endpoint))
+            .route("/webhooks/endpoints", web::get().to(crate::webhooks_api::list_endpoints))
+            .route("/webhooks/endpoints/{id}", web::delete().to(crate::webhooks_api::delete_endpoint))
+            .route("/webhooks/dead-letters", web::get().to(crate::webhooks_api::list_dead_letters))
+            .route("/webhooks/dead-letters/{id}/replay", web::post().to(crate::webhooks_api::replay_dead_letter))
+            .route("/usage", web::post().to(crate::metering_api::record_usage))
+            .route("/usage/permissions/{id}/charges", web::get().to(crate::metering_api::list_usage_charges))
+            .route("/invoices", web::post().to(crate::invoice_api::issue_invoice))
+            .route("/invoices/{id}", web::get().to(crate::invoice_api::get_invoice))
+            .route("/statements", web::get().to(crate::statement_api::get_statement))
+            .route("/exports/{kind}", web::get().to(crate::statement_api::export_ledger))
+            .default_service(web::route().to(not_found))
     );
 }
 
+fn reject_input(e: impl std::fmt::Display) -> ActixError {
+    BillingError::Validation(e.to_string()).into()
+}
+
+async fn not_found(req: HttpRequest) -> Result<HttpResponse, BillingError> {
+    Err(BillingError::NotFound(format!("No route for {} {}", req.method(), req.path())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/zcash/test",
+    tag = "service",
+    responses((status = 200, description = "Zcash routes are mounted", body = Object))
+)]
 async fn zcash_test_endpoint() -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({
         "message": "Direct Zcash test endpoint works!",
@@ -29,72 +75,154 @@ async fn zcash_test_endpoint() -> impl Responder {
     }))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/sessions",
+    tag = "sessions",
+    request_body = CreateSessionRequest,
+    responses(
+        (status = 200, description = "Session created", body = CreateSessionResponse),
+        (status = 400, description = "Invalid request", body = Problem, content_type = "application/problem+json"),
+        (status = 402, description = "Insufficient balance", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "No wallet ownership proof", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No spending permit or permission", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = [], "wallet_proof" = []), ("api_key" = [], "wallet_proof" = []))
+)]
 async fn create_session(
     engine: web::Data<Arc<BillingEngine>>,
+    integrated: web::Data<Arc<IntegratedBillingEngine>>,
+    principal: Principal,
     req: web::Json<CreateSessionRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, BillingError> {
     // Validate inputs
-    if let Err(e) = Validator::validate_and_sanitize_vendor_id(&req.vendor_id) {
-        return HttpResponse::BadRequest().json(format!("Invalid vendor ID: {:?}", e));
-    }
-    
-    if let Err(e) = Validator::validate_ethereum_address(&req.user_wallet_address) {
-        return HttpResponse::BadRequest().json(format!("Invalid wallet address: {:?}", e));
+    Validator::validate_and_sanitize_vendor_id(&req.vendor_id)?;
+    let zcash = !req.user_wallet_address.starts_with("0x");
+    if zcash {
+        Validator::validate_zcash_address(&req.user_wallet_address)?;
+    } else {
+        Validator::validate_ethereum_address(&req.user_wallet_address)?;
     }
 
-    match engine.create_session(
-        req.user_wallet_address.clone(),
-        req.vendor_id.clone(),
-    ).await {
-        Ok(response) => HttpResponse::Ok().json(response),
-        Err(BillingError::Config(msg)) => HttpResponse::BadRequest().json(msg),
-        Err(BillingError::InsufficientBalance) => HttpResponse::PaymentRequired().json("Insufficient balance"),
-        Err(BillingError::SessionNotFound) => HttpResponse::NotFound().json("Session not found"),
-        Err(BillingError::InvalidSessionCode) => HttpResponse::BadRequest().json("Invalid session code"),
-        Err(_) => HttpResponse::InternalServerError().json("Internal server error"),
-    }
+    principal.require_wallet_proof(&req.user_wallet_address)?;
+
+    // Zcash wallets bill against their spending permission, Ethereum
+    // wallets on-chain against their permit
+    let response = if zcash {
+        integrated.create_session_with_permission(
+            req.user_wallet_address.clone(),
+            req.vendor_id.clone(),
+            req.chain_id,
+        ).await?
+    } else {
+        engine.create_session(
+            req.user_wallet_address.clone(),
+            req.vendor_id.clone(),
+            req.chain_id,
+        ).await?
+    };
+
+    Ok(HttpResponse::Ok().json(response))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/sessions/activate",
+    tag = "sessions",
+    request_body = ActivateSessionRequest,
+    responses(
+        (status = 200, description = "Session activated", body = StreamingSession),
+        (status = 403, description = "Not the session's user or vendor", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Session not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
 async fn activate_session(
     engine: web::Data<Arc<BillingEngine>>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
     req: web::Json<ActivateSessionRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, BillingError> {
     // Validate session code
-    if let Err(e) = Validator::validate_session_code(&req.session_code) {
-        return HttpResponse::BadRequest().json(format!("Invalid session code: {:?}", e));
-    }
+    Validator::validate_session_code(&req.session_code)?;
 
-    match engine.activate_session(&req.session_code).await {
-        Ok(session) => HttpResponse::Ok().json(session),
-        Err(BillingError::Config(msg)) => HttpResponse::BadRequest().json(msg),
-        Err(BillingError::SessionNotFound) => HttpResponse::NotFound().json("Session not found"),
-        Err(BillingError::InvalidSessionCode) => HttpResponse::BadRequest().json("Invalid session code"),
-        Err(_) => HttpResponse::InternalServerError().json("Internal server error"),
-    }
+    authorize_session(&db_pool, &principal, &req.session_code).await?;
+
+    let session = engine.activate_session(&req.session_code).await?;
+    Ok(HttpResponse::Ok().json(session))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/sessions/end",
+    tag = "sessions",
+    request_body = EndSessionRequest,
+    responses(
+        (status = 200, description = "Session ended and billed", body = BillingTransaction),
+        (status = 402, description = "Insufficient balance", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Not the session's user or vendor", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Session not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
 async fn end_session(
     engine: web::Data<Arc<BillingEngine>>,
+    integrated: web::Data<Arc<IntegratedBillingEngine>>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
     req: web::Json<EndSessionRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, BillingError> {
     // Validate session code
-    if let Err(e) = Validator::validate_session_code(&req.session_code) {
-        return HttpResponse::BadRequest().json(format!("Invalid session code: {:?}", e));
-    }
+    Validator::validate_session_code(&req.session_code)?;
 
-    match engine.end_session(&req.session_code).await {
-        Ok(transaction) => HttpResponse::Ok().json(transaction),
-        Err(BillingError::Config(msg)) => HttpResponse::BadRequest().json(msg),
-        Err(BillingError::SessionNotFound) => HttpResponse::NotFound().json("Session not found"),
-        Err(BillingError::InvalidSessionCode) => HttpResponse::BadRequest().json("Invalid session code"),
-        Err(BillingError::InsufficientBalance) => HttpResponse::PaymentRequired().json("Insufficient balance"),
-        Err(_) => HttpResponse::InternalServerError().json("Internal server error"),
-    }
+    let session = authorize_session(&db_pool, &principal, &req.session_code).await?;
+
+    // The final charge comes from wherever the session was billed
+    let transaction = match db::get_session_permission_id(db_pool.get_ref(), session.id).await? {
+        Some(_) => integrated.end_session_with_permission(&req.session_code).await?,
+        None => engine.end_session(&req.session_code).await?,
+    };
+    Ok(HttpResponse::Ok().json(transaction))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/health",
+    tag = "service",
+    responses((status = 200, description = "Service is up", body = Object))
+)]
 async fn health_check() -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({
         "status": "healthy",
         "service": "paygo-billing"
     }))
-}
\ No newline at end of file
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/scheduler/metrics",
+    tag = "service",
+    responses(
+        (status = 200, description = "Billing scheduler shards and last tick on this instance", body = SchedulerMetricsSnapshot),
+        (status = 403, description = "Not an admin", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+async fn scheduler_metrics(
+    metrics: web::Data<Arc<SchedulerMetrics>>,
+    principal: Principal,
+) -> Result<HttpResponse, BillingError> {
+    principal.authorize_vendor(None, Access::Read)?;
+    Ok(HttpResponse::Ok().json(metrics.snapshot()))
+}
+
+/// Only the session's user, its vendor or an admin may change it
+async fn authorize_session(
+    db_pool: &PgPool,
+    principal: &Principal,
+    session_code: &str,
+) -> Result<StreamingSession, BillingError> {
+    let session = db::get_session_by_code(db_pool, session_code).await?;
+    principal.authorize_session(&session, Access::Write)?;
+    Ok(session)
+}
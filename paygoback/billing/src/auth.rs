@@ -0,0 +1,488 @@
+// src/auth.rs
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::config::{ApiKeyConfig, AuthConfig};
+use crate::error::BillingError;
+use crate::models::StreamingSession;
+
+/// Read and write anything, for support and operations tooling
+pub const SCOPE_ADMIN: &str = "admin";
+/// Read any session, permission or balance without changing it
+pub const SCOPE_SUPPORT_READ: &str = "support:read";
+
+/// Audience of wallet ownership proof tokens, so they are never mistaken
+/// for login tokens
+const WALLET_PROOF_AUDIENCE: &str = "paygo:wallet-proof";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// The authenticated caller of an API request: a user or vendor signed in
+/// through the Node backend, or a service holding an API key.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String,
+    pub wallet_address: Option<String>,
+    pub vendor_id: Option<String>,
+    pub scopes: Vec<String>,
+    pub proven_wallets: Vec<String>, // from `X-Wallet-Proof` tokens
+}
+
+impl Principal {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    fn can_override(&self, access: Access) -> bool {
+        self.has_scope(SCOPE_ADMIN) || (access == Access::Read && self.has_scope(SCOPE_SUPPORT_READ))
+    }
+
+    pub fn owns_wallet(&self, wallet_address: &str) -> bool {
+        let wallet_address = normalize_wallet(wallet_address);
+
+        self.wallet_address
+            .as_deref()
+            .map(|own| normalize_wallet(own) == wallet_address)
+            .unwrap_or(false)
+            || self.proven_wallets.contains(&wallet_address)
+    }
+
+    /// Allow billing a wallet: the caller must have signed a challenge with
+    /// it (see `ownership`), not merely claim it
+    pub fn require_wallet_proof(&self, wallet_address: &str) -> Result<(), BillingError> {
+        if self.proven_wallets.contains(&normalize_wallet(wallet_address)) || self.has_scope(SCOPE_ADMIN) {
+            return Ok(());
+        }
+
+        Err(BillingError::Forbidden(format!(
+            "Wallet ownership proof required for {}",
+            wallet_address
+        )))
+    }
+
+    /// Allow acting on a wallet's permissions, permits and balances
+    pub fn authorize_wallet(&self, wallet_address: &str, access: Access) -> Result<(), BillingError> {
+        if self.owns_wallet(wallet_address) || self.can_override(access) {
+            return Ok(());
+        }
+
+        Err(BillingError::Forbidden(format!(
+            "{} may not access wallet {}",
+            self.subject, wallet_address
+        )))
+    }
+
+    /// Allow acting on a session: its user, its vendor, or an admin
+    pub fn authorize_session(&self, session: &StreamingSession, access: Access) -> Result<(), BillingError> {
+        let is_vendor = self.vendor_id.as_deref() == Some(session.vendor_id.as_str());
+
+        if self.owns_wallet(&session.user_wallet_address) || is_vendor || self.can_override(access) {
+            return Ok(());
+        }
+
+        Err(BillingError::Forbidden(format!(
+            "{} may not access session {}",
+            self.subject, session.session_code
+        )))
+    }
+
+    /// Allow managing a vendor's resources; `None` means system-wide ones,
+    /// which only admins manage
+    pub fn authorize_vendor(&self, vendor_id: Option<&str>, access: Access) -> Result<(), BillingError> {
+        let is_vendor = vendor_id.is_some() && self.vendor_id.as_deref() == vendor_id;
+
+        if is_vendor || self.can_override(access) {
+            return Ok(());
+        }
+
+        Err(BillingError::Forbidden(format!(
+            "{} may not access vendor {}",
+            self.subject,
+            vendor_id.unwrap_or("(system)")
+        )))
+    }
+}
+
+/// Ethereum addresses compare case-insensitively; Zcash addresses are
+/// case-sensitive and compare as given
+fn normalize_wallet(wallet_address: &str) -> String {
+    if wallet_address.starts_with("0x") || wallet_address.starts_with("0X") {
+        wallet_address.to_lowercase()
+    } else {
+        wallet_address.to_string()
+    }
+}
+
+/// Claims of the tokens the Node backend issues (`User.createJWT`)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Claims {
+    user_id: serde_json::Value,
+    role: String,
+    #[serde(default)]
+    wallet_address: Option<String>,
+}
+
+/// Claims of a wallet ownership proof, bound to the caller that proved it
+#[derive(Debug, Serialize, Deserialize)]
+struct WalletProofClaims {
+    sub: String,
+    wallet: String,
+    aud: String,
+    exp: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WalletProof {
+    pub token: String,
+    pub wallet_address: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Verifies `Authorization: Bearer <jwt>` and `X-API-Key` credentials, and
+/// issues and checks the `X-Wallet-Proof` tokens that go with them
+pub struct Authenticator {
+    decoding_key: DecodingKey,
+    validation: Validation,
+    api_keys: HashMap<String, ApiKeyConfig>,
+    proof_encoding_key: EncodingKey,
+    proof_decoding_key: DecodingKey,
+    proof_validation: Validation,
+    proof_ttl_seconds: i64,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        if let Some(issuer) = &config.jwt_issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if let Some(audience) = &config.jwt_audience {
+            validation.set_audience(&[audience]);
+        }
+
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|key| (key.key_sha256.to_lowercase(), key.clone()))
+            .collect();
+
+        let mut proof_validation = Validation::new(Algorithm::HS256);
+        proof_validation.set_audience(&[WALLET_PROOF_AUDIENCE]);
+
+        let proof_secret = config.wallet_proof_secret.expose().as_bytes();
+
+        Self {
+            decoding_key: DecodingKey::from_secret(config.jwt_secret.expose().as_bytes()),
+            validation,
+            api_keys,
+            proof_encoding_key: EncodingKey::from_secret(proof_secret),
+            proof_decoding_key: DecodingKey::from_secret(proof_secret),
+            proof_validation,
+            proof_ttl_seconds: config.wallet_proof_ttl_seconds,
+        }
+    }
+
+    pub fn authenticate(&self, req: &HttpRequest) -> Result<Principal, BillingError> {
+        let mut principal = match req.headers().get("X-API-Key") {
+            Some(key) => {
+                let key = key
+                    .to_str()
+                    .map_err(|_| BillingError::Unauthorized("Malformed API key".to_string()))?;
+                self.authenticate_api_key(key)?
+            }
+            None => {
+                let token = req
+                    .headers()
+                    .get("Authorization")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .ok_or_else(|| BillingError::Unauthorized("Missing credentials".to_string()))?;
+                self.authenticate_jwt(token.trim())?
+            }
+        };
+
+        for proof in req.headers().get_all("X-Wallet-Proof") {
+            let proof = proof
+                .to_str()
+                .map_err(|_| BillingError::Unauthorized("Malformed wallet proof".to_string()))?;
+            let wallet = self.verify_wallet_proof(&principal, proof.trim())?;
+            principal.proven_wallets.push(wallet);
+        }
+
+        Ok(principal)
+    }
+
+    /// Short-lived token recording that `principal` controls `wallet_address`
+    pub fn issue_wallet_proof(&self, principal: &Principal, wallet_address: &str) -> Result<WalletProof, BillingError> {
+        let expires_at = Utc::now() + Duration::seconds(self.proof_ttl_seconds);
+
+        let claims = WalletProofClaims {
+            sub: principal.subject.clone(),
+            wallet: normalize_wallet(wallet_address),
+            aud: WALLET_PROOF_AUDIENCE.to_string(),
+            exp: expires_at.timestamp(),
+        };
+
+        let token = encode(&Header::new(Algorithm::HS256), &claims, &self.proof_encoding_key)
+            .map_err(|e| BillingError::Config(format!("Cannot sign wallet proof: {}", e)))?;
+
+        Ok(WalletProof {
+            token,
+            wallet_address: wallet_address.to_string(),
+            expires_at,
+        })
+    }
+
+    /// Wallet a proof token vouches for; proofs are not transferable
+    fn verify_wallet_proof(&self, principal: &Principal, token: &str) -> Result<String, BillingError> {
+        let claims = decode::<WalletProofClaims>(token, &self.proof_decoding_key, &self.proof_validation)
+            .map_err(|e| BillingError::Unauthorized(format!("Invalid wallet proof: {}", e)))?
+            .claims;
+
+        if claims.sub != principal.subject {
+            return Err(BillingError::Unauthorized(
+                "Wallet proof was issued to another caller".to_string(),
+            ));
+        }
+
+        Ok(claims.wallet)
+    }
+
+    fn authenticate_jwt(&self, token: &str) -> Result<Principal, BillingError> {
+        let claims = decode::<Claims>(token, &self.decoding_key, &self.validation)
+            .map_err(|e| BillingError::Unauthorized(format!("Invalid token: {}", e)))?
+            .claims;
+
+        let user_id = match &claims.user_id {
+            serde_json::Value::String(id) => id.clone(),
+            serde_json::Value::Number(id) => id.to_string(),
+            _ => return Err(BillingError::Unauthorized("Invalid token subject".to_string())),
+        };
+
+        // Vendor accounts bill under their user id
+        let (vendor_id, scopes) = match claims.role.as_str() {
+            "user" => (None, Vec::new()),
+            "vendor" => (Some(user_id.clone()), Vec::new()),
+            "admin" => (None, vec![SCOPE_ADMIN.to_string()]),
+            role => return Err(BillingError::Unauthorized(format!("Unknown role {}", role))),
+        };
+
+        Ok(Principal {
+            subject: format!("user:{}", user_id),
+            wallet_address: claims.wallet_address.filter(|wallet| !wallet.is_empty()),
+            vendor_id,
+            scopes,
+            proven_wallets: Vec::new(),
+        })
+    }
+
+    fn authenticate_api_key(&self, key: &str) -> Result<Principal, BillingError> {
+        let hash = hex::encode(Sha256::digest(key.as_bytes()));
+
+        let api_key = self
+            .api_keys
+            .get(&hash)
+            .ok_or_else(|| BillingError::Unauthorized("Invalid API key".to_string()))?;
+
+        Ok(Principal {
+            subject: format!("service:{}", api_key.name),
+            wallet_address: None,
+            vendor_id: api_key.vendor_id.clone(),
+            scopes: api_key.scopes.clone(),
+            proven_wallets: Vec::new(),
+        })
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = BillingError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let result = match req.app_data::<web::Data<Arc<Authenticator>>>() {
+            Some(authenticator) => authenticator.authenticate(req),
+            None => Err(BillingError::Config("Authenticator not configured".to_string())),
+        };
+
+        ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SessionStatus;
+    use crate::pricing::PricingPlan;
+    use rust_decimal::Decimal;
+    use sqlx::types::Json;
+    use uuid::Uuid;
+
+    const WALLET: &str = "0xAbC0000000000000000000000000000000000001";
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            jwt_secret: "jwt-secret".to_string().into(),
+            jwt_issuer: None,
+            jwt_audience: None,
+            api_keys: Vec::new(),
+            wallet_proof_secret: "proof-secret".to_string().into(),
+            wallet_proof_domain: "app.example".to_string(),
+            wallet_challenge_ttl_seconds: 300,
+            wallet_proof_ttl_seconds: 600,
+        }
+    }
+
+    fn principal(subject: &str, wallet_address: Option<&str>, vendor_id: Option<&str>, scopes: &[&str]) -> Principal {
+        Principal {
+            subject: subject.to_string(),
+            wallet_address: wallet_address.map(str::to_string),
+            vendor_id: vendor_id.map(str::to_string),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            proven_wallets: Vec::new(),
+        }
+    }
+
+    fn session(user_wallet_address: &str, vendor_id: &str) -> StreamingSession {
+        let now = Utc::now();
+        StreamingSession {
+            id: Uuid::new_v4(),
+            session_code: "ABC123".to_string(),
+            user_wallet_address: user_wallet_address.to_string(),
+            vendor_wallet_address: "0xvendor".to_string(),
+            vendor_id: vendor_id.to_string(),
+            start_time: now,
+            last_billed_time: now,
+            end_time: None,
+            rate_per_hour: Decimal::ONE,
+            pricing_plan: Json(PricingPlan::flat(Decimal::ONE)),
+            billed_micros: 0,
+            token_address: None,
+            chain_id: 1,
+            total_amount_billed: Decimal::ZERO,
+            status: SessionStatus::Active,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn jwt(user_id: serde_json::Value, role: &str) -> String {
+        let claims = serde_json::json!({
+            "userId": user_id,
+            "role": role,
+            "walletAddress": WALLET,
+            "exp": (Utc::now() + Duration::hours(1)).timestamp(),
+        });
+        encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(b"jwt-secret")).unwrap()
+    }
+
+    #[test]
+    fn normalize_wallet_lowercases_only_ethereum_addresses() {
+        assert_eq!(normalize_wallet(WALLET), WALLET.to_lowercase());
+        assert_eq!(normalize_wallet("0XABC"), "0xabc");
+        assert_eq!(normalize_wallet("zs1AbC"), "zs1AbC");
+    }
+
+    #[test]
+    fn authorize_wallet_allows_owners_and_read_only_support() {
+        let owner = principal("user:1", Some(&WALLET.to_lowercase()), None, &[]);
+        assert!(owner.authorize_wallet(WALLET, Access::Write).is_ok());
+
+        let mut prover = principal("user:2", None, None, &[]);
+        assert!(prover.authorize_wallet(WALLET, Access::Read).is_err());
+        prover.proven_wallets.push(normalize_wallet(WALLET));
+        assert!(prover.authorize_wallet(WALLET, Access::Write).is_ok());
+
+        let support = principal("service:support", None, None, &[SCOPE_SUPPORT_READ]);
+        assert!(support.authorize_wallet(WALLET, Access::Read).is_ok());
+        assert!(support.authorize_wallet(WALLET, Access::Write).is_err());
+
+        let admin = principal("user:3", None, None, &[SCOPE_ADMIN]);
+        assert!(admin.authorize_wallet(WALLET, Access::Write).is_ok());
+    }
+
+    #[test]
+    fn authorize_session_allows_its_user_vendor_and_admins() {
+        let session = session(WALLET, "vendor-1");
+
+        assert!(principal("user:1", Some(WALLET), None, &[]).authorize_session(&session, Access::Write).is_ok());
+        assert!(principal("user:2", None, Some("vendor-1"), &[]).authorize_session(&session, Access::Write).is_ok());
+        assert!(principal("user:3", None, None, &[SCOPE_ADMIN]).authorize_session(&session, Access::Write).is_ok());
+
+        let other_vendor = principal("user:4", None, Some("vendor-2"), &[]);
+        assert!(matches!(
+            other_vendor.authorize_session(&session, Access::Read),
+            Err(BillingError::Forbidden(_))
+        ));
+        let support = principal("service:support", None, None, &[SCOPE_SUPPORT_READ]);
+        assert!(support.authorize_session(&session, Access::Read).is_ok());
+        assert!(support.authorize_session(&session, Access::Write).is_err());
+    }
+
+    #[test]
+    fn authorize_vendor_leaves_system_resources_to_admins() {
+        let vendor = principal("user:1", None, Some("vendor-1"), &[]);
+        assert!(vendor.authorize_vendor(Some("vendor-1"), Access::Write).is_ok());
+        assert!(vendor.authorize_vendor(Some("vendor-2"), Access::Read).is_err());
+        assert!(vendor.authorize_vendor(None, Access::Read).is_err());
+
+        let user = principal("user:2", Some(WALLET), None, &[]);
+        assert!(user.authorize_vendor(None, Access::Read).is_err());
+
+        let admin = principal("user:3", None, None, &[SCOPE_ADMIN]);
+        assert!(admin.authorize_vendor(None, Access::Write).is_ok());
+        let support = principal("service:support", None, None, &[SCOPE_SUPPORT_READ]);
+        assert!(support.authorize_vendor(None, Access::Read).is_ok());
+        assert!(support.authorize_vendor(None, Access::Write).is_err());
+    }
+
+    #[test]
+    fn wallet_proofs_are_bound_to_their_subject() {
+        let authenticator = Authenticator::new(&config());
+        let user = principal("user:1", None, None, &[]);
+        let proof = authenticator.issue_wallet_proof(&user, WALLET).unwrap();
+
+        assert_eq!(authenticator.verify_wallet_proof(&user, &proof.token).unwrap(), WALLET.to_lowercase());
+
+        let other = principal("user:2", None, None, &[]);
+        assert!(matches!(
+            authenticator.verify_wallet_proof(&other, &proof.token),
+            Err(BillingError::Unauthorized(_))
+        ));
+        assert!(authenticator.verify_wallet_proof(&user, "not-a-token").is_err());
+    }
+
+    #[test]
+    fn jwt_roles_map_to_vendor_and_scopes() {
+        let authenticator = Authenticator::new(&config());
+
+        let user = authenticator.authenticate_jwt(&jwt(serde_json::json!("42"), "user")).unwrap();
+        assert_eq!(user.subject, "user:42");
+        assert_eq!(user.wallet_address.as_deref(), Some(WALLET));
+        assert_eq!(user.vendor_id, None);
+        assert!(user.scopes.is_empty());
+
+        let vendor = authenticator.authenticate_jwt(&jwt(serde_json::json!(7), "vendor")).unwrap();
+        assert_eq!(vendor.subject, "user:7");
+        assert_eq!(vendor.vendor_id.as_deref(), Some("7"));
+        assert!(vendor.scopes.is_empty());
+
+        let admin = authenticator.authenticate_jwt(&jwt(serde_json::json!("1"), "admin")).unwrap();
+        assert_eq!(admin.vendor_id, None);
+        assert!(admin.has_scope(SCOPE_ADMIN));
+
+        assert!(authenticator.authenticate_jwt(&jwt(serde_json::json!("1"), "superuser")).is_err());
+        assert!(authenticator.authenticate_jwt(&jwt(serde_json::json!(null), "user")).is_err());
+    }
+}
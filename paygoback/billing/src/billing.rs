@@ -1,39 +1,35 @@
-use sqlx::PgPool;
+use sqlx::{types::Json, PgPool};
 use redis::Client as RedisClient;
 use std::sync::Arc;
-use chrono::{Utc, Duration};
+use chrono::Utc;
 use uuid::Uuid;
 use rust_decimal::Decimal;
-use std::str::FromStr;
-use tracing::{info, warn, error};
+use tracing::info;
 
 use crate::models::*;
-use crate::blockchain::BlockchainClient;
-use crate::config::Config;
+use crate::charging::SessionCharger;
 use crate::error::BillingError;
 use crate::db;
 use crate::cache;
-use crate::models::VendorInfo;
+use crate::events::{BillingEvent, BillingEventKind};
+use crate::outbox;
 
 pub struct BillingEngine {
     db_pool: PgPool,
     redis_client: RedisClient,
-    blockchain_client: Arc<BlockchainClient>,
-    config: Config,
+    charger: Arc<SessionCharger>,
 }
 
 impl BillingEngine {
     pub fn new(
         db_pool: PgPool,
         redis_client: RedisClient,
-        blockchain_client: Arc<BlockchainClient>,
-        config: Config,
+        charger: Arc<SessionCharger>,
     ) -> Self {
         Self {
             db_pool,
             redis_client,
-            blockchain_client,
-            config,
+            charger,
         }
     }
     
@@ -41,25 +37,29 @@ impl BillingEngine {
         &self,
         user_wallet_address: String,
         vendor_id: String,
+        chain_id: Option<u64>,
     ) -> Result<CreateSessionResponse, BillingError> {
         // Generate unique session code
         let session_code = self.generate_session_code();
         
         // Fetch vendor details (this would come from your Node.js service)
-        let vendor_wallet_address = self.get_vendor_wallet(&vendor_id).await?;
-        let rate_per_hour = self.get_vendor_rate(&vendor_id).await?;
+        let vendor = self.charger.vendor_terms(&vendor_id, chain_id).await?;
         
         let now = Utc::now();
         let session = StreamingSession {
             id: Uuid::new_v4(),
             session_code: session_code.clone(),
             user_wallet_address,
-            vendor_wallet_address,
+            vendor_wallet_address: vendor.wallet_address,
             vendor_id,
             start_time: now,
             last_billed_time: now,
             end_time: None,
-            rate_per_hour,
+            rate_per_hour: vendor.pricing_plan.rate_per_hour,
+            pricing_plan: Json(vendor.pricing_plan),
+            billed_micros: 0,
+            token_address: vendor.token_address,
+            chain_id: vendor.chain_id,
             total_amount_billed: Decimal::ZERO,
             status: SessionStatus::Active,
             created_at: now,
@@ -87,13 +87,21 @@ impl BillingEngine {
     
     pub async fn activate_session(&self, session_code: &str) -> Result<StreamingSession, BillingError> {
         let mut session = db::get_session_by_code(&self.db_pool, session_code).await?;
+        let kind = if session.status == SessionStatus::Paused {
+            BillingEventKind::SessionResumed
+        } else {
+            BillingEventKind::SessionStarted
+        };
         
         // Update start time to now when activated
         session.start_time = Utc::now();
         session.last_billed_time = Utc::now();
         session.status = SessionStatus::Active;
         
-        db::update_session(&self.db_pool, &session).await?;
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+        db::update_session(&mut *tx, &session).await?;
+        outbox::record_billing(&mut *tx, &BillingEvent::for_session(kind, &session)).await?;
+        tx.commit().await.map_err(BillingError::Database)?;
         
         info!("Activated session {}", session_code);
         
@@ -103,107 +111,32 @@ impl BillingEngine {
     pub async fn end_session(&self, session_code: &str) -> Result<BillingTransaction, BillingError> {
         let mut session = db::get_session_by_code(&self.db_pool, session_code).await?;
         
-        if session.status != SessionStatus::Active {
-            return Err(BillingError::InvalidSessionCode);
-        }
-        
         // Calculate final billing
         let now = Utc::now();
-        let duration = now.signed_duration_since(session.last_billed_time);
-        let transaction = self.bill_session(&mut session, duration).await?;
-        
-        // Mark session as completed
+        let transaction = self.charger
+            .bill_session(&mut session, now, true)
+            .await?
+            .ok_or(BillingError::InvalidSessionCode)?;
+        
+        // Mark session as completed; the final charge already billed it up
+        // to now, so nothing is due in between
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+        let mut session = db::lock_session(&mut *tx, session.id).await?;
         session.status = SessionStatus::Completed;
         session.end_time = Some(now);
-        db::update_session(&self.db_pool, &session).await?;
+        db::update_session(&mut *tx, &session).await?;
+        outbox::record_billing(
+            &mut *tx,
+            &BillingEvent::for_session(BillingEventKind::SessionEnded, &session).with_charge(transaction.amount),
+        )
+        .await?;
+        tx.commit().await.map_err(BillingError::Database)?;
         
-        info!("Ended session {} with final bill ${}", session_code, transaction.amount);
+        info!("Ended session {} with final bill {}", session_code, transaction.amount);
         
         Ok(transaction)
     }
     
-    pub async fn process_active_sessions(&self) -> Result<(), BillingError> {
-        let active_sessions = db::get_active_sessions(&self.db_pool).await?;
-        
-        info!("Processing {} active sessions", active_sessions.len());
-        
-        for mut session in active_sessions {
-            let now = Utc::now();
-            let duration = now.signed_duration_since(session.last_billed_time);
-            
-            // Bill if interval has passed
-            if duration.num_seconds() >= self.config.billing_interval_seconds as i64 {
-                match self.bill_session(&mut session, duration).await {
-                    Ok(transaction) => {
-                        info!(
-                            "Billed session {} for ${} (tx: {})",
-                            session.session_code,
-                            transaction.amount,
-                            transaction.tx_hash.unwrap_or_default()
-                        );
-                    }
-                    Err(e) => {
-                        error!("Failed to bill session {}: {:?}", session.session_code, e);
-                        session.status = SessionStatus::Failed;
-                        let _ = db::update_session(&self.db_pool, &session).await;
-                    }
-                }
-            }
-        }
-        
-        Ok(())
-    }
-    
-    async fn bill_session(
-        &self,
-        session: &mut StreamingSession,
-        duration: Duration,
-    ) -> Result<BillingTransaction, BillingError> {
-        let hours = Decimal::from(duration.num_seconds()) / Decimal::from(3600);
-        let amount = session.rate_per_hour * hours;
-        
-        // Check user balance
-        let balance = self.blockchain_client
-            .get_user_balance(&session.user_wallet_address)
-            .await?;
-        
-        if balance < amount {
-            warn!("Insufficient balance for user {}", session.user_wallet_address);
-            return Err(BillingError::InsufficientBalance);
-        }
-        
-        // Execute blockchain transaction
-        let tx_hash = self.blockchain_client
-            .bill_user(
-                &session.user_wallet_address,
-                &session.vendor_wallet_address,
-                amount,
-            )
-            .await?;
-        
-        // Create transaction record
-        let transaction = BillingTransaction {
-            id: Uuid::new_v4(),
-            session_id: session.id,
-            user_wallet_address: session.user_wallet_address.clone(),
-            vendor_wallet_address: session.vendor_wallet_address.clone(),
-            amount,
-            duration_minutes: duration.num_minutes(),
-            tx_hash: Some(tx_hash),
-            status: TransactionStatus::Confirmed,
-            created_at: Utc::now(),
-        };
-        
-        let saved_transaction = db::create_transaction(&self.db_pool, &transaction).await?;
-        
-        // Update session
-        session.last_billed_time = Utc::now();
-        session.total_amount_billed += amount;
-        db::update_session(&self.db_pool, session).await?;
-        
-        Ok(saved_transaction)
-    }
-    
     fn generate_session_code(&self) -> String {
         use rand_chacha::rand_core::{SeedableRng, RngCore};
         
@@ -218,83 +151,4 @@ impl BillingEngine {
             })
             .collect()
     }
-    
-    async fn get_vendor_wallet(&self, vendor_id: &str) -> Result<String, BillingError> {
-        // For testing, if vendor service is mock, return mock data
-        if self.config.vendor_service_url.contains("mock-vendor-service") {
-            return Ok("0x1234567890123456789012345678901234567890".to_string());
-        }
-
-        let url = format!("{}/internal/vendors/{}", self.config.vendor_service_url, vendor_id);
-
-        let client = reqwest::Client::new();
-        let response = client.get(&url)
-            .bearer_auth(&self.config.vendor_service_token)
-            .send()
-            .await
-            .map_err(|e| BillingError::Config(format!("vendor service request failed: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(BillingError::Config(
-                format!("Vendor Service returned status {} for vendor {}",
-                    response.status(),  
-                    vendor_id
-            )
-            ));
-        }
-
-        let vendor: VendorInfo = response
-        .json()
-        .await
-        .map_err(|e| BillingError::Config(format!("Invalid vendor JSON: {}", e)))?;
-
-        //Basic validation on wallet address format for length and prefix
-        if !vendor.wallet_address.starts_with("0x") || vendor.wallet_address.len() != 42 {
-            return Err(BillingError::Config(format!(
-                "Invalid vendor wallet for {}",
-                vendor_id
-            )));
-
-        }
-        Ok(vendor.wallet_address)
-    }
-    
-    async fn get_vendor_rate(&self, vendor_id: &str) -> Result<Decimal, BillingError> {
-        // For testing, if vendor service is mock, return mock data
-        if self.config.vendor_service_url.contains("mock-vendor-service") {
-            return Ok(Decimal::from_str_exact("10.50").unwrap()); // $10.50 per hour
-        }
-
-        let url = format!("{}/internal/vendors/{}", self.config.vendor_service_url, vendor_id);
-
-        let client = reqwest::Client::new();
-        let response = client.get(&url)
-        .bearer_auth(&self.config.vendor_service_token)
-        .send()
-        .await
-        .map_err(|e| BillingError::Config(format!("Vendor service request failed: {}", e)))?;
-
-    if !response.status().is_success() {
-        return Err(BillingError::Config(format!("Vendor service returned status {} for vendor {}", 
-        response.status(),
-        vendor_id
-    )));
-    }
-
-    let vendor: VendorInfo = response
-    .json()
-    .await
-    .map_err(|e| BillingError::Config(format!("Invalid vendor JSON: {}", e)))?;
-
-    //basic check on the rate of the vendor
-    if vendor.rate_per_hour <= Decimal::ZERO || vendor.rate_per_hour > Decimal::from(1_000) {
-        return Err(BillingError::Config(format!(
-            "Suspicious rate per hour {} for vendor {}",
-            vendor.rate_per_hour,
-            vendor_id
-        )));
-    }
-        Ok(vendor.rate_per_hour)
-    }
 }
-
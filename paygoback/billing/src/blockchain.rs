@@ -1,88 +1,282 @@
 use ethers::{
     prelude::*,
-    providers::{Provider, Ws},
-    signers::{LocalWallet, Signer},
+    providers::Provider,
+    signers::Signer,
     types::Address,
 };
+use ethers::abi::Abi;
+use ethers::types::transaction::eip2718::TypedTransaction;
 use std::sync::Arc;
+use tracing::{info, warn};
 use crate::error::BillingError;
-use rust_decimal::{prelude::ToPrimitive, Decimal};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use crate::config::ContractDeployment;
+use crate::rpc::FailoverClient;
+use crate::signer::ServiceSigner;
+use crate::nonce::{GasEscalationPolicy, NonceManager, SubmissionOutcome, SubmittedTransaction};
+use crate::token::{from_token_units, to_token_units, Erc20Token};
+use rust_decimal::Decimal;
 
-// ABI for the billing smart contract
+// Interface the service relies on. Deployments may load their full ABI from
+// an artifact file, which must contain at least these functions and events.
 abigen!(
     BillingContract,
     r#"[
         function billUser(address user, address vendor, uint256 amount) external returns (bytes32)
+        function billUserBatch(address[] users, address[] vendors, uint256[] amounts) external returns (bytes32)
         function getUserBalance(address user) external view returns (uint256)
+        event Deposit(address indexed user, uint256 amount)
+        event Withdrawal(address indexed user, uint256 amount)
+        event UserBilled(address indexed user, address indexed vendor, uint256 amount, bytes32 billId)
     ]"#,
 );
 
+type SignerClient = SignerMiddleware<Arc<Provider<FailoverClient>>, ServiceSigner>;
+
+/// Whether a mined transaction succeeded. A reverted `billUser`,
+/// `billUserBatch` or `transferFrom` still spends its nonce but moves no funds.
+fn receipt_outcome(receipt: &TransactionReceipt) -> SubmissionOutcome {
+    if receipt.status == Some(1u64.into()) {
+        SubmissionOutcome::Confirmed
+    } else {
+        SubmissionOutcome::Reverted
+    }
+}
+
+/// Load the billing contract ABI from a compiler artifact (a Hardhat or
+/// Foundry JSON with an `abi` field, or a bare ABI array), or use the
+/// built-in interface when no path is given. Fails if the artifact lacks
+/// any function or event the service calls or indexes.
+pub fn load_contract_abi(path: Option<&str>) -> Result<Abi, BillingError> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(BILLINGCONTRACT_ABI.clone()),
+    };
+
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| BillingError::Config(format!("Cannot read contract ABI {}: {}", path, e)))?;
+
+    let artifact: serde_json::Value = serde_json::from_str(&raw)
+        .map_err(|e| BillingError::Config(format!("Invalid contract ABI {}: {}", path, e)))?;
+
+    let abi_json = match artifact {
+        serde_json::Value::Object(mut fields) => fields
+            .remove("abi")
+            .ok_or_else(|| BillingError::Config(format!("No abi field in artifact {}", path)))?,
+        abi => abi,
+    };
+
+    let abi: Abi = serde_json::from_value(abi_json)
+        .map_err(|e| BillingError::Config(format!("Invalid contract ABI {}: {}", path, e)))?;
+
+    for function in BILLINGCONTRACT_ABI.functions() {
+        if !abi.functions().any(|f| f.signature() == function.signature()) {
+            return Err(BillingError::Config(format!(
+                "Contract ABI {} is missing {}",
+                path,
+                function.signature()
+            )));
+        }
+    }
+
+    for event in BILLINGCONTRACT_ABI.events() {
+        if !abi.events().any(|e| e.signature() == event.signature()) {
+            return Err(BillingError::Config(format!(
+                "Contract ABI {} is missing event {}",
+                path, event.name
+            )));
+        }
+    }
+
+    Ok(abi)
+}
+
 pub struct BlockchainClient {
-    provider: Option<Arc<Provider<Ws>>>,
-    wallet: Option<LocalWallet>,
-    contract: Option<BillingContract<SignerMiddleware<Arc<Provider<Ws>>, LocalWallet>>>,
+    chain_id: u64,
+    contract_address: String,
+    provider: Option<Arc<Provider<FailoverClient>>>,
+    client: Option<Arc<SignerClient>>,
+    contract: Option<BillingContract<SignerClient>>,
+    nonce_manager: Option<NonceManager>,
+    gas_policy: GasEscalationPolicy,
+    contract_decimals: u8,
+    token_decimals: RwLock<HashMap<Address, u8>>,
 }
 
 impl BlockchainClient {
-    pub fn disabled() -> Self {
+    pub fn disabled(chain_id: u64, contract_address: &str) -> Self {
         Self {
+            chain_id,
+            contract_address: contract_address.to_lowercase(),
             provider: None,
-            wallet: None,
+            client: None,
             contract: None,
+            nonce_manager: None,
+            gas_policy: GasEscalationPolicy::default(),
+            contract_decimals: 18,
+            token_decimals: RwLock::new(HashMap::new()),
         }
     }
 
     pub async fn new(
-        rpc_url: &str,
-        contract_address: &str,
+        deployment: &ContractDeployment,
+        abi: Abi,
+        signer: ServiceSigner,
+        gas_policy: GasEscalationPolicy,
     ) -> Result<Self, BillingError> {
-        let provider = Provider::<Ws>::connect(rpc_url)
-            .await
-            .map_err(|e| BillingError::Blockchain(e.to_string()))?;
-        
-        let provider = Arc::new(provider);
-        
-        let private_key = std::env::var("PRIVATE_KEY")
-            .map_err(|e| BillingError::Config(e.to_string()))?;
-        
-        let wallet: LocalWallet = private_key
-            .parse()
-            .map_err(|e| BillingError::Blockchain(format!("Invalid private key: {}", e)))?;
-        
-        let chain_id = std::env::var("CHAIN_ID")
-            .unwrap_or_else(|_| "1".to_string())
-            .parse::<u64>()
-            .map_err(|e| BillingError::Config(e.to_string()))?;
+        if deployment.rpc_urls.is_empty() {
+            return Err(BillingError::Config(format!(
+                "No RPC endpoints configured for chain {}",
+                deployment.chain_id
+            )));
+        }
+
+        // Endpoints connect lazily, so an RPC outage here no longer disables the client
+        let provider = Arc::new(Provider::new(FailoverClient::new(&deployment.rpc_urls)));
         
-        let wallet = wallet.with_chain_id(chain_id);
+        let wallet = signer.with_chain_id(deployment.chain_id);
         
-        let client = SignerMiddleware::new(provider.clone(), wallet.clone());
+        let client = Arc::new(SignerMiddleware::new(provider.clone(), wallet.clone()));
         
-        let address: Address = contract_address
+        let address: Address = deployment.contract_address
             .parse()
             .map_err(|e| BillingError::Blockchain(format!("Invalid contract address: {}", e)))?;
         
-        let contract = BillingContract::new(address, Arc::new(client));
+        // Calls are encoded against the deployment's own ABI
+        let contract: BillingContract<SignerClient> = Contract::new(address, abi, client.clone()).into();
+        let nonce_manager = NonceManager::new(wallet.address());
         
         Ok(Self {
+            chain_id: deployment.chain_id,
+            contract_address: format!("{:?}", address),
             provider: Some(provider),
-            wallet: Some(wallet),
+            client: Some(client),
             contract: Some(contract),
+            nonce_manager: Some(nonce_manager),
+            gas_policy,
+            contract_decimals: deployment.contract_decimals,
+            token_decimals: RwLock::new(HashMap::new()),
         })
     }
     
+    /// Charge `amount` to the user. With `token_address` set, this is an ERC-20
+    /// `transferFrom` spending the user's allowance to the service signer;
+    /// otherwise it goes through the billing contract's `billUser`.
     pub async fn bill_user(
         &self,
         user_address: &str,
         vendor_address: &str,
         amount: Decimal,
-    ) -> Result<String, BillingError> {
-        // Check if blockchain client is available
-        let contract = match &self.contract {
-            Some(contract) => contract,
-            None => return Err(BillingError::Blockchain("Blockchain client not available - using Zcash instead".to_string())),
-        };
+        token_address: Option<&str>,
+    ) -> Result<SubmittedTransaction, BillingError> {
+        let tx = self.build_bill_transaction(user_address, vendor_address, amount, token_address).await?;
+        
+        self.send_with_escalation(tx, None, None).await
+    }
+
+    /// Settle many user/vendor charges in a single `billUserBatch` call.
+    /// Entries are `(user, vendor, amount)` in token units.
+    pub async fn bill_user_batch(
+        &self,
+        charges: &[(String, String, Decimal)],
+    ) -> Result<SubmittedTransaction, BillingError> {
+        let tx = self.build_batch_transaction(charges)?;
+
+        self.send_with_escalation(tx, None, None).await
+    }
+
+    /// Re-broadcast a `billUserBatch` call under its recorded nonce
+    pub async fn replace_bill_batch(
+        &self,
+        nonce: U256,
+        last_gas_price: U256,
+        charges: &[(String, String, Decimal)],
+    ) -> Result<SubmittedTransaction, BillingError> {
+        let tx = self.build_batch_transaction(charges)?;
+
+        let gas_price = self.gas_policy.bump(last_gas_price);
+        self.send_with_escalation(tx, Some(nonce), Some(gas_price)).await
+    }
+
+    /// Where a broadcast transaction stands: pending until it is mined, then
+    /// confirmed or reverted by its receipt
+    pub async fn transaction_outcome(&self, tx_hash: &str) -> Result<SubmissionOutcome, BillingError> {
+        let hash: TxHash = tx_hash
+            .parse()
+            .map_err(|e| BillingError::Blockchain(format!("Invalid transaction hash: {}", e)))?;
+
+        Ok(self
+            .find_mined(&[hash])
+            .await?
+            .map_or(SubmissionOutcome::Pending, |receipt| receipt_outcome(&receipt)))
+    }
+
+    /// Replace a stuck transaction with a zero-value self transfer using the
+    /// same nonce, priced above the last attempt so nodes accept the swap.
+    pub async fn cancel_transaction(
+        &self,
+        nonce: U256,
+        last_gas_price: U256,
+    ) -> Result<SubmittedTransaction, BillingError> {
+        let (_, nonce_manager) = self.signer()?;
+        let self_address = nonce_manager.address();
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(self_address)
+            .to(self_address)
+            .value(U256::zero())
+            .into();
 
+        let gas_price = self.gas_policy.bump(last_gas_price);
+        info!("Cancelling transaction with nonce {} at gas price {}", nonce, gas_price);
+
+        self.send_with_escalation(tx, Some(nonce), Some(gas_price)).await
+    }
+
+    /// Re-broadcast a billing call under an existing nonce, e.g. after a
+    /// restart left the original attempt stuck in the mempool.
+    pub async fn replace_bill_transaction(
+        &self,
+        nonce: U256,
+        last_gas_price: U256,
+        user_address: &str,
+        vendor_address: &str,
+        amount: Decimal,
+        token_address: Option<&str>,
+    ) -> Result<SubmittedTransaction, BillingError> {
+        let tx = self.build_bill_transaction(user_address, vendor_address, amount, token_address).await?;
+
+        let gas_price = self.gas_policy.bump(last_gas_price);
+        self.send_with_escalation(tx, Some(nonce), Some(gas_price)).await
+    }
+
+    /// Decimals reported by the token contract, cached after the first read
+    pub async fn token_decimals(&self, token_address: &str) -> Result<u8, BillingError> {
+        let token = self.token(token_address)?;
+
+        if let Some(decimals) = self.token_decimals.read().await.get(&token.address()) {
+            return Ok(*decimals);
+        }
+
+        let decimals = token
+            .decimals()
+            .call()
+            .await
+            .map_err(|e| BillingError::Blockchain(format!("Token decimals query failed: {}", e)))?;
+
+        self.token_decimals.write().await.insert(token.address(), decimals);
+
+        Ok(decimals)
+    }
+
+    async fn build_bill_transaction(
+        &self,
+        user_address: &str,
+        vendor_address: &str,
+        amount: Decimal,
+        token_address: Option<&str>,
+    ) -> Result<TypedTransaction, BillingError> {
         let user_addr: Address = user_address
             .parse()
             .map_err(|e| BillingError::Blockchain(format!("Invalid user address: {}", e)))?;
@@ -90,34 +284,336 @@ impl BlockchainClient {
         let vendor_addr: Address = vendor_address
             .parse()
             .map_err(|e| BillingError::Blockchain(format!("Invalid vendor address: {}", e)))?;
-        
-        // Convert decimal to wei (assuming 18 decimals)
-        let amount_wei = (amount * Decimal::from(10_u64.pow(18)))
-            .to_u128()
-            .ok_or_else(|| BillingError::Blockchain("Amount overflow".to_string()))?;
-        
-        let tx = contract
-            .bill_user(user_addr, vendor_addr, U256::from(amount_wei))
-            .send()
-            .await
-            .map_err(|e| BillingError::Blockchain(format!("Transaction failed: {}", e)))?
+
+        if let Some(token_address) = token_address {
+            let token = self.token(token_address)?;
+            let decimals = self.token_decimals(token_address).await?;
+            let amount_units = to_token_units(amount, decimals)?;
+
+            return Ok(token
+                .transfer_from(user_addr, vendor_addr, amount_units)
+                .legacy()
+                .tx);
+        }
+
+        // Check if blockchain client is available
+        let contract = match &self.contract {
+            Some(contract) => contract,
+            None => return Err(BillingError::Blockchain("Blockchain client not available - using Zcash instead".to_string())),
+        };
+
+        let amount_units = to_token_units(amount, self.contract_decimals)?;
+
+        Ok(contract
+            .bill_user(user_addr, vendor_addr, amount_units)
+            .legacy()
+            .tx)
+    }
+
+    fn build_batch_transaction(
+        &self,
+        charges: &[(String, String, Decimal)],
+    ) -> Result<TypedTransaction, BillingError> {
+        let contract = match &self.contract {
+            Some(contract) => contract,
+            None => return Err(BillingError::Blockchain("Blockchain client not available - using Zcash instead".to_string())),
+        };
+
+        let mut users = Vec::with_capacity(charges.len());
+        let mut vendors = Vec::with_capacity(charges.len());
+        let mut amounts = Vec::with_capacity(charges.len());
+
+        for (user_address, vendor_address, amount) in charges {
+            let user_addr: Address = user_address
+                .parse()
+                .map_err(|e| BillingError::Blockchain(format!("Invalid user address: {}", e)))?;
+            
+            let vendor_addr: Address = vendor_address
+                .parse()
+                .map_err(|e| BillingError::Blockchain(format!("Invalid vendor address: {}", e)))?;
+
+            let amount_units = to_token_units(*amount, self.contract_decimals)?;
+
+            users.push(user_addr);
+            vendors.push(vendor_addr);
+            amounts.push(amount_units);
+        }
+
+        Ok(contract
+            .bill_user_batch(users, vendors, amounts)
+            .legacy()
+            .tx)
+    }
+
+    fn token(&self, token_address: &str) -> Result<Erc20Token<SignerClient>, BillingError> {
+        let (client, _) = self.signer()?;
+
+        let address: Address = token_address
+            .parse()
+            .map_err(|e| BillingError::Blockchain(format!("Invalid token address: {}", e)))?;
+
+        Ok(Erc20Token::new(address, client.clone()))
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Billing contract address as lowercase hex
+    pub fn contract_address(&self) -> &str {
+        &self.contract_address
+    }
+
+    pub fn contract_decimals(&self) -> u8 {
+        self.contract_decimals
+    }
+
+    pub async fn block_number(&self) -> Result<u64, BillingError> {
+        let provider = match &self.provider {
+            Some(provider) => provider,
+            None => return Err(BillingError::Blockchain("Blockchain client not available - using Zcash instead".to_string())),
+        };
+
+        provider
+            .get_block_number()
             .await
-            .map_err(|e| BillingError::Blockchain(format!("Transaction receipt failed: {}", e)))?
-            .ok_or_else(|| BillingError::Blockchain("No transaction receipt".to_string()))?;
-        
-        Ok(format!("{:?}", tx.transaction_hash))
+            .map(|block| block.as_u64())
+            .map_err(|e| BillingError::Blockchain(format!("Block number query failed: {}", e)))
     }
-    
-    pub async fn get_user_balance(&self, user_address: &str) -> Result<Decimal, BillingError> {
-        // Check if blockchain client is available
+
+    /// Billing contract events emitted in `[from_block, to_block]`, with the
+    /// block and log position of each
+    pub async fn query_contract_events(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<(BillingContractEvents, LogMeta)>, BillingError> {
         let contract = match &self.contract {
             Some(contract) => contract,
             None => return Err(BillingError::Blockchain("Blockchain client not available - using Zcash instead".to_string())),
         };
 
+        contract
+            .events()
+            .from_block(from_block)
+            .to_block(to_block)
+            .query_with_meta()
+            .await
+            .map_err(|e| BillingError::Blockchain(format!("Log query failed: {}", e)))
+    }
+
+    /// Probe the configured RPC endpoints and fail back to the preferred one
+    pub async fn check_rpc_health(&self) {
+        if let Some(provider) = &self.provider {
+            provider.as_ref().as_ref().check_health().await;
+        }
+    }
+
+    fn signer(&self) -> Result<(&Arc<SignerClient>, &NonceManager), BillingError> {
+        match (&self.client, &self.nonce_manager) {
+            (Some(client), Some(nonce_manager)) => Ok((client, nonce_manager)),
+            _ => Err(BillingError::Blockchain("Blockchain client not available - using Zcash instead".to_string())),
+        }
+    }
+
+    /// Broadcast `tx` with an explicit nonce and gas price, bumping the price
+    /// and re-broadcasting under the same nonce whenever it stays pending past
+    /// the policy deadline. Returns unconfirmed once bumps are exhausted so the
+    /// caller can record the nonce and cancel or replace it later. Errors only
+    /// when nothing was broadcast; once any attempt is out, failures leave the
+    /// submission pending under its hashes.
+    async fn send_with_escalation(
+        &self,
+        mut tx: TypedTransaction,
+        nonce: Option<U256>,
+        gas_price: Option<U256>,
+    ) -> Result<SubmittedTransaction, BillingError> {
+        let (client, nonce_manager) = self.signer()?;
+
+        let fresh_nonce = nonce.is_none();
+        let nonce = match nonce {
+            Some(nonce) => nonce,
+            None => nonce_manager.next(client.as_ref()).await?,
+        };
+
+        let mut gas_price = match gas_price {
+            Some(gas_price) => gas_price,
+            None => client
+                .get_gas_price()
+                .await
+                .map_err(|e| BillingError::Blockchain(format!("Gas price query failed: {}", e)))?,
+        };
+
+        tx.set_nonce(nonce);
+
+        let mut hashes: Vec<TxHash> = Vec::new();
+        let mut broadcast_gas_price = gas_price;
+        let mut bumps = 0;
+
+        loop {
+            tx.set_gas_price(gas_price);
+
+            let pending = match client.send_transaction(tx.clone(), None).await {
+                Ok(pending) => pending,
+                Err(e) if hashes.is_empty() => {
+                    if fresh_nonce {
+                        // The nonce never reached the mempool, so hand it back
+                        // and catch up with the node if it was already used
+                        nonce_manager.release(nonce).await;
+                        nonce_manager.resync(client.as_ref()).await?;
+                    }
+
+                    return Err(BillingError::Blockchain(format!("Transaction failed: {}", e)));
+                }
+                Err(e) => {
+                    // A replacement can lose the race against the original
+                    // being mined; otherwise the earlier attempts stay pending
+                    warn!("Replacing nonce {} at gas price {} failed: {}", nonce, gas_price, e);
+                    return Ok(self.settle_attempts(&hashes, nonce, broadcast_gas_price).await);
+                }
+            };
+
+            let tx_hash = pending.tx_hash();
+            hashes.push(tx_hash);
+            broadcast_gas_price = gas_price;
+
+            match tokio::time::timeout(self.gas_policy.pending_timeout, pending).await {
+                Ok(Ok(Some(receipt))) => {
+                    return Ok(Self::mined(&receipt, nonce, gas_price, &hashes));
+                }
+                Ok(Err(e)) => {
+                    // The transaction was broadcast, so it stays pending under
+                    // its nonce for the stuck transaction sweep
+                    warn!("Receipt poll for {:?} with nonce {} failed: {}", tx_hash, nonce, e);
+                    return Ok(Self::submission(tx_hash, nonce, gas_price, &hashes, SubmissionOutcome::Pending));
+                }
+                // Dropped from the mempool or past the deadline; an earlier
+                // attempt may still have landed
+                Ok(Ok(None)) | Err(_) => match self.find_mined(&hashes).await {
+                    Ok(Some(receipt)) => return Ok(Self::mined(&receipt, nonce, gas_price, &hashes)),
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Receipt query for nonce {} failed: {:?}", nonce, e);
+                        return Ok(Self::submission(tx_hash, nonce, gas_price, &hashes, SubmissionOutcome::Pending));
+                    }
+                },
+            }
+
+            if !self.gas_policy.can_bump(gas_price, bumps) {
+                warn!(
+                    "Transaction {:?} with nonce {} still pending after {} gas bumps",
+                    tx_hash, nonce, bumps
+                );
+                return Ok(Self::submission(tx_hash, nonce, gas_price, &hashes, SubmissionOutcome::Pending));
+            }
+
+            bumps += 1;
+            gas_price = self.gas_policy.bump(gas_price);
+            warn!(
+                "Transaction {:?} pending past deadline, replacing nonce {} at gas price {}",
+                tx_hash, nonce, gas_price
+            );
+        }
+    }
+
+    /// The outcome of attempts already broadcast under `nonce`: mined if any
+    /// of them landed, otherwise pending under the last one
+    async fn settle_attempts(&self, hashes: &[TxHash], nonce: U256, gas_price: U256) -> SubmittedTransaction {
+        match self.find_mined(hashes).await {
+            Ok(Some(receipt)) => Self::mined(&receipt, nonce, gas_price, hashes),
+            Ok(None) => Self::submission(hashes[hashes.len() - 1], nonce, gas_price, hashes, SubmissionOutcome::Pending),
+            Err(e) => {
+                warn!("Receipt query for nonce {} failed: {:?}", nonce, e);
+                Self::submission(hashes[hashes.len() - 1], nonce, gas_price, hashes, SubmissionOutcome::Pending)
+            }
+        }
+    }
+
+    async fn find_mined(&self, hashes: &[TxHash]) -> Result<Option<TransactionReceipt>, BillingError> {
+        let (client, _) = self.signer()?;
+
+        for hash in hashes {
+            let receipt = client
+                .get_transaction_receipt(*hash)
+                .await
+                .map_err(|e| BillingError::Blockchain(format!("Receipt query failed: {}", e)))?;
+
+            if receipt.is_some() {
+                return Ok(receipt);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// A mined attempt, confirmed only if the receipt reports success
+    fn mined(receipt: &TransactionReceipt, nonce: U256, gas_price: U256, hashes: &[TxHash]) -> SubmittedTransaction {
+        let outcome = receipt_outcome(receipt);
+        if outcome == SubmissionOutcome::Reverted {
+            warn!("Transaction {:?} with nonce {} reverted", receipt.transaction_hash, nonce);
+        }
+
+        Self::submission(receipt.transaction_hash, nonce, gas_price, hashes, outcome)
+    }
+
+    fn submission(
+        tx_hash: TxHash,
+        nonce: U256,
+        gas_price: U256,
+        hashes: &[TxHash],
+        outcome: SubmissionOutcome,
+    ) -> SubmittedTransaction {
+        SubmittedTransaction {
+            tx_hash: format!("{:?}", tx_hash),
+            nonce,
+            gas_price,
+            attempts: hashes.len() as u32,
+            replaced_tx_hashes: hashes
+                .iter()
+                .filter(|hash| **hash != tx_hash)
+                .map(|hash| format!("{:?}", hash))
+                .collect(),
+            outcome,
+        }
+    }
+    
+    /// Amount the service can currently charge the user: the billing
+    /// contract balance, or for an ERC-20 the lesser of the user's token
+    /// balance and their allowance to the service signer.
+    pub async fn get_user_balance(
+        &self,
+        user_address: &str,
+        token_address: Option<&str>,
+    ) -> Result<Decimal, BillingError> {
         let user_addr: Address = user_address
             .parse()
             .map_err(|e| BillingError::Blockchain(format!("Invalid user address: {}", e)))?;
+
+        if let Some(token_address) = token_address {
+            let token = self.token(token_address)?;
+            let (_, nonce_manager) = self.signer()?;
+            let decimals = self.token_decimals(token_address).await?;
+
+            let balance = token
+                .balance_of(user_addr)
+                .call()
+                .await
+                .map_err(|e| BillingError::Blockchain(format!("Token balance query failed: {}", e)))?;
+
+            let allowance = token
+                .allowance(user_addr, nonce_manager.address())
+                .call()
+                .await
+                .map_err(|e| BillingError::Blockchain(format!("Token allowance query failed: {}", e)))?;
+
+            return from_token_units(balance.min(allowance), decimals);
+        }
+
+        // Check if blockchain client is available
+        let contract = match &self.contract {
+            Some(contract) => contract,
+            None => return Err(BillingError::Blockchain("Blockchain client not available - using Zcash instead".to_string())),
+        };
         
         let balance = contract
             .get_user_balance(user_addr)
@@ -125,9 +621,34 @@ impl BlockchainClient {
             .await
             .map_err(|e| BillingError::Blockchain(format!("Balance query failed: {}", e)))?;
         
-        // Convert from wei to token units (assuming 18 decimals)
-        let balance_decimal = Decimal::from(balance.as_u128()) / Decimal::from(10_u64.pow(18));
-        
-        Ok(balance_decimal)
+        from_token_units(balance, self.contract_decimals)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(status: Option<u64>) -> TransactionReceipt {
+        TransactionReceipt {
+            status: status.map(U64::from),
+            ..TransactionReceipt::default()
+        }
+    }
+
+    #[test]
+    fn successful_receipt_confirms() {
+        assert_eq!(receipt_outcome(&receipt(Some(1))), SubmissionOutcome::Confirmed);
+    }
+
+    #[test]
+    fn reverted_receipt_does_not_confirm() {
+        assert_eq!(receipt_outcome(&receipt(Some(0))), SubmissionOutcome::Reverted);
+    }
+
+    #[test]
+    fn receipt_without_status_does_not_confirm() {
+        // Pre-Byzantium receipts carry a state root instead of a status
+        assert_eq!(receipt_outcome(&receipt(None)), SubmissionOutcome::Reverted);
+    }
+}
@@ -0,0 +1,144 @@
+// src/chains.rs
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::blockchain::{load_contract_abi, BlockchainClient};
+use crate::config::Config;
+use crate::error::BillingError;
+use crate::signer::ServiceSigner;
+
+/// One `BlockchainClient` per configured billing contract deployment, keyed
+/// by chain id. Sessions, charges, batches and permits record the chain they
+/// settle on and look their client up here.
+pub struct ChainRegistry {
+    default_chain_id: u64,
+    clients: HashMap<u64, Arc<BlockchainClient>>,
+}
+
+impl ChainRegistry {
+    /// Connect every deployment. A deployment that fails to initialise gets
+    /// a disabled client so the rest of the service keeps running; a missing
+    /// or incompatible ABI artifact is a configuration error.
+    pub async fn connect(config: &Config) -> Result<Self, BillingError> {
+        let mut clients = HashMap::new();
+
+        // One signing key serves every chain; nonces are tracked per chain
+        let signer = ServiceSigner::connect(&config.signer).await;
+        if let Err(e) = &signer {
+            warn!("Failed to initialize signer (continuing without on-chain billing): {}", e);
+        }
+
+        for deployment in &config.deployments {
+            let abi_path = deployment.abi_path.as_deref().or(config.contract_abi_path.as_deref());
+            let abi = load_contract_abi(abi_path)?;
+
+            let connected = match &signer {
+                Ok(signer) => {
+                    BlockchainClient::new(deployment, abi, signer.clone(), config.gas_escalation_policy()).await
+                }
+                Err(e) => Err(BillingError::Config(format!("No signer: {}", e))),
+            };
+
+            let client = match connected {
+                Ok(client) => {
+                    info!("Billing contract {} on chain {}", deployment.contract_address, deployment.chain_id);
+                    client
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to initialize blockchain client for chain {} (continuing without it): {}",
+                        deployment.chain_id, e
+                    );
+                    BlockchainClient::disabled(deployment.chain_id, &deployment.contract_address)
+                }
+            };
+
+            clients.insert(deployment.chain_id, Arc::new(client));
+        }
+
+        Ok(Self {
+            default_chain_id: config.default_chain_id,
+            clients,
+        })
+    }
+
+    pub fn default_chain_id(&self) -> u64 {
+        self.default_chain_id
+    }
+
+    pub fn client(&self, chain_id: u64) -> Result<Arc<BlockchainClient>, BillingError> {
+        self.clients
+            .get(&chain_id)
+            .cloned()
+            .ok_or_else(|| BillingError::Config(format!("No billing contract deployed on chain {}", chain_id)))
+    }
+
+    /// Chain a new session settles on: the one the user asked for, else the
+    /// vendor's, else the default deployment
+    pub fn resolve(&self, requested: Option<u64>, vendor_chain: Option<u64>) -> Result<u64, BillingError> {
+        let chain_id = requested.or(vendor_chain).unwrap_or(self.default_chain_id);
+
+        if !self.clients.contains_key(&chain_id) {
+            return Err(BillingError::Validation(format!("No billing contract deployed on chain {}", chain_id)));
+        }
+
+        Ok(chain_id)
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &Arc<BlockchainClient>> {
+        self.clients.values()
+    }
+
+    pub async fn check_health(&self) {
+        for client in self.clients.values() {
+            client.check_rpc_health().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTRACT: &str = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+
+    fn registry(default_chain_id: u64, chain_ids: &[u64]) -> ChainRegistry {
+        ChainRegistry {
+            default_chain_id,
+            clients: chain_ids
+                .iter()
+                .map(|&chain_id| (chain_id, Arc::new(BlockchainClient::disabled(chain_id, CONTRACT))))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn sessions_settle_on_the_requested_then_vendor_then_default_chain() {
+        let chains = registry(1, &[1, 10, 8453]);
+
+        assert_eq!(chains.resolve(Some(10), Some(8453)).unwrap(), 10);
+        assert_eq!(chains.resolve(None, Some(8453)).unwrap(), 8453);
+        assert_eq!(chains.resolve(None, None).unwrap(), 1);
+    }
+
+    #[test]
+    fn undeployed_chains_are_rejected() {
+        let chains = registry(1, &[1]);
+
+        assert!(matches!(chains.resolve(Some(137), None), Err(BillingError::Validation(_))));
+        assert!(matches!(chains.resolve(None, Some(137)), Err(BillingError::Validation(_))));
+        assert!(matches!(chains.client(137), Err(BillingError::Config(_))));
+    }
+
+    #[test]
+    fn clients_are_keyed_by_chain_id() {
+        let chains = registry(1, &[1, 10]);
+
+        let client = chains.client(10).unwrap();
+        assert_eq!(client.chain_id(), 10);
+        assert_eq!(client.contract_address(), CONTRACT.to_lowercase());
+        assert_eq!(chains.clients().count(), 2);
+        assert_eq!(chains.default_chain_id(), 1);
+    }
+}
@@ -0,0 +1,490 @@
+// src/charging.rs
+use sqlx::{PgConnection, PgPool};
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use rust_decimal::Decimal;
+use tracing::{error, warn};
+
+use crate::models::*;
+use crate::blockchain::BlockchainClient;
+use crate::chains::ChainRegistry;
+use crate::permit::PermitService;
+use crate::config::Config;
+use crate::error::BillingError;
+use crate::db;
+use crate::indexer;
+use crate::interval::{self, IntervalCharge};
+use crate::nonce::{SubmissionOutcome, SubmittedTransaction};
+use crate::events::{BillingEvent, BillingEventKind};
+use crate::fees::FeeBreakdown;
+use crate::oracle::PriceService;
+use crate::outbox;
+use crate::pricing::PricingPlan;
+use crate::webhooks::WebhookEvent;
+
+/// What a new session takes from its vendor: who is paid, at what prices,
+/// and where the charges settle
+#[derive(Debug, Clone)]
+pub struct VendorTerms {
+    pub wallet_address: String,
+    pub pricing_plan: PricingPlan,
+    pub chain_id: i64,
+    pub token_address: Option<String>, // the ERC-20 the vendor's currency maps to, if any
+}
+
+/// How often a charge is priced again after another charge billed the
+/// session first, before giving up
+const CLAIM_ATTEMPTS: usize = 3;
+
+/// A charge priced and reserved against a session snapshot, not yet claimed
+struct PreparedCharge {
+    transaction: BillingTransaction,
+    fees: FeeBreakdown,
+    charge: IntervalCharge,
+    permit_id: Uuid,
+}
+
+/// Vendor lookups and on-chain interval charges, shared by the legacy and
+/// integrated billing engines
+pub struct SessionCharger {
+    db_pool: PgPool,
+    chains: Arc<ChainRegistry>,
+    permit_service: Arc<PermitService>,
+    prices: Arc<PriceService>,
+    config: Config,
+}
+
+impl SessionCharger {
+    pub fn new(
+        db_pool: PgPool,
+        chains: Arc<ChainRegistry>,
+        permit_service: Arc<PermitService>,
+        prices: Arc<PriceService>,
+        config: Config,
+    ) -> Self {
+        Self {
+            db_pool,
+            chains,
+            permit_service,
+            prices,
+            config,
+        }
+    }
+
+    /// The vendor's wallet, pricing plan and settlement chain and token,
+    /// from one vendor service lookup
+    pub async fn vendor_terms(
+        &self,
+        vendor_id: &str,
+        requested_chain: Option<u64>,
+    ) -> Result<VendorTerms, BillingError> {
+        let vendor = self.get_vendor(vendor_id).await?;
+
+        //Basic validation on wallet address format for length and prefix
+        if !vendor.wallet_address.starts_with("0x") || vendor.wallet_address.len() != 42 {
+            return Err(BillingError::Upstream(format!("Invalid vendor wallet for {}", vendor_id)));
+        }
+
+        let pricing_plan = Self::pricing_plan(&vendor)?;
+        let chain_id = self.chains.resolve(requested_chain, vendor.chain_id)?;
+
+        Ok(VendorTerms {
+            wallet_address: vendor.wallet_address,
+            pricing_plan,
+            chain_id: chain_id as i64,
+            token_address: self.config.token_for_currency(chain_id, &vendor.currency),
+        })
+    }
+
+    /// The vendor's current pricing plan
+    pub async fn vendor_pricing(&self, vendor_id: &str) -> Result<PricingPlan, BillingError> {
+        let vendor = self.get_vendor(vendor_id).await?;
+        Self::pricing_plan(&vendor)
+    }
+
+    /// Charge the session for its time up to `now`, as its final charge if
+    /// it `ended`. The charge is priced and funded against a snapshot of the
+    /// session, then claims its interval in a short transaction that re-reads
+    /// the session under lock and starts over if another charge billed it in
+    /// between, so a billing tick and an end request can't both bill it
+    /// without holding the lock across oracle and RPC calls. Returns `None`
+    /// when a tick finds it no longer due. Each database step commits with
+    /// the events describing it, so a crash mid-charge never announces a
+    /// write that didn't happen.
+    pub async fn bill_session(
+        &self,
+        session: &mut StreamingSession,
+        now: DateTime<Utc>,
+        ended: bool,
+    ) -> Result<Option<BillingTransaction>, BillingError> {
+        let blockchain_client = self.chains.client(session.chain_id as u64)?;
+
+        let mut claimed = None;
+        for _ in 0..CLAIM_ATTEMPTS {
+            *session = db::get_session(&self.db_pool, session.id).await?;
+            if !self.is_due(session, now, ended)? {
+                return Ok(None);
+            }
+
+            let prepared = self.prepare_charge(session, &blockchain_client, now, ended).await?;
+            let amount = prepared.transaction.amount;
+            let permit_id = prepared.permit_id;
+
+            match self.claim_charge(session, prepared).await {
+                Ok(Some(recorded)) => {
+                    claimed = Some(recorded);
+                    break;
+                }
+                // Billed by someone else since the snapshot; price it again
+                Ok(None) => self.release_permit(permit_id, amount).await,
+                Err(e) => {
+                    self.release_permit(permit_id, amount).await;
+                    return Err(e);
+                }
+            }
+        }
+        let Some((saved_transaction, billed_session)) = claimed else {
+            return Err(BillingError::Conflict(format!(
+                "Session {} kept changing while it was being billed",
+                session.session_code
+            )));
+        };
+        let amount = saved_transaction.amount;
+        let unbilled = std::mem::replace(session, billed_session);
+
+        if matches!(saved_transaction.status, TransactionStatus::Accrued) {
+            return Ok(Some(saved_transaction));
+        }
+
+        // Execute blockchain transaction. An error means nothing reached the
+        // mempool; once anything was broadcast the charge stays pending under
+        // its nonce for the stuck transaction sweep, even if it never confirms.
+        let submission = match blockchain_client
+            .bill_user(
+                &session.user_wallet_address,
+                &session.vendor_wallet_address,
+                amount,
+                session.token_address.as_deref(),
+            )
+            .await
+        {
+            Ok(submission) => submission,
+            Err(e) => {
+                if let Err(abandon_error) = self.abandon_charge(session, &unbilled, &saved_transaction, None).await {
+                    error!("Failed to abandon charge {}: {:?}", saved_transaction.id, abandon_error);
+                }
+                return Err(e);
+            }
+        };
+
+        // A reverted charge moved no funds, so it fails like one never sent
+        if submission.outcome == SubmissionOutcome::Reverted {
+            if let Err(abandon_error) = self.abandon_charge(session, &unbilled, &saved_transaction, Some(&submission)).await {
+                error!("Failed to abandon charge {}: {:?}", saved_transaction.id, abandon_error);
+            }
+            return Err(BillingError::Blockchain(format!("Charge {} reverted", submission.tx_hash)));
+        }
+
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+        db::record_transaction_submission(&mut *tx, saved_transaction.id, &submission, submission.status()).await?;
+
+        let transaction = db::get_transaction(&mut *tx, saved_transaction.id).await?;
+        outbox::record(&mut *tx, &WebhookEvent::transaction(&transaction, &session.vendor_id)).await?;
+        outbox::record_billing(
+            &mut *tx,
+            &BillingEvent::for_session(BillingEventKind::SessionBilled, session).with_charge(amount),
+        )
+        .await?;
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        Ok(Some(transaction))
+    }
+
+    /// Whether the session has a charge due at `now`; ending a session that
+    /// is no longer active is an error rather than nothing to bill
+    fn is_due(&self, session: &StreamingSession, now: DateTime<Utc>, ended: bool) -> Result<bool, BillingError> {
+        if session.status != SessionStatus::Active {
+            return if ended { Err(BillingError::InvalidSessionCode) } else { Ok(false) };
+        }
+
+        Ok(ended
+            || now.signed_duration_since(session.last_billed_time).num_seconds()
+                >= self.config.billing_interval_seconds as i64)
+    }
+
+    /// Price the session's next charge, check the user can cover it and
+    /// reserve it against their permit, all from a snapshot of the session
+    async fn prepare_charge(
+        &self,
+        session: &StreamingSession,
+        blockchain_client: &BlockchainClient,
+        now: DateTime<Utc>,
+        ended: bool,
+    ) -> Result<PreparedCharge, BillingError> {
+        let precision = interval::ethereum_precision(blockchain_client, session.token_address.as_deref()).await?;
+        let asset = self.config.settlement_asset(session.chain_id as u64, session.token_address.as_deref());
+        let (charge, conversion) = interval::settlement_charge(
+            &self.prices,
+            session,
+            &asset,
+            now,
+            ended,
+            precision,
+            self.config.billing_rounding,
+        )
+        .await?;
+        let amount = charge.amount;
+
+        // Check user balance, net of charges accrued for the next batch or
+        // still confirming, so the same funds aren't charged twice
+        let balance = indexer::user_balance(
+            &self.db_pool,
+            blockchain_client,
+            &self.config,
+            &session.user_wallet_address,
+            session.token_address.as_deref(),
+        )
+        .await?
+            - db::get_unsettled_amount(
+                &self.db_pool,
+                &session.user_wallet_address,
+                session.chain_id,
+                session.token_address.as_deref(),
+            )
+            .await?;
+
+        if balance < amount {
+            warn!("Insufficient balance for user {}", session.user_wallet_address);
+            return Err(BillingError::InsufficientBalance);
+        }
+
+        // Pre-authorise the charge against the user's signed EIP-712 permit
+        let peak_rate = session.pricing_plan.peak_rate_per_hour();
+        let peak_rate = conversion.as_ref().map_or(peak_rate, |conversion| conversion.to_asset(peak_rate));
+        let permit = self.permit_service.reserve(session, amount, peak_rate).await?;
+        let fees = self.config.fees.apply_to_session(session, amount, precision, self.config.billing_rounding);
+
+        // In batch mode the charge is only accrued here and settled later by the
+        // BatchSettler; ERC-20 charges have no batch entry point and bill directly.
+        let batched = self.config.billing_batch_mode && session.token_address.is_none();
+        let transaction = BillingTransaction {
+            id: Uuid::new_v4(),
+            session_id: session.id,
+            user_wallet_address: session.user_wallet_address.clone(),
+            vendor_wallet_address: session.vendor_wallet_address.clone(),
+            amount,
+            fee_amount: fees.fee,
+            net_amount: fees.net,
+            token_address: session.token_address.clone(),
+            chain_id: Some(session.chain_id),
+            duration_minutes: charge.duration.num_minutes(),
+            duration_micros: charge.duration.num_microseconds().unwrap_or(i64::MAX),
+            tx_hash: None,
+            status: if batched { TransactionStatus::Accrued } else { TransactionStatus::Pending },
+            nonce: None,
+            gas_price_wei: None,
+            submission_attempts: 0,
+            replaced_tx_hashes: Vec::new(),
+            batch_id: None,
+            created_at: Utc::now(),
+            fiat_currency: None,
+            fiat_amount: None,
+            exchange_rate: None,
+            exchange_rate_at: None,
+            permit_id: Some(permit.id),
+        }
+        .with_conversion(conversion.as_ref());
+
+        Ok(PreparedCharge {
+            transaction,
+            fees,
+            charge,
+            permit_id: permit.id,
+        })
+    }
+
+    /// Record a prepared charge and move the session past its interval in
+    /// one short transaction, provided the session is still as it was when
+    /// the charge was priced; returns `None` if it changed in between. An
+    /// on-chain charge claims its interval before broadcasting, so the nonce
+    /// and any gas replacements can be tracked against its row.
+    async fn claim_charge(
+        &self,
+        snapshot: &StreamingSession,
+        prepared: PreparedCharge,
+    ) -> Result<Option<(BillingTransaction, StreamingSession)>, BillingError> {
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        let locked = db::lock_session(&mut *tx, snapshot.id).await?;
+        if locked.status != snapshot.status
+            || locked.last_billed_time != snapshot.last_billed_time
+            || locked.billed_micros != snapshot.billed_micros
+        {
+            return Ok(None);
+        }
+
+        let recorded = if matches!(prepared.transaction.status, TransactionStatus::Accrued) {
+            Self::record_accrual(&mut tx, &locked, &prepared.transaction, &prepared.fees, &prepared.charge).await?
+        } else {
+            Self::record_claim(&mut tx, &locked, &prepared.transaction, &prepared.fees, &prepared.charge).await?
+        };
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        Ok(Some(recorded))
+    }
+
+    /// Hand a reservation back to its permit on a charge's error path,
+    /// logging a failure rather than hiding the charge's own error
+    async fn release_permit(&self, permit_id: Uuid, amount: Decimal) {
+        if let Err(e) = self.permit_service.release(permit_id, amount).await {
+            error!("Failed to release permit {} after a failed charge: {:?}", permit_id, e);
+        }
+    }
+
+    /// Store an accrued charge, the session's new billed-up-to time and
+    /// their events on the connection holding the session's lock
+    async fn record_accrual(
+        conn: &mut PgConnection,
+        session: &StreamingSession,
+        transaction: &BillingTransaction,
+        fees: &FeeBreakdown,
+        charge: &IntervalCharge,
+    ) -> Result<(BillingTransaction, StreamingSession), BillingError> {
+        let saved_transaction = db::create_transaction(&mut *conn, transaction).await?;
+        db::create_revenue_splits(&mut *conn, Some(saved_transaction.id), None, &fees.allocations).await?;
+        outbox::record(&mut *conn, &WebhookEvent::transaction(&saved_transaction, &session.vendor_id)).await?;
+
+        let mut billed_session = session.clone();
+        billed_session.last_billed_time = charge.billed_until;
+        billed_session.billed_micros = charge.billed_micros;
+        billed_session.total_amount_billed += transaction.amount;
+        db::update_session(&mut *conn, &billed_session).await?;
+        outbox::record_billing(
+            &mut *conn,
+            &BillingEvent::for_session(BillingEventKind::SessionBilled, &billed_session).with_charge(transaction.amount),
+        )
+        .await?;
+
+        Ok((saved_transaction, billed_session))
+    }
+
+    /// Store a charge about to be broadcast with its revenue splits and
+    /// move the session's billed-up-to time past its interval, on the
+    /// connection holding the session's lock
+    async fn record_claim(
+        conn: &mut PgConnection,
+        session: &StreamingSession,
+        transaction: &BillingTransaction,
+        fees: &FeeBreakdown,
+        charge: &IntervalCharge,
+    ) -> Result<(BillingTransaction, StreamingSession), BillingError> {
+        let saved_transaction = db::create_transaction(&mut *conn, transaction).await?;
+        db::create_revenue_splits(&mut *conn, Some(saved_transaction.id), None, &fees.allocations).await?;
+
+        let mut billed_session = session.clone();
+        billed_session.last_billed_time = charge.billed_until;
+        billed_session.billed_micros = charge.billed_micros;
+        billed_session.total_amount_billed += transaction.amount;
+        db::update_session(&mut *conn, &billed_session).await?;
+
+        Ok((saved_transaction, billed_session))
+    }
+
+    /// Mark a charge that never reached the chain, or reverted there under
+    /// `submission`, failed, return its permit reservation and hand its
+    /// interval back to the session, unless the session was billed past it
+    /// since
+    async fn abandon_charge(
+        &self,
+        session: &mut StreamingSession,
+        unbilled: &StreamingSession,
+        transaction: &BillingTransaction,
+        submission: Option<&SubmittedTransaction>,
+    ) -> Result<(), BillingError> {
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        let mut locked = db::lock_session(&mut *tx, session.id).await?;
+        if locked.last_billed_time == session.last_billed_time {
+            locked.last_billed_time = unbilled.last_billed_time;
+            locked.billed_micros = unbilled.billed_micros;
+            locked.total_amount_billed -= transaction.amount;
+            db::update_session(&mut *tx, &locked).await?;
+        }
+
+        match submission {
+            Some(submission) => {
+                db::record_transaction_submission(&mut *tx, transaction.id, submission, TransactionStatus::Failed).await?
+            }
+            None => db::update_transaction_status(&mut *tx, transaction.id, TransactionStatus::Failed).await?,
+        }
+        db::release_permit_reservations(&mut *tx, &[transaction.id]).await?;
+        let failed_transaction = db::get_transaction(&mut *tx, transaction.id).await?;
+        outbox::record(&mut *tx, &WebhookEvent::transaction(&failed_transaction, &session.vendor_id)).await?;
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        *session = locked;
+        Ok(())
+    }
+
+    async fn get_vendor(&self, vendor_id: &str) -> Result<VendorInfo, BillingError> {
+        // For testing, mock vendors get a fixed wallet and rate and bill
+        // through the billing contract
+        if self.config.vendor_service_url.contains("mock-vendor-service") {
+            return Ok(VendorInfo {
+                id: vendor_id.to_string(),
+                wallet_address: "0x1234567890123456789012345678901234567890".to_string(),
+                rate_per_hour: Decimal::new(1050, 2), // $10.50 per hour
+                currency: "ETH".to_string(),
+                chain_id: None,
+                pricing_plan: None,
+            });
+        }
+
+        let url = format!("{}/internal/vendors/{}", self.config.vendor_service_url, vendor_id);
+
+        let client = reqwest::Client::new();
+        let response = client.get(&url)
+            .bearer_auth(self.config.vendor_service_token.expose())
+            .send()
+            .await
+            .map_err(|e| BillingError::Upstream(format!("Vendor service request failed: {}", e)))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(BillingError::NotFound(format!("Vendor {} not found", vendor_id)));
+        }
+
+        if !response.status().is_success() {
+            return Err(BillingError::Upstream(format!(
+                "Vendor service returned status {} for vendor {}",
+                response.status(),
+                vendor_id
+            )));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| BillingError::Upstream(format!("Invalid vendor JSON: {}", e)))
+    }
+
+    /// The vendor's pricing plan, or a flat plan at its hourly rate
+    fn pricing_plan(vendor: &VendorInfo) -> Result<PricingPlan, BillingError> {
+        //basic check on the rate of the vendor
+        if vendor.rate_per_hour <= Decimal::ZERO || vendor.rate_per_hour > Decimal::from(1_000) {
+            return Err(BillingError::Upstream(format!(
+                "Suspicious rate per hour {} for vendor {}",
+                vendor.rate_per_hour,
+                vendor.id
+            )));
+        }
+
+        let plan = vendor.pricing_plan.clone().unwrap_or_else(|| PricingPlan::flat(vendor.rate_per_hour));
+        plan.validate().map_err(|e| BillingError::Upstream(format!(
+            "Vendor {} has an unusable pricing plan: {}",
+            vendor.id, e
+        )))?;
+
+        Ok(plan)
+    }
+}
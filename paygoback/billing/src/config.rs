@@ -1,11 +1,18 @@
-// src/zcash/zcash_config.rs
+// src/config.rs
+use rust_decimal::Decimal;
 use serde::Deserialize;
+use std::collections::HashMap;
+use ethers::types::{Address, U256};
+
+use crate::fees::{FeeSchedule, FeeSchedules};
+use crate::interval::{Rounding, ZCASH_ASSET};
+use crate::nonce::GasEscalationPolicy;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ZcashConfig {
     pub rpc_url: String,
     pub rpc_user: String,
-    pub rpc_password: String,
+    pub rpc_password: SecretString,
     pub service_wallet_address: String,
     pub min_confirmations: u32,
     pub default_permission_duration_days: i64,
@@ -17,7 +24,7 @@ impl ZcashConfig {
             rpc_url: std::env::var("ZCASH_RPC_URL")
                 .unwrap_or_else(|_| "http://127.0.0.1:8232".to_string()),
             rpc_user: std::env::var("ZCASH_RPC_USER")?,
-            rpc_password: std::env::var("ZCASH_RPC_PASSWORD")?,
+            rpc_password: std::env::var("ZCASH_RPC_PASSWORD")?.into(),
             service_wallet_address: std::env::var("ZCASH_SERVICE_WALLET")?,
             min_confirmations: std::env::var("ZCASH_MIN_CONFIRMATIONS")
                 .unwrap_or_else(|_| "1".to_string())
@@ -29,33 +36,195 @@ impl ZcashConfig {
     }
 }
 
+/// Per-route request limits (see `middleware::configure_rate_limits`).
+/// The Redis backend shares buckets across instances; the in-memory one
+/// is per process.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub backend: String, // "redis" or "memory"
+    pub trust_proxy: bool, // key anonymous callers by X-Forwarded-For / Forwarded
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let backend = std::env::var("RATE_LIMIT_BACKEND").unwrap_or_else(|_| "redis".to_string());
+        if backend != "redis" && backend != "memory" {
+            return Err(format!("RATE_LIMIT_BACKEND must be redis or memory, got {}", backend).into());
+        }
+
+        Ok(RateLimitConfig {
+            enabled: std::env::var("RATE_LIMIT_ENABLED")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()?,
+            backend,
+            trust_proxy: std::env::var("RATE_LIMIT_TRUST_PROXY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
+        })
+    }
+}
+
+/// Outbound webhook delivery: retries back off exponentially from
+/// `backoff_base_seconds` up to `backoff_max_seconds`, and a delivery is
+/// dead-lettered after `max_attempts`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    pub backoff_base_seconds: u64,
+    pub backoff_max_seconds: u64,
+    pub timeout_seconds: u64,
+    pub poll_interval_seconds: u64,
+    pub batch_size: u32,
+    pub allow_private_urls: bool, // local development only
+}
+
+impl WebhookConfig {
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(WebhookConfig {
+            max_attempts: std::env::var("WEBHOOK_MAX_ATTEMPTS")
+                .unwrap_or_else(|_| "8".to_string())
+                .parse()?,
+            backoff_base_seconds: std::env::var("WEBHOOK_BACKOFF_BASE_SECONDS")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()?,
+            backoff_max_seconds: std::env::var("WEBHOOK_BACKOFF_MAX_SECONDS")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()?,
+            timeout_seconds: std::env::var("WEBHOOK_TIMEOUT_SECONDS")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()?,
+            poll_interval_seconds: std::env::var("WEBHOOK_POLL_INTERVAL_SECONDS")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            batch_size: std::env::var("WEBHOOK_BATCH_SIZE")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()?,
+            allow_private_urls: std::env::var("WEBHOOK_ALLOW_PRIVATE_URLS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
+        })
+    }
+}
+
+/// Prices for plans in a fiat currency, from `PRICE_ORACLE`: `fixed` (the
+/// JSON `PRICE_ORACLE_FILE`, for offline use) or `http` (the feed at
+/// `PRICE_ORACLE_URL`, see `oracle::HttpPriceOracle`).
+#[derive(Clone, Debug, Deserialize)]
+pub struct PriceOracleConfig {
+    pub source: PriceSource,
+    pub max_age_seconds: i64, // older prices are refused
+    pub max_deviation_percent: Decimal, // largest move from the last accepted price; 0 disables
+    pub cache_seconds: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PriceSource {
+    Fixed {
+        path: Option<String>,
+    },
+    Http {
+        url: String,
+        auth_token: Option<SecretString>,
+    },
+}
+
+impl PriceOracleConfig {
+    pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let kind = std::env::var("PRICE_ORACLE").unwrap_or_else(|_| "fixed".to_string());
+        let source = match kind.as_str() {
+            "fixed" => PriceSource::Fixed {
+                path: std::env::var("PRICE_ORACLE_FILE").ok(),
+            },
+            "http" => PriceSource::Http {
+                url: std::env::var("PRICE_ORACLE_URL")?,
+                auth_token: std::env::var("PRICE_ORACLE_TOKEN").ok().map(Into::into),
+            },
+            other => return Err(format!("Unknown PRICE_ORACLE {}", other).into()),
+        };
+
+        Ok(PriceOracleConfig {
+            source,
+            max_age_seconds: std::env::var("PRICE_MAX_AGE_SECONDS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()?,
+            max_deviation_percent: std::env::var("PRICE_MAX_DEVIATION_PERCENT")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()?,
+            cache_seconds: std::env::var("PRICE_CACHE_SECONDS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()?,
+        })
+    }
+}
+
 // Update the main Config struct
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
-    pub database_url: String,
-    pub redis_url: String,
-    pub rpc_url: String,
-    pub contract_address: String,
-    pub private_key: String,
-    pub chain_id: u64,
+    pub database_url: SecretString, // connection URLs may embed credentials
+    pub redis_url: SecretString,
+    pub deployments: Vec<ContractDeployment>,
+    pub default_chain_id: u64,
+    pub contract_abi_path: Option<String>,
+    pub rpc_health_check_interval_seconds: u64,
+    pub signer: SignerConfig,
     pub host: String,
     pub port: u16,
     pub billing_interval_seconds: u64,
+    pub billing_rounding: Rounding,
+    pub billing_concurrency: usize, // sessions billed at once per instance
+    pub billing_page_size: i64,
+    pub billing_shards: u32, // fixed across instances; changing it reshuffles sessions
+    pub billing_max_shards_per_instance: u32, // 0 means no cap
+    pub usage_billing_interval_seconds: u64, // metered usage is charged once per period
+    pub invoice_issuer: String, // name printed at the top of invoices and receipts
+    pub invoice_number_prefix: String,
+    pub export_dir: Option<String>, // monthly accounting exports are written here when set
+    pub leader_lease_ttl_seconds: u64, // a dead scheduler leader is replaced within this
+    pub leader_renew_interval_seconds: u64,
+    pub low_balance_threshold_hours: Decimal, // streaming time left that triggers a low_balance event
+    pub outbox_poll_interval_ms: u64,
+    pub outbox_batch_size: u32,
+    pub outbox_stream_max_len: u64, // approximate cap on the Redis event stream
+    pub outbox_retention_hours: i64, // published rows are purged after this
+    pub tx_pending_timeout_seconds: u64,
+    pub gas_bump_percent: u64,
+    pub max_gas_bumps: u32,
+    pub max_gas_price_gwei: Option<u64>,
+    pub tx_cancel_after_seconds: u64, // stuck charges older than this are cancelled, not re-broadcast
+    pub billing_batch_mode: bool,
+    pub batch_settlement_interval_seconds: u64,
+    pub max_batch_charges: u32,
+    pub indexer_enabled: bool,
+    pub indexer_confirmations: u64,
+    pub indexer_block_range: u64,
+    pub indexer_poll_interval_seconds: u64,
+    pub indexer_max_lag_seconds: i64,
     pub vendor_service_url: String,
-    pub vendor_service_token: String,
+    pub vendor_service_token: SecretString,
+    pub auth: AuthConfig,
+    pub webhooks: WebhookConfig,
+    pub rate_limits: RateLimitConfig,
+    pub price_oracle: PriceOracleConfig,
+    pub fees: FeeSchedules,
     pub zcash: ZcashConfig,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
         Ok(Config {
-            database_url: std::env::var("DATABASE_URL")?,
+            database_url: std::env::var("DATABASE_URL")?.into(),
             redis_url: std::env::var("REDIS_URL")
-                .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string()),
-            rpc_url: std::env::var("RPC_URL")?,
-            contract_address: std::env::var("CONTRACT_ADDRESS")?,
-            private_key: std::env::var("PRIVATE_KEY")?,
-            chain_id: std::env::var("CHAIN_ID")?.parse()?,
+                .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string())
+                .into(),
+            deployments: load_deployments()?,
+            default_chain_id: std::env::var("CHAIN_ID")?.parse()?,
+            contract_abi_path: std::env::var("CONTRACT_ABI_PATH").ok(),
+            rpc_health_check_interval_seconds: std::env::var("RPC_HEALTH_CHECK_INTERVAL_SECONDS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()?,
+            signer: SignerConfig::from_env()?,
             host: std::env::var("HOST")
                 .unwrap_or_else(|_| "0.0.0.0".to_string()),
             port: std::env::var("PORT")
@@ -64,9 +233,512 @@ impl Config {
             billing_interval_seconds: std::env::var("BILLING_INTERVAL_SECONDS")
                 .unwrap_or_else(|_| "60".to_string())
                 .parse()?,
+            billing_rounding: match std::env::var("BILLING_ROUNDING").as_deref() {
+                Ok("bankers") | Err(_) => Rounding::Bankers,
+                Ok("ceil") => Rounding::Ceil,
+                Ok(other) => return Err(format!("BILLING_ROUNDING must be bankers or ceil, got {}", other).into()),
+            },
+            billing_concurrency: std::env::var("BILLING_CONCURRENCY")
+                .unwrap_or_else(|_| "8".to_string())
+                .parse()?,
+            billing_page_size: std::env::var("BILLING_PAGE_SIZE")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse()?,
+            billing_shards: std::env::var("BILLING_SHARDS")
+                .unwrap_or_else(|_| "1".to_string())
+                .parse::<u32>()?
+                .max(1),
+            billing_max_shards_per_instance: std::env::var("BILLING_MAX_SHARDS_PER_INSTANCE")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()?,
+            usage_billing_interval_seconds: std::env::var("USAGE_BILLING_INTERVAL_SECONDS")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()?,
+            invoice_issuer: std::env::var("INVOICE_ISSUER")
+                .unwrap_or_else(|_| "PayGo".to_string()),
+            invoice_number_prefix: std::env::var("INVOICE_NUMBER_PREFIX")
+                .unwrap_or_else(|_| "INV".to_string()),
+            export_dir: std::env::var("EXPORT_DIR").ok(),
+            low_balance_threshold_hours: std::env::var("LOW_BALANCE_THRESHOLD_HOURS")
+                .unwrap_or_else(|_| "0.25".to_string())
+                .parse()?,
+            leader_lease_ttl_seconds: std::env::var("LEADER_LEASE_TTL_SECONDS")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()?,
+            leader_renew_interval_seconds: std::env::var("LEADER_RENEW_INTERVAL_SECONDS")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            outbox_poll_interval_ms: std::env::var("OUTBOX_POLL_INTERVAL_MS")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse()?,
+            outbox_batch_size: std::env::var("OUTBOX_BATCH_SIZE")
+                .unwrap_or_else(|_| "200".to_string())
+                .parse()?,
+            outbox_stream_max_len: std::env::var("OUTBOX_STREAM_MAX_LEN")
+                .unwrap_or_else(|_| "100000".to_string())
+                .parse()?,
+            outbox_retention_hours: std::env::var("OUTBOX_RETENTION_HOURS")
+                .unwrap_or_else(|_| "72".to_string())
+                .parse()?,
+            tx_pending_timeout_seconds: std::env::var("TX_PENDING_TIMEOUT_SECONDS")
+                .unwrap_or_else(|_| "90".to_string())
+                .parse()?,
+            gas_bump_percent: std::env::var("GAS_BUMP_PERCENT")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()?,
+            max_gas_bumps: std::env::var("MAX_GAS_BUMPS")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()?,
+            tx_cancel_after_seconds: std::env::var("TX_CANCEL_AFTER_SECONDS")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()?,
+            max_gas_price_gwei: match std::env::var("MAX_GAS_PRICE_GWEI") {
+                Ok(value) => Some(value.parse()?),
+                Err(_) => None,
+            },
+            billing_batch_mode: std::env::var("BILLING_BATCH_MODE")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
+            batch_settlement_interval_seconds: std::env::var("BATCH_SETTLEMENT_INTERVAL_SECONDS")
+                .unwrap_or_else(|_| "3600".to_string())
+                .parse()?,
+            max_batch_charges: std::env::var("MAX_BATCH_CHARGES")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse()?,
+            indexer_enabled: std::env::var("INDEXER_ENABLED")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
+            indexer_confirmations: std::env::var("INDEXER_CONFIRMATIONS")
+                .unwrap_or_else(|_| "6".to_string())
+                .parse()?,
+            indexer_block_range: std::env::var("INDEXER_BLOCK_RANGE")
+                .unwrap_or_else(|_| "2000".to_string())
+                .parse()?,
+            indexer_poll_interval_seconds: std::env::var("INDEXER_POLL_INTERVAL_SECONDS")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()?,
+            indexer_max_lag_seconds: std::env::var("INDEXER_MAX_LAG_SECONDS")
+                .unwrap_or_else(|_| "120".to_string())
+                .parse()?,
             vendor_service_url: std::env::var("VENDOR_SERVICE_URL")?,
-            vendor_service_token: std::env::var("VENDOR_SERVICE_TOKEN")?,
+            vendor_service_token: std::env::var("VENDOR_SERVICE_TOKEN")?.into(),
+            auth: AuthConfig::from_env()?,
+            webhooks: WebhookConfig::from_env()?,
+            rate_limits: RateLimitConfig::from_env()?,
+            price_oracle: PriceOracleConfig::from_env()?,
+            fees: load_fee_schedules()?,
             zcash: ZcashConfig::from_env()?,
         })
     }
-}
\ No newline at end of file
+}
+
+/// A credential or credential-bearing value. Its Debug output is redacted so
+/// secrets don't reach logs when the Config is printed.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("\"[REDACTED]\"")
+    }
+}
+
+/// Where the service's Ethereum signing key lives, chosen by `SIGNER_KIND`:
+/// `private_key` (raw hex `PRIVATE_KEY`, the default), `keystore` (an
+/// encrypted JSON keystore at `KEYSTORE_PATH` unlocked with
+/// `KEYSTORE_PASSPHRASE` or `KEYSTORE_PASSPHRASE_FILE`) or `remote` (an
+/// external signer at `REMOTE_SIGNER_URL`, see `signer::RemoteSigner`).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SignerConfig {
+    PrivateKey {
+        private_key: SecretString,
+    },
+    Keystore {
+        path: String,
+        passphrase: SecretString,
+    },
+    Remote {
+        url: String,
+        auth_token: Option<SecretString>,
+    },
+}
+
+impl SignerConfig {
+    fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let kind = std::env::var("SIGNER_KIND").unwrap_or_else(|_| "private_key".to_string());
+
+        match kind.as_str() {
+            "private_key" => Ok(SignerConfig::PrivateKey {
+                private_key: std::env::var("PRIVATE_KEY")?.into(),
+            }),
+            "keystore" => {
+                let passphrase = match std::env::var("KEYSTORE_PASSPHRASE_FILE") {
+                    Ok(path) => std::fs::read_to_string(path)?.trim_end().to_string(),
+                    Err(_) => std::env::var("KEYSTORE_PASSPHRASE")?,
+                };
+
+                Ok(SignerConfig::Keystore {
+                    path: std::env::var("KEYSTORE_PATH")?,
+                    passphrase: passphrase.into(),
+                })
+            }
+            "remote" => Ok(SignerConfig::Remote {
+                url: std::env::var("REMOTE_SIGNER_URL")?,
+                auth_token: std::env::var("REMOTE_SIGNER_TOKEN").ok().map(Into::into),
+            }),
+            other => Err(format!("Unknown SIGNER_KIND {}", other).into()),
+        }
+    }
+}
+
+/// Caller authentication: HS256 JWTs issued by the Node backend (sharing
+/// `JWT_SECRET`) and service API keys listed in `API_KEYS_FILE`. Wallet
+/// ownership proofs are signed with their own `WALLET_PROOF_SECRET`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuthConfig {
+    pub jwt_secret: SecretString,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    pub api_keys: Vec<ApiKeyConfig>,
+    pub wallet_proof_secret: SecretString,
+    pub wallet_proof_domain: String, // frontend host named in sign-in messages
+    pub wallet_challenge_ttl_seconds: i64,
+    pub wallet_proof_ttl_seconds: i64,
+}
+
+/// A service-to-service API key. Only the SHA-256 of the key is configured.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    pub key_sha256: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub vendor_id: Option<String>, // key acts for this vendor's sessions
+}
+
+impl AuthConfig {
+    fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let api_keys = match std::env::var("API_KEYS_FILE") {
+            Ok(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            Err(_) => Vec::new(),
+        };
+
+        Ok(AuthConfig {
+            jwt_secret: std::env::var("JWT_SECRET")?.into(),
+            jwt_issuer: std::env::var("JWT_ISSUER").ok(),
+            jwt_audience: std::env::var("JWT_AUDIENCE").ok(),
+            api_keys,
+            wallet_proof_secret: std::env::var("WALLET_PROOF_SECRET")?.into(),
+            wallet_proof_domain: std::env::var("WALLET_PROOF_DOMAIN")
+                .unwrap_or_else(|_| "localhost".to_string()),
+            wallet_challenge_ttl_seconds: std::env::var("WALLET_CHALLENGE_TTL_SECONDS")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()?,
+            wallet_proof_ttl_seconds: std::env::var("WALLET_PROOF_TTL_SECONDS")
+                .unwrap_or_else(|_| "900".to_string())
+                .parse()?,
+        })
+    }
+}
+
+/// A billing contract deployment on one chain. The default deployment comes
+/// from `CHAIN_ID`/`RPC_URLS`/`CONTRACT_ADDRESS`; further chains (an L2
+/// alongside mainnet, say) are listed in the JSON `CONTRACT_DEPLOYMENTS_FILE`.
+#[derive(Clone, Deserialize)]
+pub struct ContractDeployment {
+    pub chain_id: u64,
+    pub rpc_urls: Vec<String>,
+    pub contract_address: String,
+    #[serde(default = "default_contract_decimals")]
+    pub contract_decimals: u8,
+    #[serde(default)]
+    pub abi_path: Option<String>, // falls back to CONTRACT_ABI_PATH, then the built-in ABI
+    #[serde(default)]
+    pub billing_tokens: HashMap<String, String>,
+    #[serde(default)]
+    pub start_block: u64, // first block the event indexer scans
+}
+
+// Hosted RPC URLs usually carry an API key in the path or query, so only
+// the scheme and host are printed
+impl std::fmt::Debug for ContractDeployment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let endpoints: Vec<String> = self.rpc_urls.iter().map(|url| redact_url(url)).collect();
+
+        f.debug_struct("ContractDeployment")
+            .field("chain_id", &self.chain_id)
+            .field("rpc_urls", &endpoints)
+            .field("contract_address", &self.contract_address)
+            .field("contract_decimals", &self.contract_decimals)
+            .field("abi_path", &self.abi_path)
+            .field("billing_tokens", &self.billing_tokens)
+            .field("start_block", &self.start_block)
+            .finish()
+    }
+}
+
+fn redact_url(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let host = rest.split(['/', '?']).next().unwrap_or_default();
+            let host = host.rsplit('@').next().unwrap_or_default();
+            format!("{}://{}/[REDACTED]", scheme, host)
+        }
+        // IPC socket paths carry no credentials
+        None => url.to_string(),
+    }
+}
+
+impl Config {
+    pub fn deployment(&self, chain_id: u64) -> Option<&ContractDeployment> {
+        self.deployments.iter().find(|d| d.chain_id == chain_id)
+    }
+
+    /// ERC-20 contract a vendor's currency settles in on `chain_id`, or
+    /// `None` for the billing contract's native accounting
+    pub fn token_for_currency(&self, chain_id: u64, currency: &str) -> Option<String> {
+        self.deployment(chain_id)?
+            .billing_tokens
+            .get(&currency.to_uppercase())
+            .cloned()
+    }
+
+    /// Symbol of the asset a charge settles in on `chain_id`: the currency
+    /// mapped to `token_address`, or ETH for the billing contract
+    pub fn settlement_asset(&self, chain_id: u64, token_address: Option<&str>) -> String {
+        let Some(token_address) = token_address else {
+            return "ETH".to_string();
+        };
+
+        self.deployment(chain_id)
+            .and_then(|deployment| {
+                deployment
+                    .billing_tokens
+                    .iter()
+                    .find(|(_, address)| address.eq_ignore_ascii_case(token_address))
+            })
+            .map(|(currency, _)| currency.clone())
+            .unwrap_or_else(|| token_address.to_string())
+    }
+
+    /// Asset a recorded transaction settled in; those without a chain were
+    /// deducted from Zcash spending permissions
+    pub fn transaction_asset(&self, chain_id: Option<i64>, token_address: Option<&str>) -> String {
+        match chain_id {
+            Some(chain_id) => self.settlement_asset(chain_id as u64, token_address),
+            None => ZCASH_ASSET.to_string(),
+        }
+    }
+
+    pub fn gas_escalation_policy(&self) -> GasEscalationPolicy {
+        GasEscalationPolicy {
+            pending_timeout: std::time::Duration::from_secs(self.tx_pending_timeout_seconds),
+            bump_percent: self.gas_bump_percent,
+            max_bumps: self.max_gas_bumps,
+            max_gas_price: self.max_gas_price_gwei
+                .map(|gwei| U256::from(gwei) * U256::exp10(9)),
+        }
+    }
+}
+
+fn default_contract_decimals() -> u8 {
+    18
+}
+
+/// The default deployment from the environment, followed by any listed in
+/// `CONTRACT_DEPLOYMENTS_FILE`
+fn load_deployments() -> Result<Vec<ContractDeployment>, Box<dyn std::error::Error>> {
+    let mut deployments = vec![ContractDeployment {
+        chain_id: std::env::var("CHAIN_ID")?.parse()?,
+        rpc_urls: parse_rpc_urls()?,
+        contract_address: std::env::var("CONTRACT_ADDRESS")?,
+        contract_decimals: std::env::var("CONTRACT_DECIMALS")
+            .unwrap_or_else(|_| "18".to_string())
+            .parse()?,
+        abi_path: None,
+        billing_tokens: parse_billing_tokens(
+            &std::env::var("BILLING_TOKENS").unwrap_or_default(),
+        )?,
+        start_block: std::env::var("INDEXER_START_BLOCK")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()?,
+    }];
+
+    if let Ok(path) = std::env::var("CONTRACT_DEPLOYMENTS_FILE") {
+        add_deployments(&mut deployments, &std::fs::read_to_string(&path)?, &path)?;
+    }
+
+    Ok(deployments)
+}
+
+/// Validate the JSON deployment list read from `source` and append it,
+/// rejecting a second deployment on a chain that already has one
+fn add_deployments(
+    deployments: &mut Vec<ContractDeployment>,
+    raw: &str,
+    source: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let extra: Vec<ContractDeployment> = serde_json::from_str(raw)?;
+
+    for deployment in extra {
+        if deployments.iter().any(|d| d.chain_id == deployment.chain_id) {
+            return Err(format!("Duplicate deployment for chain {} in {}", deployment.chain_id, source).into());
+        }
+        deployment.contract_address.parse::<Address>()?;
+        for address in deployment.billing_tokens.values() {
+            address.parse::<Address>()?;
+        }
+        deployments.push(ContractDeployment {
+            billing_tokens: deployment.billing_tokens
+                .into_iter()
+                .map(|(symbol, address)| (symbol.to_uppercase(), address))
+                .collect(),
+            ..deployment
+        });
+    }
+
+    Ok(())
+}
+
+/// Platform fees and revenue splits from `FEE_SCHEDULES_FILE`, or a single
+/// default schedule from `PLATFORM_FEE_PERCENT` and `PLATFORM_FEE_FIXED`
+fn load_fee_schedules() -> Result<FeeSchedules, Box<dyn std::error::Error>> {
+    let schedules = match std::env::var("FEE_SCHEDULES_FILE") {
+        Ok(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        Err(_) => FeeSchedules {
+            default: FeeSchedule {
+                percent: std::env::var("PLATFORM_FEE_PERCENT")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()?,
+                fixed: std::env::var("PLATFORM_FEE_FIXED")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()?,
+                splits: Vec::new(),
+            },
+            vendors: HashMap::new(),
+        },
+    };
+    schedules.validate()?;
+
+    Ok(schedules)
+}
+
+/// Ordered RPC endpoints from `RPC_URLS` (comma separated, highest priority
+/// first), falling back to the single `RPC_URL`
+fn parse_rpc_urls() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let raw = match std::env::var("RPC_URLS") {
+        Ok(urls) => urls,
+        Err(_) => std::env::var("RPC_URL")?,
+    };
+
+    Ok(raw
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Parse `BILLING_TOKENS` entries of the form `USDC=0x...,USDT=0x...`
+fn parse_billing_tokens(raw: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut tokens = HashMap::new();
+
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (symbol, address) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid BILLING_TOKENS entry: {}", entry))?;
+
+        address.trim().parse::<Address>()?;
+        tokens.insert(symbol.trim().to_uppercase(), address.trim().to_string());
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTRACT: &str = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+    const USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+
+    fn deployment(chain_id: u64) -> ContractDeployment {
+        ContractDeployment {
+            chain_id,
+            rpc_urls: vec!["http://localhost:8545".to_string()],
+            contract_address: CONTRACT.to_string(),
+            contract_decimals: 18,
+            abi_path: None,
+            billing_tokens: HashMap::new(),
+            start_block: 0,
+        }
+    }
+
+    #[test]
+    fn deployment_files_fill_in_defaults_and_uppercase_token_symbols() {
+        let mut deployments = vec![deployment(1)];
+        let raw = format!(
+            r#"[{{"chain_id": 8453, "rpc_urls": ["https://base.example"], "contract_address": "{}", "billing_tokens": {{"usdc": "{}"}}}}]"#,
+            CONTRACT, USDC
+        );
+
+        add_deployments(&mut deployments, &raw, "deployments.json").unwrap();
+
+        assert_eq!(deployments.len(), 2);
+        let base = &deployments[1];
+        assert_eq!(base.chain_id, 8453);
+        assert_eq!(base.contract_decimals, 18);
+        assert_eq!(base.abi_path, None);
+        assert_eq!(base.start_block, 0);
+        assert_eq!(base.billing_tokens.get("USDC").map(String::as_str), Some(USDC));
+    }
+
+    #[test]
+    fn deployment_files_cannot_redeploy_a_configured_chain() {
+        let mut deployments = vec![deployment(1)];
+        let raw = format!(r#"[{{"chain_id": 1, "rpc_urls": [], "contract_address": "{}"}}]"#, CONTRACT);
+
+        let err = add_deployments(&mut deployments, &raw, "deployments.json").unwrap_err();
+
+        assert!(err.to_string().contains("Duplicate deployment for chain 1"));
+        assert_eq!(deployments.len(), 1);
+    }
+
+    #[test]
+    fn deployment_files_reject_malformed_addresses() {
+        let bad_contract = r#"[{"chain_id": 10, "rpc_urls": [], "contract_address": "0x1234"}]"#;
+        assert!(add_deployments(&mut vec![deployment(1)], bad_contract, "deployments.json").is_err());
+
+        let bad_token = format!(
+            r#"[{{"chain_id": 10, "rpc_urls": [], "contract_address": "{}", "billing_tokens": {{"USDC": "not-an-address"}}}}]"#,
+            CONTRACT
+        );
+        assert!(add_deployments(&mut vec![deployment(1)], &bad_token, "deployments.json").is_err());
+    }
+
+    #[test]
+    fn billing_tokens_are_parsed_from_symbol_address_pairs() {
+        let tokens = parse_billing_tokens(&format!(" usdc = {} ,, ", USDC)).unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens.get("USDC").map(String::as_str), Some(USDC));
+
+        assert!(parse_billing_tokens("").unwrap().is_empty());
+        assert!(parse_billing_tokens(USDC).is_err());
+        assert!(parse_billing_tokens("USDC=0x1234").is_err());
+    }
+}
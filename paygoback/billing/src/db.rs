@@ -1,8 +1,12 @@
 use chrono::Utc;
 // src/db.rs
-use sqlx::{PgPool, postgres::PgPoolOptions};
+use sqlx::{PgExecutor, PgPool, postgres::PgPoolOptions};
+use uuid::Uuid;
+use rust_decimal::Decimal;
 use crate::models::*;
 use crate::error::BillingError;
+use crate::fees::Allocation;
+use crate::nonce::SubmittedTransaction;
 
 pub async fn create_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
     PgPoolOptions::new()
@@ -19,11 +23,11 @@ pub async fn create_session(
         r#"
         INSERT INTO streaming_sessions 
         (id, session_code, user_wallet_address, vendor_wallet_address, vendor_id, 
-         start_time, last_billed_time, rate_per_hour, total_amount_billed, 
+         start_time, last_billed_time, rate_per_hour, pricing_plan, billed_micros, token_address, chain_id, total_amount_billed, 
          status AS "status: SessionStatus", created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
         RETURNING id, session_code, user_wallet_address, vendor_wallet_address, vendor_id,
-                  start_time, last_billed_time, end_time, rate_per_hour, total_amount_billed,
+                  start_time, last_billed_time, end_time, rate_per_hour, pricing_plan, billed_micros, token_address, chain_id, total_amount_billed,
                   status AS "status: SessionStatus", created_at, updated_at
         "#
     )
@@ -35,6 +39,10 @@ pub async fn create_session(
     .bind(session.start_time)
     .bind(session.last_billed_time)
     .bind(session.rate_per_hour)
+    .bind(&session.pricing_plan)
+    .bind(session.billed_micros)
+    .bind(session.token_address.clone())
+    .bind(session.chain_id)
     .bind(session.total_amount_billed)
     .bind(session.status.clone() as SessionStatus)
     .bind(session.created_at,)
@@ -52,7 +60,7 @@ pub async fn get_session_by_code(
     let session = sqlx::query_as::<_, StreamingSession>(
         r#"
         SELECT id, session_code, user_wallet_address, vendor_wallet_address, vendor_id,
-               start_time, last_billed_time, end_time, rate_per_hour, total_amount_billed,
+               start_time, last_billed_time, end_time, rate_per_hour, pricing_plan, billed_micros, token_address, chain_id, total_amount_billed,
                status AS "status: SessionStatus", created_at, updated_at
         FROM streaming_sessions
         WHERE session_code = $1
@@ -69,24 +77,112 @@ pub async fn get_session_by_code(
     }
 }
 
-pub async fn get_active_sessions(pool: &PgPool) -> Result<Vec<StreamingSession>, BillingError> {
+pub async fn get_session<'e>(
+    executor: impl PgExecutor<'e>,
+    session_id: Uuid,
+) -> Result<StreamingSession, BillingError> {
+    let session = sqlx::query_as::<_, StreamingSession>(
+        r#"
+        SELECT id, session_code, user_wallet_address, vendor_wallet_address, vendor_id,
+               start_time, last_billed_time, end_time, rate_per_hour, pricing_plan, billed_micros, token_address, chain_id, total_amount_billed,
+               status AS "status: SessionStatus", created_at, updated_at
+        FROM streaming_sessions
+        WHERE id = $1
+        "#
+    )
+    .bind(session_id)
+    .fetch_optional(executor)
+    .await
+    .map_err(BillingError::Database)?;
+
+    session.ok_or(BillingError::SessionNotFound)
+}
+
+/// Load a session and lock its row until the caller's transaction ends, so
+/// concurrent billing paths can't charge the same interval twice
+pub async fn lock_session<'e>(
+    executor: impl PgExecutor<'e>,
+    session_id: Uuid,
+) -> Result<StreamingSession, BillingError> {
+    let session = sqlx::query_as::<_, StreamingSession>(
+        r#"
+        SELECT id, session_code, user_wallet_address, vendor_wallet_address, vendor_id,
+               start_time, last_billed_time, end_time, rate_per_hour, pricing_plan, billed_micros, token_address, chain_id, total_amount_billed,
+               status AS "status: SessionStatus", created_at, updated_at
+        FROM streaming_sessions
+        WHERE id = $1
+        FOR UPDATE
+        "#
+    )
+    .bind(session_id)
+    .fetch_optional(executor)
+    .await
+    .map_err(BillingError::Database)?;
+
+    session.ok_or(BillingError::SessionNotFound)
+}
+
+/// The Zcash spending permission a session bills against, if it was
+/// created with one
+pub async fn get_session_permission_id<'e>(
+    executor: impl PgExecutor<'e>,
+    session_id: Uuid,
+) -> Result<Option<Uuid>, BillingError> {
+    let permission_id: Option<(Uuid,)> = sqlx::query_as(
+        r#"
+        SELECT permission_id
+        FROM session_permissions
+        WHERE session_id = $1
+        "#
+    )
+    .bind(session_id)
+    .fetch_optional(executor)
+    .await
+    .map_err(BillingError::Database)?;
+
+    Ok(permission_id.map(|(id,)| id))
+}
+
+/// One keyset page of active sessions due for billing in the given shards,
+/// ordered by id. A session's shard is a hash of its id modulo
+/// `shard_count`, so every instance agrees on which shard owns it.
+pub async fn get_billable_sessions_page(
+    pool: &PgPool,
+    billed_before: chrono::DateTime<Utc>,
+    shards: &[u32],
+    shard_count: u32,
+    after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<StreamingSession>, BillingError> {
+    let shards: Vec<i64> = shards.iter().map(|&shard| shard as i64).collect();
+
     let sessions = sqlx::query_as::<_, StreamingSession>(
         r#"
         SELECT id, session_code, user_wallet_address, vendor_wallet_address, vendor_id,
-               start_time, last_billed_time, end_time, rate_per_hour, total_amount_billed,
+               start_time, last_billed_time, end_time, rate_per_hour, pricing_plan, billed_micros, token_address, chain_id, total_amount_billed,
                status AS "status: SessionStatus", created_at, updated_at
         FROM streaming_sessions
         WHERE status = 'active'
+          AND last_billed_time <= $1
+          AND mod(hashtext(id::text)::bigint + 2147483648, $2) = ANY($3)
+          AND ($4::uuid IS NULL OR id > $4)
+        ORDER BY id
+        LIMIT $5
         "#
     )
+    .bind(billed_before)
+    .bind(shard_count as i64)
+    .bind(&shards)
+    .bind(after)
+    .bind(limit)
     .fetch_all(pool)
     .await?;
 
     Ok(sessions)
 }
 
-pub async fn update_session(
-    pool: &PgPool,
+pub async fn update_session<'e>(
+    executor: impl PgExecutor<'e>,
     session: &StreamingSession,
 ) -> Result<(), BillingError> {
     sqlx::query::<_>(
@@ -96,8 +192,9 @@ pub async fn update_session(
             end_time = $2,
             total_amount_billed = $3,
             status = $4,
-            updated_at = $5
-        WHERE id = $6
+            updated_at = $5,
+            billed_micros = $6
+        WHERE id = $7
         "#
     )
     .bind(session.last_billed_time)
@@ -105,25 +202,32 @@ pub async fn update_session(
     .bind(session.total_amount_billed)
     .bind(session.status.clone() as SessionStatus)
     .bind(Utc::now())
+    .bind(session.billed_micros)
     .bind(session.id)
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(())
 }
 
-pub async fn create_transaction(
-    pool: &PgPool,
+pub async fn create_transaction<'e>(
+    executor: impl PgExecutor<'e>,
     transaction: &BillingTransaction,
 ) -> Result<BillingTransaction, BillingError> {
     let record = sqlx::query_as::<_, BillingTransaction>(
         r#"
         INSERT INTO billing_transactions
-        (id, session_id, user_wallet_address, vendor_wallet_address, amount, 
-         duration_minutes, tx_hash, status AS "status: TransactionStatus", created_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-        RETURNING id, session_id, user_wallet_address, vendor_wallet_address, amount,
-                  duration_minutes, tx_hash, status AS "status: TransactionStatus", created_at
+        (id, session_id, user_wallet_address, vendor_wallet_address, amount, token_address, chain_id,
+         duration_minutes, duration_micros, tx_hash, status AS "status: TransactionStatus", nonce,
+         gas_price_wei, submission_attempts, replaced_tx_hashes, batch_id, created_at,
+         fee_amount, net_amount, fiat_currency, fiat_amount, exchange_rate, exchange_rate_at,
+         permit_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24)
+        RETURNING id, session_id, user_wallet_address, vendor_wallet_address, amount, token_address, chain_id,
+                  duration_minutes, duration_micros, tx_hash, status AS "status: TransactionStatus", nonce,
+                  gas_price_wei, submission_attempts, replaced_tx_hashes, batch_id, created_at,
+                  fee_amount, net_amount, fiat_currency, fiat_amount, exchange_rate, exchange_rate_at,
+                  permit_id
         "#
     )
     .bind(transaction.id)
@@ -131,12 +235,772 @@ pub async fn create_transaction(
     .bind(transaction.user_wallet_address.clone())
     .bind(transaction.vendor_wallet_address.clone())
     .bind(transaction.amount)
+    .bind(transaction.token_address.clone())
+    .bind(transaction.chain_id)
     .bind(transaction.duration_minutes)
+    .bind(transaction.duration_micros)
     .bind(transaction.tx_hash.clone())
     .bind(transaction.status.clone() as TransactionStatus)
+    .bind(transaction.nonce)
+    .bind(transaction.gas_price_wei)
+    .bind(transaction.submission_attempts)
+    .bind(transaction.replaced_tx_hashes.clone())
+    .bind(transaction.batch_id)
     .bind(transaction.created_at)
-    .fetch_one(pool)
+    .bind(transaction.fee_amount)
+    .bind(transaction.net_amount)
+    .bind(transaction.fiat_currency.clone())
+    .bind(transaction.fiat_amount)
+    .bind(transaction.exchange_rate)
+    .bind(transaction.exchange_rate_at)
+    .bind(transaction.permit_id)
+    .fetch_one(executor)
     .await?;
 
     Ok(record)
-}
\ No newline at end of file
+}
+
+/// Record how a billing transaction or usage charge divides between the
+/// platform, the vendor and its payees
+pub async fn create_revenue_splits<'e>(
+    executor: impl PgExecutor<'e>,
+    transaction_id: Option<Uuid>,
+    usage_charge_id: Option<Uuid>,
+    allocations: &[Allocation],
+) -> Result<(), BillingError> {
+    if allocations.is_empty() {
+        return Ok(());
+    }
+
+    let roles: Vec<&str> = allocations.iter().map(|allocation| allocation.role.as_str()).collect();
+    let recipients: Vec<&str> = allocations.iter().map(|allocation| allocation.recipient.as_str()).collect();
+    let wallets: Vec<Option<&str>> = allocations.iter().map(|allocation| allocation.wallet_address.as_deref()).collect();
+    let amounts: Vec<Decimal> = allocations.iter().map(|allocation| allocation.amount).collect();
+
+    sqlx::query(
+        r#"
+        INSERT INTO revenue_splits (transaction_id, usage_charge_id, role, recipient, wallet_address, amount)
+        SELECT $1, $2, role, recipient, wallet_address, amount
+        FROM UNNEST($3::VARCHAR[], $4::VARCHAR[], $5::VARCHAR[], $6::DECIMAL[])
+            AS split(role, recipient, wallet_address, amount)
+        "#
+    )
+    .bind(transaction_id)
+    .bind(usage_charge_id)
+    .bind(&roles)
+    .bind(&recipients)
+    .bind(&wallets)
+    .bind(&amounts)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_transaction<'e>(
+    executor: impl PgExecutor<'e>,
+    transaction_id: Uuid,
+) -> Result<BillingTransaction, BillingError> {
+    let transaction = sqlx::query_as::<_, BillingTransaction>(
+        r#"
+        SELECT id, session_id, user_wallet_address, vendor_wallet_address, amount, token_address, chain_id,
+               duration_minutes, duration_micros, tx_hash, status AS "status: TransactionStatus", nonce,
+               gas_price_wei, submission_attempts, replaced_tx_hashes, batch_id, created_at,
+               fee_amount, net_amount, fiat_currency, fiat_amount, exchange_rate, exchange_rate_at,
+               permit_id
+        FROM billing_transactions
+        WHERE id = $1
+        "#
+    )
+    .bind(transaction_id)
+    .fetch_optional(executor)
+    .await?;
+
+    transaction.ok_or_else(|| BillingError::NotFound("Transaction not found".to_string()))
+}
+
+/// Record the nonce, final gas price and replacement history of an on-chain
+/// submission against its billing transaction row.
+pub async fn record_transaction_submission<'e>(
+    executor: impl PgExecutor<'e>,
+    transaction_id: Uuid,
+    submission: &SubmittedTransaction,
+    status: TransactionStatus,
+) -> Result<(), BillingError> {
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET tx_hash = $1,
+            nonce = $2,
+            gas_price_wei = $3,
+            submission_attempts = submission_attempts + $4,
+            replaced_tx_hashes = replaced_tx_hashes || $5,
+            status = $6,
+            updated_at = $7
+        WHERE id = $8
+        "#
+    )
+    .bind(submission.tx_hash.clone())
+    .bind(submission.nonce.low_u64() as i64)
+    .bind(Decimal::from(submission.gas_price.low_u128()))
+    .bind(submission.attempts as i32)
+    .bind(submission.replaced_tx_hashes.clone())
+    .bind(status as TransactionStatus)
+    .bind(Utc::now())
+    .bind(transaction_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Pending on-chain transactions that have not been touched since `before`,
+/// i.e. ones that exhausted their gas bumps and are still in the mempool.
+pub async fn get_stuck_transactions(
+    pool: &PgPool,
+    before: chrono::DateTime<Utc>,
+) -> Result<Vec<BillingTransaction>, BillingError> {
+    let transactions = sqlx::query_as::<_, BillingTransaction>(
+        r#"
+        SELECT id, session_id, user_wallet_address, vendor_wallet_address, amount, token_address, chain_id,
+               duration_minutes, duration_micros, tx_hash, status AS "status: TransactionStatus", nonce,
+               gas_price_wei, submission_attempts, replaced_tx_hashes, batch_id, created_at,
+               fee_amount, net_amount, fiat_currency, fiat_amount, exchange_rate, exchange_rate_at,
+               permit_id
+        FROM billing_transactions
+        WHERE status = 'pending'
+        AND nonce IS NOT NULL
+        AND updated_at < $1
+        ORDER BY nonce
+        "#
+    )
+    .bind(before)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(transactions)
+}
+
+pub async fn update_transaction_status<'e>(
+    executor: impl PgExecutor<'e>,
+    transaction_id: Uuid,
+    status: TransactionStatus,
+) -> Result<(), BillingError> {
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET status = $1,
+            updated_at = $2
+        WHERE id = $3
+        "#
+    )
+    .bind(status as TransactionStatus)
+    .bind(Utc::now())
+    .bind(transaction_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Sum of a user's charges on `chain_id` in `token_address` (None for the
+/// billing contract) that the chain balance doesn't reflect yet: accrued for
+/// the next batch, or broadcast and still awaiting confirmation
+pub async fn get_unsettled_amount(
+    pool: &PgPool,
+    user_wallet_address: &str,
+    chain_id: i64,
+    token_address: Option<&str>,
+) -> Result<Decimal, BillingError> {
+    let (amount,): (Option<Decimal>,) = sqlx::query_as(
+        r#"
+        SELECT SUM(amount)
+        FROM billing_transactions
+        WHERE user_wallet_address = $1
+        AND chain_id = $2
+        AND status IN ('accrued', 'pending')
+        AND token_address IS NOT DISTINCT FROM $3
+        "#
+    )
+    .bind(user_wallet_address)
+    .bind(chain_id)
+    .bind(token_address)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(amount.unwrap_or(Decimal::ZERO))
+}
+
+/// Open a settlement batch and atomically assign up to `limit` unclaimed
+/// accrued charges on the batch's chain to it, so concurrent settlers never pick up the same
+/// interval twice. Returns no charges (and leaves no batch row) when the
+/// accrued pool is empty.
+pub async fn claim_accrued_transactions(
+    pool: &PgPool,
+    batch: &SettlementBatch,
+    limit: i64,
+) -> Result<Vec<BillingTransaction>, BillingError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO settlement_batches
+        (id, chain_id, total_amount, charge_count, pair_count, status, created_at)
+        VALUES ($1, $2, 0, 0, 0, $3, $4)
+        "#
+    )
+    .bind(batch.id)
+    .bind(batch.chain_id)
+    .bind(batch.status.clone() as TransactionStatus)
+    .bind(batch.created_at)
+    .execute(&mut *tx)
+    .await?;
+
+    let transactions = sqlx::query_as::<_, BillingTransaction>(
+        r#"
+        UPDATE billing_transactions
+        SET batch_id = $1,
+            updated_at = NOW()
+        WHERE id IN (
+            SELECT id
+            FROM billing_transactions
+            WHERE status = 'accrued'
+            AND batch_id IS NULL
+            AND chain_id = $3
+            ORDER BY created_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, session_id, user_wallet_address, vendor_wallet_address, amount, token_address, chain_id,
+                  duration_minutes, duration_micros, tx_hash, status AS "status: TransactionStatus", nonce,
+                  gas_price_wei, submission_attempts, replaced_tx_hashes, batch_id, created_at,
+                  fee_amount, net_amount, fiat_currency, fiat_amount, exchange_rate, exchange_rate_at,
+                  permit_id
+        "#
+    )
+    .bind(batch.id)
+    .bind(limit)
+    .bind(batch.chain_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if transactions.is_empty() {
+        tx.rollback().await?;
+        return Ok(transactions);
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE settlement_batches
+        SET (total_amount, charge_count, pair_count) = (
+            SELECT SUM(amount),
+                   COUNT(*),
+                   COUNT(DISTINCT (user_wallet_address, vendor_wallet_address))
+            FROM billing_transactions
+            WHERE batch_id = $1
+        )
+        WHERE id = $1
+        "#
+    )
+    .bind(batch.id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(transactions)
+}
+
+/// Return a failed batch's charges to the accrued pool for the next period
+pub async fn release_batch_transactions(
+    pool: &PgPool,
+    batch_id: Uuid,
+) -> Result<(), BillingError> {
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET batch_id = NULL,
+            updated_at = NOW()
+        WHERE batch_id = $1
+        AND status = 'accrued'
+        "#
+    )
+    .bind(batch_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Return what charges that will never land reserved from their EIP-712
+/// permits, reopening permits they had exhausted
+pub async fn release_permit_reservations<'e>(
+    executor: impl PgExecutor<'e>,
+    transaction_ids: &[Uuid],
+) -> Result<(), BillingError> {
+    sqlx::query(
+        r#"
+        UPDATE ethereum_permits p
+        SET remaining_amount = p.remaining_amount + released.amount,
+            status = CASE WHEN p.status = 'exhausted' THEN 'active' ELSE p.status END,
+            updated_at = NOW()
+        FROM (
+            SELECT permit_id, SUM(amount) AS amount
+            FROM billing_transactions
+            WHERE id = ANY($1)
+            AND permit_id IS NOT NULL
+            GROUP BY permit_id
+        ) released
+        WHERE p.id = released.permit_id
+        "#
+    )
+    .bind(transaction_ids)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Fail charges that can't be settled on-chain, e.g. for a malformed wallet
+/// address, taking them out of their batch and its totals
+pub async fn fail_batch_transactions(
+    pool: &PgPool,
+    batch_id: Uuid,
+    transaction_ids: &[Uuid],
+) -> Result<(), BillingError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET status = 'failed',
+            batch_id = NULL,
+            updated_at = NOW()
+        WHERE id = ANY($1)
+        AND batch_id = $2
+        "#
+    )
+    .bind(transaction_ids)
+    .bind(batch_id)
+    .execute(&mut *tx)
+    .await?;
+
+    release_permit_reservations(&mut *tx, transaction_ids).await?;
+
+    sqlx::query(
+        r#"
+        UPDATE settlement_batches
+        SET (total_amount, charge_count, pair_count) = (
+            SELECT COALESCE(SUM(amount), 0),
+                   COUNT(*),
+                   COUNT(DISTINCT (user_wallet_address, vendor_wallet_address))
+            FROM billing_transactions
+            WHERE batch_id = $1
+        )
+        WHERE id = $1
+        "#
+    )
+    .bind(batch_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Batches on `chain_id` broadcast before `before` and still unconfirmed
+pub async fn get_stuck_batches(
+    pool: &PgPool,
+    chain_id: i64,
+    before: chrono::DateTime<Utc>,
+) -> Result<Vec<PendingBatch>, BillingError> {
+    let batches = sqlx::query_as::<_, PendingBatch>(
+        r#"
+        SELECT id, tx_hash, nonce, gas_price_wei, created_at
+        FROM settlement_batches
+        WHERE status = 'pending'
+        AND chain_id = $1
+        AND nonce IS NOT NULL
+        AND settled_at < $2
+        ORDER BY nonce
+        "#
+    )
+    .bind(chain_id)
+    .bind(before)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(batches)
+}
+
+/// The interval charges a batch settles
+pub async fn get_batch_transactions(
+    pool: &PgPool,
+    batch_id: Uuid,
+) -> Result<Vec<BillingTransaction>, BillingError> {
+    let transactions = sqlx::query_as::<_, BillingTransaction>(
+        r#"
+        SELECT id, session_id, user_wallet_address, vendor_wallet_address, amount, token_address, chain_id,
+               duration_minutes, duration_micros, tx_hash, status AS "status: TransactionStatus", nonce,
+               gas_price_wei, submission_attempts, replaced_tx_hashes, batch_id, created_at,
+               fee_amount, net_amount, fiat_currency, fiat_amount, exchange_rate, exchange_rate_at,
+               permit_id
+        FROM billing_transactions
+        WHERE batch_id = $1
+        "#
+    )
+    .bind(batch_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(transactions)
+}
+
+/// Record the gas price of a cancellation still waiting to be mined, so
+/// the next attempt outbids it
+pub async fn record_batch_gas_price(
+    pool: &PgPool,
+    batch_id: Uuid,
+    submission: &SubmittedTransaction,
+) -> Result<(), BillingError> {
+    sqlx::query(
+        r#"
+        UPDATE settlement_batches
+        SET gas_price_wei = $1,
+            settled_at = NOW()
+        WHERE id = $2
+        "#
+    )
+    .bind(Decimal::from(submission.gas_price.low_u128()))
+    .bind(batch_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Close a batch whose nonce went to a cancellation and return its charges
+/// to the accrued pool for the next period
+pub async fn cancel_batch(pool: &PgPool, batch_id: Uuid) -> Result<(), BillingError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE settlement_batches SET status = 'cancelled' WHERE id = $1")
+        .bind(batch_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET status = 'accrued',
+            tx_hash = NULL,
+            batch_id = NULL,
+            updated_at = NOW()
+        WHERE batch_id = $1
+        "#
+    )
+    .bind(batch_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Close a batch whose `billUserBatch` reverted under `submission` and
+/// return its charges to the accrued pool for the next period
+pub async fn revert_batch(
+    pool: &PgPool,
+    batch_id: Uuid,
+    submission: &SubmittedTransaction,
+) -> Result<(), BillingError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE settlement_batches
+        SET tx_hash = $1,
+            nonce = $2,
+            gas_price_wei = $3,
+            status = 'failed',
+            settled_at = NOW()
+        WHERE id = $4
+        "#
+    )
+    .bind(submission.tx_hash.clone())
+    .bind(submission.nonce.low_u64() as i64)
+    .bind(Decimal::from(submission.gas_price.low_u128()))
+    .bind(batch_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET status = 'accrued',
+            tx_hash = NULL,
+            batch_id = NULL,
+            updated_at = NOW()
+        WHERE batch_id = $1
+        "#
+    )
+    .bind(batch_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Record a batch's on-chain outcome and propagate the hash and status to
+/// every interval charge it settled.
+pub async fn record_batch_settlement(
+    pool: &PgPool,
+    batch_id: Uuid,
+    submission: &SubmittedTransaction,
+    status: TransactionStatus,
+) -> Result<(), BillingError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE settlement_batches
+        SET tx_hash = $1,
+            nonce = $2,
+            gas_price_wei = $3,
+            status = $4,
+            settled_at = $5
+        WHERE id = $6
+        "#
+    )
+    .bind(submission.tx_hash.clone())
+    .bind(submission.nonce.low_u64() as i64)
+    .bind(Decimal::from(submission.gas_price.low_u128()))
+    .bind(status.clone() as TransactionStatus)
+    .bind(Utc::now())
+    .bind(batch_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET tx_hash = $1,
+            status = $2,
+            updated_at = NOW()
+        WHERE batch_id = $3
+        "#
+    )
+    .bind(submission.tx_hash.clone())
+    .bind(status as TransactionStatus)
+    .bind(batch_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn update_settlement_batch_status(
+    pool: &PgPool,
+    batch_id: Uuid,
+    status: TransactionStatus,
+) -> Result<(), BillingError> {
+    sqlx::query(
+        r#"
+        UPDATE settlement_batches
+        SET status = $1
+        WHERE id = $2
+        "#
+    )
+    .bind(status as TransactionStatus)
+    .bind(batch_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Last block the chain indexer has fully processed for `cursor`
+pub async fn get_indexer_cursor(
+    pool: &PgPool,
+    cursor: &str,
+) -> Result<Option<i64>, BillingError> {
+    let row: Option<(i64,)> = sqlx::query_as(
+        "SELECT last_block FROM indexer_cursors WHERE name = $1"
+    )
+    .bind(cursor)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(block,)| block))
+}
+
+/// Mark the cursor as live without advancing it, so balance reads can tell
+/// a caught-up indexer from a stalled one.
+pub async fn touch_indexer_cursor(pool: &PgPool, cursor: &str) -> Result<(), BillingError> {
+    sqlx::query("UPDATE indexer_cursors SET updated_at = NOW() WHERE name = $1")
+        .bind(cursor)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Store a block range of contract events, apply their balance deltas and
+/// advance the cursor to `to_block`, all in one transaction. Events already
+/// indexed (e.g. a range replayed after a crash) are skipped, so replays
+/// never double-count. Returns the number of newly indexed events.
+pub async fn index_contract_events(
+    pool: &PgPool,
+    cursor: &str,
+    chain_id: i64,
+    contract_address: &str,
+    events: &[ContractEvent],
+    to_block: u64,
+) -> Result<u64, BillingError> {
+    let mut tx = pool.begin().await?;
+    let mut indexed = 0;
+
+    for event in events {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO contract_events
+            (chain_id, contract_address, event_type, user_address, vendor_address, amount,
+             block_number, block_hash, tx_hash, log_index)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (chain_id, tx_hash, log_index) DO NOTHING
+            "#
+        )
+        .bind(chain_id)
+        .bind(contract_address)
+        .bind(event.event_type.as_str())
+        .bind(&event.user_address)
+        .bind(&event.vendor_address)
+        .bind(event.amount)
+        .bind(event.block_number as i64)
+        .bind(&event.block_hash)
+        .bind(&event.tx_hash)
+        .bind(event.log_index as i64)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if inserted == 0 {
+            continue;
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO indexed_balances (chain_id, contract_address, user_address, balance, last_block)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (chain_id, contract_address, user_address) DO UPDATE
+            SET balance = indexed_balances.balance + EXCLUDED.balance,
+                last_block = GREATEST(indexed_balances.last_block, EXCLUDED.last_block),
+                updated_at = NOW()
+            "#
+        )
+        .bind(chain_id)
+        .bind(contract_address)
+        .bind(&event.user_address)
+        .bind(event.balance_delta())
+        .bind(event.block_number as i64)
+        .execute(&mut *tx)
+        .await?;
+
+        indexed += 1;
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO indexer_cursors (name, last_block, updated_at)
+        VALUES ($1, $2, NOW())
+        ON CONFLICT (name) DO UPDATE
+        SET last_block = EXCLUDED.last_block,
+            updated_at = NOW()
+        "#
+    )
+    .bind(cursor)
+    .bind(to_block as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(indexed)
+}
+
+/// Indexed contract balance for a user, or `None` when the cursor has not
+/// advanced within `fresh_since` and the index can't be trusted.
+pub async fn get_indexed_balance(
+    pool: &PgPool,
+    cursor: &str,
+    chain_id: i64,
+    contract_address: &str,
+    user_address: &str,
+    fresh_since: chrono::DateTime<Utc>,
+) -> Result<Option<Decimal>, BillingError> {
+    let row: Option<(Option<Decimal>,)> = sqlx::query_as(
+        r#"
+        SELECT b.balance
+        FROM indexer_cursors c
+        LEFT JOIN indexed_balances b
+            ON b.chain_id = $2
+            AND b.contract_address = $3
+            AND b.user_address = $4
+        WHERE c.name = $1
+        AND c.updated_at > $5
+        "#
+    )
+    .bind(cursor)
+    .bind(chain_id)
+    .bind(contract_address)
+    .bind(user_address)
+    .bind(fresh_since)
+    .fetch_optional(pool)
+    .await?;
+
+    // A caught-up index with no row means the user never deposited
+    Ok(row.map(|(balance,)| balance.unwrap_or(Decimal::ZERO)))
+}
+
+/// Attribute rows written before multi-chain support to the default
+/// deployment. Zcash-settled charges keep a NULL chain.
+pub async fn assign_default_chain(pool: &PgPool, chain_id: i64) -> Result<(), BillingError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE streaming_sessions SET chain_id = $1 WHERE chain_id IS NULL")
+        .bind(chain_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE billing_transactions
+        SET chain_id = $1
+        WHERE chain_id IS NULL
+        AND (tx_hash IS NOT NULL OR status IN ('pending', 'accrued'))
+        "#
+    )
+    .bind(chain_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("UPDATE settlement_batches SET chain_id = $1 WHERE chain_id IS NULL")
+        .bind(chain_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE ethereum_permits SET chain_id = $1 WHERE chain_id IS NULL")
+        .bind(chain_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -1,38 +1,178 @@
 // src/error.rs
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
 use thiserror::Error;
+use tracing::error;
+use utoipa::ToSchema;
 
 #[derive(Error, Debug)]
 pub enum BillingError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
-    
+
     #[error("Blockchain error: {0}")]
     Blockchain(String),
-    
+
     #[error("Insufficient balance")]
     InsufficientBalance,
-    
+
     #[error("Session not found")]
     SessionNotFound,
-    
+
     #[error("Invalid session code")]
     InvalidSessionCode,
-    
+
     #[error("Cache error: {0}")]
     Cache(String),
-    
+
     #[error("Configuration error: {0}")]
     Config(String),
+
+    #[error("Amount overflow: {0}")]
+    AmountOverflow(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Validation failed: {0}")]
+    Validation(String),
+
+    #[error("Spending permission not found")]
+    PermissionNotFound,
+
+    #[error("Spending permit not found")]
+    PermitNotFound,
+
+    #[error("Spending permission has expired")]
+    PermissionExpired,
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Upstream service error: {0}")]
+    Upstream(String),
+
+    #[error("Rate limit exceeded, retry in {0}s")]
+    RateLimited(u64),
+}
+
+impl BillingError {
+    /// Stable machine-readable code; clients branch on this, never on text
+    pub fn code(&self) -> &'static str {
+        match self {
+            BillingError::Database(_) => "database_error",
+            BillingError::Blockchain(_) => "blockchain_error",
+            BillingError::InsufficientBalance => "insufficient_balance",
+            BillingError::SessionNotFound => "session_not_found",
+            BillingError::InvalidSessionCode => "invalid_session_code",
+            BillingError::Cache(_) => "cache_error",
+            BillingError::Config(_) => "configuration_error",
+            BillingError::AmountOverflow(_) => "amount_out_of_range",
+            BillingError::Unauthorized(_) => "unauthorized",
+            BillingError::Forbidden(_) => "forbidden",
+            BillingError::Validation(_) => "validation_failed",
+            BillingError::PermissionNotFound => "permission_not_found",
+            BillingError::PermitNotFound => "permit_not_found",
+            BillingError::PermissionExpired => "permission_expired",
+            BillingError::NotFound(_) => "not_found",
+            BillingError::Conflict(_) => "conflict",
+            BillingError::Upstream(_) => "upstream_unavailable",
+            BillingError::RateLimited(_) => "rate_limited",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            BillingError::Database(_) | BillingError::Cache(_) | BillingError::Config(_) => "Internal server error",
+            BillingError::Blockchain(_) => "Blockchain request failed",
+            BillingError::InsufficientBalance => "Insufficient balance",
+            BillingError::SessionNotFound => "Session not found",
+            BillingError::InvalidSessionCode => "Invalid session code",
+            BillingError::AmountOverflow(_) => "Amount out of range",
+            BillingError::Unauthorized(_) => "Unauthorized",
+            BillingError::Forbidden(_) => "Forbidden",
+            BillingError::Validation(_) => "Validation failed",
+            BillingError::PermissionNotFound => "Spending permission not found",
+            BillingError::PermitNotFound => "Spending permit not found",
+            BillingError::PermissionExpired => "Spending permission has expired",
+            BillingError::NotFound(_) => "Not found",
+            BillingError::Conflict(_) => "Conflict",
+            BillingError::Upstream(_) => "Upstream service unavailable",
+            BillingError::RateLimited(_) => "Too many requests",
+        }
+    }
+
+    /// Explanation safe to show the client. Server-side failures only get
+    /// their title; the full error is logged instead.
+    fn detail(&self) -> Option<String> {
+        match self {
+            BillingError::AmountOverflow(msg)
+            | BillingError::Unauthorized(msg)
+            | BillingError::Forbidden(msg)
+            | BillingError::Validation(msg)
+            | BillingError::NotFound(msg)
+            | BillingError::Conflict(msg) => Some(msg.clone()),
+            BillingError::RateLimited(retry_after) => Some(format!("Retry in {} seconds", retry_after)),
+            _ => None,
+        }
+    }
+}
+
+/// RFC 7807 problem details
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: &'static str,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub code: &'static str,
 }
 
 impl ResponseError for BillingError {
-    fn error_response(&self) -> HttpResponse {
+    fn status_code(&self) -> StatusCode {
         match self {
-            BillingError::SessionNotFound => HttpResponse::NotFound().json(self.to_string()),
-            BillingError::InvalidSessionCode => HttpResponse::BadRequest().json(self.to_string()),
-            BillingError::InsufficientBalance => HttpResponse::PaymentRequired().json(self.to_string()),
-            _ => HttpResponse::InternalServerError().json(self.to_string()),
+            BillingError::InsufficientBalance => StatusCode::PAYMENT_REQUIRED,
+            BillingError::SessionNotFound
+            | BillingError::PermissionNotFound
+            | BillingError::PermitNotFound
+            | BillingError::NotFound(_) => StatusCode::NOT_FOUND,
+            BillingError::InvalidSessionCode
+            | BillingError::AmountOverflow(_)
+            | BillingError::Validation(_) => StatusCode::BAD_REQUEST,
+            BillingError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            BillingError::Forbidden(_) => StatusCode::FORBIDDEN,
+            BillingError::PermissionExpired => StatusCode::GONE,
+            BillingError::Conflict(_) => StatusCode::CONFLICT,
+            BillingError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            BillingError::Blockchain(_) | BillingError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            BillingError::Database(_) | BillingError::Cache(_) | BillingError::Config(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("Request failed: {}", self);
+        }
+
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .json(Problem {
+                problem_type: format!("urn:paygo:problem:{}", self.code()),
+                title: self.title(),
+                status: status.as_u16(),
+                detail: self.detail(),
+                code: self.code(),
+            })
+    }
 }
@@ -0,0 +1,312 @@
+// src/events.rs
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use redis::Client as RedisClient;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::BillingError;
+use crate::models::{SessionStatus, StreamingSession};
+use crate::zcash::zcash_service::SpendingPermission;
+
+/// Redis channel the outbox relay publishes session events to and every
+/// instance's hub subscribes on
+pub const EVENTS_CHANNEL: &str = "paygo:events";
+
+/// Events buffered per instance before a slow subscriber starts missing them
+const HUB_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BillingEventKind {
+    SessionStarted,
+    SessionBilled,
+    LowBalance,
+    SessionPaused,
+    SessionResumed,
+    SessionEnded,
+    SessionFailed,
+}
+
+impl BillingEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BillingEventKind::SessionStarted => "session_started",
+            BillingEventKind::SessionBilled => "session_billed",
+            BillingEventKind::LowBalance => "low_balance",
+            BillingEventKind::SessionPaused => "session_paused",
+            BillingEventKind::SessionResumed => "session_resumed",
+            BillingEventKind::SessionEnded => "session_ended",
+            BillingEventKind::SessionFailed => "session_failed",
+        }
+    }
+}
+
+/// A change to a session's billing state, pushed to clients watching the
+/// session or the spending permission paying for it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BillingEvent {
+    pub id: Uuid,
+    pub kind: BillingEventKind,
+    pub session_id: Uuid,
+    pub vendor_id: String,
+    pub permission_id: Option<Uuid>,
+    pub status: SessionStatus,
+    pub amount: Option<Decimal>, // charge for this interval
+    pub total_amount_billed: Decimal,
+    pub remaining_amount: Option<Decimal>,
+    pub remaining_hours: Option<Decimal>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl BillingEvent {
+    pub fn for_session(kind: BillingEventKind, session: &StreamingSession) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            kind,
+            session_id: session.id,
+            vendor_id: session.vendor_id.clone(),
+            permission_id: None,
+            status: session.status.clone(),
+            amount: None,
+            total_amount_billed: session.total_amount_billed,
+            remaining_amount: None,
+            remaining_hours: None,
+            occurred_at: Utc::now(),
+        }
+    }
+
+    pub fn with_charge(mut self, amount: Decimal) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn with_permission(mut self, permission_id: Uuid) -> Self {
+        self.permission_id = Some(permission_id);
+        self
+    }
+
+    /// Attach the permission's balance after this event's charge
+    pub fn with_balance(mut self, permission: &SpendingPermission) -> Self {
+        self.permission_id = Some(permission.id);
+        self.remaining_amount = Some(permission.remaining_amount);
+        self.remaining_hours = remaining_hours(permission.remaining_amount, permission.rate_per_hour);
+        self
+    }
+
+    /// Server-Sent Events frame
+    pub fn to_sse(&self) -> Result<Bytes, BillingError> {
+        let data = serde_json::to_string(self)
+            .map_err(|e| BillingError::Config(format!("Failed to encode event: {}", e)))?;
+
+        Ok(Bytes::from(format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            self.id,
+            self.kind.as_str(),
+            data
+        )))
+    }
+}
+
+pub fn remaining_hours(remaining_amount: Decimal, rate_per_hour: Decimal) -> Option<Decimal> {
+    if rate_per_hour > Decimal::ZERO {
+        Some(remaining_amount / rate_per_hour)
+    } else {
+        None
+    }
+}
+
+/// One Redis subscription per instance, fanned out in-process to every
+/// connected client
+pub struct EventHub {
+    redis_client: RedisClient,
+    sender: broadcast::Sender<BillingEvent>,
+}
+
+impl EventHub {
+    pub fn new(redis_client: RedisClient) -> Self {
+        let (sender, _) = broadcast::channel(HUB_CAPACITY);
+        Self { redis_client, sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BillingEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Relay events from Redis until the process exits, resubscribing after
+    /// connection failures
+    pub async fn run(&self) {
+        loop {
+            if let Err(e) = self.relay().await {
+                warn!("Event subscription lost: {:?}", e);
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn relay(&self) -> Result<(), BillingError> {
+        let mut pubsub = self
+            .redis_client
+            .get_async_connection()
+            .await
+            .map_err(|e| BillingError::Cache(e.to_string()))?
+            .into_pubsub();
+
+        pubsub
+            .subscribe(EVENTS_CHANNEL)
+            .await
+            .map_err(|e| BillingError::Cache(e.to_string()))?;
+
+        info!("Subscribed to {}", EVENTS_CHANNEL);
+
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let payload: String = match message.get_payload() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!("Unreadable event payload: {}", e);
+                    continue;
+                }
+            };
+
+            match serde_json::from_str::<BillingEvent>(&payload) {
+                // Sending fails only when nobody is listening on this instance
+                Ok(event) => {
+                    self.sender.send(event).ok();
+                }
+                Err(e) => warn!("Malformed billing event: {}", e),
+            }
+        }
+
+        Err(BillingError::Cache("Event subscription closed".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::PricingPlan;
+    use crate::test_support::dec;
+    use crate::zcash::zcash_service::PermissionStatus;
+    use sqlx::types::Json;
+
+    fn session() -> StreamingSession {
+        let now = Utc::now();
+        StreamingSession {
+            id: Uuid::new_v4(),
+            session_code: "ABC123".to_string(),
+            user_wallet_address: "0xuser".to_string(),
+            vendor_wallet_address: "0xvendor".to_string(),
+            vendor_id: "vendor-1".to_string(),
+            start_time: now,
+            last_billed_time: now,
+            end_time: None,
+            rate_per_hour: dec("2"),
+            pricing_plan: Json(PricingPlan::flat(dec("2"))),
+            billed_micros: 0,
+            token_address: None,
+            chain_id: 1,
+            total_amount_billed: dec("3.5"),
+            status: SessionStatus::Active,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn permission(remaining_amount: &str, rate_per_hour: &str) -> SpendingPermission {
+        let now = Utc::now();
+        SpendingPermission {
+            id: Uuid::new_v4(),
+            user_wallet_address: "zs1user".to_string(),
+            approved_amount: dec("10"),
+            remaining_amount: dec(remaining_amount),
+            rate_per_hour: dec(rate_per_hour),
+            max_streaming_hours: dec("5"),
+            used_streaming_hours: dec("1"),
+            status: PermissionStatus::Active,
+            expires_at: now,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[test]
+    fn kinds_serialize_as_their_sse_event_names() {
+        for kind in [
+            BillingEventKind::SessionStarted,
+            BillingEventKind::SessionBilled,
+            BillingEventKind::LowBalance,
+            BillingEventKind::SessionPaused,
+            BillingEventKind::SessionResumed,
+            BillingEventKind::SessionEnded,
+            BillingEventKind::SessionFailed,
+        ] {
+            assert_eq!(serde_json::to_value(kind).unwrap(), kind.as_str());
+        }
+    }
+
+    #[test]
+    fn session_events_serialize_the_charge_and_balance() {
+        let permission = permission("6", "2");
+        let event = BillingEvent::for_session(BillingEventKind::SessionBilled, &session())
+            .with_charge(dec("0.5"))
+            .with_balance(&permission);
+
+        let payload = serde_json::to_value(&event).unwrap();
+
+        assert_eq!(payload["kind"], "session_billed");
+        assert_eq!(payload["status"], "Active");
+        assert_eq!(payload["vendor_id"], "vendor-1");
+        assert_eq!(payload["permission_id"], permission.id.to_string());
+        assert_eq!(payload["amount"], 0.5);
+        assert_eq!(payload["total_amount_billed"], 3.5);
+        assert_eq!(payload["remaining_amount"], 6.0);
+        assert_eq!(payload["remaining_hours"], 3.0);
+    }
+
+    #[test]
+    fn events_without_a_charge_or_permission_serialize_nulls() {
+        let payload = serde_json::to_value(BillingEvent::for_session(BillingEventKind::SessionStarted, &session())).unwrap();
+
+        assert!(payload["amount"].is_null());
+        assert!(payload["permission_id"].is_null());
+        assert!(payload["remaining_amount"].is_null());
+        assert!(payload["remaining_hours"].is_null());
+    }
+
+    #[test]
+    fn relayed_payloads_round_trip() {
+        let event = BillingEvent::for_session(BillingEventKind::LowBalance, &session())
+            .with_balance(&permission("0.25", "1"));
+
+        let relayed: BillingEvent = serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+
+        assert_eq!(relayed.id, event.id);
+        assert_eq!(relayed.kind, BillingEventKind::LowBalance);
+        assert_eq!(relayed.status, SessionStatus::Active);
+        assert_eq!(relayed.remaining_hours, Some(dec("0.25")));
+        assert_eq!(relayed.occurred_at, event.occurred_at);
+    }
+
+    #[test]
+    fn sse_frames_carry_the_event_id_name_and_json_payload() {
+        let event = BillingEvent::for_session(BillingEventKind::SessionEnded, &session());
+
+        let frame = String::from_utf8(event.to_sse().unwrap().to_vec()).unwrap();
+
+        let data = format!("data: {}\n\n", serde_json::to_string(&event).unwrap());
+        assert!(frame.starts_with(&format!("id: {}\nevent: session_ended\n", event.id)));
+        assert!(frame.ends_with(&data));
+    }
+
+    #[test]
+    fn remaining_hours_needs_a_positive_rate() {
+        assert_eq!(remaining_hours(dec("3"), dec("2")), Some(dec("1.5")));
+        assert_eq!(remaining_hours(dec("3"), Decimal::ZERO), None);
+    }
+}
@@ -0,0 +1,115 @@
+// src/events_api.rs
+use actix_web::{web, web::Bytes, HttpResponse};
+use futures_util::Stream;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::auth::{Access, Principal};
+use crate::db;
+use crate::error::BillingError;
+use crate::events::{BillingEvent, EventHub};
+use crate::zcash::ZcashService;
+
+/// Comment frames keep idle streams open through proxies
+const KEEPALIVE: std::time::Duration = std::time::Duration::from_secs(15);
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events/sessions/{id}",
+    tag = "events",
+    params(("id" = Uuid, Path, description = "Session id")),
+    responses(
+        (status = 200, description = "Server-Sent Events stream of the session's billing events", body = BillingEvent, content_type = "text/event-stream"),
+        (status = 403, description = "Not the session's user or vendor", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Session not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn stream_session_events(
+    hub: web::Data<Arc<EventHub>>,
+    db_pool: web::Data<PgPool>,
+    principal: Principal,
+    session_id: web::Path<Uuid>,
+) -> Result<HttpResponse, BillingError> {
+    let session_id = session_id.into_inner();
+    let session = db::get_session(db_pool.get_ref(), session_id).await?;
+    principal.authorize_session(&session, Access::Read)?;
+
+    // Subscribe before responding so nothing billed in between is missed
+    let events = hub.subscribe();
+    Ok(event_stream_response(events, move |event| event.session_id == session_id))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events/permissions/{id}",
+    tag = "events",
+    params(("id" = Uuid, Path, description = "Spending permission id")),
+    responses(
+        (status = 200, description = "Server-Sent Events stream for every session paid by the permission", body = BillingEvent, content_type = "text/event-stream"),
+        (status = 403, description = "Not the permission's wallet", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Permission not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn stream_permission_events(
+    hub: web::Data<Arc<EventHub>>,
+    zcash_service: web::Data<Arc<ZcashService>>,
+    principal: Principal,
+    permission_id: web::Path<Uuid>,
+) -> Result<HttpResponse, BillingError> {
+    let permission_id = permission_id.into_inner();
+    let permission = zcash_service.get_permission(permission_id).await?;
+    principal.authorize_wallet(&permission.user_wallet_address, Access::Read)?;
+
+    let events = hub.subscribe();
+    Ok(event_stream_response(events, move |event| event.permission_id == Some(permission_id)))
+}
+
+fn event_stream_response<F>(events: broadcast::Receiver<BillingEvent>, matches: F) -> HttpResponse
+where
+    F: Fn(&BillingEvent) -> bool + 'static,
+{
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(event_stream(events, matches))
+}
+
+fn event_stream<F>(
+    events: broadcast::Receiver<BillingEvent>,
+    matches: F,
+) -> impl Stream<Item = Result<Bytes, BillingError>>
+where
+    F: Fn(&BillingEvent) -> bool + 'static,
+{
+    let mut keepalive = tokio::time::interval(KEEPALIVE);
+    keepalive.reset(); // the first tick would otherwise fire immediately
+
+    futures_util::stream::unfold(
+        (events, keepalive, matches),
+        |(mut events, mut keepalive, matches)| async move {
+            let frame = loop {
+                tokio::select! {
+                    received = events.recv() => match received {
+                        Ok(event) if matches(&event) => break event.to_sse(),
+                        Ok(_) => continue,
+                        // Tell the client to refetch state; the dropped events are gone
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Event subscriber lagged, dropped {} events", skipped);
+                            break Ok(Bytes::from(format!("event: lagged\ndata: {}\n\n", skipped)));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    },
+                    _ = keepalive.tick() => break Ok(Bytes::from_static(b": keepalive\n\n")),
+                }
+            };
+
+            Some((frame, (events, keepalive, matches)))
+        },
+    )
+}
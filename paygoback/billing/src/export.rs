@@ -0,0 +1,320 @@
+// src/export.rs
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::statement::{LedgerEntry, LedgerKind};
+
+/// QuickBooks accounts the IIF journal posts to, each suffixed with the
+/// asset. They must exist in the company file, in the asset's currency,
+/// before the import.
+const CLEARING_ACCOUNT: &str = "PayGo Clearing";
+const FEE_INCOME_ACCOUNT: &str = "PayGo Fee Income";
+const VENDOR_PAYABLE_ACCOUNT: &str = "PayGo Vendor Payable";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportKind {
+    Transactions, // charges
+    Payouts,      // vendors' and payees' shares
+    Refunds,      // reversed charges
+}
+
+impl ExportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportKind::Transactions => "transactions",
+            ExportKind::Payouts => "payouts",
+            ExportKind::Refunds => "refunds",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv, // every field
+    Iif,  // QuickBooks Desktop general journal
+    Xero, // Xero bank statement import
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv | ExportFormat::Xero => "text/csv; charset=utf-8",
+            ExportFormat::Iif => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv | ExportFormat::Xero => "csv",
+            ExportFormat::Iif => "iif",
+        }
+    }
+}
+
+pub fn render(entries: &[LedgerEntry], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => csv(entries),
+        ExportFormat::Iif => iif(entries),
+        ExportFormat::Xero => xero(entries),
+    }
+}
+
+/// Quote a CSV field when it holds a delimiter, quote or line break. Text
+/// that a spreadsheet would read as a formula is prefixed with `'`; numbers,
+/// negative amounts included, are left alone.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) && Decimal::from_str(value).is_err() {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+fn optional(value: &Option<impl ToString>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+fn amount(value: Decimal) -> String {
+    value.normalize().to_string()
+}
+
+fn kind(entry: &LedgerEntry) -> &'static str {
+    match entry.kind {
+        LedgerKind::Charge => "charge",
+        LedgerKind::Refund => "refund",
+        LedgerKind::Payout => "payout",
+    }
+}
+
+fn csv(entries: &[LedgerEntry]) -> String {
+    let mut out = csv_row(
+        &[
+            "id",
+            "kind",
+            "occurred_at",
+            "vendor_id",
+            "user_wallet_address",
+            "description",
+            "asset",
+            "amount",
+            "fee_amount",
+            "net_amount",
+            "fiat_currency",
+            "fiat_amount",
+            "status",
+            "settled",
+            "role",
+            "recipient",
+            "recipient_wallet_address",
+            "tx_hash",
+        ]
+        .map(str::to_string),
+    );
+
+    for entry in entries {
+        out.push_str(&csv_row(&[
+            entry.id.to_string(),
+            kind(entry).to_string(),
+            entry.occurred_at.to_rfc3339(),
+            entry.vendor_id.clone(),
+            entry.user_wallet_address.clone(),
+            entry.description.clone(),
+            entry.asset.clone(),
+            amount(entry.amount),
+            amount(entry.fee_amount),
+            amount(entry.net_amount),
+            optional(&entry.fiat_currency),
+            optional(&entry.fiat_amount.map(amount)),
+            entry.status.clone(),
+            entry.settled.to_string(),
+            entry.role.map(|role| role.as_str()).unwrap_or_default().to_string(),
+            optional(&entry.recipient),
+            optional(&entry.recipient_wallet_address),
+            optional(&entry.tx_hash),
+        ]));
+    }
+
+    out
+}
+
+/// Money in is positive: charges come from the user's wallet, refunds and
+/// payouts go out. Dates are day/month/year as Xero expects.
+fn xero(entries: &[LedgerEntry]) -> String {
+    let mut out = csv_row(&["*Date", "*Amount", "Payee", "Description", "Reference"].map(str::to_string));
+
+    for entry in entries {
+        let (signed, payee) = match entry.kind {
+            LedgerKind::Charge => (entry.amount, entry.user_wallet_address.clone()),
+            LedgerKind::Refund => (-entry.amount, entry.user_wallet_address.clone()),
+            LedgerKind::Payout => (-entry.amount, optional(&entry.recipient)),
+        };
+
+        out.push_str(&csv_row(&[
+            entry.occurred_at.format("%d/%m/%Y").to_string(),
+            amount(signed),
+            payee,
+            format!("{} ({} {})", entry.description, amount(entry.amount), entry.asset),
+            entry.tx_hash.clone().unwrap_or_else(|| entry.id.to_string()),
+        ]));
+    }
+
+    out
+}
+
+/// IIF is tab separated with no quoting, so tabs and line breaks can't
+/// appear in a field
+fn iif_field(value: &str) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
+
+fn iif_line(record: &str, date: DateTime<Utc>, account: &str, name: &str, amount: Decimal, entry: &LedgerEntry) -> String {
+    format!(
+        "{}\tGENERAL JOURNAL\t{}\t{}\t{}\t{}\t{}\t{}\r\n",
+        record,
+        date.format("%m/%d/%Y"),
+        iif_field(account),
+        iif_field(name),
+        amount.normalize(),
+        entry.id,
+        iif_field(&entry.description),
+    )
+}
+
+/// One balanced general journal transaction per entry. A charge debits the
+/// clearing account with the gross and credits fee income and the vendor
+/// payable; a refund reverses that, and a payout settles the payable.
+fn iif(entries: &[LedgerEntry]) -> String {
+    let mut out = String::from(
+        "!TRNS\tTRNSTYPE\tDATE\tACCNT\tNAME\tAMOUNT\tDOCNUM\tMEMO\r\n\
+         !SPL\tTRNSTYPE\tDATE\tACCNT\tNAME\tAMOUNT\tDOCNUM\tMEMO\r\n\
+         !ENDTRNS\r\n",
+    );
+
+    for entry in entries {
+        let clearing = format!("{} {}", CLEARING_ACCOUNT, entry.asset);
+        let fee_income = format!("{} {}", FEE_INCOME_ACCOUNT, entry.asset);
+        let vendor_payable = format!("{} {}", VENDOR_PAYABLE_ACCOUNT, entry.asset);
+        let date = entry.occurred_at;
+
+        match entry.kind {
+            LedgerKind::Charge | LedgerKind::Refund => {
+                let sign = if entry.kind == LedgerKind::Refund { -Decimal::ONE } else { Decimal::ONE };
+                out.push_str(&iif_line("TRNS", date, &clearing, &entry.user_wallet_address, sign * entry.amount, entry));
+                if !entry.fee_amount.is_zero() {
+                    out.push_str(&iif_line("SPL", date, &fee_income, "", -sign * entry.fee_amount, entry));
+                }
+                out.push_str(&iif_line(
+                    "SPL",
+                    date,
+                    &vendor_payable,
+                    &entry.vendor_id,
+                    -sign * entry.net_amount,
+                    entry,
+                ));
+            }
+            LedgerKind::Payout => {
+                let recipient = optional(&entry.recipient);
+                out.push_str(&iif_line("TRNS", date, &vendor_payable, &recipient, entry.amount, entry));
+                out.push_str(&iif_line("SPL", date, &clearing, &recipient, -entry.amount, entry));
+            }
+        }
+        out.push_str("ENDTRNS\r\n");
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+    use crate::statement::tests::entry;
+
+    #[test]
+    fn csv_quotes_fields_with_delimiters_and_quotes() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+
+        let mut charge = entry(LedgerKind::Charge, "ZEC", "0.10000000", true);
+        charge.description = "Streaming, session \"ABC\"".to_string();
+        let out = csv(&[charge]);
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[1].contains(",\"Streaming, session \"\"ABC\"\"\",ZEC,0.1,0.01,0.09,"));
+    }
+
+    #[test]
+    fn csv_defuses_formulas_but_not_negative_amounts() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("+1+2"), "'+1+2");
+        assert_eq!(csv_field("\tcmd"), "'\tcmd");
+        assert_eq!(csv_field("-0.5"), "-0.5");
+
+        let mut charge = entry(LedgerKind::Refund, "ZEC", "0.5", false);
+        charge.user_wallet_address = "=cmd|' /C calc'!A0".to_string();
+        assert!(xero(&[charge]).lines().nth(1).unwrap().starts_with("15/01/2024,-0.5,'=cmd|' /C calc'!A0,"));
+    }
+
+    #[test]
+    fn iif_transactions_balance() {
+        let entries = [
+            entry(LedgerKind::Charge, "ZEC", "1", true),
+            entry(LedgerKind::Refund, "ZEC", "0.5", false),
+            entry(LedgerKind::Payout, "ZEC", "0.1", true),
+        ];
+        let out = iif(&entries);
+
+        let mut balance = Decimal::ZERO;
+        let mut transactions = 0;
+        for line in out.lines().skip(3) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields[0] {
+                "TRNS" | "SPL" => {
+                    balance += dec(fields[5]);
+                    assert!(fields[3].ends_with(" ZEC"), "account {} has no asset", fields[3]);
+                }
+                "ENDTRNS" => {
+                    assert_eq!(balance, Decimal::ZERO, "unbalanced transaction in {}", out);
+                    transactions += 1;
+                }
+                other => panic!("unexpected record {}", other),
+            }
+            if fields[0] == "TRNS" {
+                assert_eq!(fields[2], "01/15/2024");
+            }
+        }
+        assert_eq!(transactions, 3);
+    }
+
+    #[test]
+    fn xero_signs_money_in_and_out() {
+        let out = xero(&[
+            entry(LedgerKind::Charge, "ZEC", "1", true),
+            entry(LedgerKind::Refund, "ZEC", "0.5", false),
+            entry(LedgerKind::Payout, "ZEC", "0.1", true),
+        ]);
+        let amounts: Vec<&str> = out.lines().skip(1).map(|row| row.split(',').nth(1).unwrap()).collect();
+
+        assert_eq!(amounts, vec!["1", "-0.5", "-0.1"]);
+        assert!(out.lines().nth(3).unwrap().starts_with("15/01/2024,-0.1,referrer-9,"));
+    }
+}
@@ -0,0 +1,287 @@
+// src/fees.rs
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::interval::Rounding;
+use crate::models::StreamingSession;
+
+/// What the platform takes from each charge: `percent` of the gross plus a
+/// `fixed` amount per charge, in the asset the charge settles in, never
+/// more than the charge itself. What is left is the vendor's, less the
+/// `splits` paid to other payees out of it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    #[serde(default)]
+    pub percent: Decimal,
+    #[serde(default)]
+    pub fixed: Decimal,
+    #[serde(default)]
+    pub splits: Vec<RevenueSplit>,
+}
+
+/// A payee, such as a referrer or agency, receiving `percent` of the
+/// vendor's net revenue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RevenueSplit {
+    pub recipient: String,
+    pub wallet_address: String,
+    pub percent: Decimal,
+}
+
+/// The default schedule and per-vendor overrides, from `FEE_SCHEDULES_FILE`
+/// or `PLATFORM_FEE_PERCENT`/`PLATFORM_FEE_FIXED`. An override replaces the
+/// default entirely.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FeeSchedules {
+    #[serde(default)]
+    pub default: FeeSchedule,
+    #[serde(default)]
+    pub vendors: HashMap<String, FeeSchedule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitRole {
+    Platform,
+    Vendor,
+    Payee,
+}
+
+impl SplitRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SplitRole::Platform => "platform",
+            SplitRole::Vendor => "vendor",
+            SplitRole::Payee => "payee",
+        }
+    }
+}
+
+/// One payee's share of a charge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    pub role: SplitRole,
+    pub recipient: String,
+    pub wallet_address: Option<String>,
+    pub amount: Decimal,
+}
+
+/// A charge divided between the platform, the vendor and its payees. The
+/// allocations add up to `gross`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeBreakdown {
+    pub gross: Decimal,
+    pub fee: Decimal,
+    pub net: Decimal, // the vendor's share before splits
+    pub allocations: Vec<Allocation>,
+}
+
+impl FeeSchedules {
+    pub fn for_vendor(&self, vendor_id: &str) -> &FeeSchedule {
+        self.vendors.get(vendor_id).unwrap_or(&self.default)
+    }
+
+    /// Divide a session's charge by its vendor's schedule
+    pub fn apply_to_session(
+        &self,
+        session: &StreamingSession,
+        amount: Decimal,
+        precision: u32,
+        rounding: Rounding,
+    ) -> FeeBreakdown {
+        self.for_vendor(&session.vendor_id).apply(
+            amount,
+            &session.vendor_id,
+            Some(&session.vendor_wallet_address),
+            precision,
+            rounding,
+        )
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.default.validate().map_err(|e| format!("Default fee schedule: {}", e))?;
+        for (vendor_id, schedule) in &self.vendors {
+            schedule.validate().map_err(|e| format!("Fee schedule for {}: {}", vendor_id, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl FeeSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        let hundred = Decimal::ONE_HUNDRED;
+
+        if self.percent < Decimal::ZERO || self.percent > hundred {
+            return Err("percent must be between 0 and 100".to_string());
+        }
+        if self.fixed < Decimal::ZERO {
+            return Err("fixed fee can't be negative".to_string());
+        }
+        for split in &self.splits {
+            if split.recipient.is_empty() || split.wallet_address.is_empty() {
+                return Err("splits need a recipient and a wallet address".to_string());
+            }
+            if split.percent <= Decimal::ZERO {
+                return Err(format!("split for {} must be a positive percent", split.recipient));
+            }
+        }
+        if self.splits.iter().map(|split| split.percent).sum::<Decimal>() > hundred {
+            return Err("splits add up to more than 100 percent".to_string());
+        }
+        Ok(())
+    }
+
+    /// Divide a `gross` charge to the vendor, paid at `vendor_wallet_address`
+    /// when the charge names one.
+    /// The fee is rounded to `precision` by the billing rounding policy;
+    /// payees' shares are rounded down, so the vendor keeps the remainder.
+    pub fn apply(
+        &self,
+        gross: Decimal,
+        vendor_id: &str,
+        vendor_wallet_address: Option<&str>,
+        precision: u32,
+        rounding: Rounding,
+    ) -> FeeBreakdown {
+        let fee = if gross > Decimal::ZERO {
+            rounding
+                .round(gross * self.percent / Decimal::ONE_HUNDRED + self.fixed, precision)
+                .min(gross)
+        } else {
+            Decimal::ZERO
+        };
+        let net = gross - fee;
+
+        let mut allocations = Vec::new();
+        if fee > Decimal::ZERO {
+            allocations.push(Allocation {
+                role: SplitRole::Platform,
+                recipient: "platform".to_string(),
+                wallet_address: None,
+                amount: fee,
+            });
+        }
+
+        let mut vendor_share = net;
+        for split in &self.splits {
+            let amount = (net * split.percent / Decimal::ONE_HUNDRED)
+                .round_dp_with_strategy(precision, RoundingStrategy::ToZero);
+            if amount > Decimal::ZERO {
+                vendor_share -= amount;
+                allocations.push(Allocation {
+                    role: SplitRole::Payee,
+                    recipient: split.recipient.clone(),
+                    wallet_address: Some(split.wallet_address.clone()),
+                    amount,
+                });
+            }
+        }
+        if vendor_share > Decimal::ZERO {
+            allocations.push(Allocation {
+                role: SplitRole::Vendor,
+                recipient: vendor_id.to_string(),
+                wallet_address: vendor_wallet_address.map(str::to_string),
+                amount: vendor_share,
+            });
+        }
+
+        FeeBreakdown {
+            gross,
+            fee,
+            net,
+            allocations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    fn split(recipient: &str, percent: &str) -> RevenueSplit {
+        RevenueSplit {
+            recipient: recipient.to_string(),
+            wallet_address: format!("0x{}", recipient),
+            percent: dec(percent),
+        }
+    }
+
+    fn allocated(breakdown: &FeeBreakdown) -> Decimal {
+        breakdown.allocations.iter().map(|allocation| allocation.amount).sum()
+    }
+
+    #[test]
+    fn percent_and_fixed_fees_come_off_the_gross() {
+        let schedule = FeeSchedule {
+            percent: dec("2.5"),
+            fixed: dec("0.01"),
+            splits: Vec::new(),
+        };
+        let breakdown = schedule.apply(dec("4"), "vendor-1", Some("0xvendor"), 8, Rounding::Bankers);
+
+        assert_eq!(breakdown.fee, dec("0.11"));
+        assert_eq!(breakdown.net, dec("3.89"));
+        assert_eq!(allocated(&breakdown), dec("4"));
+        assert_eq!(breakdown.allocations.last().unwrap().role, SplitRole::Vendor);
+    }
+
+    #[test]
+    fn fee_never_exceeds_the_charge() {
+        let schedule = FeeSchedule {
+            fixed: dec("1"),
+            ..Default::default()
+        };
+
+        let small = schedule.apply(dec("0.25"), "vendor-1", Some("0xvendor"), 8, Rounding::Bankers);
+        assert_eq!(small.fee, dec("0.25"));
+        assert_eq!(small.net, Decimal::ZERO);
+
+        let zero = schedule.apply(Decimal::ZERO, "vendor-1", Some("0xvendor"), 8, Rounding::Bankers);
+        assert_eq!(zero.fee, Decimal::ZERO);
+        assert!(zero.allocations.is_empty());
+    }
+
+    #[test]
+    fn splits_are_paid_from_the_net_and_the_vendor_keeps_the_remainder() {
+        let schedule = FeeSchedule {
+            percent: dec("10"),
+            fixed: Decimal::ZERO,
+            splits: vec![split("referrer", "10"), split("agency", "33.333")],
+        };
+        let breakdown = schedule.apply(dec("0.00000007"), "vendor-1", Some("0xvendor"), 8, Rounding::Bankers);
+
+        assert_eq!(allocated(&breakdown), breakdown.gross);
+
+        let breakdown = schedule.apply(dec("1"), "vendor-1", Some("0xvendor"), 8, Rounding::Bankers);
+        let amounts: Vec<(SplitRole, Decimal)> =
+            breakdown.allocations.iter().map(|allocation| (allocation.role, allocation.amount)).collect();
+        assert_eq!(
+            amounts,
+            vec![
+                (SplitRole::Platform, dec("0.1")),
+                (SplitRole::Payee, dec("0.09")),
+                (SplitRole::Payee, dec("0.299997")),
+                (SplitRole::Vendor, dec("0.510003")),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_fees_and_oversubscribed_splits() {
+        let negative = FeeSchedule {
+            fixed: dec("-1"),
+            ..Default::default()
+        };
+        let oversubscribed = FeeSchedule {
+            splits: vec![split("a", "60"), split("b", "50")],
+            ..Default::default()
+        };
+
+        assert!(FeeSchedule::default().validate().is_ok());
+        assert!(negative.validate().is_err());
+        assert!(oversubscribed.validate().is_err());
+    }
+}
@@ -0,0 +1,291 @@
+// src/indexer.rs
+use ethers::contract::LogMeta;
+use rust_decimal::Decimal;
+use sqlx::PgPool;
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use tracing::{info, error};
+
+use crate::blockchain::{BillingContractEvents, BlockchainClient};
+use crate::config::Config;
+use crate::error::BillingError;
+use crate::models::*;
+use crate::token::from_token_units;
+use crate::db;
+
+/// Indexes billing contract events (deposits, withdrawals and bills, ours or
+/// anyone else's) into Postgres and keeps a per-user balance from them.
+///
+/// Logs are polled with `eth_getLogs` from a persisted block cursor rather
+/// than pushed over a subscription: the same loop backfills whatever was
+/// missed while the service was down and then follows the chain head, and it
+/// works over every transport the RPC failover client can sit on. Only blocks
+/// `indexer_confirmations` deep are indexed, so reorgs above that depth never
+/// reach the index. Each chain deployment runs its own indexer.
+pub struct ContractIndexer {
+    db_pool: PgPool,
+    blockchain_client: Arc<BlockchainClient>,
+    start_block: u64,
+    config: Config,
+}
+
+impl ContractIndexer {
+    pub fn new(
+        db_pool: PgPool,
+        blockchain_client: Arc<BlockchainClient>,
+        start_block: u64,
+        config: Config,
+    ) -> Self {
+        Self {
+            db_pool,
+            blockchain_client,
+            start_block,
+            config,
+        }
+    }
+
+    /// Index everything between the persisted cursor and the confirmed head.
+    /// Returns the number of new events stored.
+    pub async fn sync(&self) -> Result<u64, BillingError> {
+        let cursor = cursor_name(&self.blockchain_client);
+        let head = self.blockchain_client.block_number().await?;
+
+        let from_block = match db::get_indexer_cursor(&self.db_pool, &cursor).await? {
+            Some(last_block) => last_block as u64 + 1,
+            None => self.start_block,
+        };
+
+        let ranges = block_ranges(
+            from_block,
+            head,
+            self.config.indexer_confirmations,
+            self.config.indexer_block_range,
+        );
+        if ranges.is_empty() {
+            db::touch_indexer_cursor(&self.db_pool, &cursor).await?;
+            return Ok(0);
+        }
+
+        let mut indexed = 0;
+        let decimals = self.blockchain_client.contract_decimals();
+
+        for (from_block, to_block) in ranges {
+            let events = self.blockchain_client
+                .query_contract_events(from_block, to_block)
+                .await?
+                .into_iter()
+                .map(|(event, meta)| decode_event(event, meta, decimals))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            indexed += db::index_contract_events(
+                &self.db_pool,
+                &cursor,
+                self.blockchain_client.chain_id() as i64,
+                self.blockchain_client.contract_address(),
+                &events,
+                to_block,
+            )
+            .await?;
+        }
+
+        Ok(indexed)
+    }
+
+    /// Follow the chain forever, starting with a backfill from the cursor
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            self.config.indexer_poll_interval_seconds,
+        ));
+
+        info!("Contract event indexer started for chain {}", self.blockchain_client.chain_id());
+
+        loop {
+            interval.tick().await;
+
+            match self.sync().await {
+                Ok(0) => {}
+                Ok(count) => info!(
+                    "Indexed {} billing contract events on chain {}",
+                    count,
+                    self.blockchain_client.chain_id()
+                ),
+                Err(e) => error!(
+                    "Contract indexer sync failed on chain {}: {:?}",
+                    self.blockchain_client.chain_id(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+/// Cursor key for a chain's billing contract
+fn cursor_name(blockchain_client: &BlockchainClient) -> String {
+    format!("billing:{}:{}", blockchain_client.chain_id(), blockchain_client.contract_address())
+}
+
+/// The `(from, to)` block ranges, at most `range` blocks each, that index
+/// everything from `from_block` up to the newest block `confirmations` deep
+/// below `head`. Empty when the index is already there.
+fn block_ranges(from_block: u64, head: u64, confirmations: u64, range: u64) -> Vec<(u64, u64)> {
+    let confirmed = head.saturating_sub(confirmations);
+    let range = range.max(1);
+
+    let mut ranges = Vec::new();
+    let mut from_block = from_block;
+    while from_block <= confirmed {
+        let to_block = from_block.saturating_add(range - 1).min(confirmed);
+        ranges.push((from_block, to_block));
+        from_block = to_block + 1;
+    }
+
+    ranges
+}
+
+/// Normalise a decoded billing contract log for the index, converting its
+/// amount from the contract's `decimals`
+fn decode_event(event: BillingContractEvents, meta: LogMeta, decimals: u8) -> Result<ContractEvent, BillingError> {
+    let (event_type, user, vendor, amount) = match event {
+        BillingContractEvents::DepositFilter(e) => (ContractEventType::Deposit, e.user, None, e.amount),
+        BillingContractEvents::WithdrawalFilter(e) => (ContractEventType::Withdrawal, e.user, None, e.amount),
+        BillingContractEvents::UserBilledFilter(e) => (ContractEventType::Billed, e.user, Some(e.vendor), e.amount),
+    };
+
+    Ok(ContractEvent {
+        event_type,
+        user_address: format!("{:?}", user),
+        vendor_address: vendor.map(|vendor| format!("{:?}", vendor)),
+        amount: from_token_units(amount, decimals)?,
+        block_number: meta.block_number.as_u64(),
+        block_hash: format!("{:?}", meta.block_hash),
+        tx_hash: format!("{:?}", meta.transaction_hash),
+        log_index: meta.log_index.as_u64(),
+    })
+}
+
+/// Balance the service can charge a user. Contract balances come from the
+/// event index while it is enabled and caught up; ERC-20 balances (which
+/// depend on allowances the index doesn't track) and a lagging index fall
+/// back to a live RPC read.
+pub async fn user_balance(
+    db_pool: &PgPool,
+    blockchain_client: &BlockchainClient,
+    config: &Config,
+    user_address: &str,
+    token_address: Option<&str>,
+) -> Result<Decimal, BillingError> {
+    if config.indexer_enabled && token_address.is_none() {
+        let indexed = db::get_indexed_balance(
+            db_pool,
+            &cursor_name(blockchain_client),
+            blockchain_client.chain_id() as i64,
+            blockchain_client.contract_address(),
+            &user_address.to_lowercase(),
+            Utc::now() - Duration::seconds(config.indexer_max_lag_seconds),
+        )
+        .await?;
+
+        if let Some(balance) = indexed {
+            return Ok(balance);
+        }
+    }
+
+    blockchain_client.get_user_balance(user_address, token_address).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{DepositFilter, UserBilledFilter, WithdrawalFilter};
+    use crate::test_support::dec;
+    use ethers::types::{Address, H256, U256, U64};
+
+    const USER: &str = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+    const VENDOR: &str = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8";
+
+    fn address(value: &str) -> Address {
+        value.parse().unwrap()
+    }
+
+    fn meta(block_number: u64, log_index: u64) -> LogMeta {
+        LogMeta {
+            address: Address::zero(),
+            block_number: U64::from(block_number),
+            block_hash: H256::repeat_byte(0xab),
+            transaction_hash: H256::repeat_byte(0xcd),
+            transaction_index: U64::zero(),
+            log_index: U256::from(log_index),
+        }
+    }
+
+    #[test]
+    fn deposits_decode_with_lowercase_addresses_and_scaled_amounts() {
+        let event = BillingContractEvents::DepositFilter(DepositFilter {
+            user: address(USER),
+            amount: U256::from(1_500_000u64),
+        });
+
+        let decoded = decode_event(event, meta(42, 3), 6).unwrap();
+
+        assert_eq!(decoded.event_type, ContractEventType::Deposit);
+        assert_eq!(decoded.user_address, USER.to_lowercase());
+        assert_eq!(decoded.vendor_address, None);
+        assert_eq!(decoded.amount, dec("1.5"));
+        assert_eq!(decoded.block_number, 42);
+        assert_eq!(decoded.log_index, 3);
+        assert_eq!(decoded.block_hash, format!("0x{}", "ab".repeat(32)));
+        assert_eq!(decoded.tx_hash, format!("0x{}", "cd".repeat(32)));
+        assert_eq!(decoded.balance_delta(), dec("1.5"));
+    }
+
+    #[test]
+    fn bills_decode_with_their_vendor_and_reduce_the_balance() {
+        let event = BillingContractEvents::UserBilledFilter(UserBilledFilter {
+            user: address(USER),
+            vendor: address(VENDOR),
+            amount: U256::exp10(17),
+            bill_id: [0; 32],
+        });
+
+        let decoded = decode_event(event, meta(7, 0), 18).unwrap();
+
+        assert_eq!(decoded.event_type, ContractEventType::Billed);
+        assert_eq!(decoded.vendor_address.as_deref(), Some(VENDOR.to_lowercase().as_str()));
+        assert_eq!(decoded.balance_delta(), dec("-0.1"));
+    }
+
+    #[test]
+    fn withdrawals_reduce_the_balance() {
+        let event = BillingContractEvents::WithdrawalFilter(WithdrawalFilter {
+            user: address(USER),
+            amount: U256::from(25u64),
+        });
+
+        let decoded = decode_event(event, meta(1, 0), 2).unwrap();
+
+        assert_eq!(decoded.event_type, ContractEventType::Withdrawal);
+        assert_eq!(decoded.balance_delta(), dec("-0.25"));
+    }
+
+    #[test]
+    fn blocks_within_the_confirmation_depth_are_not_indexed() {
+        assert_eq!(block_ranges(100, 111, 12, 1000), Vec::new());
+        assert_eq!(block_ranges(100, 112, 12, 1000), vec![(100, 100)]);
+    }
+
+    #[test]
+    fn a_chain_shorter_than_the_confirmation_depth_indexes_nothing_past_genesis() {
+        assert_eq!(block_ranges(1, 5, 12, 1000), Vec::new());
+        assert_eq!(block_ranges(0, 5, 12, 1000), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn backfill_is_split_into_ranges() {
+        assert_eq!(block_ranges(0, 262, 12, 100), vec![(0, 99), (100, 199), (200, 250)]);
+    }
+
+    #[test]
+    fn a_zero_range_still_makes_progress() {
+        assert_eq!(block_ranges(5, 7, 0, 0), vec![(5, 5), (6, 6), (7, 7)]);
+    }
+}
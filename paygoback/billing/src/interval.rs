@@ -0,0 +1,274 @@
+// src/interval.rs
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+
+use crate::blockchain::BlockchainClient;
+use crate::error::BillingError;
+use crate::models::StreamingSession;
+use crate::oracle::{Conversion, PriceService};
+use crate::pricing::{PricingPlan, MICROS_PER_HOUR};
+
+/// Amounts are stored as DECIMAL(20,8), so no charge is finer than this
+pub const STORED_AMOUNT_SCALE: u32 = 8;
+
+/// Zcash amounts are whole zatoshis
+pub const ZCASH_PRECISION: u32 = 8;
+
+/// Asset Zcash spending permissions are denominated in
+pub const ZCASH_ASSET: &str = "ZEC";
+
+/// How a charge is rounded to the currency's precision (`BILLING_ROUNDING`).
+/// `bankers` rounds half to even; `ceil` rounds up, so a session is never
+/// billed less than its time so far.
+/// Either way the rounding never accumulates: see [`interval_charge`].
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    Bankers,
+    Ceil,
+}
+
+impl Rounding {
+    pub fn round(self, amount: Decimal, precision: u32) -> Decimal {
+        let strategy = match self {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::Ceil => RoundingStrategy::ToPositiveInfinity,
+        };
+        amount.round_dp_with_strategy(precision, strategy)
+    }
+}
+
+/// The charge for one billing interval of a session
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalCharge {
+    pub billed_until: DateTime<Utc>, // the session's next last_billed_time
+    pub billed_micros: i64, // the session's active time billed so far, including this interval
+    pub duration: Duration,
+    pub hours: Decimal,
+    pub amount: Decimal,
+}
+
+/// Charge for the time from `billed_from` up to `now`, counted in whole
+/// microseconds (the resolution Postgres stores), after `billed_micros` of
+/// the session were already billed under `plan`.
+///
+/// The charge is the rounded cost of all usage so far less the rounded cost
+/// of the part already billed, so rounding differences carry into the next
+/// interval instead of being dropped, and a session's charges always add
+/// up to its total cost rounded once. `ended` applies the plan's minimum
+/// charge to a session's final interval.
+pub fn interval_charge(
+    plan: &PricingPlan,
+    billed_micros: i64,
+    billed_from: DateTime<Utc>,
+    now: DateTime<Utc>,
+    ended: bool,
+    precision: u32,
+    rounding: Rounding,
+) -> IntervalCharge {
+    let billed_until = now
+        .duration_trunc(Duration::microseconds(1))
+        .unwrap_or(now)
+        .max(billed_from);
+    let duration = billed_until.signed_duration_since(billed_from);
+    let micros = duration.num_microseconds().unwrap_or(i64::MAX);
+    let usage = billed_micros.saturating_add(micros);
+
+    let billed_cost = rounding.round(plan.cost(billed_micros, false), precision);
+    let cost = rounding.round(plan.cost(usage, ended), precision);
+
+    IntervalCharge {
+        billed_until,
+        billed_micros: usage,
+        duration,
+        hours: Decimal::from(micros) / Decimal::from(MICROS_PER_HOUR),
+        amount: cost - billed_cost,
+    }
+}
+
+/// The charge for a session's time since it was last billed, under the
+/// plan snapshotted when it was created
+pub fn session_charge(
+    session: &StreamingSession,
+    now: DateTime<Utc>,
+    ended: bool,
+    precision: u32,
+    rounding: Rounding,
+) -> IntervalCharge {
+    interval_charge(
+        &session.pricing_plan,
+        session.billed_micros,
+        session.last_billed_time,
+        now,
+        ended,
+        precision,
+        rounding,
+    )
+}
+
+/// The session's charge in `asset`, the asset it settles in. A plan priced
+/// in fiat is charged in fiat to the stored precision, so its rounding
+/// still carries between intervals, and converted at the current price.
+pub async fn settlement_charge(
+    prices: &PriceService,
+    session: &StreamingSession,
+    asset: &str,
+    now: DateTime<Utc>,
+    ended: bool,
+    precision: u32,
+    rounding: Rounding,
+) -> Result<(IntervalCharge, Option<Conversion>), BillingError> {
+    let Some(currency) = &session.pricing_plan.currency else {
+        return Ok((session_charge(session, now, ended, precision, rounding), None));
+    };
+
+    let charge = session_charge(session, now, ended, STORED_AMOUNT_SCALE, rounding);
+    let conversion = prices.convert(charge.amount, currency, asset, precision, rounding).await?;
+
+    Ok((
+        IntervalCharge {
+            amount: conversion.amount,
+            ..charge
+        },
+        Some(conversion),
+    ))
+}
+
+/// Precision of a charge settled on chain: the token's or the billing
+/// contract's decimals, capped at what the database stores
+pub async fn ethereum_precision(
+    client: &BlockchainClient,
+    token_address: Option<&str>,
+) -> Result<u32, BillingError> {
+    let decimals = match token_address {
+        Some(token_address) => client.token_decimals(token_address).await?,
+        None => client.contract_decimals(),
+    };
+
+    Ok((decimals as u32).min(STORED_AMOUNT_SCALE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::PriceTier;
+    use crate::test_support::dec;
+    use chrono::TimeZone;
+
+    fn at(micros: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000, 0).unwrap() + Duration::microseconds(micros)
+    }
+
+    /// Bill a flat-rate session at the given tick times and return the charges
+    fn bill_ticks(rate: Decimal, ticks: &[i64], precision: u32, rounding: Rounding) -> Vec<IntervalCharge> {
+        let plan = PricingPlan::flat(rate);
+        let mut billed_from = at(0);
+        let mut billed_micros = 0;
+        ticks
+            .iter()
+            .map(|&tick| {
+                let charge = interval_charge(&plan, billed_micros, billed_from, at(tick), false, precision, rounding);
+                billed_from = charge.billed_until;
+                billed_micros = charge.billed_micros;
+                charge
+            })
+            .collect()
+    }
+
+    #[test]
+    fn charges_sum_to_rate_times_total_duration() {
+        let rate = dec("0.37");
+        // Irregular ticks, each a minute and change late
+        let ticks: Vec<i64> = (1..=90).map(|n| n * 60_000_000 + n * 123_457).collect();
+        let total_micros = *ticks.last().unwrap();
+
+        for rounding in [Rounding::Bankers, Rounding::Ceil] {
+            let charges = bill_ticks(rate, &ticks, ZCASH_PRECISION, rounding);
+            let billed: Decimal = charges.iter().map(|c| c.amount).sum();
+            let exact = rate * Decimal::from(total_micros) / Decimal::from(MICROS_PER_HOUR);
+
+            assert_eq!(billed, rounding.round(exact, ZCASH_PRECISION));
+            assert!(charges.iter().all(|c| c.amount.scale() <= ZCASH_PRECISION));
+        }
+    }
+
+    #[test]
+    fn billed_durations_cover_the_segment_exactly() {
+        let ticks = [59_999_999, 120_000_001, 181_500_000];
+        let charges = bill_ticks(Decimal::ONE, &ticks, ZCASH_PRECISION, Rounding::Bankers);
+
+        let covered: i64 = charges.iter().map(|c| c.duration.num_microseconds().unwrap()).sum();
+        assert_eq!(covered, 181_500_000);
+        assert_eq!(charges.last().unwrap().billed_until, at(181_500_000));
+    }
+
+    #[test]
+    fn sub_unit_intervals_carry_instead_of_rounding_to_zero() {
+        // 0.00000001 per hour is one unit per hour; each minute alone rounds to nothing
+        let rate = dec("0.00000001");
+        let ticks: Vec<i64> = (1..=60).map(|n| n * 60_000_000).collect();
+        let charges = bill_ticks(rate, &ticks, ZCASH_PRECISION, Rounding::Bankers);
+
+        let billed: Decimal = charges.iter().map(|c| c.amount).sum();
+        assert_eq!(billed, rate);
+    }
+
+    #[test]
+    fn ceil_never_undercharges_the_time_so_far() {
+        let rate = dec("1.23456789");
+        let ticks: Vec<i64> = (1..=30).map(|n| n * 61_234_567).collect();
+        let charges = bill_ticks(rate, &ticks, 6, Rounding::Ceil);
+
+        let mut billed = Decimal::ZERO;
+        for (charge, &tick) in charges.iter().zip(&ticks) {
+            billed += charge.amount;
+            let exact = rate * Decimal::from(tick) / Decimal::from(MICROS_PER_HOUR);
+            assert!(billed >= exact);
+            assert!(billed - exact < Decimal::new(1, 6));
+        }
+    }
+
+    #[test]
+    fn plan_charges_sum_to_the_plan_cost_of_the_session() {
+        let plan = PricingPlan {
+            tiers: vec![PriceTier { after_minutes: 30, rate_per_hour: dec("0.7") }],
+            billing_increment_seconds: 60,
+            free_minutes: 5,
+            session_fee: dec("0.05"),
+            minimum_charge: Decimal::from(2),
+            ..PricingPlan::flat(dec("1.3"))
+        };
+        let ticks: Vec<i64> = (1..=45).map(|n| n * 60_500_000).collect();
+
+        let mut billed_from = at(0);
+        let mut billed_micros = 0;
+        let mut billed = Decimal::ZERO;
+        for (n, &tick) in ticks.iter().enumerate() {
+            let ended = n + 1 == ticks.len();
+            let charge = interval_charge(&plan, billed_micros, billed_from, at(tick), ended, 6, Rounding::Bankers);
+            billed_from = charge.billed_until;
+            billed_micros = charge.billed_micros;
+            billed += charge.amount;
+        }
+
+        let total = plan.cost(*ticks.last().unwrap(), true);
+        assert_eq!(billed, Rounding::Bankers.round(total, 6));
+    }
+
+    #[test]
+    fn bankers_rounds_half_to_even_and_ceil_rounds_up() {
+        assert_eq!(Rounding::Bankers.round(dec("0.125"), 2), dec("0.12"));
+        assert_eq!(Rounding::Bankers.round(dec("0.135"), 2), dec("0.14"));
+        assert_eq!(Rounding::Ceil.round(dec("0.121"), 2), dec("0.13"));
+    }
+
+    #[test]
+    fn sub_microsecond_time_is_left_for_the_next_interval() {
+        let now = at(60_000_000) + Duration::nanoseconds(999);
+        let plan = PricingPlan::flat(Decimal::ONE);
+        let charge = interval_charge(&plan, 0, at(0), now, false, ZCASH_PRECISION, Rounding::Bankers);
+
+        assert_eq!(charge.billed_until, at(60_000_000));
+    }
+}
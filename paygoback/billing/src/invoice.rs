@@ -0,0 +1,716 @@
+// src/invoice.rs
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::BTreeMap;
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::auth::{Access, Principal};
+use crate::config::Config;
+use crate::db;
+use crate::error::BillingError;
+use crate::interval::ZCASH_ASSET;
+use crate::models::SessionStatus;
+use crate::outbox;
+use crate::webhooks::WebhookEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceKind {
+    Session, // one ended session's charges
+    Period,  // everything a wallet was charged by a vendor in a period
+}
+
+impl InvoiceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvoiceKind::Session => "session",
+            InvoiceKind::Period => "period",
+        }
+    }
+
+    fn parse(kind: &str) -> Self {
+        match kind {
+            "session" => InvoiceKind::Session,
+            _ => InvoiceKind::Period,
+        }
+    }
+}
+
+/// A receipt once every charge on it is paid, an invoice until then
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentType {
+    Invoice,
+    Receipt,
+}
+
+/// Whether a line's charges have reached the vendor. Ordered so the least
+/// settled charge decides a grouped line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LineStatus {
+    Pending, // submitted on chain, not yet confirmed
+    Accrued, // waiting for batch settlement
+    Paid,
+}
+
+/// One line per billing interval, or per UTC day
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LineGrouping {
+    #[default]
+    Interval,
+    Day,
+}
+
+/// Exactly one of `session_code` and the period fields names what to invoice
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct IssueInvoiceRequest {
+    pub session_code: Option<String>,
+    pub vendor_id: Option<String>,
+    pub user_wallet_address: Option<String>,
+    pub period_start: Option<DateTime<Utc>>,
+    pub period_end: Option<DateTime<Utc>>, // exclusive
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct InvoiceLine {
+    pub description: String,
+    pub charged_at: DateTime<Utc>, // the start of the day for daily lines
+    pub quantity: Decimal,
+    pub unit: String,
+    pub asset: String, // what the charge settled in
+    pub amount: Decimal,
+    pub fee_amount: Decimal,
+    pub net_amount: Decimal,
+    pub fiat_currency: Option<String>, // set when every charge on the line was priced in it
+    pub fiat_amount: Option<Decimal>,
+    pub status: LineStatus,
+    pub tx_references: Vec<String>, // on-chain transactions that paid the line
+}
+
+/// What the lines in one asset add up to
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct InvoiceTotal {
+    pub asset: String,
+    pub amount: Decimal,
+    pub fee_amount: Decimal,
+    pub net_amount: Decimal,
+    pub amount_due: Decimal, // not yet paid
+    pub fiat_currency: Option<String>,
+    pub fiat_amount: Option<Decimal>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Invoice {
+    pub id: Uuid,
+    pub number: String,
+    pub kind: InvoiceKind,
+    pub document: DocumentType,
+    pub vendor_id: String,
+    pub user_wallet_address: String,
+    pub session_id: Option<Uuid>,
+    pub session_code: Option<String>,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub issued_at: DateTime<Utc>,
+    pub lines: Vec<InvoiceLine>,
+    pub totals: Vec<InvoiceTotal>,
+}
+
+/// The numbered record of an issued invoice. Its lines are built from the
+/// charges it covers whenever it is fetched, so statuses and transaction
+/// references stay current while the amounts never change.
+#[derive(Debug, Clone, Serialize, FromRow)]
+struct InvoiceRecord {
+    id: Uuid,
+    number: String,
+    kind: String,
+    vendor_id: String,
+    user_wallet_address: String,
+    session_id: Option<Uuid>,
+    period_start: DateTime<Utc>,
+    period_end: DateTime<Utc>,
+    issued_at: DateTime<Utc>,
+}
+
+/// A single charge an invoice covers: a billing transaction or a usage charge
+#[derive(Debug, Clone, PartialEq)]
+struct Charge {
+    description: String,
+    charged_at: DateTime<Utc>,
+    quantity: Decimal,
+    unit: String,
+    asset: String,
+    amount: Decimal,
+    fee_amount: Decimal,
+    net_amount: Decimal,
+    fiat_currency: Option<String>,
+    fiat_amount: Option<Decimal>,
+    status: LineStatus,
+    tx_reference: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct TransactionRow {
+    session_code: String,
+    created_at: DateTime<Utc>,
+    duration_minutes: i64,
+    chain_id: Option<i64>,
+    token_address: Option<String>,
+    amount: Decimal,
+    fee_amount: Decimal,
+    net_amount: Decimal,
+    fiat_currency: Option<String>,
+    fiat_amount: Option<Decimal>,
+    status: String,
+    tx_hash: Option<String>,
+    batch_status: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct UsageRow {
+    meter: String,
+    unit: Option<String>,
+    created_at: DateTime<Utc>,
+    quantity: Decimal,
+    amount: Decimal,
+    fee_amount: Decimal,
+    net_amount: Decimal,
+    fiat_currency: Option<String>,
+    fiat_amount: Option<Decimal>,
+}
+
+/// Issues sequentially numbered invoices for ended sessions and closed
+/// periods, and builds their lines from the charges they cover.
+pub struct InvoiceService {
+    db_pool: PgPool,
+    config: Config,
+}
+
+impl InvoiceService {
+    pub fn new(db_pool: PgPool, config: Config) -> Self {
+        Self { db_pool, config }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.config.invoice_issuer
+    }
+
+    /// Issue the invoice for a session or period, or return the one already
+    /// issued for it. Returns the invoice and whether it was new.
+    pub async fn issue(
+        &self,
+        principal: &Principal,
+        request: &IssueInvoiceRequest,
+        grouping: LineGrouping,
+    ) -> Result<(Invoice, bool), BillingError> {
+        let scope = self.scope(principal, request).await?;
+        let now = Utc::now();
+
+        // Numbers are gapless: the year's counter row is locked until the
+        // invoice commits, and rolls back with it
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+        let (sequence,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO invoice_sequences (year, last_number)
+            VALUES ($1, 1)
+            ON CONFLICT (year) DO UPDATE SET last_number = invoice_sequences.last_number + 1
+            RETURNING last_number
+            "#
+        )
+        .bind(now.year())
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?;
+
+        let existing = sqlx::query_as::<_, InvoiceRecord>(
+            r#"
+            SELECT id, number, kind, vendor_id, user_wallet_address, session_id,
+                   period_start, period_end, issued_at
+            FROM invoices
+            WHERE kind = $1
+            AND vendor_id = $2
+            AND user_wallet_address = $3
+            AND session_id IS NOT DISTINCT FROM $4
+            AND (session_id IS NOT NULL OR (period_start = $5 AND period_end = $6))
+            "#
+        )
+        .bind(&scope.kind)
+        .bind(&scope.vendor_id)
+        .bind(&scope.user_wallet_address)
+        .bind(scope.session_id)
+        .bind(scope.period_start)
+        .bind(scope.period_end)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?;
+
+        if let Some(existing) = existing {
+            tx.rollback().await.map_err(BillingError::Database)?;
+            return Ok((self.build(existing, grouping).await?, false));
+        }
+
+        let record = InvoiceRecord {
+            id: Uuid::new_v4(),
+            number: invoice_number(&self.config.invoice_number_prefix, now.year(), sequence),
+            issued_at: now,
+            ..scope
+        };
+        let charges = self.charges(&record).await?;
+        if charges.is_empty() {
+            return Err(BillingError::Conflict("Nothing was charged to invoice".to_string()));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO invoices
+            (id, number, kind, vendor_id, user_wallet_address, session_id, period_start, period_end, issued_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#
+        )
+        .bind(record.id)
+        .bind(&record.number)
+        .bind(&record.kind)
+        .bind(&record.vendor_id)
+        .bind(&record.user_wallet_address)
+        .bind(record.session_id)
+        .bind(record.period_start)
+        .bind(record.period_end)
+        .bind(record.issued_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?;
+
+        outbox::record(&mut *tx, &WebhookEvent::new("invoice.issued", Some(&record.vendor_id), &record)).await?;
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        info!("Issued invoice {} to {}", record.number, record.user_wallet_address);
+
+        Ok((self.assemble(record, &charges, grouping).await?, true))
+    }
+
+    /// An issued invoice, for its wallet, its vendor or an admin
+    pub async fn get(&self, principal: &Principal, id: Uuid, grouping: LineGrouping) -> Result<Invoice, BillingError> {
+        let record = sqlx::query_as::<_, InvoiceRecord>(
+            r#"
+            SELECT id, number, kind, vendor_id, user_wallet_address, session_id,
+                   period_start, period_end, issued_at
+            FROM invoices
+            WHERE id = $1
+            "#
+        )
+        .bind(id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?
+        .ok_or_else(|| BillingError::NotFound(format!("Invoice {} not found", id)))?;
+
+        authorize(principal, &record.vendor_id, &record.user_wallet_address, Access::Read)?;
+
+        self.build(record, grouping).await
+    }
+
+    /// What a request invoices, checked against the caller
+    async fn scope(&self, principal: &Principal, request: &IssueInvoiceRequest) -> Result<InvoiceRecord, BillingError> {
+        let period = (
+            &request.vendor_id,
+            &request.user_wallet_address,
+            request.period_start,
+            request.period_end,
+        );
+
+        match (&request.session_code, period) {
+            (Some(session_code), (None, None, None, None)) => {
+                let session = db::get_session_by_code(&self.db_pool, session_code).await?;
+                principal.authorize_session(&session, Access::Write)?;
+                if !matches!(session.status, SessionStatus::Completed | SessionStatus::Failed) {
+                    return Err(BillingError::Conflict(format!("Session {} hasn't ended", session_code)));
+                }
+
+                Ok(InvoiceRecord {
+                    id: Uuid::nil(),
+                    number: String::new(),
+                    kind: InvoiceKind::Session.as_str().to_string(),
+                    vendor_id: session.vendor_id,
+                    user_wallet_address: session.user_wallet_address,
+                    session_id: Some(session.id),
+                    period_start: session.start_time,
+                    period_end: session.end_time.unwrap_or(session.updated_at),
+                    issued_at: Utc::now(),
+                })
+            }
+            (None, (Some(vendor_id), Some(user_wallet_address), Some(period_start), Some(period_end))) => {
+                authorize(principal, vendor_id, user_wallet_address, Access::Write)?;
+                if period_start >= period_end {
+                    return Err(BillingError::Validation("period_start must be before period_end".to_string()));
+                }
+                if period_end > Utc::now() {
+                    return Err(BillingError::Conflict("The period hasn't ended".to_string()));
+                }
+
+                Ok(InvoiceRecord {
+                    id: Uuid::nil(),
+                    number: String::new(),
+                    kind: InvoiceKind::Period.as_str().to_string(),
+                    vendor_id: vendor_id.clone(),
+                    user_wallet_address: user_wallet_address.clone(),
+                    session_id: None,
+                    period_start,
+                    period_end,
+                    issued_at: Utc::now(),
+                })
+            }
+            _ => Err(BillingError::Validation(
+                "Either session_code, or vendor_id, user_wallet_address, period_start and period_end, is required"
+                    .to_string(),
+            )),
+        }
+    }
+
+    async fn build(&self, record: InvoiceRecord, grouping: LineGrouping) -> Result<Invoice, BillingError> {
+        let charges = self.charges(&record).await?;
+        self.assemble(record, &charges, grouping).await
+    }
+
+    async fn assemble(
+        &self,
+        record: InvoiceRecord,
+        charges: &[Charge],
+        grouping: LineGrouping,
+    ) -> Result<Invoice, BillingError> {
+        let session_code = match record.session_id {
+            Some(session_id) => Some(db::get_session(&self.db_pool, session_id).await?.session_code),
+            None => None,
+        };
+        let lines = invoice_lines(charges, grouping);
+        let totals = invoice_totals(&lines);
+        let document = if lines.iter().all(|line| line.status == LineStatus::Paid) {
+            DocumentType::Receipt
+        } else {
+            DocumentType::Invoice
+        };
+
+        Ok(Invoice {
+            id: record.id,
+            number: record.number,
+            kind: InvoiceKind::parse(&record.kind),
+            document,
+            vendor_id: record.vendor_id,
+            user_wallet_address: record.user_wallet_address,
+            session_id: record.session_id,
+            session_code,
+            period_start: record.period_start,
+            period_end: record.period_end,
+            issued_at: record.issued_at,
+            lines,
+            totals,
+        })
+    }
+
+    /// The session's charges, or everything the wallet was charged by the
+    /// vendor in the period: session intervals and metered usage. Failed
+    /// and cancelled charges collected nothing and are left out.
+    async fn charges(&self, record: &InvoiceRecord) -> Result<Vec<Charge>, BillingError> {
+        let transactions = sqlx::query_as::<_, TransactionRow>(
+            r#"
+            SELECT s.session_code, t.created_at, t.duration_minutes, t.chain_id, t.token_address,
+                   t.amount, t.fee_amount, t.net_amount, t.fiat_currency, t.fiat_amount, t.status::text AS status,
+                   COALESCE(t.tx_hash, b.tx_hash) AS tx_hash, b.status AS batch_status
+            FROM billing_transactions t
+            JOIN streaming_sessions s ON s.id = t.session_id
+            LEFT JOIN settlement_batches b ON b.id = t.batch_id
+            WHERE t.status NOT IN ('failed', 'cancelled')
+            AND CASE WHEN $1::uuid IS NOT NULL THEN t.session_id = $1
+                ELSE s.vendor_id = $2 AND t.user_wallet_address = $3
+                     AND t.created_at >= $4 AND t.created_at < $5
+                END
+            ORDER BY t.created_at
+            "#
+        )
+        .bind(record.session_id)
+        .bind(&record.vendor_id)
+        .bind(&record.user_wallet_address)
+        .bind(record.period_start)
+        .bind(record.period_end)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        let mut charges: Vec<Charge> = transactions
+            .into_iter()
+            .map(|row| {
+                let asset = self.config.transaction_asset(row.chain_id, row.token_address.as_deref());
+                let status = match (row.status.as_str(), row.batch_status.as_deref()) {
+                    ("confirmed", _) | ("accrued", Some("confirmed")) => LineStatus::Paid,
+                    ("accrued", _) => LineStatus::Accrued,
+                    _ => LineStatus::Pending,
+                };
+
+                Charge {
+                    description: format!("Streaming, session {}", row.session_code),
+                    charged_at: row.created_at,
+                    quantity: Decimal::from(row.duration_minutes),
+                    unit: "minute".to_string(),
+                    asset,
+                    amount: row.amount,
+                    fee_amount: row.fee_amount,
+                    net_amount: row.net_amount,
+                    fiat_currency: row.fiat_currency,
+                    fiat_amount: row.fiat_amount,
+                    status,
+                    tx_reference: row.tx_hash,
+                }
+            })
+            .collect();
+
+        // Usage charges are per permission rather than per session, so
+        // only period invoices include them
+        if record.session_id.is_none() {
+            let usage = sqlx::query_as::<_, UsageRow>(
+                r#"
+                SELECT c.meter, (SELECT e.unit FROM usage_events e WHERE e.charge_id = c.id LIMIT 1) AS unit,
+                       c.created_at, c.quantity, c.amount, c.fee_amount, c.net_amount,
+                       c.fiat_currency, c.fiat_amount
+                FROM usage_charges c
+                JOIN spending_permissions p ON p.id = c.permission_id
+                WHERE c.status = 'charged'
+                AND c.vendor_id = $1
+                AND p.user_wallet_address = $2
+                AND c.created_at >= $3 AND c.created_at < $4
+                ORDER BY c.created_at
+                "#
+            )
+            .bind(&record.vendor_id)
+            .bind(&record.user_wallet_address)
+            .bind(record.period_start)
+            .bind(record.period_end)
+            .fetch_all(&self.db_pool)
+            .await
+            .map_err(BillingError::Database)?;
+
+            charges.extend(usage.into_iter().map(|row| Charge {
+                description: format!("{} usage", row.meter),
+                charged_at: row.created_at,
+                quantity: row.quantity,
+                unit: row.unit.unwrap_or(row.meter),
+                asset: ZCASH_ASSET.to_string(),
+                amount: row.amount,
+                fee_amount: row.fee_amount,
+                net_amount: row.net_amount,
+                fiat_currency: row.fiat_currency,
+                fiat_amount: row.fiat_amount,
+                status: LineStatus::Paid,
+                tx_reference: None,
+            }));
+            charges.sort_by_key(|charge| charge.charged_at);
+        }
+
+        Ok(charges)
+    }
+}
+
+/// Invoices are the vendor's to issue and the wallet's to receive
+fn authorize(
+    principal: &Principal,
+    vendor_id: &str,
+    user_wallet_address: &str,
+    access: Access,
+) -> Result<(), BillingError> {
+    principal
+        .authorize_vendor(Some(vendor_id), access)
+        .or_else(|_| principal.authorize_wallet(user_wallet_address, access))
+}
+
+/// `INV-2024-000042`: numbered from 1 each year
+fn invoice_number(prefix: &str, year: i32, sequence: i64) -> String {
+    format!("{}-{}-{:06}", prefix, year, sequence)
+}
+
+/// The fiat total of some charges, if they were all priced in one currency
+fn fiat_sum<'a>(items: impl Iterator<Item = (Option<&'a str>, Option<Decimal>)>) -> (Option<String>, Option<Decimal>) {
+    let mut currency: Option<&str> = None;
+    let mut total = Decimal::ZERO;
+
+    for item in items {
+        match (item, currency) {
+            ((Some(c), Some(amount)), None) => {
+                currency = Some(c);
+                total += amount;
+            }
+            ((Some(c), Some(amount)), Some(current)) if c == current => total += amount,
+            _ => return (None, None),
+        }
+    }
+
+    match currency {
+        Some(currency) => (Some(currency.to_string()), Some(total)),
+        None => (None, None),
+    }
+}
+
+fn invoice_line(charges: &[&Charge], charged_at: DateTime<Utc>) -> InvoiceLine {
+    let first = charges[0];
+    let (fiat_currency, fiat_amount) =
+        fiat_sum(charges.iter().map(|charge| (charge.fiat_currency.as_deref(), charge.fiat_amount)));
+
+    let mut tx_references: Vec<String> = Vec::new();
+    for reference in charges.iter().filter_map(|charge| charge.tx_reference.as_ref()) {
+        if !tx_references.contains(reference) {
+            tx_references.push(reference.clone());
+        }
+    }
+
+    InvoiceLine {
+        description: first.description.clone(),
+        charged_at,
+        quantity: charges.iter().map(|charge| charge.quantity).sum(),
+        unit: first.unit.clone(),
+        asset: first.asset.clone(),
+        amount: charges.iter().map(|charge| charge.amount).sum(),
+        fee_amount: charges.iter().map(|charge| charge.fee_amount).sum(),
+        net_amount: charges.iter().map(|charge| charge.net_amount).sum(),
+        fiat_currency,
+        fiat_amount,
+        status: charges.iter().map(|charge| charge.status).min().unwrap_or(LineStatus::Paid),
+        tx_references,
+    }
+}
+
+/// Lines for `charges`, which are in time order. Daily lines sum each day's
+/// charges of one kind in one asset.
+fn invoice_lines(charges: &[Charge], grouping: LineGrouping) -> Vec<InvoiceLine> {
+    match grouping {
+        LineGrouping::Interval => charges
+            .iter()
+            .map(|charge| invoice_line(&[charge], charge.charged_at))
+            .collect(),
+        LineGrouping::Day => {
+            let mut days: BTreeMap<(NaiveDate, &str, &str, &str), Vec<&Charge>> = BTreeMap::new();
+            for charge in charges {
+                let key = (
+                    charge.charged_at.date_naive(),
+                    charge.description.as_str(),
+                    charge.asset.as_str(),
+                    charge.unit.as_str(),
+                );
+                days.entry(key).or_default().push(charge);
+            }
+
+            days.into_iter()
+                .map(|((day, ..), charges)| invoice_line(&charges, Utc.from_utc_datetime(&day.and_time(NaiveTime::MIN))))
+                .collect()
+        }
+    }
+}
+
+/// One total per asset, in the order the assets first appear
+fn invoice_totals(lines: &[InvoiceLine]) -> Vec<InvoiceTotal> {
+    let mut assets: Vec<&str> = Vec::new();
+    for line in lines {
+        if !assets.contains(&line.asset.as_str()) {
+            assets.push(&line.asset);
+        }
+    }
+
+    assets
+        .into_iter()
+        .map(|asset| {
+            let lines: Vec<&InvoiceLine> = lines.iter().filter(|line| line.asset == asset).collect();
+            let (fiat_currency, fiat_amount) =
+                fiat_sum(lines.iter().map(|line| (line.fiat_currency.as_deref(), line.fiat_amount)));
+
+            InvoiceTotal {
+                asset: asset.to_string(),
+                amount: lines.iter().map(|line| line.amount).sum(),
+                fee_amount: lines.iter().map(|line| line.fee_amount).sum(),
+                net_amount: lines.iter().map(|line| line.net_amount).sum(),
+                amount_due: lines
+                    .iter()
+                    .filter(|line| line.status != LineStatus::Paid)
+                    .map(|line| line.amount)
+                    .sum(),
+                fiat_currency,
+                fiat_amount,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    fn charge(hour: u32, amount: &str, status: LineStatus, tx: Option<&str>) -> Charge {
+        Charge {
+            description: "Streaming, session ABC123".to_string(),
+            charged_at: Utc.with_ymd_and_hms(2024, 1, 15, hour, 0, 0).unwrap(),
+            quantity: Decimal::ONE,
+            unit: "minute".to_string(),
+            asset: "ZEC".to_string(),
+            amount: dec(amount),
+            fee_amount: dec(amount) / Decimal::TEN,
+            net_amount: dec(amount) - dec(amount) / Decimal::TEN,
+            fiat_currency: Some("USD".to_string()),
+            fiat_amount: Some(dec(amount) * Decimal::TEN),
+            status,
+            tx_reference: tx.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn invoice_numbers_are_zero_padded_per_year() {
+        assert_eq!(invoice_number("INV", 2024, 42), "INV-2024-000042");
+        assert_eq!(invoice_number("ACME", 2025, 1_234_567), "ACME-2025-1234567");
+    }
+
+    #[test]
+    fn daily_lines_sum_a_days_charges_and_keep_the_least_settled_status() {
+        let mut next_day = charge(9, "0.5", LineStatus::Paid, Some("0xccc"));
+        next_day.charged_at += chrono::Duration::days(1);
+        let charges = vec![
+            charge(10, "0.1", LineStatus::Paid, Some("0xaaa")),
+            charge(11, "0.2", LineStatus::Accrued, Some("0xaaa")),
+            charge(12, "0.3", LineStatus::Paid, Some("0xbbb")),
+            next_day,
+        ];
+
+        assert_eq!(invoice_lines(&charges, LineGrouping::Interval).len(), 4);
+
+        let lines = invoice_lines(&charges, LineGrouping::Day);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].amount, dec("0.6"));
+        assert_eq!(lines[0].quantity, dec("3"));
+        assert_eq!(lines[0].status, LineStatus::Accrued);
+        assert_eq!(lines[0].tx_references, vec!["0xaaa", "0xbbb"]);
+        assert_eq!(lines[0].charged_at, Utc.with_ymd_and_hms(2024, 1, 15, 0, 0, 0).unwrap());
+        assert_eq!(lines[0].fiat_amount, Some(dec("6")));
+
+        let totals = invoice_totals(&lines);
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].amount, dec("1.1"));
+        assert_eq!(totals[0].fee_amount + totals[0].net_amount, dec("1.1"));
+        assert_eq!(totals[0].amount_due, dec("0.6"));
+    }
+
+    #[test]
+    fn fiat_totals_are_left_out_when_currencies_mix() {
+        let usd = charge(10, "0.1", LineStatus::Paid, None);
+        let mut eur = charge(11, "0.1", LineStatus::Paid, None);
+        eur.fiat_currency = Some("EUR".to_string());
+        let mut unpriced = charge(12, "0.1", LineStatus::Paid, None);
+        unpriced.fiat_currency = None;
+        unpriced.fiat_amount = None;
+
+        let line = invoice_line(&[&usd, &eur], usd.charged_at);
+        assert_eq!((line.fiat_currency, line.fiat_amount), (None, None));
+
+        let line = invoice_line(&[&usd, &unpriced], usd.charged_at);
+        assert_eq!((line.fiat_currency, line.fiat_amount), (None, None));
+    }
+}
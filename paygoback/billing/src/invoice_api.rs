@@ -0,0 +1,102 @@
+// src/invoice_api.rs
+use actix_web::{http::StatusCode, web, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::auth::Principal;
+use crate::error::BillingError;
+use crate::invoice::{Invoice, InvoiceService, IssueInvoiceRequest, LineGrouping};
+use crate::invoice_render;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceFormat {
+    #[default]
+    Json,
+    Html,
+    Pdf,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct InvoiceQuery {
+    #[serde(default)]
+    pub format: InvoiceFormat,
+    #[serde(default)]
+    pub lines: LineGrouping,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/invoices",
+    tag = "invoices",
+    request_body = IssueInvoiceRequest,
+    params(InvoiceQuery),
+    responses(
+        (status = 201, description = "Invoice issued with the next number", content(
+            ("application/json" = Invoice),
+            ("text/html" = String),
+            ("application/pdf" = String),
+        )),
+        (status = 200, description = "Already issued; the existing invoice is returned", body = Invoice),
+        (status = 400, description = "Neither a session nor a complete period given", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Not the session's user or vendor, or the period's wallet or vendor", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Session not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Session or period not over, or nothing charged", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn issue_invoice(
+    service: web::Data<Arc<InvoiceService>>,
+    principal: Principal,
+    query: web::Query<InvoiceQuery>,
+    req: web::Json<IssueInvoiceRequest>,
+) -> Result<HttpResponse, BillingError> {
+    let (invoice, created) = service.issue(&principal, &req, query.lines).await?;
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+
+    Ok(render(&service, &invoice, query.format, status))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/invoices/{id}",
+    tag = "invoices",
+    params(("id" = Uuid, Path, description = "Invoice id"), InvoiceQuery),
+    responses(
+        (status = 200, description = "The invoice, or a receipt once every line is paid", content(
+            ("application/json" = Invoice),
+            ("text/html" = String),
+            ("application/pdf" = String),
+        )),
+        (status = 403, description = "Not the invoice's wallet or vendor", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Invoice not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn get_invoice(
+    service: web::Data<Arc<InvoiceService>>,
+    principal: Principal,
+    invoice_id: web::Path<Uuid>,
+    query: web::Query<InvoiceQuery>,
+) -> Result<HttpResponse, BillingError> {
+    let invoice = service.get(&principal, *invoice_id, query.lines).await?;
+    Ok(render(&service, &invoice, query.format, StatusCode::OK))
+}
+
+fn render(service: &InvoiceService, invoice: &Invoice, format: InvoiceFormat, status: StatusCode) -> HttpResponse {
+    match format {
+        InvoiceFormat::Json => HttpResponse::build(status).json(invoice),
+        InvoiceFormat::Html => HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .body(invoice_render::html(invoice, service.issuer())),
+        InvoiceFormat::Pdf => HttpResponse::build(status)
+            .content_type("application/pdf")
+            .insert_header((
+                "Content-Disposition",
+                format!("inline; filename=\"{}.pdf\"", invoice.number),
+            ))
+            .body(invoice_render::pdf(invoice, service.issuer())),
+    }
+}
@@ -0,0 +1,371 @@
+// src/invoice_render.rs
+use chrono::{DateTime, Utc};
+use pdf_writer::{Content, Name, Pdf, Rect, Ref, Str};
+use rust_decimal::Decimal;
+
+use crate::invoice::{DocumentType, Invoice, InvoiceLine, LineStatus};
+
+const PAGE_WIDTH: f32 = 595.0; // A4, in points
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const ROW_HEIGHT: f32 = 14.0;
+
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+/// Left edges of the line item columns in the PDF
+const COLUMNS: [(&str, f32); 7] = [
+    ("Date (UTC)", 50.0),
+    ("Description", 135.0),
+    ("Quantity", 285.0),
+    ("Amount", 345.0),
+    ("Fee", 415.0),
+    ("Fiat", 470.0),
+    ("Status", 520.0),
+];
+
+fn title(invoice: &Invoice) -> &'static str {
+    match invoice.document {
+        DocumentType::Invoice => "Invoice",
+        DocumentType::Receipt => "Receipt",
+    }
+}
+
+fn status(status: LineStatus) -> &'static str {
+    match status {
+        LineStatus::Pending => "pending",
+        LineStatus::Accrued => "accrued",
+        LineStatus::Paid => "paid",
+    }
+}
+
+/// Amounts are stored to 8 places; documents drop the trailing zeros
+fn amount(value: Decimal) -> String {
+    value.normalize().to_string()
+}
+
+fn fiat(currency: &Option<String>, value: Option<Decimal>) -> String {
+    match (currency, value) {
+        (Some(currency), Some(value)) => format!("{} {}", value.round_dp(2), currency),
+        _ => String::new(),
+    }
+}
+
+fn date(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// The header fields both renderings print under the title
+fn details(invoice: &Invoice) -> Vec<(&'static str, String)> {
+    let mut details = vec![
+        ("Number", invoice.number.clone()),
+        ("Issued", date(invoice.issued_at)),
+        ("Vendor", invoice.vendor_id.clone()),
+        ("Billed to", invoice.user_wallet_address.clone()),
+    ];
+    if let Some(session_code) = &invoice.session_code {
+        details.push(("Session", session_code.clone()));
+    }
+    details.push(("Period", format!("{} to {}", date(invoice.period_start), date(invoice.period_end))));
+    details
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A standalone HTML page, styled inline so it can be emailed or printed
+pub fn html(invoice: &Invoice, issuer: &str) -> String {
+    let mut page = String::new();
+    let title = format!("{} {}", title(invoice), invoice.number);
+
+    page.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    page.push_str(&format!("<title>{}</title>\n", escape(&title)));
+    page.push_str(
+        "<style>body{font-family:sans-serif;margin:2em;color:#222}table{border-collapse:collapse;width:100%}\
+         th,td{padding:4px 8px;border-bottom:1px solid #ddd;text-align:left}td.num{text-align:right}\
+         .refs{font-size:0.8em;color:#666}</style>\n",
+    );
+    page.push_str("</head>\n<body>\n");
+    page.push_str(&format!("<h1>{}</h1>\n<h2>{}</h2>\n<dl>\n", escape(issuer), escape(&title)));
+    for (label, value) in details(invoice) {
+        page.push_str(&format!("<dt>{}</dt><dd>{}</dd>\n", label, escape(&value)));
+    }
+    page.push_str("</dl>\n<table>\n<thead><tr>");
+    for heading in ["Date (UTC)", "Description", "Quantity", "Amount", "Fee", "Net", "Fiat", "Status", "Transactions"] {
+        page.push_str(&format!("<th>{}</th>", heading));
+    }
+    page.push_str("</tr></thead>\n<tbody>\n");
+    for line in &invoice.lines {
+        page.push_str(&html_line(line));
+    }
+    page.push_str("</tbody>\n</table>\n<h3>Totals</h3>\n<table>\n");
+    page.push_str("<thead><tr><th>Asset</th><th>Amount</th><th>Fee</th><th>Net</th><th>Due</th><th>Fiat</th></tr></thead>\n<tbody>\n");
+    for total in &invoice.totals {
+        page.push_str(&format!(
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td></tr>\n",
+            escape(&total.asset),
+            amount(total.amount),
+            amount(total.fee_amount),
+            amount(total.net_amount),
+            amount(total.amount_due),
+            escape(&fiat(&total.fiat_currency, total.fiat_amount)),
+        ));
+    }
+    page.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+    page
+}
+
+fn html_line(line: &InvoiceLine) -> String {
+    let references: Vec<String> = line.tx_references.iter().map(|reference| escape(reference)).collect();
+
+    format!(
+        "<tr><td>{}</td><td>{}</td><td class=\"num\">{} {}</td><td class=\"num\">{} {}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td><td>{}</td><td class=\"refs\">{}</td></tr>\n",
+        date(line.charged_at),
+        escape(&line.description),
+        amount(line.quantity),
+        escape(&line.unit),
+        amount(line.amount),
+        escape(&line.asset),
+        amount(line.fee_amount),
+        amount(line.net_amount),
+        escape(&fiat(&line.fiat_currency, line.fiat_amount)),
+        status(line.status),
+        references.join("<br>"),
+    )
+}
+
+/// Text in the standard encoding of the base fonts, which only reliably
+/// covers ASCII
+fn pdf_text(text: &str, max_chars: usize) -> Vec<u8> {
+    text.chars()
+        .take(max_chars)
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' })
+        .collect()
+}
+
+/// Writes text top to bottom, starting a new page when one fills up
+struct PdfPages {
+    pages: Vec<Content>,
+    y: f32,
+}
+
+impl PdfPages {
+    fn new() -> Self {
+        let mut pages = Self { pages: Vec::new(), y: 0.0 };
+        pages.new_page();
+        pages
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(Content::new());
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    /// Move down `height`, on a new page if this one has no room
+    fn advance(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            self.new_page();
+        }
+        self.y -= height;
+    }
+
+    fn text(&mut self, font: Name, size: f32, x: f32, text: &str, max_chars: usize) {
+        let y = self.y;
+        let content = self.pages.last_mut().unwrap();
+        content.begin_text();
+        content.set_font(font, size);
+        content.next_line(x, y);
+        content.show(Str(&pdf_text(text, max_chars)));
+        content.end_text();
+    }
+
+    fn rule(&mut self) {
+        let y = self.y - 4.0;
+        let content = self.pages.last_mut().unwrap();
+        content.set_line_width(0.5);
+        content.move_to(MARGIN, y);
+        content.line_to(PAGE_WIDTH - MARGIN, y);
+        content.stroke();
+    }
+
+    fn column_headings(&mut self) {
+        self.advance(ROW_HEIGHT * 1.5);
+        for (heading, x) in COLUMNS {
+            self.text(BOLD, 9.0, x, heading, 20);
+        }
+        self.rule();
+    }
+}
+
+/// An A4 PDF using the base Helvetica fonts, so no font is embedded
+pub fn pdf(invoice: &Invoice, issuer: &str) -> Vec<u8> {
+    let mut pages = PdfPages::new();
+
+    pages.advance(18.0);
+    pages.text(BOLD, 18.0, MARGIN, issuer, 60);
+    pages.advance(24.0);
+    pages.text(BOLD, 14.0, MARGIN, &format!("{} {}", title(invoice), invoice.number), 60);
+    pages.advance(6.0);
+    for (label, value) in details(invoice) {
+        pages.advance(ROW_HEIGHT);
+        pages.text(BOLD, 10.0, MARGIN, label, 20);
+        pages.text(REGULAR, 10.0, 135.0, &value, 80);
+    }
+
+    pages.advance(ROW_HEIGHT);
+    pages.column_headings();
+    for line in &invoice.lines {
+        if pages.y - ROW_HEIGHT * 2.0 < MARGIN {
+            pages.new_page();
+            pages.column_headings();
+        }
+        pages.advance(ROW_HEIGHT);
+        let cells = [
+            date(line.charged_at),
+            line.description.clone(),
+            format!("{} {}", amount(line.quantity), line.unit),
+            format!("{} {}", amount(line.amount), line.asset),
+            amount(line.fee_amount),
+            fiat(&line.fiat_currency, line.fiat_amount),
+            status(line.status).to_string(),
+        ];
+        for ((_, x), cell) in COLUMNS.iter().zip(&cells) {
+            pages.text(REGULAR, 8.0, *x, cell, 26);
+        }
+        for reference in &line.tx_references {
+            pages.advance(ROW_HEIGHT * 0.8);
+            pages.text(REGULAR, 7.0, 135.0, &format!("tx {}", reference), 90);
+        }
+    }
+
+    pages.advance(ROW_HEIGHT * 2.0);
+    pages.text(BOLD, 11.0, MARGIN, "Totals", 20);
+    for total in &invoice.totals {
+        pages.advance(ROW_HEIGHT);
+        let summary = format!(
+            "{} {}  (fee {}, net {}, due {})  {}",
+            amount(total.amount),
+            total.asset,
+            amount(total.fee_amount),
+            amount(total.net_amount),
+            amount(total.amount_due),
+            fiat(&total.fiat_currency, total.fiat_amount),
+        );
+        pages.text(REGULAR, 10.0, MARGIN, &summary, 100);
+    }
+
+    // Catalog, page tree and fonts first, then each page and its contents
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let regular_id = Ref::new(3);
+    let bold_id = Ref::new(4);
+    let page_ids: Vec<Ref> = (0..pages.pages.len()).map(|n| Ref::new(5 + 2 * n as i32)).collect();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id).kids(page_ids.iter().copied()).count(page_ids.len() as i32);
+    pdf.type1_font(regular_id).base_font(Name(b"Helvetica"));
+    pdf.type1_font(bold_id).base_font(Name(b"Helvetica-Bold"));
+
+    for (page_id, content) in page_ids.iter().zip(pages.pages) {
+        let content_id = Ref::new(page_id.get() + 1);
+        let mut page = pdf.page(*page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT));
+        page.parent(page_tree_id);
+        page.contents(content_id);
+        page.resources().fonts().pair(REGULAR, regular_id).pair(BOLD, bold_id);
+        drop(page);
+        pdf.stream(content_id, &content.finish());
+    }
+
+    pdf.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invoice::{InvoiceKind, InvoiceTotal};
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    fn invoice(lines: usize) -> Invoice {
+        let at = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let line = InvoiceLine {
+            description: "Streaming, session <b>ABC123</b>".to_string(),
+            charged_at: at,
+            quantity: Decimal::ONE,
+            unit: "minute".to_string(),
+            asset: "ZEC".to_string(),
+            amount: Decimal::new(10_000_000, 8),
+            fee_amount: Decimal::ZERO,
+            net_amount: Decimal::new(10_000_000, 8),
+            fiat_currency: None,
+            fiat_amount: None,
+            status: LineStatus::Paid,
+            tx_references: vec!["0xabc".to_string()],
+        };
+
+        Invoice {
+            id: Uuid::new_v4(),
+            number: "INV-2024-000001".to_string(),
+            kind: InvoiceKind::Session,
+            document: DocumentType::Receipt,
+            vendor_id: "vendor-1".to_string(),
+            user_wallet_address: "0xuser".to_string(),
+            session_id: None,
+            session_code: Some("ABC123".to_string()),
+            period_start: at,
+            period_end: at,
+            issued_at: at,
+            lines: vec![line; lines],
+            totals: vec![InvoiceTotal {
+                asset: "ZEC".to_string(),
+                amount: Decimal::ONE,
+                fee_amount: Decimal::ZERO,
+                net_amount: Decimal::ONE,
+                amount_due: Decimal::ZERO,
+                fiat_currency: None,
+                fiat_amount: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn html_escapes_text_and_trims_amounts() {
+        let page = html(&invoice(1), "Acme & Co");
+
+        assert!(page.contains("<h1>Acme &amp; Co</h1>"));
+        assert!(page.contains("Receipt INV-2024-000001"));
+        assert!(page.contains("session &lt;b&gt;ABC123&lt;/b&gt;"));
+        assert!(page.contains("0.1 ZEC"));
+    }
+
+    #[test]
+    fn pdf_starts_new_pages_when_lines_overflow() {
+        let short = pdf(&invoice(1), "PayGo");
+        let long = pdf(&invoice(120), "PayGo");
+
+        assert!(short.starts_with(b"%PDF-"));
+        let pages = |document: &[u8]| document.windows(9).filter(|w| w == b"/MediaBox").count();
+        assert_eq!(pages(&short), 1);
+        assert!(pages(&long) > 1);
+    }
+
+    #[test]
+    fn pdf_text_replaces_characters_the_base_fonts_lack() {
+        assert_eq!(pdf_text("Zürich (CH)", 40), b"Z?rich (CH)".to_vec());
+        assert_eq!(pdf_text("abcdef", 3), b"abc".to_vec());
+    }
+}
@@ -0,0 +1,318 @@
+// src/leader.rs
+use redis::{aio::ConnectionManager, Client as RedisClient};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::cache;
+use crate::config::Config;
+use crate::error::BillingError;
+
+/// Extend the lease only while we still hold it
+const RENEW_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// Give the lease up only if it is still ours
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// Lease-based leader election over a Redis key. One instance holds the
+/// lease and renews it every `leader_renew_interval_seconds`; if it dies,
+/// the key expires after `leader_lease_ttl_seconds` and the next instance
+/// to try takes over.
+///
+/// Leadership is only assumed until the lease could have expired since the
+/// last successful renewal, so a leader cut off from Redis stops acting
+/// before anyone else can start.
+pub struct LeaderElection {
+    redis_client: RedisClient,
+    connection: OnceCell<ConnectionManager>,
+    key: String,
+    instance_id: String,
+    lease_ttl: Duration,
+    valid_until: Mutex<Option<Instant>>,
+}
+
+impl LeaderElection {
+    pub fn new(redis_client: RedisClient, name: &str, instance_id: &str, config: &Config) -> Self {
+        Self {
+            redis_client,
+            connection: OnceCell::new(),
+            key: format!("paygo:leader:{}", name),
+            instance_id: instance_id.to_string(),
+            lease_ttl: Duration::from_secs(config.leader_lease_ttl_seconds),
+            valid_until: Mutex::new(None),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.valid_until
+            .lock()
+            .ok()
+            .and_then(|valid_until| *valid_until)
+            .is_some_and(|valid_until| Instant::now() < valid_until)
+    }
+
+    /// Renew the lease if we hold it, otherwise take it if it is free and
+    /// `may_acquire`. Returns whether we are leader afterwards.
+    pub async fn refresh(&self, may_acquire: bool) -> bool {
+        let was_leader = self.is_leader();
+        let started = Instant::now();
+
+        match self.try_acquire_or_renew(may_acquire).await {
+            Ok(true) => {
+                self.set_valid_until(Some(started + self.lease_ttl));
+                if !was_leader {
+                    info!("Acquired leadership of {}", self.key);
+                }
+            }
+            Ok(false) => {
+                self.set_valid_until(None);
+                if was_leader {
+                    warn!("Lost leadership of {}", self.key);
+                }
+            }
+            // Keep the current lease until it runs out; the next renewal may succeed
+            Err(e) => warn!("Leader election for {} failed: {:?}", self.key, e),
+        }
+
+        self.is_leader()
+    }
+
+    /// Hand the lease over straight away on shutdown instead of waiting for
+    /// it to expire
+    pub async fn release(&self) {
+        if !self.is_leader() {
+            return;
+        }
+        self.set_valid_until(None);
+
+        let result = async {
+            let mut conn = self.connection().await?;
+            redis::Script::new(RELEASE_SCRIPT)
+                .key(&self.key)
+                .arg(&self.instance_id)
+                .invoke_async::<_, i64>(&mut conn)
+                .await
+                .map_err(|e| BillingError::Cache(e.to_string()))
+        }
+        .await;
+
+        match result {
+            Ok(_) => info!("Released leadership of {}", self.key),
+            Err(e) => warn!("Failed to release leadership of {}: {:?}", self.key, e),
+        }
+    }
+
+    /// Renew first: after a failed renewal the key may still be ours even
+    /// though we stopped acting as leader
+    async fn try_acquire_or_renew(&self, may_acquire: bool) -> Result<bool, BillingError> {
+        let mut conn = self.connection().await?;
+        let ttl_ms = self.lease_ttl.as_millis() as u64;
+
+        let renewed: i64 = redis::Script::new(RENEW_SCRIPT)
+            .key(&self.key)
+            .arg(&self.instance_id)
+            .arg(ttl_ms)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| BillingError::Cache(e.to_string()))?;
+
+        if renewed == 1 {
+            return Ok(true);
+        }
+        if !may_acquire {
+            return Ok(false);
+        }
+
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(&self.key)
+            .arg(&self.instance_id)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| BillingError::Cache(e.to_string()))?;
+
+        Ok(acquired.is_some())
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager, BillingError> {
+        Ok(self
+            .connection
+            .get_or_try_init(|| cache::get_connection(&self.redis_client))
+            .await?
+            .clone())
+    }
+
+    fn set_valid_until(&self, valid_until: Option<Instant>) {
+        if let Ok(mut current) = self.valid_until.lock() {
+            *current = valid_until;
+        }
+    }
+}
+
+/// Session shards of the billing scheduler, each behind its own lease, so
+/// instances split the shards between them and pick up a dead instance's
+/// shards when its leases expire. With one shard this is plain leader
+/// election.
+pub struct ShardLeases {
+    shards: Vec<LeaderElection>,
+    max_owned: usize,
+    renew_interval: Duration,
+}
+
+impl ShardLeases {
+    pub fn new(redis_client: RedisClient, name: &str, config: &Config) -> Self {
+        let instance_id = Uuid::new_v4().to_string();
+        let shard_count = config.billing_shards;
+        let shards = (0..shard_count)
+            .map(|shard| {
+                LeaderElection::new(
+                    redis_client.clone(),
+                    &shard_lease_name(name, shard, shard_count),
+                    &instance_id,
+                    config,
+                )
+            })
+            .collect();
+
+        let max_owned = shard_cap(shard_count, config.billing_max_shards_per_instance);
+
+        info!("Contending for {} {} shards as {}", shard_count, name, instance_id);
+
+        Self {
+            shards,
+            max_owned,
+            renew_interval: Duration::from_secs(config.leader_renew_interval_seconds),
+        }
+    }
+
+    pub fn owned_shards(&self) -> Vec<u32> {
+        (0..self.shards.len() as u32)
+            .filter(|&shard| self.shards[shard as usize].is_leader())
+            .collect()
+    }
+
+    /// Renew owned shards and claim free ones, up to `max_owned`, until the
+    /// process exits
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(self.renew_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let mut owned = self.owned_shards().len();
+            for shard in &self.shards {
+                let was_leader = shard.is_leader();
+                let is_leader = shard.refresh(may_claim(was_leader, owned, self.max_owned)).await;
+                match (was_leader, is_leader) {
+                    (false, true) => owned += 1,
+                    (true, false) => owned -= 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub async fn release(&self) {
+        for shard in &self.shards {
+            shard.release().await;
+        }
+    }
+}
+
+/// Lease name for one shard. It includes the shard count, so instances
+/// configured with different counts never share a lease.
+fn shard_lease_name(name: &str, shard: u32, shard_count: u32) -> String {
+    format!("{}:{}-of-{}", name, shard, shard_count)
+}
+
+/// Most shards one instance may hold; 0 means no cap
+fn shard_cap(shard_count: u32, max_per_instance: u32) -> usize {
+    match max_per_instance {
+        0 => shard_count as usize,
+        max => max as usize,
+    }
+}
+
+/// Owned shards are always renewed; free ones are claimed only below the cap
+fn may_claim(was_leader: bool, owned: usize, max_owned: usize) -> bool {
+    was_leader || owned < max_owned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn election(name: &str, valid_until: Option<Instant>) -> LeaderElection {
+        LeaderElection {
+            redis_client: RedisClient::open("redis://127.0.0.1/").unwrap(),
+            connection: OnceCell::new(),
+            key: format!("paygo:leader:{}", name),
+            instance_id: "instance-1".to_string(),
+            lease_ttl: Duration::from_secs(30),
+            valid_until: Mutex::new(valid_until),
+        }
+    }
+
+    #[test]
+    fn leadership_lasts_until_the_lease_could_have_expired() {
+        let now = Instant::now();
+
+        assert!(election("billing", Some(now + Duration::from_secs(10))).is_leader());
+        assert!(!election("billing", Some(now)).is_leader());
+        assert!(!election("billing", None).is_leader());
+    }
+
+    #[test]
+    fn owned_shards_are_the_ones_with_a_live_lease() {
+        let live = Some(Instant::now() + Duration::from_secs(10));
+        let leases = ShardLeases {
+            shards: vec![
+                election("billing:0-of-4", live),
+                election("billing:1-of-4", None),
+                election("billing:2-of-4", Some(Instant::now())),
+                election("billing:3-of-4", live),
+            ],
+            max_owned: 4,
+            renew_interval: Duration::from_secs(10),
+        };
+
+        assert_eq!(leases.owned_shards(), vec![0, 3]);
+    }
+
+    #[test]
+    fn shard_leases_are_named_by_index_and_count() {
+        assert_eq!(shard_lease_name("billing", 0, 1), "billing:0-of-1");
+        assert_eq!(shard_lease_name("billing", 3, 8), "billing:3-of-8");
+        assert_ne!(shard_lease_name("billing", 1, 4), shard_lease_name("billing", 1, 8));
+    }
+
+    #[test]
+    fn a_zero_cap_lets_one_instance_own_every_shard() {
+        assert_eq!(shard_cap(8, 0), 8);
+        assert_eq!(shard_cap(8, 3), 3);
+    }
+
+    #[test]
+    fn free_shards_are_claimed_only_below_the_cap() {
+        assert!(may_claim(false, 2, 3));
+        assert!(!may_claim(false, 3, 3));
+        // Shards already held are renewed even over the cap
+        assert!(may_claim(true, 3, 3));
+        assert!(may_claim(true, 5, 3));
+    }
+}
@@ -1,7 +1,7 @@
 // src/main.rs
-use actix_web::{web, App, HttpServer, middleware};
+use actix_web::{middleware::{Condition, Logger}, web, App, HttpServer};
 use tokio_cron_scheduler::JobScheduler;
-use tracing::{info, error};
+use tracing::{info, warn, error};
 use std::sync::Arc;
 
 mod models;
@@ -14,10 +14,60 @@ mod config;
 mod error;
 mod zcash;
 mod validation;
+mod nonce;
+mod settlement;
+mod token;
+mod permit;
+mod permit_api;
+mod rpc;
+mod indexer;
+mod chains;
+mod signer;
+mod auth;
+mod ownership;
+mod ownership_api;
+mod openapi;
+mod events;
+mod events_api;
+mod webhooks;
+mod webhooks_api;
+mod outbox;
+mod middleware;
+mod leader;
+mod interval;
+mod pricing;
+mod metrics;
+mod metering;
+mod metering_api;
+mod oracle;
+mod fees;
+mod invoice;
+mod invoice_api;
+mod invoice_render;
+mod export;
+mod statement;
+mod statement_api;
+mod charging;
+#[cfg(test)]
+mod test_support;
 
 use crate::config::Config;
-use crate::zcash::{ZcashService, configure_zcash_routes, IntegratedBillingEngine};
-use std::time::Duration;
+use crate::zcash::{ZcashService, IntegratedBillingEngine};
+use crate::permit::PermitService;
+use crate::chains::ChainRegistry;
+use crate::charging::SessionCharger;
+use crate::auth::Authenticator;
+use crate::ownership::WalletOwnershipService;
+use crate::events::EventHub;
+use crate::outbox::OutboxRelay;
+use crate::leader::ShardLeases;
+use crate::metrics::SchedulerMetrics;
+use crate::metering::MeteringService;
+use crate::invoice::InvoiceService;
+use crate::statement::StatementService;
+use crate::oracle::PriceService;
+use crate::webhooks::WebhookService;
+use crate::middleware::{InMemoryRateLimiter, RateLimit, RateLimitMiddleware, RedisRateLimiter};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -31,42 +81,92 @@ async fn main() -> std::io::Result<()> {
     let config = Config::from_env().expect("Failed to load configuration");
     
     // Initialize database pool
-    let db_pool = db::create_pool(&config.database_url)
+    let db_pool = db::create_pool(config.database_url.expose())
         .await
         .expect("Failed to create database pool");
 
     // Initialize Redis cache
-    let redis_client = cache::create_redis_client(&config.redis_url)
+    let redis_client = cache::create_redis_client(config.redis_url.expose())
         .expect("Failed to create Redis client");
 
-    // Initialize blockchain client (for fallback) - optional
-    let blockchain_client = match blockchain::BlockchainClient::new(&config.rpc_url, &config.contract_address).await {
-        Ok(client) => Arc::new(client),
-        Err(e) => {
-            tracing::warn!("Failed to initialize blockchain client (continuing without it): {}", e);
-            // Create a disabled client - methods will handle being unavailable
-            Arc::new(blockchain::BlockchainClient::disabled())
-        }
-    };
+    // Attribute rows from before multi-chain support to the default chain
+    db::assign_default_chain(&db_pool, config.default_chain_id as i64)
+        .await
+        .expect("Failed to assign default chain");
+
+    // Initialize a blockchain client per contract deployment (for fallback) -
+    // deployments that fail to connect are disabled
+    let chains = Arc::new(
+        ChainRegistry::connect(&config)
+            .await
+            .expect("Failed to load contract deployments")
+    );
+
+    // Verifies caller JWTs and API keys on every authenticated route
+    let authenticator = Arc::new(Authenticator::new(&config.auth));
+
+    // Initialize Ethereum spending permit service
+    let permit_service = Arc::new(PermitService::new(db_pool.clone(), chains.clone()));
 
     // Initialize Zcash service
     let zcash_service = Arc::new(
-        ZcashService::new(
-            config.zcash.rpc_url.clone(),
-            config.zcash.rpc_user.clone(),
-            config.zcash.rpc_password.clone(),
-            config.zcash.service_wallet_address.clone(),
-            db_pool.clone(),
-        )
+        ZcashService::new(&config.zcash, db_pool.clone())
+    );
+
+    // Wallet ownership challenges, signed into proofs by the authenticator
+    let ownership_service = Arc::new(WalletOwnershipService::new(
+        db_pool.clone(),
+        zcash_service.clone(),
+        authenticator.clone(),
+        &config.auth,
+        config.default_chain_id,
+    ));
+
+    // Signed webhook deliveries, retried with backoff and dead-lettered
+    let webhook_service = Arc::new(WebhookService::new(db_pool.clone(), config.webhooks.clone()));
+    let webhook_service_clone = webhook_service.clone();
+    tokio::spawn(async move {
+        webhook_service_clone.run().await;
+    });
+
+    // Events are written to the outbox with the state change they describe;
+    // the relay queues webhooks and forwards them to Redis, where each
+    // instance's hub picks them up for its Server-Sent Events clients
+    let outbox_relay = Arc::new(OutboxRelay::new(db_pool.clone(), redis_client.clone(), config.clone()));
+    let outbox_relay_clone = outbox_relay.clone();
+    tokio::spawn(async move {
+        outbox_relay_clone.run().await;
+    });
+
+    let event_hub = Arc::new(EventHub::new(redis_client.clone()));
+    let event_hub_clone = event_hub.clone();
+    tokio::spawn(async move {
+        event_hub_clone.run().await;
+    });
+
+    // Exchange rates for vendors pricing in fiat
+    let price_service = Arc::new(
+        PriceService::from_config(&config.price_oracle).expect("Failed to initialize price oracle")
     );
 
+    // Vendor lookups and on-chain charges, shared by both billing engines
+    let session_charger = Arc::new(SessionCharger::new(
+        db_pool.clone(),
+        chains.clone(),
+        permit_service.clone(),
+        price_service.clone(),
+        config.clone(),
+    ));
+
     // Initialize integrated billing engine (with Zcash support)
     let integrated_billing = Arc::new(
         IntegratedBillingEngine::new(
             db_pool.clone(),
             redis_client.clone(),
-            blockchain_client.clone(),
+            chains.clone(),
             zcash_service.clone(),
+            session_charger.clone(),
+            price_service.clone(),
             config.clone(),
         )
     );
@@ -76,51 +176,187 @@ async fn main() -> std::io::Result<()> {
         billing::BillingEngine::new(
             db_pool.clone(),
             redis_client.clone(),
-            blockchain_client.clone(),
-            config.clone(),
+            session_charger.clone(),
         )
     );
 
+    // Every instance runs the scheduler and bills the session shards whose
+    // leases it holds
+    let scheduler_leases = Arc::new(ShardLeases::new(redis_client.clone(), "billing-scheduler", &config));
+    let scheduler_leases_clone = scheduler_leases.clone();
+    tokio::spawn(async move {
+        scheduler_leases_clone.run().await;
+    });
+    let scheduler_metrics = Arc::new(SchedulerMetrics::new(config.billing_shards));
+
     // Start background billing processor
     let integrated_billing_clone = integrated_billing.clone();
+    let scheduler_leases_clone = scheduler_leases.clone();
+    let scheduler_metrics_clone = scheduler_metrics.clone();
+    tokio::spawn(async move {
+        start_billing_scheduler(integrated_billing_clone, scheduler_leases_clone, scheduler_metrics_clone).await;
+    });
+
+    // Bill metered usage once each period has closed
+    let metering_service = Arc::new(MeteringService::new(
+        db_pool.clone(),
+        zcash_service.clone(),
+        session_charger.clone(),
+        price_service.clone(),
+        config.clone(),
+    ));
+    let metering_service_clone = metering_service.clone();
+    let scheduler_leases_clone = scheduler_leases.clone();
+    tokio::spawn(async move {
+        start_usage_scheduler(metering_service_clone, scheduler_leases_clone).await;
+    });
+
+    let invoice_service = Arc::new(InvoiceService::new(db_pool.clone(), config.clone()));
+
+    // Write last month's accounting exports once it has closed
+    let statement_service = Arc::new(StatementService::new(db_pool.clone(), config.clone()));
+    if config.export_dir.is_some() {
+        let statement_service_clone = statement_service.clone();
+        let scheduler_leases_clone = scheduler_leases.clone();
+        tokio::spawn(async move {
+            start_export_scheduler(statement_service_clone, scheduler_leases_clone).await;
+        });
+    }
+
+    // Start batch settlement of accrued on-chain charges
+    if config.billing_batch_mode {
+        let settler = Arc::new(settlement::BatchSettler::new(
+            db_pool.clone(),
+            chains.clone(),
+            config.clone(),
+        ));
+        let interval = config.batch_settlement_interval_seconds;
+        let scheduler_leases_clone = scheduler_leases.clone();
+        tokio::spawn(async move {
+            start_settlement_scheduler(settler, scheduler_leases_clone, interval).await;
+        });
+    }
+
+    // Index billing contract events, backfilling from the stored cursor
+    if config.indexer_enabled {
+        for deployment in &config.deployments {
+            let client = chains.client(deployment.chain_id).expect("Deployment without a client");
+            let indexer = indexer::ContractIndexer::new(
+                db_pool.clone(),
+                client,
+                deployment.start_block,
+                config.clone(),
+            );
+            tokio::spawn(async move {
+                indexer.run().await;
+            });
+        }
+    }
+
+    // Keep probing RPC endpoints so the Ethereum rail recovers from outages
+    let chains_clone = chains.clone();
+    let rpc_health_interval = config.rpc_health_check_interval_seconds;
     tokio::spawn(async move {
-        start_billing_scheduler(integrated_billing_clone).await;
+        start_rpc_health_checker(chains_clone, rpc_health_interval).await;
     });
 
     // Start background permission expiry checker
     let zcash_service_clone = zcash_service.clone();
+    let permit_service_clone = permit_service.clone();
+    let ownership_service_clone = ownership_service.clone();
     tokio::spawn(async move {
-        start_permission_checker(zcash_service_clone).await;
+        start_permission_checker(
+            zcash_service_clone,
+            permit_service_clone,
+            ownership_service_clone,
+            outbox_relay,
+        )
+        .await;
     });
 
+    // Shared by every worker so in-memory buckets aren't split per thread
+    let rate_limiter: Arc<dyn RateLimit + Send + Sync> = match config.rate_limits.backend.as_str() {
+        "memory" => Arc::new(InMemoryRateLimiter::new()),
+        _ => Arc::new(RedisRateLimiter::new(redis_client.clone())),
+    };
+    let rate_limit_config = config.rate_limits.clone();
+
     info!("Starting HTTP server on {}:{}", config.host, config.port);
 
     // Start HTTP server
     HttpServer::new(move || {
         App::new()
-            .wrap(middleware::Logger::default())
+            .wrap(Condition::new(
+                rate_limit_config.enabled,
+                RateLimitMiddleware::new(rate_limiter.clone(), &rate_limit_config),
+            ))
+            .wrap(Logger::default())
             .app_data(web::Data::new(integrated_billing.clone()))
             .app_data(web::Data::new(legacy_billing.clone()))
             .app_data(web::Data::new(zcash_service.clone()))
+            .app_data(web::Data::new(permit_service.clone()))
             .app_data(web::Data::new(db_pool.clone()))
+            .app_data(web::Data::new(authenticator.clone()))
+            .app_data(web::Data::new(ownership_service.clone()))
+            .app_data(web::Data::new(event_hub.clone()))
+            .app_data(web::Data::new(webhook_service.clone()))
+            .app_data(web::Data::new(scheduler_metrics.clone()))
+            .app_data(web::Data::new(metering_service.clone()))
+            .app_data(web::Data::new(invoice_service.clone()))
+            .app_data(web::Data::new(statement_service.clone()))
             .configure(api::configure_routes)
     })
     .bind((config.host.as_str(), config.port))?
     .run()
-    .await
+    .await?;
+
+    // Let other instances take over our shards without waiting out the leases
+    scheduler_leases.release().await;
+
+    Ok(())
 }
 
-async fn start_billing_scheduler(billing_engine: Arc<IntegratedBillingEngine>) {
+async fn start_billing_scheduler(
+    billing_engine: Arc<IntegratedBillingEngine>,
+    leases: Arc<ShardLeases>,
+    metrics: Arc<SchedulerMetrics>,
+) {
     let scheduler = JobScheduler::new().await.expect("Failed to create scheduler");
+    let tick_running = Arc::new(tokio::sync::Mutex::new(()));
 
     // Process active sessions every minute
     scheduler
         .add(
             tokio_cron_scheduler::Job::new_async("0 * * * * *", move |_uuid, _l| {
                 let engine = billing_engine.clone();
+                let leases = leases.clone();
+                let metrics = metrics.clone();
+                let tick_running = tick_running.clone();
                 Box::pin(async move {
-                    if let Err(e) = engine.process_active_sessions_with_permissions().await {
-                        error!("Error processing active sessions: {:?}", e);
+                    let shards = leases.owned_shards();
+                    metrics.record_owned_shards(&shards);
+                    if shards.is_empty() {
+                        return;
+                    }
+                    // An overrunning tick keeps going; starting another would
+                    // only contend for the same sessions
+                    let Ok(_running) = tick_running.try_lock() else {
+                        warn!("Skipping billing tick: the previous one is still running");
+                        metrics.record_skipped_tick();
+                        return;
+                    };
+
+                    let started_at = chrono::Utc::now();
+                    let started = std::time::Instant::now();
+                    match engine.process_active_sessions_with_permissions(&shards).await {
+                        Ok(report) => metrics.record_tick(started_at, started.elapsed(), &report),
+                        Err(e) => error!("Error processing active sessions: {:?}", e),
+                    }
+                    // Stuck transactions aren't sharded; shard 0's owner handles them
+                    if shards.contains(&0) {
+                        if let Err(e) = engine.process_stuck_transactions().await {
+                            error!("Error replacing stuck transactions: {:?}", e);
+                        }
                     }
                 })
             })
@@ -139,7 +375,117 @@ async fn start_billing_scheduler(billing_engine: Arc<IntegratedBillingEngine>) {
     }
 }
 
-async fn start_permission_checker(zcash_service: Arc<ZcashService>) {
+async fn start_usage_scheduler(metering: Arc<MeteringService>, leases: Arc<ShardLeases>) {
+    let scheduler = JobScheduler::new().await.expect("Failed to create usage scheduler");
+
+    // Checked every minute so a closed period is billed promptly; usage isn't
+    // sharded, so shard 0's owner bills all of it
+    scheduler
+        .add(
+            tokio_cron_scheduler::Job::new_async("30 * * * * *", move |_uuid, _l| {
+                let metering = metering.clone();
+                let leases = leases.clone();
+                Box::pin(async move {
+                    if !leases.owned_shards().contains(&0) {
+                        return;
+                    }
+                    if let Err(e) = metering.bill_usage().await {
+                        error!("Error billing metered usage: {:?}", e);
+                    }
+                })
+            })
+            .expect("Failed to create usage job"),
+        )
+        .await
+        .expect("Failed to add usage job");
+
+    scheduler.start().await.expect("Failed to start usage scheduler");
+
+    info!("Usage billing scheduler started");
+}
+
+async fn start_export_scheduler(statements: Arc<StatementService>, leases: Arc<ShardLeases>) {
+    let scheduler = JobScheduler::new().await.expect("Failed to create export scheduler");
+
+    // Daily, so a month missed while no instance held shard 0 is still
+    // written; a month already marked complete is skipped
+    scheduler
+        .add(
+            tokio_cron_scheduler::Job::new_async("0 15 0 * * *", move |_uuid, _l| {
+                let statements = statements.clone();
+                let leases = leases.clone();
+                Box::pin(async move {
+                    if !leases.owned_shards().contains(&0) {
+                        return;
+                    }
+                    if let Err(e) = statements.export_previous_month().await {
+                        error!("Error writing monthly exports: {:?}", e);
+                    }
+                })
+            })
+            .expect("Failed to create export job"),
+        )
+        .await
+        .expect("Failed to add export job");
+
+    scheduler.start().await.expect("Failed to start export scheduler");
+
+    info!("Monthly export scheduler started");
+}
+
+async fn start_rpc_health_checker(chains: Arc<ChainRegistry>, interval_seconds: u64) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+
+    info!("RPC health checker started");
+
+    loop {
+        interval.tick().await;
+        chains.check_health().await;
+    }
+}
+
+async fn start_settlement_scheduler(
+    settler: Arc<settlement::BatchSettler>,
+    leases: Arc<ShardLeases>,
+    interval_seconds: u64,
+) {
+    let scheduler = JobScheduler::new().await.expect("Failed to create settlement scheduler");
+
+    // Settle accrued charges once per batch period; batches span every
+    // shard's sessions, so shard 0's owner settles them all
+    scheduler
+        .add(
+            tokio_cron_scheduler::Job::new_repeated_async(
+                std::time::Duration::from_secs(interval_seconds),
+                move |_uuid, _l| {
+                    let settler = settler.clone();
+                    let leases = leases.clone();
+                    Box::pin(async move {
+                        if !leases.owned_shards().contains(&0) {
+                            return;
+                        }
+                        if let Err(e) = settler.settle_accrued_charges().await {
+                            error!("Error settling accrued charges: {:?}", e);
+                        }
+                    })
+                },
+            )
+            .expect("Failed to create settlement job"),
+        )
+        .await
+        .expect("Failed to add settlement job");
+
+    scheduler.start().await.expect("Failed to start settlement scheduler");
+    
+    info!("Batch settlement scheduler started");
+}
+
+async fn start_permission_checker(
+    zcash_service: Arc<ZcashService>,
+    permit_service: Arc<PermitService>,
+    ownership_service: Arc<WalletOwnershipService>,
+    outbox_relay: Arc<OutboxRelay>,
+) {
     let scheduler = JobScheduler::new().await.expect("Failed to create permission checker");
 
     // Check expired permissions every hour
@@ -147,11 +493,22 @@ async fn start_permission_checker(zcash_service: Arc<ZcashService>) {
         .add(
             tokio_cron_scheduler::Job::new_async("0 0 * * * *", move |_uuid, _l| {
                 let service = zcash_service.clone();
+                let permits = permit_service.clone();
+                let ownership = ownership_service.clone();
+                let outbox = outbox_relay.clone();
                 Box::pin(async move {
-                    if let Err(e) = service.check_expired_permissions().await {
-                        error!("Error checking expired permissions: {:?}", e);
-                    } else {
-                        info!("Successfully checked and updated expired permissions");
+                    match service.check_expired_permissions().await {
+                        Ok(_) => info!("Successfully checked and updated expired permissions"),
+                        Err(e) => error!("Error checking expired permissions: {:?}", e),
+                    }
+                    if let Err(e) = permits.check_expired_permits().await {
+                        error!("Error checking expired Ethereum permits: {:?}", e);
+                    }
+                    if let Err(e) = ownership.purge_expired_challenges().await {
+                        error!("Error purging expired wallet challenges: {:?}", e);
+                    }
+                    if let Err(e) = outbox.purge_published().await {
+                        error!("Error purging published outbox events: {:?}", e);
                     }
                 })
             })
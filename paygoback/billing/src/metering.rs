@@ -0,0 +1,472 @@
+// src/metering.rs
+use chrono::{DateTime, DurationRound, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::auth::{Access, Principal};
+use crate::charging::SessionCharger;
+use crate::config::Config;
+use crate::db;
+use crate::error::BillingError;
+use crate::interval;
+use crate::models::SessionStatus;
+use crate::oracle::PriceService;
+use crate::outbox;
+use crate::webhooks::WebhookEvent;
+use crate::zcash::zcash_service::{PermissionStatus, ZcashService};
+
+/// A unit of non-streaming usage reported by a vendor: API calls, GB
+/// transferred, consultation minutes. Exactly one of `session_code` and
+/// `permission_id` names what it is billed against.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RecordUsageRequest {
+    pub event_id: String, // the vendor's id for the event; reposting it is a no-op
+    pub meter: String,
+    pub quantity: Decimal,
+    pub session_code: Option<String>,
+    pub permission_id: Option<Uuid>,
+    pub vendor_id: Option<String>, // admins only; defaults to the caller's vendor
+    pub occurred_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct UsageEvent {
+    pub id: Uuid,
+    pub vendor_id: String,
+    pub event_id: String,
+    pub permission_id: Uuid,
+    pub session_id: Option<Uuid>,
+    pub meter: String,
+    pub unit: String,
+    pub quantity: Decimal,
+    pub unit_price: Decimal, // from the pricing plan when the event was recorded
+    pub currency: Option<String>, // the plan's fiat currency; None when priced in ZEC
+    pub occurred_at: DateTime<Utc>,
+    pub charge_id: Option<Uuid>, // set once billed
+    pub created_at: DateTime<Utc>,
+}
+
+/// One billing period's usage of a meter by a permission, deducted in one go
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct UsageCharge {
+    pub id: Uuid,
+    pub permission_id: Uuid,
+    pub vendor_id: String,
+    pub meter: String,
+    pub quantity: Decimal,
+    pub amount: Decimal, // in ZEC
+    pub fee_amount: Decimal, // platform fee out of the amount
+    pub net_amount: Decimal,
+    pub fiat_currency: Option<String>,
+    pub fiat_amount: Option<Decimal>,
+    pub exchange_rate: Option<Decimal>, // fiat per ZEC the charge was converted at
+    pub status: String, // "charged", or "failed" if the permission couldn't cover it
+    pub period_end: DateTime<Utc>, // covers usage recorded before this
+    pub created_at: DateTime<Utc>,
+}
+
+/// Unbilled usage of one meter against one permission
+#[derive(Debug, FromRow)]
+struct PendingUsage {
+    permission_id: Uuid,
+    vendor_id: String,
+    meter: String,
+    currency: Option<String>,
+}
+
+/// Records metered usage and bills it from Zcash spending permissions once
+/// per `usage_billing_interval_seconds`, one charge per permission, vendor
+/// and meter.
+pub struct MeteringService {
+    db_pool: PgPool,
+    zcash_service: Arc<ZcashService>,
+    charger: Arc<SessionCharger>,
+    prices: Arc<PriceService>,
+    config: Config,
+}
+
+impl MeteringService {
+    pub fn new(
+        db_pool: PgPool,
+        zcash_service: Arc<ZcashService>,
+        charger: Arc<SessionCharger>,
+        prices: Arc<PriceService>,
+        config: Config,
+    ) -> Self {
+        Self {
+            db_pool,
+            zcash_service,
+            charger,
+            prices,
+            config,
+        }
+    }
+
+    /// Record a usage event priced by the session's plan, or the vendor's
+    /// current plan for usage against a permission. Returns the event and
+    /// whether it was new.
+    pub async fn record_usage(
+        &self,
+        principal: &Principal,
+        request: &RecordUsageRequest,
+    ) -> Result<(UsageEvent, bool), BillingError> {
+        if request.event_id.is_empty() || request.event_id.len() > 255 {
+            return Err(BillingError::Validation("event_id must be 1 to 255 characters".to_string()));
+        }
+        if request.quantity <= Decimal::ZERO {
+            return Err(BillingError::Validation("Usage quantity must be positive".to_string()));
+        }
+
+        let (vendor_id, permission_id, session_id, plan) = match (&request.session_code, request.permission_id) {
+            (Some(session_code), None) => {
+                let session = db::get_session_by_code(&self.db_pool, session_code).await?;
+                principal.authorize_vendor(Some(&session.vendor_id), Access::Write)?;
+                if session.status == SessionStatus::Failed {
+                    return Err(BillingError::Conflict(format!("Session {} has failed", session_code)));
+                }
+                let permission_id = db::get_session_permission_id(&self.db_pool, session.id)
+                    .await?
+                    .ok_or_else(|| {
+                        BillingError::Conflict(
+                            "Metered usage is billed from Zcash spending permissions; the session bills on-chain".to_string(),
+                        )
+                    })?;
+                (session.vendor_id, permission_id, Some(session.id), session.pricing_plan.0)
+            }
+            (None, Some(permission_id)) => {
+                let vendor_id = request
+                    .vendor_id
+                    .clone()
+                    .or_else(|| principal.vendor_id.clone())
+                    .ok_or_else(|| BillingError::Validation("vendor_id is required".to_string()))?;
+                principal.authorize_vendor(Some(&vendor_id), Access::Write)?;
+
+                // Permissions aren't tied to a vendor: the holder consents to
+                // a vendor by streaming from it under the permission
+                let permission = self.zcash_service.get_permission(permission_id).await?;
+                if principal.authorize_wallet(&permission.user_wallet_address, Access::Write).is_err()
+                    && !self.has_vendor_session(permission_id, &vendor_id).await?
+                {
+                    return Err(BillingError::Forbidden(format!(
+                        "Permission {} has no session with vendor {}",
+                        permission_id, vendor_id
+                    )));
+                }
+                if permission.status != PermissionStatus::Active {
+                    return Err(BillingError::Conflict("Permission is not active".to_string()));
+                }
+
+                let plan = self.charger.vendor_pricing(&vendor_id).await?;
+                (vendor_id, permission_id, None, plan)
+            }
+            _ => {
+                return Err(BillingError::Validation(
+                    "Exactly one of session_code and permission_id is required".to_string(),
+                ))
+            }
+        };
+
+        let price = plan.meter(&request.meter).ok_or_else(|| {
+            BillingError::Validation(format!("Vendor {} has no price for meter {}", vendor_id, request.meter))
+        })?;
+
+        // A repost of a known event returns the original untouched
+        let inserted = sqlx::query_as::<_, UsageEvent>(
+            r#"
+            INSERT INTO usage_events
+            (id, vendor_id, event_id, permission_id, session_id, meter, unit, quantity, unit_price, currency, occurred_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (vendor_id, event_id) DO NOTHING
+            RETURNING id, vendor_id, event_id, permission_id, session_id, meter, unit, quantity,
+                      unit_price, currency, occurred_at, charge_id, created_at
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(&vendor_id)
+        .bind(&request.event_id)
+        .bind(permission_id)
+        .bind(session_id)
+        .bind(&price.meter)
+        .bind(&price.unit)
+        .bind(request.quantity)
+        .bind(price.price_per_unit)
+        .bind(&plan.currency)
+        .bind(request.occurred_at.unwrap_or_else(Utc::now))
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        match inserted {
+            Some(event) => Ok((event, true)),
+            None => Ok((self.get_event(&vendor_id, &request.event_id).await?, false)),
+        }
+    }
+
+    /// Usage charges against a permission, newest first
+    pub async fn list_charges(
+        &self,
+        principal: &Principal,
+        permission_id: Uuid,
+    ) -> Result<Vec<UsageCharge>, BillingError> {
+        let permission = self.zcash_service.get_permission(permission_id).await?;
+        principal.authorize_wallet(&permission.user_wallet_address, Access::Read)?;
+
+        sqlx::query_as::<_, UsageCharge>(
+            r#"
+            SELECT id, permission_id, vendor_id, meter, quantity, amount, fee_amount, net_amount,
+                   fiat_currency, fiat_amount, exchange_rate, status, period_end, created_at
+            FROM usage_charges
+            WHERE permission_id = $1
+            ORDER BY created_at DESC
+            LIMIT 500
+            "#
+        )
+        .bind(permission_id)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)
+    }
+
+    /// Bill the usage recorded before the start of the current period. Each
+    /// charge commits on its own, so one failure doesn't stop the rest.
+    pub async fn bill_usage(&self) -> Result<(), BillingError> {
+        let period = chrono::Duration::seconds(self.config.usage_billing_interval_seconds as i64);
+        let period_end = period_end(Utc::now(), period);
+
+        let pending = sqlx::query_as::<_, PendingUsage>(
+            r#"
+            SELECT DISTINCT permission_id, vendor_id, meter, currency
+            FROM usage_events
+            WHERE charge_id IS NULL AND created_at < $1
+            "#
+        )
+        .bind(period_end)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        for usage in pending {
+            if let Err(e) = self.bill_meter(&usage, period_end).await {
+                error!(
+                    "Failed to bill {} usage of permission {}: {:?}",
+                    usage.meter, usage.permission_id, e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn bill_meter(&self, usage: &PendingUsage, period_end: DateTime<Utc>) -> Result<(), BillingError> {
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        // Locked so an overlapping run can't bill the same events again
+        let events: Vec<(Uuid, Decimal, Decimal)> = sqlx::query_as(
+            r#"
+            SELECT id, quantity, unit_price
+            FROM usage_events
+            WHERE permission_id = $1 AND vendor_id = $2 AND meter = $3
+              AND currency IS NOT DISTINCT FROM $4 AND charge_id IS NULL AND created_at < $5
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .bind(usage.permission_id)
+        .bind(&usage.vendor_id)
+        .bind(&usage.meter)
+        .bind(&usage.currency)
+        .bind(period_end)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?;
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let (quantity, cost) = total_usage(&events);
+        let rounding = self.config.billing_rounding;
+        let conversion = match &usage.currency {
+            Some(currency) => {
+                let fiat_amount = rounding.round(cost, interval::STORED_AMOUNT_SCALE);
+                let conversion = self
+                    .prices
+                    .convert(fiat_amount, currency, interval::ZCASH_ASSET, interval::ZCASH_PRECISION, rounding)
+                    .await?;
+                Some(conversion)
+            }
+            None => None,
+        };
+        let amount = match &conversion {
+            Some(conversion) => conversion.amount,
+            None => rounding.round(cost, interval::ZCASH_PRECISION),
+        };
+
+        let fees = self
+            .config
+            .fees
+            .for_vendor(&usage.vendor_id)
+            .apply(amount, &usage.vendor_id, None, interval::ZCASH_PRECISION, rounding);
+
+        let status = match self
+            .zcash_service
+            .deduct_usage(&mut tx, usage.permission_id, amount)
+            .await
+        {
+            Ok(_) => "charged",
+            // Rolled back; billed again next period
+            Err(e @ BillingError::Database(_)) => return Err(e),
+            Err(e) => {
+                warn!(
+                    "Permission {} could not cover {} {} usage: {:?}",
+                    usage.permission_id, amount, usage.meter, e
+                );
+                "failed"
+            }
+        };
+
+        let charge = sqlx::query_as::<_, UsageCharge>(
+            r#"
+            INSERT INTO usage_charges
+            (id, permission_id, vendor_id, meter, quantity, amount, fee_amount, net_amount,
+             fiat_currency, fiat_amount, exchange_rate, status, period_end)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+            RETURNING id, permission_id, vendor_id, meter, quantity, amount, fee_amount, net_amount,
+                      fiat_currency, fiat_amount, exchange_rate, status, period_end, created_at
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(usage.permission_id)
+        .bind(&usage.vendor_id)
+        .bind(&usage.meter)
+        .bind(quantity)
+        .bind(amount)
+        .bind(fees.fee)
+        .bind(fees.net)
+        .bind(&usage.currency)
+        .bind(conversion.as_ref().map(|conversion| conversion.fiat_amount))
+        .bind(conversion.as_ref().map(|conversion| conversion.quote.price))
+        .bind(status)
+        .bind(period_end)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?;
+
+        // Only collected charges are divided and close out their events;
+        // usage a permission couldn't cover is billed again next period
+        let event_ids: Vec<Uuid> = events.iter().map(|(id, _, _)| *id).collect();
+        if status == "charged" {
+            db::create_revenue_splits(&mut *tx, None, Some(charge.id), &fees.allocations).await?;
+            sqlx::query("UPDATE usage_events SET charge_id = $1 WHERE id = ANY($2)")
+                .bind(charge.id)
+                .bind(&event_ids)
+                .execute(&mut *tx)
+                .await
+                .map_err(BillingError::Database)?;
+        }
+
+        let event_type = if status == "charged" { "usage.charged" } else { "usage.charge_failed" };
+        outbox::record(&mut *tx, &WebhookEvent::new(event_type, Some(&usage.vendor_id), &charge)).await?;
+
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        info!(
+            "Billed {} {} usage ({} events) to permission {}: {}",
+            quantity, usage.meter, event_ids.len(), usage.permission_id, status
+        );
+
+        Ok(())
+    }
+
+    /// Whether the permission has streamed from the vendor
+    async fn has_vendor_session(&self, permission_id: Uuid, vendor_id: &str) -> Result<bool, BillingError> {
+        let (exists,): (bool,) = sqlx::query_as(
+            r#"
+            SELECT EXISTS (
+                SELECT 1
+                FROM session_permissions sp
+                JOIN streaming_sessions s ON s.id = sp.session_id
+                WHERE sp.permission_id = $1 AND s.vendor_id = $2
+            )
+            "#
+        )
+        .bind(permission_id)
+        .bind(vendor_id)
+        .fetch_one(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(exists)
+    }
+
+    async fn get_event(&self, vendor_id: &str, event_id: &str) -> Result<UsageEvent, BillingError> {
+        sqlx::query_as::<_, UsageEvent>(
+            r#"
+            SELECT id, vendor_id, event_id, permission_id, session_id, meter, unit, quantity,
+                   unit_price, currency, occurred_at, charge_id, created_at
+            FROM usage_events
+            WHERE vendor_id = $1 AND event_id = $2
+            "#
+        )
+        .bind(vendor_id)
+        .bind(event_id)
+        .fetch_one(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)
+    }
+}
+
+/// The start of the current billing period: usage recorded before it is due
+fn period_end(now: DateTime<Utc>, period: chrono::Duration) -> DateTime<Utc> {
+    now.duration_trunc(period).unwrap_or(now)
+}
+
+/// Total quantity and cost of a meter's events, each at the unit price it
+/// was recorded with
+fn total_usage(events: &[(Uuid, Decimal, Decimal)]) -> (Decimal, Decimal) {
+    events.iter().fold((Decimal::ZERO, Decimal::ZERO), |(quantity, cost), (_, event_quantity, unit_price)| {
+        (quantity + event_quantity, cost + event_quantity * unit_price)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+    use chrono::TimeZone;
+
+    fn event(quantity: &str, unit_price: &str) -> (Uuid, Decimal, Decimal) {
+        (Uuid::new_v4(), dec(quantity), dec(unit_price))
+    }
+
+    #[test]
+    fn usage_is_priced_per_unit_at_the_recorded_price() {
+        // The plan's price changed between the second and third event
+        let events = [event("100", "0.001"), event("250", "0.001"), event("50", "0.002")];
+
+        assert_eq!(total_usage(&events), (dec("400"), dec("0.45")));
+        assert_eq!(total_usage(&[]), (Decimal::ZERO, Decimal::ZERO));
+    }
+
+    #[test]
+    fn usage_is_billed_up_to_the_current_period() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 5, 10, 7, 30).unwrap();
+
+        assert_eq!(
+            period_end(now, chrono::Duration::hours(1)),
+            Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, 0).unwrap()
+        );
+        assert_eq!(
+            period_end(now, chrono::Duration::days(1)),
+            Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap()
+        );
+
+        // A boundary belongs to the period it starts
+        let boundary = Utc.with_ymd_and_hms(2024, 3, 5, 11, 0, 0).unwrap();
+        assert_eq!(period_end(boundary, chrono::Duration::hours(1)), boundary);
+    }
+}
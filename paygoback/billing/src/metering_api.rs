@@ -0,0 +1,57 @@
+// src/metering_api.rs
+use actix_web::{web, HttpResponse};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth::Principal;
+use crate::error::BillingError;
+use crate::metering::{MeteringService, RecordUsageRequest};
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/usage",
+    tag = "usage",
+    request_body = RecordUsageRequest,
+    responses(
+        (status = 201, description = "Usage recorded for the next billing period", body = UsageEvent),
+        (status = 200, description = "Event id already recorded; the original event is returned", body = UsageEvent),
+        (status = 400, description = "Invalid quantity or meter not in the vendor's pricing plan", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Not the session's vendor, or the permission has no session with the vendor", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Session or permission not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Session failed or bills on-chain, or permission inactive", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn record_usage(
+    service: web::Data<Arc<MeteringService>>,
+    principal: Principal,
+    req: web::Json<RecordUsageRequest>,
+) -> Result<HttpResponse, BillingError> {
+    let (event, created) = service.record_usage(&principal, &req).await?;
+    if created {
+        Ok(HttpResponse::Created().json(event))
+    } else {
+        Ok(HttpResponse::Ok().json(event))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/usage/permissions/{id}/charges",
+    tag = "usage",
+    params(("id" = Uuid, Path, description = "Spending permission id")),
+    responses(
+        (status = 200, description = "Usage charges against the permission, newest first", body = [UsageCharge]),
+        (status = 403, description = "Not the permission's wallet or an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Permission not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn list_usage_charges(
+    service: web::Data<Arc<MeteringService>>,
+    principal: Principal,
+    permission_id: web::Path<Uuid>,
+) -> Result<HttpResponse, BillingError> {
+    let charges = service.list_charges(&principal, *permission_id).await?;
+    Ok(HttpResponse::Ok().json(charges))
+}
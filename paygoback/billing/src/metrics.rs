@@ -0,0 +1,67 @@
+// src/metrics.rs
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// Outcome of one billing pass over this instance's shards
+#[derive(Debug, Default, Clone)]
+pub struct TickReport {
+    pub sessions: u64, // due for billing when the tick started
+    pub failed: u64,
+    pub max_lag_seconds: i64,
+}
+
+/// Billing scheduler health as seen by this instance
+#[derive(Debug, Default, Clone, Serialize, ToSchema)]
+pub struct SchedulerMetricsSnapshot {
+    pub owned_shards: Vec<u32>,
+    pub shard_count: u32,
+    pub ticks: u64,
+    /// Ticks dropped because the previous one was still running
+    pub skipped_ticks: u64,
+    pub last_tick_started_at: Option<DateTime<Utc>>,
+    pub last_tick_duration_ms: Option<u64>,
+    pub last_tick_sessions: u64,
+    pub last_tick_failed: u64,
+    /// How long past its billing interval the most overdue session was
+    pub last_tick_max_lag_seconds: i64,
+}
+
+pub struct SchedulerMetrics {
+    snapshot: Mutex<SchedulerMetricsSnapshot>,
+}
+
+impl SchedulerMetrics {
+    pub fn new(shard_count: u32) -> Self {
+        Self {
+            snapshot: Mutex::new(SchedulerMetricsSnapshot {
+                shard_count,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn record_owned_shards(&self, shards: &[u32]) {
+        self.snapshot.lock().unwrap().owned_shards = shards.to_vec();
+    }
+
+    pub fn record_tick(&self, started_at: DateTime<Utc>, duration: Duration, report: &TickReport) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.ticks += 1;
+        snapshot.last_tick_started_at = Some(started_at);
+        snapshot.last_tick_duration_ms = Some(duration.as_millis() as u64);
+        snapshot.last_tick_sessions = report.sessions;
+        snapshot.last_tick_failed = report.failed;
+        snapshot.last_tick_max_lag_seconds = report.max_lag_seconds;
+    }
+
+    pub fn record_skipped_tick(&self) {
+        self.snapshot.lock().unwrap().skipped_ticks += 1;
+    }
+
+    pub fn snapshot(&self) -> SchedulerMetricsSnapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+}
@@ -1,228 +1,422 @@
 // src/middleware.rs
-use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, Result};
-use actix_web::middleware::{Logger, Condition};
-use actix_web::web::Data;
-use actix_web::{HttpMessage, HttpResponse};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::http::Method;
+use actix_web::{web, Error, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use redis::{aio::ConnectionManager, Client as RedisClient};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
-use redis::Client as RedisClient;
+use tokio::sync::OnceCell;
+use tracing::warn;
 
+use crate::auth::Authenticator;
+use crate::cache;
+use crate::config::RateLimitConfig;
 use crate::error::BillingError;
 
-// Simple in-memory rate limiter for demonstration
-// In production, use Redis or a dedicated rate limiting service
+/// In-memory buckets kept before idle ones are swept
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// Token bucket refill, atomic in Redis. Uses the server clock so every
+/// instance agrees on elapsed time, and expires the key once the bucket
+/// would be full again, which is the same as it not existing.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_per_ms = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(state[1]) or capacity
+local ts = tonumber(state[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * refill_per_ms)
+
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil((capacity - tokens) / refill_per_ms) + 1)
+return {allowed, tostring(tokens)}
+"#;
+
+/// A bucket of `capacity` requests that refills completely over `period`
+#[derive(Debug, Clone)]
+pub struct RateLimitRule {
+    pub name: &'static str,
+    pub path_prefix: &'static str,
+    pub method: Option<Method>,
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimitRule {
+    fn refill_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        self.method.as_ref().is_none_or(|m| m == method)
+            && (self.path_prefix.is_empty() || path.starts_with(self.path_prefix))
+    }
+}
+
+/// Outcome of taking a token, and what to tell the client about its quota
+#[derive(Debug, Clone)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_seconds: u64, // until the bucket is full again
+    pub retry_after_seconds: u64, // until the next token, when denied
+}
+
+impl RateLimitDecision {
+    fn new(rule: &RateLimitRule, allowed: bool, tokens: f64) -> Self {
+        let refill = rule.refill_per_second();
+
+        Self {
+            allowed,
+            limit: rule.capacity,
+            remaining: tokens.floor().max(0.0) as u32,
+            reset_seconds: ((rule.capacity as f64 - tokens) / refill).ceil().max(0.0) as u64,
+            retry_after_seconds: ((1.0 - tokens) / refill).ceil().max(1.0) as u64,
+        }
+    }
+
+    fn apply_headers(&self, headers: &mut HeaderMap) {
+        let mut insert = |name: &'static str, value: u64| {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+        };
+
+        insert("ratelimit-limit", self.limit as u64);
+        insert("ratelimit-remaining", self.remaining as u64);
+        insert("ratelimit-reset", self.reset_seconds);
+
+        if !self.allowed {
+            headers.insert(RETRY_AFTER, HeaderValue::from(self.retry_after_seconds));
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait RateLimit {
+    async fn check(&self, key: &str, rule: &RateLimitRule) -> Result<RateLimitDecision, BillingError>;
+}
+
+/// Token buckets local to this process, for single-instance deployments
 pub struct InMemoryRateLimiter {
-    requests: Arc<RwLock<HashMap<String, Vec<Instant>>>>,
-    max_requests: usize,
-    window: Duration,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
 }
 
 impl InMemoryRateLimiter {
-    pub fn new(max_requests: usize, window: Duration) -> Self {
+    pub fn new() -> Self {
         Self {
-            requests: Arc::new(RwLock::new(HashMap::new())),
-            max_requests,
-            window,
+            buckets: Mutex::new(HashMap::new()),
         }
     }
+}
 
-    pub async fn is_allowed(&self, key: &str) -> Result<bool, BillingError> {
-        let mut requests = self.requests.write().await;
+#[async_trait::async_trait]
+impl RateLimit for InMemoryRateLimiter {
+    async fn check(&self, key: &str, rule: &RateLimitRule) -> Result<RateLimitDecision, BillingError> {
+        let capacity = rule.capacity as f64;
+        let refill = rule.refill_per_second();
         let now = Instant::now();
-        
-        // Get or create request list for this key
-        let request_times = requests.entry(key.to_string()).or_insert_with(Vec::new);
-        
-        // Remove old requests outside the window
-        request_times.retain(|&time| now.duration_since(time) <= self.window);
-        
-        // Check if under limit
-        if request_times.len() < self.max_requests {
-            request_times.push(now);
-            Ok(true)
-        } else {
-            Ok(false)
+
+        let mut buckets = self
+            .buckets
+            .lock()
+            .map_err(|_| BillingError::Cache("Rate limiter lock poisoned".to_string()))?;
+
+        // Full buckets carry no state, so they can be dropped
+        if buckets.len() >= MAX_MEMORY_BUCKETS {
+            buckets.retain(|_, (tokens, updated)| {
+                *tokens + now.duration_since(*updated).as_secs_f64() * refill < capacity
+            });
+        }
+
+        let (tokens, updated) = buckets.entry(key.to_string()).or_insert((capacity, now));
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * refill).min(capacity);
+        *updated = now;
+
+        let allowed = *tokens >= 1.0;
+        if allowed {
+            *tokens -= 1.0;
         }
+
+        Ok(RateLimitDecision::new(rule, allowed, *tokens))
     }
 }
 
-// Redis-based rate limiter for production
+/// Token buckets shared by every instance through Redis
 pub struct RedisRateLimiter {
     redis_client: RedisClient,
-    max_requests: usize,
-    window_seconds: u64,
+    connection: OnceCell<ConnectionManager>,
+    script: redis::Script,
 }
 
 impl RedisRateLimiter {
-    pub fn new(redis_client: RedisClient, max_requests: usize, window_seconds: u64) -> Self {
+    pub fn new(redis_client: RedisClient) -> Self {
         Self {
             redis_client,
-            max_requests,
-            window_seconds,
+            connection: OnceCell::new(),
+            script: redis::Script::new(TOKEN_BUCKET_SCRIPT),
         }
     }
+}
 
-    pub async fn is_allowed(&self, key: &str) -> Result<bool, BillingError> {
-        let mut conn = self.redis_client
-            .get_multiplexed_async_connection()
-            .await
-            .map_err(|e| BillingError::Cache(format!("Redis connection failed: {}", e)))?;
-
-        let redis_key = format!("rate_limit:{}", key);
-        
-        // Use Redis pipeline for atomic operations
-        let mut pipe = redis::pipe();
-        pipe.cmd("INCR").arg(&redis_key);
-        pipe.cmd("EXPIRE").arg(&redis_key).arg(self.window_seconds);
-        
-        let results: Vec<i64> = pipe
-            .query_async(&mut conn)
+#[async_trait::async_trait]
+impl RateLimit for RedisRateLimiter {
+    async fn check(&self, key: &str, rule: &RateLimitRule) -> Result<RateLimitDecision, BillingError> {
+        let mut conn = self
+            .connection
+            .get_or_try_init(|| cache::get_connection(&self.redis_client))
+            .await?
+            .clone();
+
+        let (allowed, tokens): (i64, String) = self
+            .script
+            .key(format!("rate_limit:{}", key))
+            .arg(rule.capacity)
+            .arg(rule.refill_per_second() / 1000.0)
+            .invoke_async(&mut conn)
             .await
-            .map_err(|e| BillingError::Cache(format!("Redis query failed: {}", e)))?;
+            .map_err(|e| BillingError::Cache(format!("Rate limit check failed: {}", e)))?;
 
-        let current_count = results[0];
-        Ok(current_count <= self.max_requests as i64)
+        let tokens = tokens.parse::<f64>().unwrap_or_default();
+        Ok(RateLimitDecision::new(rule, allowed == 1, tokens))
     }
 }
 
-// Rate limiting middleware
+/// Applies the first matching rule from `configure_rate_limits` to each
+/// request, keyed by the authenticated caller or, failing that, the client
+/// IP. Every limited response carries `RateLimit-*` headers; rejected ones
+/// are a 429 problem with `Retry-After`. If the limiter itself fails the
+/// request is let through.
 pub struct RateLimitMiddleware {
     limiter: Arc<dyn RateLimit + Send + Sync>,
+    rules: Arc<Vec<RateLimitRule>>,
+    trust_proxy: bool,
 }
 
 impl RateLimitMiddleware {
-    pub fn new_in_memory(max_requests: usize, window: Duration) -> Self {
-        let limiter = Arc::new(InMemoryRateLimiter::new(max_requests, window));
-        Self { limiter }
-    }
-
-    pub fn new_redis(redis_client: RedisClient, max_requests: usize, window_seconds: u64) -> Self {
-        let limiter = Arc::new(RedisRateLimiter::new(redis_client, max_requests, window_seconds));
-        Self { limiter }
-    }
-}
-
-#[async_trait::async_trait]
-pub trait RateLimit {
-    async fn is_allowed(&self, key: &str) -> Result<bool, BillingError>;
-}
-
-#[async_trait::async_trait]
-impl RateLimit for InMemoryRateLimiter {
-    async fn is_allowed(&self, key: &str) -> Result<bool, BillingError> {
-        self.is_allowed(key).await
-    }
-}
-
-#[async_trait::async_trait]
-impl RateLimit for RedisRateLimiter {
-    async fn is_allowed(&self, key: &str) -> Result<bool, BillingError> {
-        self.is_allowed(key).await
+    pub fn new(limiter: Arc<dyn RateLimit + Send + Sync>, config: &RateLimitConfig) -> Self {
+        Self {
+            limiter,
+            rules: Arc::new(configure_rate_limits()),
+            trust_proxy: config.trust_proxy,
+        }
     }
 }
 
-impl actix_web::dev::Transform for RateLimitMiddleware {
+impl<S, B> Transform<S, ServiceRequest> for RateLimitMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
-    type Transform = RateLimitMiddlewareService;
+    type Transform = RateLimitMiddlewareService<S>;
     type InitError = ();
-    type Future = Result<Self::Transform, Self::InitError>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
-    fn new_transform(&self, _: ServiceRequest) -> Self::Future {
-        Ok(RateLimitMiddlewareService {
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddlewareService {
+            service: Rc::new(service),
             limiter: self.limiter.clone(),
-        })
+            rules: self.rules.clone(),
+            trust_proxy: self.trust_proxy,
+        }))
     }
 }
 
-pub struct RateLimitMiddlewareService {
+pub struct RateLimitMiddlewareService<S> {
+    service: Rc<S>,
     limiter: Arc<dyn RateLimit + Send + Sync>,
+    rules: Arc<Vec<RateLimitRule>>,
+    trust_proxy: bool,
 }
 
-impl actix_web::dev::Service for RateLimitMiddlewareService {
-    type Request = ServiceRequest;
-    type Response = ServiceResponse;
+impl<S, B> Service<ServiceRequest> for RateLimitMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
-    type Future = actix_web::dev::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
-    fn poll_ready(&self, _: &mut std::task::Context<'_>) -> Result<actix_web::dev::PollReady, Self::Error> {
-        actix_web::dev::PollReady::Ready
-    }
+    forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
         let limiter = self.limiter.clone();
-        
+        let rule = matching_rule(&self.rules, req.method(), req.path()).cloned();
+        let caller = self.caller_key(&req);
+
         Box::pin(async move {
-            // Get client IP from connection info
-            let client_ip = req
-                .connection_info()
-                .peer_addr()
-                .unwrap_or_else(|_| "127.0.0.1:8080")
-                .to_string();
-
-            // Create rate limit key based on IP and endpoint
-            let path = req.path();
-            let rate_limit_key = format!("{}:{}", client_ip, path);
-
-            // Check rate limit
-            match limiter.is_allowed(&rate_limit_key).await {
-                Ok(true) => {
-                    // Request allowed, continue
-                    let response = req.into_response(
-                        actix_web::dev::ServiceResponse::new(
-                            req.into_parts().0,
-                            HttpResponse::Ok().finish()
-                        )
-                    ).await?;
-                    Ok(response)
-                }
-                Ok(false) => {
-                    // Rate limit exceeded
-                    let error_response = HttpResponse::TooManyRequests().json(serde_json::json!({
-                        "error": "Rate limit exceeded",
-                        "message": "Too many requests, please try again later"
-                    }));
-                    
-                    Ok(req.into_response(
-                        actix_web::dev::ServiceResponse::new(
-                            req.into_parts().0,
-                            error_response
-                        )
-                    ))
-                }
-                Err(_) => {
-                    // Error checking rate limit, allow request but log
-                    warn!("Rate limiter error, allowing request");
-                    let response = req.into_response(
-                        actix_web::dev::ServiceResponse::new(
-                            req.into_parts().0,
-                            HttpResponse::Ok().finish()
-                        )
-                    ).await?;
-                    Ok(response)
+            let Some(rule) = rule else {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+
+            let decision = match limiter.check(&format!("{}:{}", rule.name, caller), &rule).await {
+                Ok(decision) => decision,
+                Err(e) => {
+                    warn!("Rate limiter unavailable, allowing request: {:?}", e);
+                    return service.call(req).await.map(ServiceResponse::map_into_left_body);
                 }
+            };
+
+            if !decision.allowed {
+                let mut response = BillingError::RateLimited(decision.retry_after_seconds).error_response();
+                decision.apply_headers(response.headers_mut());
+                return Ok(req.into_response(response).map_into_right_body());
             }
+
+            let mut response = service.call(req).await?;
+            decision.apply_headers(response.headers_mut());
+            Ok(response.map_into_left_body())
         })
     }
 }
 
-// Different rate limits for different endpoints
-pub fn configure_rate_limits() -> Vec<(&'static str, usize, Duration)> {
+impl<S> RateLimitMiddlewareService<S> {
+    /// Authenticated callers share one bucket however many addresses they
+    /// call from; everyone else is limited per IP
+    fn caller_key(&self, req: &ServiceRequest) -> String {
+        let principal = req
+            .app_data::<web::Data<Arc<Authenticator>>>()
+            .and_then(|authenticator| authenticator.authenticate(req.request()).ok());
+
+        if let Some(principal) = principal {
+            return principal.subject;
+        }
+
+        let connection_info = req.connection_info();
+        let ip = if self.trust_proxy {
+            connection_info.realip_remote_addr()
+        } else {
+            connection_info.peer_addr()
+        };
+
+        format!("ip:{}", ip.unwrap_or("unknown"))
+    }
+}
+
+/// The first rule covering a request
+fn matching_rule<'a>(rules: &'a [RateLimitRule], method: &Method, path: &str) -> Option<&'a RateLimitRule> {
+    rules.iter().find(|rule| rule.matches(method, path))
+}
+
+/// Limits per route, first match wins, so specific rules go first
+pub fn configure_rate_limits() -> Vec<RateLimitRule> {
+    let per_minute = |name, path_prefix, method, capacity| RateLimitRule {
+        name,
+        path_prefix,
+        method,
+        capacity,
+        period: Duration::from_secs(60),
+    };
+
     vec![
         // Health check - very permissive
-        ("/api/v1/health", 1000, Duration::from_secs(60)),
-        
+        per_minute("health", "/api/v1/health", None, 1000),
+
         // Balance checks - moderate
-        ("/api/v1/zcash/balance", 60, Duration::from_secs(60)),
-        
-        // Permission creation - strict
-        ("/api/v1/zcash/permissions", 10, Duration::from_secs(60)),
-        
+        per_minute("balance", "/api/v1/zcash/balance", None, 60),
+
+        // Permission creation and changes - strict
+        per_minute("permission_write", "/api/v1/zcash/permissions", Some(Method::POST), 10),
+
+        // Ownership challenges - strict, each one costs a signature check
+        per_minute("wallet_challenge", "/api/v1/wallets/challenges", Some(Method::POST), 10),
+
         // Session operations - moderate
-        ("/api/v1/sessions", 30, Duration::from_secs(60)),
-        
+        per_minute("sessions", "/api/v1/sessions", None, 30),
+
+        // Metered usage - permissive, vendors post one request per event
+        per_minute("usage", "/api/v1/usage", Some(Method::POST), 600),
+
         // Default limit
-        ("default", 100, Duration::from_secs(60)),
+        per_minute("default", "", None, 100),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(capacity: u32, period_seconds: u64) -> RateLimitRule {
+        RateLimitRule {
+            name: "test",
+            path_prefix: "",
+            method: None,
+            capacity,
+            period: Duration::from_secs(period_seconds),
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_buckets_allow_capacity_then_deny() {
+        let limiter = InMemoryRateLimiter::new();
+        let rule = rule(3, 60);
+
+        for remaining in [2, 1, 0] {
+            let decision = limiter.check("caller", &rule).await.unwrap();
+            assert!(decision.allowed);
+            assert_eq!(decision.limit, 3);
+            assert_eq!(decision.remaining, remaining);
+        }
+
+        let denied = limiter.check("caller", &rule).await.unwrap();
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        assert_eq!(denied.retry_after_seconds, 20);
+        assert_eq!(denied.reset_seconds, 60);
+
+        // Callers have their own buckets
+        assert!(limiter.check("other", &rule).await.unwrap().allowed);
+    }
+
+    #[test]
+    fn decisions_report_remaining_reset_and_retry_after() {
+        let rule = rule(10, 60); // a token every 6 seconds
+
+        let allowed = RateLimitDecision::new(&rule, true, 4.5);
+        assert_eq!(allowed.remaining, 4);
+        assert_eq!(allowed.reset_seconds, 33);
+        assert_eq!(allowed.retry_after_seconds, 1);
+
+        let denied = RateLimitDecision::new(&rule, false, 0.25);
+        assert_eq!(denied.remaining, 0);
+        assert_eq!(denied.reset_seconds, 59);
+        assert_eq!(denied.retry_after_seconds, 5);
+
+        let full = RateLimitDecision::new(&rule, true, 10.0);
+        assert_eq!(full.remaining, 10);
+        assert_eq!(full.reset_seconds, 0);
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let rules = configure_rate_limits();
+        let name = |method: Method, path: &str| matching_rule(&rules, &method, path).unwrap().name;
+
+        assert_eq!(name(Method::GET, "/api/v1/health"), "health");
+        assert_eq!(name(Method::POST, "/api/v1/zcash/permissions"), "permission_write");
+        assert_eq!(name(Method::GET, "/api/v1/zcash/permissions/abc"), "default");
+        assert_eq!(name(Method::POST, "/api/v1/wallets/challenges/abc/verify"), "wallet_challenge");
+        assert_eq!(name(Method::POST, "/api/v1/sessions/ABC123/end"), "sessions");
+        assert_eq!(name(Method::POST, "/api/v1/usage"), "usage");
+        assert_eq!(name(Method::GET, "/api/v1/usage"), "default");
+        assert_eq!(name(Method::GET, "/api/v1/statements"), "default");
+    }
+}
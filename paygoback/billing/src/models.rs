@@ -2,8 +2,13 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use rust_decimal::Decimal;
+use sqlx::types::Json;
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+use crate::oracle::Conversion;
+use crate::pricing::PricingPlan;
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow, ToSchema)]
 pub struct StreamingSession {
     pub id: Uuid,
     pub session_code: String,
@@ -13,14 +18,19 @@ pub struct StreamingSession {
     pub start_time: DateTime<Utc>,
     pub last_billed_time: DateTime<Utc>,
     pub end_time: Option<DateTime<Utc>>,
-    pub rate_per_hour: Decimal, // in USD or token units
+    pub rate_per_hour: Decimal, // the plan's base rate, in its currency
+    #[schema(value_type = PricingPlan)]
+    pub pricing_plan: Json<PricingPlan>, // snapshot of the vendor's plan at creation
+    pub billed_micros: i64, // active time billed so far
+    pub token_address: Option<String>, // ERC-20 billed via transferFrom, None for the billing contract
+    pub chain_id: i64, // deployment the session settles on
     pub total_amount_billed: Decimal,
     pub status: SessionStatus,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, sqlx::Type, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::Type, PartialEq, ToSchema)]
 #[sqlx(type_name = "session_status", rename_all = "lowercase")]
 pub enum SessionStatus {
     Active,
@@ -29,59 +39,161 @@ pub enum SessionStatus {
     Failed,
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct BillingTransaction {
     pub id: Uuid,
     pub session_id: Uuid,
     pub user_wallet_address: String,
     pub vendor_wallet_address: String,
-    pub amount: Decimal,
+    pub amount: Decimal, // gross, as charged to the user
+    pub fee_amount: Decimal, // platform fee out of the gross
+    pub net_amount: Decimal, // the vendor's share, before any revenue splits
+    pub token_address: Option<String>,
+    pub chain_id: Option<i64>, // None for charges settled through Zcash
     pub duration_minutes: i64,
+    pub duration_micros: i64, // the exact span billed, handed back to the session if the charge is cancelled
     pub tx_hash: Option<String>,
     pub status: TransactionStatus,
+    pub nonce: Option<i64>,
+    pub gas_price_wei: Option<Decimal>,
+    pub submission_attempts: i32,
+    pub replaced_tx_hashes: Vec<String>,
+    pub batch_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
+    pub fiat_currency: Option<String>, // set when the plan is priced in fiat
+    pub fiat_amount: Option<Decimal>,
+    pub exchange_rate: Option<Decimal>, // fiat per unit of the settlement asset
+    pub exchange_rate_at: Option<DateTime<Utc>>, // when the oracle observed the rate
+    pub permit_id: Option<Uuid>, // EIP-712 permit the amount was reserved from
+}
+
+impl BillingTransaction {
+    /// Snapshot the exchange rate a fiat-priced charge was converted at
+    pub fn with_conversion(self, conversion: Option<&Conversion>) -> Self {
+        match conversion {
+            Some(conversion) => BillingTransaction {
+                fiat_currency: Some(conversion.quote.currency.clone()),
+                fiat_amount: Some(conversion.fiat_amount),
+                exchange_rate: Some(conversion.quote.price),
+                exchange_rate_at: Some(conversion.quote.observed_at),
+                ..self
+            },
+            None => self,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone)]
+#[derive(Debug, Serialize, Deserialize, sqlx::Type, Clone, ToSchema)]
 #[sqlx(type_name = "transaction_status", rename_all = "lowercase")]
 pub enum TransactionStatus {
     Pending,
     Confirmed,
     Failed,
+    Cancelled,
+    Accrued, // recorded off-chain, waiting for batch settlement
 }
 
-#[derive(Debug, Deserialize)]
+/// One on-chain `billUserBatch` settlement covering the accrued interval
+/// charges linked to it through `billing_transactions.batch_id`.
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SettlementBatch {
+    pub id: Uuid,
+    pub chain_id: i64,
+    pub total_amount: Decimal,
+    pub charge_count: i32,
+    pub pair_count: i32,
+    pub tx_hash: Option<String>,
+    pub nonce: Option<i64>,
+    pub gas_price_wei: Option<Decimal>,
+    pub status: TransactionStatus,
+    pub created_at: DateTime<Utc>,
+    pub settled_at: Option<DateTime<Utc>>,
+}
+
+/// A settlement batch broadcast under `nonce` but not yet confirmed
+#[derive(Debug, sqlx::FromRow)]
+pub struct PendingBatch {
+    pub id: Uuid,
+    pub tx_hash: Option<String>,
+    pub nonce: i64,
+    pub gas_price_wei: Option<Decimal>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Billing contract event kinds tracked by the chain indexer
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContractEventType {
+    Deposit,
+    Withdrawal,
+    Billed,
+}
+
+impl ContractEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContractEventType::Deposit => "deposit",
+            ContractEventType::Withdrawal => "withdrawal",
+            ContractEventType::Billed => "billed",
+        }
+    }
+}
+
+/// A billing contract log, decoded and normalised for the index. Addresses
+/// are lowercase hex; amounts are in contract units.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractEvent {
+    pub event_type: ContractEventType,
+    pub user_address: String,
+    pub vendor_address: Option<String>,
+    pub amount: Decimal,
+    pub block_number: u64,
+    pub block_hash: String,
+    pub tx_hash: String,
+    pub log_index: u64,
+}
+
+impl ContractEvent {
+    /// Change this event makes to the user's contract balance
+    pub fn balance_delta(&self) -> Decimal {
+        match self.event_type {
+            ContractEventType::Deposit => self.amount,
+            ContractEventType::Withdrawal | ContractEventType::Billed => -self.amount,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateSessionRequest {
     pub user_wallet_address: String,
     pub vendor_id: String,
+    pub chain_id: Option<u64>, // defaults to the vendor's chain
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CreateSessionResponse {
     pub session_code: String,
     pub session_id: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ActivateSessionRequest {
     pub session_code: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct EndSessionRequest {
     pub session_code: String,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct UpdateStreamingTimeRequest {
-    pub session_code: String,
-    pub streaming_duration_seconds: u64,
-}
-
 #[derive(Debug, Deserialize, Serialize)]
 pub struct VendorInfo {
     pub id: String,
     pub wallet_address: String,
     pub rate_per_hour: Decimal,
     pub currency: String,
+    #[serde(default)]
+    pub chain_id: Option<u64>,
+    #[serde(default)]
+    pub pricing_plan: Option<PricingPlan>, // flat rate_per_hour when absent
 }
\ No newline at end of file
@@ -0,0 +1,239 @@
+// src/nonce.rs
+use ethers::{
+    prelude::*,
+    types::{Address, BlockNumber, U256},
+};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::error::BillingError;
+use crate::models::TransactionStatus;
+
+/// Hands out nonces for the billing signer from a local counter so that
+/// concurrent `billUser` calls never race on `eth_getTransactionCount`.
+pub struct NonceManager {
+    address: Address,
+    next_nonce: Mutex<Option<U256>>,
+}
+
+impl NonceManager {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            next_nonce: Mutex::new(None),
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Reserve the next nonce, initialising the counter from the node's
+    /// pending transaction count on first use.
+    pub async fn next<M: Middleware>(&self, client: &M) -> Result<U256, BillingError> {
+        let mut next_nonce = self.next_nonce.lock().await;
+
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => self.fetch_pending_count(client).await?,
+        };
+
+        *next_nonce = Some(nonce + U256::one());
+        Ok(nonce)
+    }
+
+    /// Hand back a reserved nonce that was never broadcast. Only the latest
+    /// reservation can be returned; earlier ones are already followed by
+    /// nonces other calls hold.
+    pub async fn release(&self, nonce: U256) {
+        let mut next_nonce = self.next_nonce.lock().await;
+
+        if *next_nonce == Some(nonce + U256::one()) {
+            *next_nonce = Some(nonce);
+        }
+    }
+
+    /// Move the local counter up to the node's pending count. Used after a
+    /// "nonce too low" rejection; it never moves down, as nonces other calls
+    /// reserved may not have reached the node's mempool yet.
+    pub async fn resync<M: Middleware>(&self, client: &M) -> Result<(), BillingError> {
+        let mut next_nonce = self.next_nonce.lock().await;
+        let pending = self.fetch_pending_count(client).await?;
+        let nonce = next_nonce.map_or(pending, |local| local.max(pending));
+
+        warn!("Resynced nonce for {:?} to {}", self.address, nonce);
+        *next_nonce = Some(nonce);
+        Ok(())
+    }
+
+    async fn fetch_pending_count<M: Middleware>(&self, client: &M) -> Result<U256, BillingError> {
+        let nonce = client
+            .get_transaction_count(self.address, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(|e| BillingError::Blockchain(format!("Nonce query failed: {}", e)))?;
+
+        info!("Loaded pending nonce {} for {:?}", nonce, self.address);
+        Ok(nonce)
+    }
+}
+
+/// Controls how long a transaction may sit in the mempool before it is
+/// re-broadcast with the same nonce and a higher gas price.
+#[derive(Clone, Debug)]
+pub struct GasEscalationPolicy {
+    pub pending_timeout: Duration,
+    pub bump_percent: u64,
+    pub max_bumps: u32,
+    pub max_gas_price: Option<U256>,
+}
+
+impl Default for GasEscalationPolicy {
+    fn default() -> Self {
+        Self {
+            pending_timeout: Duration::from_secs(90),
+            bump_percent: 15,
+            max_bumps: 3,
+            max_gas_price: None,
+        }
+    }
+}
+
+impl GasEscalationPolicy {
+    /// Most nodes reject a replacement unless the price rises by at least
+    /// 10%, so smaller bump settings are raised to that floor.
+    pub fn bump(&self, gas_price: U256) -> U256 {
+        let percent = self.bump_percent.max(10);
+        let bumped = gas_price * U256::from(100 + percent) / U256::from(100);
+
+        // Guarantee progress even for tiny prices where the percentage rounds to zero
+        let bumped = if bumped <= gas_price { gas_price + U256::one() } else { bumped };
+
+        match self.max_gas_price {
+            Some(cap) if bumped > cap => cap,
+            _ => bumped,
+        }
+    }
+
+    pub fn can_bump(&self, gas_price: U256, bumps_so_far: u32) -> bool {
+        if bumps_so_far >= self.max_bumps {
+            return false;
+        }
+
+        match self.max_gas_price {
+            Some(cap) => gas_price < cap,
+            None => true,
+        }
+    }
+}
+
+/// Where a broadcast transaction stands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubmissionOutcome {
+    Pending,   // broadcast, not mined yet
+    Confirmed, // mined and succeeded
+    Reverted,  // mined but reverted, so the call had no effect
+}
+
+/// Outcome of broadcasting a billing (or cancellation) transaction,
+/// including every hash that was replaced along the way.
+#[derive(Clone, Debug)]
+pub struct SubmittedTransaction {
+    pub tx_hash: String,
+    pub nonce: U256,
+    pub gas_price: U256,
+    pub attempts: u32,
+    pub replaced_tx_hashes: Vec<String>,
+    pub outcome: SubmissionOutcome,
+}
+
+impl SubmittedTransaction {
+    /// The status a billing row takes from this submission; a reverted
+    /// charge never took the user's funds
+    pub fn status(&self) -> TransactionStatus {
+        match self.outcome {
+            SubmissionOutcome::Pending => TransactionStatus::Pending,
+            SubmissionOutcome::Confirmed => TransactionStatus::Confirmed,
+            SubmissionOutcome::Reverted => TransactionStatus::Failed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_gas_price: Option<u64>) -> GasEscalationPolicy {
+        GasEscalationPolicy {
+            max_gas_price: max_gas_price.map(U256::from),
+            ..GasEscalationPolicy::default()
+        }
+    }
+
+    #[test]
+    fn bump_raises_by_the_percentage_with_a_ten_percent_floor() {
+        assert_eq!(policy(None).bump(U256::from(1_000)), U256::from(1_150));
+
+        let low = GasEscalationPolicy { bump_percent: 5, ..policy(None) };
+        assert_eq!(low.bump(U256::from(1_000)), U256::from(1_100));
+
+        // Too small for the percentage to move it
+        assert_eq!(policy(None).bump(U256::from(1)), U256::from(2));
+        assert_eq!(policy(None).bump(U256::zero()), U256::one());
+    }
+
+    #[test]
+    fn bump_stops_at_the_cap() {
+        assert_eq!(policy(Some(1_100)).bump(U256::from(1_000)), U256::from(1_100));
+        assert_eq!(policy(Some(2_000)).bump(U256::from(1_000)), U256::from(1_150));
+    }
+
+    #[test]
+    fn can_bump_until_the_bump_limit_or_cap() {
+        let uncapped = policy(None);
+        assert!(uncapped.can_bump(U256::from(1_000), 0));
+        assert!(uncapped.can_bump(U256::from(1_000), 2));
+        assert!(!uncapped.can_bump(U256::from(1_000), 3));
+
+        let capped = policy(Some(1_100));
+        assert!(capped.can_bump(U256::from(1_099), 0));
+        assert!(!capped.can_bump(U256::from(1_100), 0));
+    }
+
+    #[tokio::test]
+    async fn resync_never_moves_the_counter_down() {
+        let (provider, mock) = Provider::mocked();
+        let manager = NonceManager::new(Address::zero());
+
+        mock.push(U256::from(5)).unwrap();
+        assert_eq!(manager.next(&provider).await.unwrap(), U256::from(5));
+        assert_eq!(manager.next(&provider).await.unwrap(), U256::from(6));
+
+        // The node has only seen the first of the two
+        mock.push(U256::from(6)).unwrap();
+        manager.resync(&provider).await.unwrap();
+        assert_eq!(manager.next(&provider).await.unwrap(), U256::from(7));
+
+        mock.push(U256::from(10)).unwrap();
+        manager.resync(&provider).await.unwrap();
+        assert_eq!(manager.next(&provider).await.unwrap(), U256::from(10));
+    }
+
+    #[tokio::test]
+    async fn release_returns_only_the_latest_reservation() {
+        let (provider, mock) = Provider::mocked();
+        let manager = NonceManager::new(Address::zero());
+
+        mock.push(U256::from(5)).unwrap();
+        let first = manager.next(&provider).await.unwrap();
+        let second = manager.next(&provider).await.unwrap();
+
+        // 6 is already out, so 5 can't be reused
+        manager.release(first).await;
+        assert_eq!(manager.next(&provider).await.unwrap(), U256::from(7));
+
+        manager.release(U256::from(7)).await;
+        assert_eq!(manager.next(&provider).await.unwrap(), U256::from(7));
+        assert_eq!(second, U256::from(6));
+    }
+}
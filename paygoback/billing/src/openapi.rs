@@ -0,0 +1,242 @@
+// src/openapi.rs
+use actix_web::{HttpResponse, Responder};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// OpenAPI 3 contract for `/api/v1`, generated from the `#[utoipa::path]`
+/// annotations on the handlers and the `ToSchema` request/response types.
+/// Adding a route to `api::configure_routes` without listing its handler
+/// here fails the tests below.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "PayGo Billing API",
+        description = "Streaming sessions billed against Zcash spending permissions and Ethereum spending permits."
+    ),
+    paths(
+        crate::api::health_check,
+        crate::api::scheduler_metrics,
+        crate::api::zcash_test_endpoint,
+        crate::api::create_session,
+        crate::api::activate_session,
+        crate::api::end_session,
+        crate::zcash::zcash_api::create_permission,
+        crate::zcash::zcash_api::verify_permission,
+        crate::zcash::zcash_api::get_permission_status,
+        crate::zcash::zcash_api::revoke_permission,
+        crate::zcash::zcash_api::get_wallet_balance,
+        crate::zcash::zcash_api::get_active_permission,
+        crate::permit_api::get_permit_domain,
+        crate::permit_api::submit_permit,
+        crate::permit_api::get_permit,
+        crate::permit_api::revoke_permit,
+        crate::ownership_api::create_challenge,
+        crate::ownership_api::verify_challenge,
+        crate::events_api::stream_session_events,
+        crate::events_api::stream_permission_events,
+        crate::webhooks_api::create_endpoint,
+        crate::webhooks_api::list_endpoints,
+        crate::webhooks_api::delete_endpoint,
+        crate::webhooks_api::list_dead_letters,
+        crate::webhooks_api::replay_dead_letter,
+        crate::metering_api::record_usage,
+        crate::metering_api::list_usage_charges,
+        crate::invoice_api::issue_invoice,
+        crate::invoice_api::get_invoice,
+        crate::statement_api::get_statement,
+        crate::statement_api::export_ledger,
+        openapi_json,
+    ),
+    components(schemas(
+        crate::error::Problem,
+        crate::models::CreateSessionRequest,
+        crate::models::CreateSessionResponse,
+        crate::models::ActivateSessionRequest,
+        crate::models::EndSessionRequest,
+        crate::models::StreamingSession,
+        crate::pricing::PricingPlan,
+        crate::pricing::PriceTier,
+        crate::pricing::MeterPrice,
+        crate::models::SessionStatus,
+        crate::models::BillingTransaction,
+        crate::models::TransactionStatus,
+        crate::zcash::zcash_api::CreatePermissionApiRequest,
+        crate::zcash::zcash_service::SpendingPermission,
+        crate::zcash::zcash_service::PermissionStatus,
+        crate::zcash::zcash_service::CreatePermissionResponse,
+        crate::zcash::zcash_service::PermissionStatusResponse,
+        crate::zcash::zcash_service::WalletBalanceResponse,
+        crate::permit::SubmitPermitRequest,
+        crate::permit::EthereumPermit,
+        crate::permit::PermitDomainResponse,
+        crate::ownership::CreateChallengeRequest,
+        crate::ownership::VerifyChallengeRequest,
+        crate::ownership::WalletChallenge,
+        crate::auth::WalletProof,
+        crate::events::BillingEvent,
+        crate::events::BillingEventKind,
+        crate::webhooks::WebhookEvent,
+        crate::webhooks::CreateWebhookEndpointRequest,
+        crate::webhooks::WebhookEndpoint,
+        crate::webhooks::CreatedWebhookEndpoint,
+        crate::webhooks::WebhookDeadLetter,
+        crate::metrics::SchedulerMetricsSnapshot,
+        crate::metering::RecordUsageRequest,
+        crate::metering::UsageEvent,
+        crate::metering::UsageCharge,
+        crate::invoice::IssueInvoiceRequest,
+        crate::invoice::Invoice,
+        crate::invoice::InvoiceKind,
+        crate::invoice::DocumentType,
+        crate::invoice::InvoiceLine,
+        crate::invoice::LineStatus,
+        crate::invoice::LineGrouping,
+        crate::invoice::InvoiceTotal,
+        crate::invoice_api::InvoiceFormat,
+        crate::statement::Statement,
+        crate::statement::StatementParty,
+        crate::statement::StatementSummary,
+        crate::statement::LedgerEntry,
+        crate::statement::LedgerKind,
+        crate::fees::SplitRole,
+        crate::statement_api::StatementFormat,
+        crate::export::ExportKind,
+        crate::export::ExportFormat,
+    )),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "sessions", description = "Streaming session lifecycle"),
+        (name = "zcash", description = "Zcash spending permissions"),
+        (name = "ethereum", description = "EIP-712 spending permits"),
+        (name = "wallets", description = "Wallet ownership proofs"),
+        (name = "events", description = "Real-time billing events over Server-Sent Events"),
+        (name = "webhooks", description = "Signed outbound webhooks for billing events"),
+        (name = "usage", description = "Metered usage of non-streaming services"),
+        (name = "invoices", description = "Numbered invoices and receipts for sessions and periods"),
+        (name = "statements", description = "Wallet and vendor statements and accounting exports"),
+        (name = "service", description = "Health, metrics and metadata"),
+    )
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "bearer_jwt",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+        components.add_security_scheme(
+            "wallet_proof",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Wallet-Proof"))),
+        );
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/openapi.json",
+    tag = "service",
+    responses((status = 200, description = "This document", body = Object))
+)]
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::collections::BTreeSet;
+
+    const METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+    /// (method, path) of every route registered in `api::configure_routes`
+    fn registered_routes() -> BTreeSet<(String, String)> {
+        let route = Regex::new(r#"\.route\("([^"]+)",\s*web::(\w+)\(\)"#).unwrap();
+
+        route
+            .captures_iter(include_str!("api.rs"))
+            .map(|c| (c[2].to_string(), format!("/api/v1{}", &c[1])))
+            .collect()
+    }
+
+    /// (method, path) of every operation in the generated document
+    fn documented_routes(doc: &serde_json::Value) -> BTreeSet<(String, String)> {
+        doc["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .filter(|method| METHODS.contains(&method.as_str()))
+                    .map(move |method| (method.clone(), path.clone()))
+            })
+            .collect()
+    }
+
+    fn schema_refs(value: &serde_json::Value, refs: &mut BTreeSet<String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    match (key.as_str(), value.as_str()) {
+                        ("$ref", Some(reference)) => {
+                            refs.insert(reference.trim_start_matches("#/components/schemas/").to_string());
+                        }
+                        _ => schema_refs(value, refs),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter().for_each(|value| schema_refs(value, refs)),
+            _ => {}
+        }
+    }
+
+    fn document() -> serde_json::Value {
+        serde_json::to_value(ApiDoc::openapi()).unwrap()
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let registered = registered_routes();
+        assert!(!registered.is_empty(), "no routes found in api.rs");
+
+        let documented = documented_routes(&document());
+        let missing: Vec<_> = registered.difference(&documented).collect();
+        assert!(missing.is_empty(), "routes without an OpenAPI operation: {:?}", missing);
+    }
+
+    #[test]
+    fn every_documented_route_is_registered() {
+        let registered = registered_routes();
+        let documented = documented_routes(&document());
+        let stale: Vec<_> = documented.difference(&registered).collect();
+        assert!(stale.is_empty(), "OpenAPI operations with no route: {:?}", stale);
+    }
+
+    #[test]
+    fn every_schema_reference_resolves() {
+        let doc = document();
+        let mut refs = BTreeSet::new();
+        schema_refs(&doc["paths"], &mut refs);
+        schema_refs(&doc["components"]["schemas"], &mut refs);
+
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+        let unresolved: Vec<_> = refs.iter().filter(|name| !schemas.contains_key(*name)).collect();
+        assert!(unresolved.is_empty(), "schemas missing from components: {:?}", unresolved);
+    }
+}
@@ -0,0 +1,342 @@
+// src/oracle.rs
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::{info, warn};
+
+use crate::config::{PriceOracleConfig, PriceSource, SecretString};
+use crate::error::BillingError;
+use crate::interval::Rounding;
+
+/// Price of one unit of a settlement asset (ZEC, ETH or a token) in a fiat
+/// currency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceQuote {
+    pub asset: String,
+    pub currency: String,
+    pub price: Decimal,
+    pub observed_at: DateTime<Utc>,
+}
+
+/// A fiat amount converted into a settlement asset at a checked price
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversion {
+    pub amount: Decimal, // in the asset, rounded to its precision
+    pub fiat_amount: Decimal,
+    pub quote: PriceQuote,
+}
+
+impl Conversion {
+    /// Another fiat amount, such as an hourly rate, at the same price
+    pub fn to_asset(&self, fiat_amount: Decimal) -> Decimal {
+        fiat_amount / self.quote.price
+    }
+}
+
+#[async_trait]
+pub trait PriceOracle {
+    async fn quote(&self, asset: &str, currency: &str) -> Result<PriceQuote, BillingError>;
+}
+
+fn pair(asset: &str, currency: &str) -> String {
+    format!("{}/{}", asset.to_uppercase(), currency.to_uppercase())
+}
+
+/// Prices read once from a JSON file such as `{"ZEC/USD": 28.5}`, for
+/// offline and test deployments. They never go stale.
+pub struct FixedPriceOracle {
+    prices: HashMap<String, Decimal>,
+}
+
+impl FixedPriceOracle {
+    pub fn new(prices: HashMap<String, Decimal>) -> Self {
+        Self {
+            prices: prices.into_iter().map(|(pair, price)| (pair.to_uppercase(), price)).collect(),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, BillingError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| BillingError::Config(format!("Cannot read price file {}: {}", path, e)))?;
+        let prices: HashMap<String, Decimal> = serde_json::from_str(&contents)
+            .map_err(|e| BillingError::Config(format!("Invalid price file {}: {}", path, e)))?;
+
+        info!("Loaded {} fixed prices from {}", prices.len(), path);
+
+        Ok(Self::new(prices))
+    }
+}
+
+#[async_trait]
+impl PriceOracle for FixedPriceOracle {
+    async fn quote(&self, asset: &str, currency: &str) -> Result<PriceQuote, BillingError> {
+        let pair = pair(asset, currency);
+        let price = self
+            .prices
+            .get(&pair)
+            .ok_or_else(|| BillingError::Upstream(format!("No fixed price for {}", pair)))?;
+
+        Ok(PriceQuote {
+            asset: asset.to_uppercase(),
+            currency: currency.to_uppercase(),
+            price: *price,
+            observed_at: Utc::now(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedPrice {
+    price: Decimal,
+    observed_at: DateTime<Utc>,
+}
+
+/// A price feed answering `GET {url}/prices/{asset}/{currency}` with
+/// `{"price": 28.5, "observed_at": "2024-01-15T10:30:00Z"}`
+pub struct HttpPriceOracle {
+    client: reqwest::Client,
+    url: String,
+    auth_token: Option<SecretString>,
+}
+
+impl HttpPriceOracle {
+    pub fn new(url: String, auth_token: Option<SecretString>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            auth_token,
+        }
+    }
+}
+
+#[async_trait]
+impl PriceOracle for HttpPriceOracle {
+    async fn quote(&self, asset: &str, currency: &str) -> Result<PriceQuote, BillingError> {
+        let url = format!("{}/prices/{}/{}", self.url, asset.to_uppercase(), currency.to_uppercase());
+        let mut request = self.client.get(&url);
+        if let Some(token) = &self.auth_token {
+            request = request.bearer_auth(token.expose());
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| BillingError::Upstream(format!("Price feed request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(BillingError::Upstream(format!(
+                "Price feed returned status {} for {}",
+                response.status(),
+                pair(asset, currency)
+            )));
+        }
+
+        let feed: FeedPrice = response
+            .json()
+            .await
+            .map_err(|e| BillingError::Upstream(format!("Invalid price feed JSON: {}", e)))?;
+
+        Ok(PriceQuote {
+            asset: asset.to_uppercase(),
+            currency: currency.to_uppercase(),
+            price: feed.price,
+            observed_at: feed.observed_at,
+        })
+    }
+}
+
+/// Refuse a quote that is older than `max_age`, or that moved more than
+/// `max_deviation_percent` from the last accepted quote for the pair while
+/// that one is still recent enough to compare against
+fn check_quote(
+    quote: &PriceQuote,
+    previous: Option<&PriceQuote>,
+    now: DateTime<Utc>,
+    max_age: Duration,
+    max_deviation_percent: Decimal,
+) -> Result<(), BillingError> {
+    let pair = pair(&quote.asset, &quote.currency);
+
+    if quote.price <= Decimal::ZERO {
+        return Err(BillingError::Upstream(format!("Price feed returned {} for {}", quote.price, pair)));
+    }
+    if now.signed_duration_since(quote.observed_at) > max_age {
+        return Err(BillingError::Upstream(format!(
+            "Price for {} is stale: observed at {}",
+            pair, quote.observed_at
+        )));
+    }
+
+    let previous = previous.filter(|previous| now.signed_duration_since(previous.observed_at) <= max_age);
+    if let (Some(previous), true) = (previous, max_deviation_percent > Decimal::ZERO) {
+        let deviation = ((quote.price - previous.price) / previous.price).abs() * Decimal::ONE_HUNDRED;
+        if deviation > max_deviation_percent {
+            return Err(BillingError::Upstream(format!(
+                "Price for {} moved {}% from {} to {}",
+                pair,
+                deviation.round_dp(2),
+                previous.price,
+                quote.price
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts charges of fiat-priced plans into the asset they settle in.
+/// Quotes are reused for `cache_seconds` and pass `check_quote` before
+/// use; the last accepted quote per pair is kept in this process only.
+pub struct PriceService {
+    oracle: Box<dyn PriceOracle + Send + Sync>,
+    max_age: Duration,
+    max_deviation_percent: Decimal,
+    cache_ttl: std::time::Duration,
+    accepted: Mutex<HashMap<String, (PriceQuote, Instant)>>,
+}
+
+impl PriceService {
+    pub fn new(oracle: Box<dyn PriceOracle + Send + Sync>, config: &PriceOracleConfig) -> Self {
+        Self {
+            oracle,
+            max_age: Duration::seconds(config.max_age_seconds),
+            max_deviation_percent: config.max_deviation_percent,
+            cache_ttl: std::time::Duration::from_secs(config.cache_seconds),
+            accepted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_config(config: &PriceOracleConfig) -> Result<Self, BillingError> {
+        let oracle: Box<dyn PriceOracle + Send + Sync> = match &config.source {
+            PriceSource::Fixed { path: Some(path) } => Box::new(FixedPriceOracle::from_file(path)?),
+            PriceSource::Fixed { path: None } => Box::new(FixedPriceOracle::new(HashMap::new())),
+            PriceSource::Http { url, auth_token } => Box::new(HttpPriceOracle::new(url.clone(), auth_token.clone())),
+        };
+
+        Ok(Self::new(oracle, config))
+    }
+
+    /// A checked price of one unit of `asset` in `currency`
+    pub async fn price(&self, asset: &str, currency: &str) -> Result<PriceQuote, BillingError> {
+        let key = pair(asset, currency);
+        if let Some((quote, fetched_at)) = self.accepted.lock().unwrap().get(&key) {
+            if fetched_at.elapsed() < self.cache_ttl {
+                return Ok(quote.clone());
+            }
+        }
+
+        let quote = self.oracle.quote(asset, currency).await?;
+
+        let mut accepted = self.accepted.lock().unwrap();
+        let previous = accepted.get(&key).map(|(previous, _)| previous);
+        if let Err(e) = check_quote(&quote, previous, Utc::now(), self.max_age, self.max_deviation_percent) {
+            warn!("Refusing price: {}", e);
+            return Err(e);
+        }
+        accepted.insert(key, (quote.clone(), Instant::now()));
+
+        Ok(quote)
+    }
+
+    /// `fiat_amount` of `currency` in `asset`, rounded to `precision`
+    pub async fn convert(
+        &self,
+        fiat_amount: Decimal,
+        currency: &str,
+        asset: &str,
+        precision: u32,
+        rounding: Rounding,
+    ) -> Result<Conversion, BillingError> {
+        let quote = self.price(asset, currency).await?;
+
+        Ok(Conversion {
+            amount: rounding.round(fiat_amount / quote.price, precision),
+            fiat_amount,
+            quote,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    fn quote(price: &str, age_seconds: i64, now: DateTime<Utc>) -> PriceQuote {
+        PriceQuote {
+            asset: "ZEC".to_string(),
+            currency: "USD".to_string(),
+            price: dec(price),
+            observed_at: now - Duration::seconds(age_seconds),
+        }
+    }
+
+    fn config(max_deviation_percent: &str) -> PriceOracleConfig {
+        PriceOracleConfig {
+            source: PriceSource::Fixed { path: None },
+            max_age_seconds: 300,
+            max_deviation_percent: dec(max_deviation_percent),
+            cache_seconds: 0,
+        }
+    }
+
+    #[test]
+    fn stale_quotes_are_refused() {
+        let now = Utc::now();
+        let max_age = Duration::seconds(300);
+
+        assert!(check_quote(&quote("30", 299, now), None, now, max_age, dec("10")).is_ok());
+        assert!(check_quote(&quote("30", 301, now), None, now, max_age, dec("10")).is_err());
+    }
+
+    #[test]
+    fn large_moves_from_a_recent_quote_are_refused() {
+        let now = Utc::now();
+        let max_age = Duration::seconds(300);
+        let previous = quote("30", 60, now);
+
+        assert!(check_quote(&quote("32.9", 0, now), Some(&previous), now, max_age, dec("10")).is_ok());
+        assert!(check_quote(&quote("33.1", 0, now), Some(&previous), now, max_age, dec("10")).is_err());
+        assert!(check_quote(&quote("26.9", 0, now), Some(&previous), now, max_age, dec("10")).is_err());
+        // Checking disabled
+        assert!(check_quote(&quote("60", 0, now), Some(&previous), now, max_age, Decimal::ZERO).is_ok());
+    }
+
+    #[test]
+    fn a_move_is_accepted_once_the_previous_quote_is_too_old_to_compare() {
+        let now = Utc::now();
+        let previous = quote("30", 400, now);
+
+        assert!(check_quote(&quote("45", 0, now), Some(&previous), now, Duration::seconds(300), dec("10")).is_ok());
+    }
+
+    #[test]
+    fn non_positive_prices_are_refused() {
+        let now = Utc::now();
+
+        assert!(check_quote(&quote("0", 0, now), None, now, Duration::seconds(300), dec("10")).is_err());
+    }
+
+    #[tokio::test]
+    async fn converts_fiat_at_the_fixed_price() {
+        let oracle = FixedPriceOracle::new(HashMap::from([("zec/usd".to_string(), dec("25"))]));
+        let prices = PriceService::new(Box::new(oracle), &config("10"));
+
+        let conversion = prices.convert(dec("1.5"), "usd", "ZEC", 8, Rounding::Bankers).await.unwrap();
+        assert_eq!(conversion.amount, dec("0.06"));
+        assert_eq!(conversion.fiat_amount, dec("1.5"));
+        assert_eq!(conversion.quote.price, dec("25"));
+        assert_eq!(conversion.to_asset(dec("5")), dec("0.2"));
+
+        assert!(prices.price("ETH", "USD").await.is_err());
+    }
+}
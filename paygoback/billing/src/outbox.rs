@@ -0,0 +1,254 @@
+// src/outbox.rs
+use redis::{aio::ConnectionManager, Client as RedisClient};
+use sqlx::{FromRow, PgExecutor, PgPool};
+use tokio::sync::OnceCell;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::cache;
+use crate::config::Config;
+use crate::error::BillingError;
+use crate::events::{BillingEvent, EVENTS_CHANNEL};
+use crate::webhooks::{self, WebhookEvent};
+
+/// Redis stream every relayed event is appended to, for consumers that
+/// want a replayable log rather than webhooks
+pub const EVENTS_STREAM: &str = "paygo:events:stream";
+
+/// Record an event in the caller's transaction; it is relayed only if the
+/// transaction commits
+pub async fn record<'e>(executor: impl PgExecutor<'e>, event: &WebhookEvent) -> Result<(), BillingError> {
+    insert(executor, event, None).await
+}
+
+/// Record a session event, which also goes to Server-Sent Events clients
+pub async fn record_billing<'e>(executor: impl PgExecutor<'e>, event: &BillingEvent) -> Result<(), BillingError> {
+    let live_event = serde_json::to_string(event)
+        .map_err(|e| BillingError::Config(format!("Failed to encode event: {}", e)))?;
+
+    insert(executor, &WebhookEvent::billing(event), Some(live_event)).await
+}
+
+async fn insert<'e>(
+    executor: impl PgExecutor<'e>,
+    event: &WebhookEvent,
+    live_event: Option<String>,
+) -> Result<(), BillingError> {
+    let payload = serde_json::to_string(event)
+        .map_err(|e| BillingError::Config(format!("Failed to encode webhook event: {}", e)))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO outbox_events (id, event_type, vendor_id, payload, live_event)
+        VALUES ($1, $2, $3, $4, $5)
+        "#
+    )
+    .bind(event.id)
+    .bind(&event.event_type)
+    .bind(&event.vendor_id)
+    .bind(&payload)
+    .bind(&live_event)
+    .execute(executor)
+    .await
+    .map_err(BillingError::Database)?;
+
+    Ok(())
+}
+
+#[derive(Debug, FromRow)]
+struct OutboxRow {
+    id: Uuid,
+    seq: i64,
+    event_type: String,
+    vendor_id: Option<String>,
+    payload: String,
+    live_event: Option<String>,
+}
+
+/// Longest the relay waits between polls while Redis or the database is
+/// failing
+const MAX_RETRY_DELAY_MS: u64 = 30_000;
+
+/// Wait before the next poll: the poll interval, doubled for each
+/// consecutive failed batch up to `MAX_RETRY_DELAY_MS`
+fn retry_delay(poll_interval_ms: u64, failures: u32) -> std::time::Duration {
+    let delay = poll_interval_ms
+        .saturating_mul(1u64 << failures.min(20))
+        .min(MAX_RETRY_DELAY_MS.max(poll_interval_ms));
+
+    std::time::Duration::from_millis(delay)
+}
+
+/// A full batch means more rows may be waiting, so relay again without
+/// waiting for the next poll
+fn batch_filled(count: usize, batch_size: u32) -> bool {
+    count > 0 && count >= batch_size as usize
+}
+
+/// Moves committed outbox rows to their consumers: webhook deliveries, the
+/// `paygo:events:stream` Redis stream and the pub/sub channel behind the
+/// Server-Sent Events hub. Delivery is at least once; a batch that fails
+/// part-way is retried whole, so consumers deduplicate on the event id.
+pub struct OutboxRelay {
+    db_pool: PgPool,
+    redis_client: RedisClient,
+    connection: OnceCell<ConnectionManager>,
+    config: Config,
+}
+
+impl OutboxRelay {
+    pub fn new(db_pool: PgPool, redis_client: RedisClient, config: Config) -> Self {
+        Self {
+            db_pool,
+            redis_client,
+            connection: OnceCell::new(),
+            config,
+        }
+    }
+
+    pub async fn run(&self) {
+        let mut failures = 0;
+
+        info!("Outbox relay started");
+
+        loop {
+            tokio::time::sleep(retry_delay(self.config.outbox_poll_interval_ms, failures)).await;
+
+            // Drain the backlog before waiting for the next poll
+            loop {
+                match self.relay_batch().await {
+                    Ok(count) => {
+                        failures = 0;
+                        if !batch_filled(count, self.config.outbox_batch_size) {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        error!("Outbox relay failed ({} in a row): {:?}", failures, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Relay one batch of unpublished events. Webhook deliveries are queued
+    /// in the same transaction that marks the rows published.
+    pub async fn relay_batch(&self) -> Result<usize, BillingError> {
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        let rows = sqlx::query_as::<_, OutboxRow>(
+            r#"
+            SELECT id, seq, event_type, vendor_id, payload, live_event
+            FROM outbox_events
+            WHERE published_at IS NULL
+            ORDER BY seq
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .bind(self.config.outbox_batch_size as i64)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?;
+
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        let mut conn = self
+            .connection
+            .get_or_try_init(|| cache::get_connection(&self.redis_client))
+            .await?
+            .clone();
+
+        for row in &rows {
+            webhooks::enqueue(&mut *tx, row.id, &row.event_type, row.vendor_id.as_deref(), &row.payload).await?;
+
+            redis::cmd("XADD")
+                .arg(EVENTS_STREAM)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(self.config.outbox_stream_max_len)
+                .arg("*")
+                .arg("id")
+                .arg(row.id.to_string())
+                .arg("seq")
+                .arg(row.seq)
+                .arg("type")
+                .arg(&row.event_type)
+                .arg("payload")
+                .arg(&row.payload)
+                .query_async::<_, String>(&mut conn)
+                .await
+                .map_err(|e| BillingError::Cache(e.to_string()))?;
+
+            if let Some(live_event) = &row.live_event {
+                redis::cmd("PUBLISH")
+                    .arg(EVENTS_CHANNEL)
+                    .arg(live_event)
+                    .query_async::<_, i64>(&mut conn)
+                    .await
+                    .map_err(|e| BillingError::Cache(e.to_string()))?;
+            }
+        }
+
+        let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+        sqlx::query("UPDATE outbox_events SET published_at = NOW() WHERE id = ANY($1)")
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await
+            .map_err(BillingError::Database)?;
+
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        Ok(rows.len())
+    }
+
+    // Background job to drop relayed events past the retention window
+    pub async fn purge_published(&self) -> Result<(), BillingError> {
+        sqlx::query(
+            "DELETE FROM outbox_events WHERE published_at < NOW() - make_interval(hours => $1)"
+        )
+        .bind(self.config.outbox_retention_hours as i32)
+        .execute(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn healthy_relays_poll_at_the_configured_interval() {
+        assert_eq!(retry_delay(500, 0), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn failures_back_off_exponentially_up_to_the_cap() {
+        assert_eq!(retry_delay(500, 1), Duration::from_millis(1_000));
+        assert_eq!(retry_delay(500, 3), Duration::from_millis(4_000));
+        assert_eq!(retry_delay(500, 6), Duration::from_millis(MAX_RETRY_DELAY_MS));
+        assert_eq!(retry_delay(500, u32::MAX), Duration::from_millis(MAX_RETRY_DELAY_MS));
+    }
+
+    #[test]
+    fn a_poll_interval_above_the_cap_is_never_shortened() {
+        assert_eq!(retry_delay(60_000, 0), Duration::from_secs(60));
+        assert_eq!(retry_delay(60_000, 4), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn only_full_batches_are_drained_without_waiting() {
+        assert!(batch_filled(100, 100));
+        assert!(!batch_filled(99, 100));
+        assert!(!batch_filled(0, 100));
+        assert!(!batch_filled(0, 0));
+    }
+}
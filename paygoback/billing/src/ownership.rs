@@ -0,0 +1,382 @@
+// src/ownership.rs
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use ethers::types::{Address, Signature};
+use ethers::utils::to_checksum;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::auth::{Authenticator, Principal, WalletProof};
+use crate::config::AuthConfig;
+use crate::error::BillingError;
+use crate::validation::Validator;
+use crate::zcash::ZcashService;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WalletKind {
+    Ethereum,
+    ZcashTransparent,
+}
+
+impl WalletKind {
+    /// Shielded Zcash addresses cannot sign messages, so they can't prove
+    /// ownership this way
+    fn of(wallet_address: &str) -> Result<Self, BillingError> {
+        if wallet_address.starts_with("0x") {
+            Validator::validate_ethereum_address(wallet_address)?;
+            return Ok(WalletKind::Ethereum);
+        }
+
+        Validator::validate_zcash_address(wallet_address)?;
+        if wallet_address.starts_with('t') {
+            return Ok(WalletKind::ZcashTransparent);
+        }
+
+        Err(BillingError::Validation(
+            "Only Ethereum and transparent Zcash addresses can prove ownership".to_string(),
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateChallengeRequest {
+    pub wallet_address: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyChallengeRequest {
+    pub signature: String,
+}
+
+/// Message the wallet must sign: EIP-4361 (Sign-In with Ethereum) text for
+/// Ethereum, signed with `personal_sign`; a plain message for Zcash, signed
+/// with `zcash-cli signmessage`.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct WalletChallenge {
+    pub id: Uuid,
+    pub wallet_address: String,
+    pub message: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A stored challenge with the caller it was issued to
+#[derive(Debug, FromRow)]
+struct IssuedChallenge {
+    #[sqlx(flatten)]
+    challenge: WalletChallenge,
+    subject: String,
+}
+
+impl IssuedChallenge {
+    /// Only the caller that asked for a challenge may redeem it
+    fn redeem_by(self, principal: &Principal) -> Result<WalletChallenge, BillingError> {
+        if self.subject != principal.subject {
+            return Err(BillingError::NotFound("Challenge not found or expired".to_string()));
+        }
+
+        Ok(self.challenge)
+    }
+}
+
+/// Challenge-response proof that a caller controls a wallet. The caller asks
+/// for a challenge, signs its message with the wallet key and trades the
+/// signature for a short-lived `X-Wallet-Proof` token. Challenges are bound
+/// to the caller and single use.
+pub struct WalletOwnershipService {
+    db_pool: PgPool,
+    zcash_service: Arc<ZcashService>,
+    authenticator: Arc<Authenticator>,
+    domain: String,
+    chain_id: u64,
+    challenge_ttl_seconds: i64,
+}
+
+impl WalletOwnershipService {
+    pub fn new(
+        db_pool: PgPool,
+        zcash_service: Arc<ZcashService>,
+        authenticator: Arc<Authenticator>,
+        config: &AuthConfig,
+        chain_id: u64,
+    ) -> Self {
+        Self {
+            db_pool,
+            zcash_service,
+            authenticator,
+            domain: config.wallet_proof_domain.clone(),
+            chain_id,
+            challenge_ttl_seconds: config.wallet_challenge_ttl_seconds,
+        }
+    }
+
+    pub async fn create_challenge(
+        &self,
+        principal: &Principal,
+        wallet_address: &str,
+    ) -> Result<WalletChallenge, BillingError> {
+        let kind = WalletKind::of(wallet_address)?;
+
+        let nonce = generate_nonce();
+        let issued_at = Utc::now();
+        let expires_at = issued_at + Duration::seconds(self.challenge_ttl_seconds);
+        let message = match kind {
+            WalletKind::Ethereum => {
+                siwe_message(&self.domain, self.chain_id, wallet_address, &nonce, issued_at, expires_at)?
+            }
+            WalletKind::ZcashTransparent => zcash_message(wallet_address, &nonce, issued_at, expires_at),
+        };
+
+        let challenge = sqlx::query_as::<_, WalletChallenge>(
+            r#"
+            INSERT INTO wallet_challenges (id, subject, wallet_address, message, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, wallet_address, message, expires_at
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(&principal.subject)
+        .bind(wallet_address)
+        .bind(&message)
+        .bind(expires_at)
+        .fetch_one(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(challenge)
+    }
+
+    /// Check the signature over a challenge and issue a proof token
+    pub async fn verify_challenge(
+        &self,
+        principal: &Principal,
+        challenge_id: Uuid,
+        signature: &str,
+    ) -> Result<WalletProof, BillingError> {
+        let challenge = sqlx::query_as::<_, IssuedChallenge>(
+            r#"
+            SELECT id, wallet_address, message, expires_at, subject
+            FROM wallet_challenges
+            WHERE id = $1 AND used_at IS NULL AND expires_at > NOW()
+            "#
+        )
+        .bind(challenge_id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?
+        .ok_or_else(|| BillingError::NotFound("Challenge not found or expired".to_string()))?
+        .redeem_by(principal)?;
+
+        let verified = match WalletKind::of(&challenge.wallet_address)? {
+            WalletKind::Ethereum => verify_ethereum_signature(&challenge.wallet_address, &challenge.message, signature)?,
+            WalletKind::ZcashTransparent => self
+                .zcash_service
+                .verify_message(&challenge.wallet_address, signature, &challenge.message)
+                .await?,
+        };
+
+        if !verified {
+            return Err(BillingError::Forbidden(format!(
+                "Signature does not prove ownership of {}",
+                challenge.wallet_address
+            )));
+        }
+
+        // Consume the challenge; a concurrent verify of the same one loses here
+        let consumed = sqlx::query(
+            "UPDATE wallet_challenges SET used_at = NOW() WHERE id = $1 AND used_at IS NULL"
+        )
+        .bind(challenge_id)
+        .execute(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        if consumed.rows_affected() == 0 {
+            return Err(BillingError::Conflict("Challenge already used".to_string()));
+        }
+
+        info!("{} proved ownership of wallet {}", principal.subject, challenge.wallet_address);
+
+        self.authenticator.issue_wallet_proof(principal, &challenge.wallet_address)
+    }
+
+    // Background job to drop used and expired challenges
+    pub async fn purge_expired_challenges(&self) -> Result<(), BillingError> {
+        sqlx::query("DELETE FROM wallet_challenges WHERE expires_at < NOW()")
+            .execute(&self.db_pool)
+            .await
+            .map_err(BillingError::Database)?;
+
+        Ok(())
+    }
+}
+
+/// EIP-4361 sign-in message for `domain` on `chain_id`
+fn siwe_message(
+    domain: &str,
+    chain_id: u64,
+    wallet_address: &str,
+    nonce: &str,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> Result<String, BillingError> {
+    let address = Address::from_str(wallet_address)
+        .map_err(|e| BillingError::Validation(format!("Invalid wallet address: {}", e)))?;
+
+    Ok(format!(
+        "{domain} wants you to sign in with your Ethereum account:\n\
+         {address}\n\
+         \n\
+         Prove ownership of this wallet to PayGo billing.\n\
+         \n\
+         URI: https://{domain}\n\
+         Version: 1\n\
+         Chain ID: {chain_id}\n\
+         Nonce: {nonce}\n\
+         Issued At: {issued_at}\n\
+         Expiration Time: {expires_at}",
+        domain = domain,
+        address = to_checksum(&address, None),
+        chain_id = chain_id,
+        nonce = nonce,
+        issued_at = issued_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        expires_at = expires_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+    ))
+}
+
+fn zcash_message(
+    wallet_address: &str,
+    nonce: &str,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> String {
+    format!(
+        "PayGo wallet ownership proof\n\
+         Address: {}\n\
+         Nonce: {}\n\
+         Issued At: {}\n\
+         Expiration Time: {}",
+        wallet_address,
+        nonce,
+        issued_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        expires_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+}
+
+/// EIP-191 `personal_sign` signature over `message` by `wallet_address`
+fn verify_ethereum_signature(
+    wallet_address: &str,
+    message: &str,
+    signature: &str,
+) -> Result<bool, BillingError> {
+    let address = Address::from_str(wallet_address)
+        .map_err(|e| BillingError::Validation(format!("Invalid wallet address: {}", e)))?;
+    let signature = Signature::from_str(signature)
+        .map_err(|e| BillingError::Validation(format!("Invalid signature: {}", e)))?;
+
+    Ok(signature.verify(message, address).is_ok())
+}
+
+/// Alphanumeric nonce, as EIP-4361 requires
+fn generate_nonce() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut rng = rand::rng();
+
+    (0..17)
+        .map(|_| CHARSET[rng.random_range(0..CHARSET.len())] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // Hardhat's first development account
+    const ADDRESS: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+    const SIGNATURE: &str = "0x791bd503010ed4715118dd24d48ee0aa0d60ebc7d4bac0d1fbf3c8c82e47594968b9e61ebc7835c6ebd9f49f68317bbe7a11436280163cfea3e8457160ab4b7c1b";
+
+    fn principal(subject: &str) -> Principal {
+        Principal {
+            subject: subject.to_string(),
+            wallet_address: None,
+            vendor_id: None,
+            scopes: Vec::new(),
+            proven_wallets: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn verifies_a_known_personal_sign_signature() {
+        assert!(verify_ethereum_signature(ADDRESS, "PayGo wallet ownership proof", SIGNATURE).unwrap());
+        assert!(!verify_ethereum_signature(ADDRESS, "PayGo wallet ownership proof!", SIGNATURE).unwrap());
+        assert!(!verify_ethereum_signature(
+            "0x70997970c51812dc3a010c7d01b50e0d17dc79c8",
+            "PayGo wallet ownership proof",
+            SIGNATURE
+        )
+        .unwrap());
+        assert!(verify_ethereum_signature(ADDRESS, "PayGo wallet ownership proof", "0x1234").is_err());
+    }
+
+    #[test]
+    fn siwe_message_follows_eip_4361() {
+        let issued_at = Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap();
+        let expires_at = issued_at + Duration::minutes(5);
+        let message = siwe_message("app.example", 10, ADDRESS, "abc123DEF456ghi78", issued_at, expires_at).unwrap();
+
+        assert_eq!(
+            message,
+            "app.example wants you to sign in with your Ethereum account:\n\
+             0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266\n\
+             \n\
+             Prove ownership of this wallet to PayGo billing.\n\
+             \n\
+             URI: https://app.example\n\
+             Version: 1\n\
+             Chain ID: 10\n\
+             Nonce: abc123DEF456ghi78\n\
+             Issued At: 2024-01-15T10:30:00Z\n\
+             Expiration Time: 2024-01-15T10:35:00Z"
+        );
+        assert!(siwe_message("app.example", 10, "0x1234", "abc", issued_at, issued_at).is_err());
+    }
+
+    #[test]
+    fn only_ethereum_and_transparent_zcash_wallets_can_sign() {
+        assert_eq!(WalletKind::of(ADDRESS).unwrap(), WalletKind::Ethereum);
+        assert_eq!(
+            WalletKind::of("t1abcdefghijklmnopqrstuvwxyz0123456").unwrap(),
+            WalletKind::ZcashTransparent
+        );
+        assert!(matches!(
+            WalletKind::of("zs1abcdefghijklmnopqrstuvwxyz0123456"),
+            Err(BillingError::Validation(_))
+        ));
+        assert!(WalletKind::of("0x1234").is_err());
+        assert!(WalletKind::of("not a wallet").is_err());
+    }
+
+    #[test]
+    fn challenges_are_redeemed_only_by_their_subject() {
+        let issued = || IssuedChallenge {
+            challenge: WalletChallenge {
+                id: Uuid::nil(),
+                wallet_address: ADDRESS.to_string(),
+                message: "sign me".to_string(),
+                expires_at: Utc::now(),
+            },
+            subject: "user:1".to_string(),
+        };
+
+        assert_eq!(issued().redeem_by(&principal("user:1")).unwrap().message, "sign me");
+        assert!(matches!(
+            issued().redeem_by(&principal("user:2")),
+            Err(BillingError::NotFound(_))
+        ));
+    }
+}
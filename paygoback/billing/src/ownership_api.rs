@@ -0,0 +1,52 @@
+// src/ownership_api.rs
+use actix_web::{web, HttpResponse};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth::Principal;
+use crate::ownership::{CreateChallengeRequest, VerifyChallengeRequest, WalletOwnershipService};
+use crate::error::BillingError;
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/wallets/challenges",
+    tag = "wallets",
+    request_body = CreateChallengeRequest,
+    responses(
+        (status = 201, description = "Message to sign with the wallet", body = WalletChallenge),
+        (status = 400, description = "Unsupported or invalid address", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn create_challenge(
+    service: web::Data<Arc<WalletOwnershipService>>,
+    principal: Principal,
+    req: web::Json<CreateChallengeRequest>,
+) -> Result<HttpResponse, BillingError> {
+    let challenge = service.create_challenge(&principal, &req.wallet_address).await?;
+    Ok(HttpResponse::Created().json(challenge))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/wallets/challenges/{id}/verify",
+    tag = "wallets",
+    params(("id" = Uuid, Path, description = "Challenge id")),
+    request_body = VerifyChallengeRequest,
+    responses(
+        (status = 200, description = "Wallet proof token for X-Wallet-Proof", body = WalletProof),
+        (status = 403, description = "Signature does not match the wallet", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Challenge not found or expired", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Challenge already used", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn verify_challenge(
+    service: web::Data<Arc<WalletOwnershipService>>,
+    principal: Principal,
+    challenge_id: web::Path<Uuid>,
+    req: web::Json<VerifyChallengeRequest>,
+) -> Result<HttpResponse, BillingError> {
+    let proof = service.verify_challenge(&principal, *challenge_id, &req.signature).await?;
+    Ok(HttpResponse::Ok().json(proof))
+}
@@ -0,0 +1,627 @@
+// src/permit.rs
+use ethers::{
+    abi::{self, Token},
+    types::{Address, RecoveryMessage, Signature, H256, U256},
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use chrono::{DateTime, TimeZone, Utc};
+use uuid::Uuid;
+use sqlx::{PgPool, prelude::FromRow};
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::chains::ChainRegistry;
+use crate::error::BillingError;
+use crate::models::StreamingSession;
+use crate::token::from_token_units;
+use crate::zcash::zcash_service::PermissionStatus;
+
+const PERMIT_DOMAIN_NAME: &str = "PayGo Billing";
+const PERMIT_DOMAIN_VERSION: &str = "1";
+const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+const PERMIT_TYPE: &str = "SpendingPermit(address user,address vendor,address token,uint256 maxAmount,uint256 maxRatePerHour,uint256 expiry,uint256 nonce)";
+
+/// Ethereum counterpart of the Zcash `SpendingPermission`: a pre-authorised
+/// spending limit backed by an EIP-712 signature from the user's wallet.
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct EthereumPermit {
+    pub id: Uuid,
+    pub chain_id: i64,
+    pub user_wallet_address: String,
+    pub vendor_wallet_address: Option<String>, // None allows any vendor
+    pub token_address: Option<String>,         // None means the billing contract
+    pub approved_amount: Decimal,
+    pub remaining_amount: Decimal,
+    pub max_rate_per_hour: Decimal,
+    pub nonce: i64,
+    pub signature: String,
+    pub status: PermissionStatus,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Helper struct for database reading
+#[derive(Debug, FromRow)]
+struct EthereumPermitDb {
+    pub id: Uuid,
+    pub chain_id: i64,
+    pub user_wallet_address: String,
+    pub vendor_wallet_address: Option<String>,
+    pub token_address: Option<String>,
+    pub approved_amount: Decimal,
+    pub remaining_amount: Decimal,
+    pub max_rate_per_hour: Decimal,
+    pub nonce: i64,
+    pub signature: String,
+    pub status: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<EthereumPermitDb> for EthereumPermit {
+    fn from(db: EthereumPermitDb) -> Self {
+        Self {
+            id: db.id,
+            chain_id: db.chain_id,
+            user_wallet_address: db.user_wallet_address,
+            vendor_wallet_address: db.vendor_wallet_address,
+            token_address: db.token_address,
+            approved_amount: db.approved_amount,
+            remaining_amount: db.remaining_amount,
+            max_rate_per_hour: db.max_rate_per_hour,
+            nonce: db.nonce,
+            signature: db.signature,
+            status: db.status.parse().unwrap_or(PermissionStatus::Pending),
+            expires_at: db.expires_at,
+            created_at: db.created_at,
+            updated_at: db.updated_at,
+        }
+    }
+}
+
+/// The signed `SpendingPermit` message exactly as the wallet saw it. Amounts
+/// are uint256 strings in the settlement asset's smallest unit.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubmitPermitRequest {
+    pub chain_id: Option<u64>, // defaults to the default deployment
+    pub user: String,
+    pub vendor: Option<String>,
+    pub token: Option<String>,
+    pub max_amount: String,
+    pub max_rate_per_hour: String,
+    pub expiry: u64,
+    pub nonce: u64,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PermitDomainResponse {
+    pub name: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub verifying_contract: String,
+    pub primary_type: String,
+    pub permit_type: String,
+}
+
+/// Permits are signed per deployment: the EIP-712 domain binds each one to
+/// a chain id and that chain's billing contract.
+pub struct PermitService {
+    db_pool: PgPool,
+    chains: Arc<ChainRegistry>,
+}
+
+impl PermitService {
+    pub fn new(db_pool: PgPool, chains: Arc<ChainRegistry>) -> Self {
+        Self { db_pool, chains }
+    }
+
+    /// EIP-712 domain clients need to build the typed data for signing
+    pub fn domain(&self, chain_id: Option<u64>) -> Result<PermitDomainResponse, BillingError> {
+        let chain_id = chain_id.unwrap_or(self.chains.default_chain_id());
+        let client = self.chains.client(chain_id)?;
+
+        Ok(PermitDomainResponse {
+            name: PERMIT_DOMAIN_NAME.to_string(),
+            version: PERMIT_DOMAIN_VERSION.to_string(),
+            chain_id,
+            verifying_contract: client.contract_address().to_string(),
+            primary_type: "SpendingPermit".to_string(),
+            permit_type: PERMIT_TYPE.to_string(),
+        })
+    }
+
+    /// Verify a signed permit and store it as an active spending permit
+    pub async fn submit_permit(
+        &self,
+        request: SubmitPermitRequest,
+    ) -> Result<EthereumPermit, BillingError> {
+        let chain_id = request.chain_id.unwrap_or(self.chains.default_chain_id());
+        let client = self.chains.client(chain_id)?;
+        let verifying_contract = parse_address(client.contract_address(), "contract")?;
+
+        let user = parse_address(&request.user, "user")?;
+        let vendor = match &request.vendor {
+            Some(vendor) => parse_address(vendor, "vendor")?,
+            None => Address::zero(),
+        };
+        let token = match &request.token {
+            Some(token) => parse_address(token, "token")?,
+            None => Address::zero(),
+        };
+
+        let max_amount = U256::from_dec_str(&request.max_amount)
+            .map_err(|e| BillingError::Validation(format!("Invalid max amount: {}", e)))?;
+        let max_rate_per_hour = U256::from_dec_str(&request.max_rate_per_hour)
+            .map_err(|e| BillingError::Validation(format!("Invalid max rate: {}", e)))?;
+
+        let expires_at = Utc
+            .timestamp_opt(request.expiry as i64, 0)
+            .single()
+            .ok_or_else(|| BillingError::Validation("Invalid permit expiry".to_string()))?;
+
+        if expires_at <= Utc::now() {
+            return Err(BillingError::Validation("Permit has expired".to_string()));
+        }
+
+        let digest = permit_digest(
+            chain_id,
+            verifying_contract,
+            user,
+            vendor,
+            token,
+            max_amount,
+            max_rate_per_hour,
+            U256::from(request.expiry),
+            U256::from(request.nonce),
+        );
+
+        let signature = Signature::from_str(&request.signature)
+            .map_err(|e| BillingError::Validation(format!("Invalid permit signature: {}", e)))?;
+
+        let signer = signature
+            .recover(RecoveryMessage::Hash(H256::from(digest)))
+            .map_err(|e| BillingError::Validation(format!("Invalid permit signature: {}", e)))?;
+
+        if signer != user {
+            return Err(BillingError::Validation(
+                "Permit signature does not match user wallet".to_string()
+            ));
+        }
+
+        let decimals = match &request.token {
+            Some(token_address) => client.token_decimals(token_address).await?,
+            None => client.contract_decimals(),
+        };
+
+        let approved_amount = from_token_units(max_amount, decimals)?;
+        let now = Utc::now();
+
+        let permit = EthereumPermit {
+            id: Uuid::new_v4(),
+            chain_id: chain_id as i64,
+            user_wallet_address: format_address(user),
+            vendor_wallet_address: request.vendor.as_ref().map(|_| format_address(vendor)),
+            token_address: request.token.as_ref().map(|_| format_address(token)),
+            approved_amount,
+            remaining_amount: approved_amount,
+            max_rate_per_hour: from_token_units(max_rate_per_hour, decimals)?,
+            nonce: request.nonce as i64,
+            signature: request.signature,
+            status: PermissionStatus::Active,
+            expires_at,
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.save_permit(&permit).await?;
+
+        info!(
+            "Stored Ethereum spending permit {} on chain {} for user {} - {} up to {} per hour",
+            permit.id,
+            permit.chain_id,
+            permit.user_wallet_address,
+            permit.approved_amount,
+            permit.max_rate_per_hour
+        );
+
+        Ok(permit)
+    }
+
+    pub async fn get_permit(&self, permit_id: Uuid) -> Result<EthereumPermit, BillingError> {
+        let permit = sqlx::query_as::<_, EthereumPermitDb>(
+            r#"
+            SELECT id, chain_id, user_wallet_address, vendor_wallet_address, token_address,
+                   approved_amount, remaining_amount, max_rate_per_hour, nonce,
+                   signature, status, expires_at, created_at, updated_at
+            FROM ethereum_permits
+            WHERE id = $1
+            "#
+        )
+        .bind(permit_id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        permit
+            .map(|p| p.into())
+            .ok_or(BillingError::PermitNotFound)
+    }
+
+    pub async fn revoke_permit(&self, permit_id: Uuid) -> Result<EthereumPermit, BillingError> {
+        sqlx::query(
+            r#"
+            UPDATE ethereum_permits
+            SET status = 'revoked', updated_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(permit_id)
+        .execute(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        info!("Revoked Ethereum permit {}", permit_id);
+
+        self.get_permit(permit_id).await
+    }
+
+    /// Atomically reserve `amount` from a permit that covers this session's
+    /// chain, user, vendor and token, and allows `rate_per_hour` in the
+    /// session's token. Call `release` if the charge then fails.
+    pub async fn reserve(
+        &self,
+        session: &StreamingSession,
+        amount: Decimal,
+        rate_per_hour: Decimal,
+    ) -> Result<EthereumPermit, BillingError> {
+        let user = session.user_wallet_address.to_lowercase();
+        let vendor = session.vendor_wallet_address.to_lowercase();
+        let token = session.token_address.as_ref().map(|t| t.to_lowercase());
+
+        let permit = sqlx::query_as::<_, EthereumPermitDb>(
+            r#"
+            UPDATE ethereum_permits
+            SET remaining_amount = remaining_amount - $1,
+                status = CASE WHEN remaining_amount - $1 <= 0 THEN 'exhausted' ELSE status END,
+                updated_at = NOW()
+            WHERE id = (
+                SELECT id
+                FROM ethereum_permits
+                WHERE user_wallet_address = $2
+                AND (vendor_wallet_address IS NULL OR vendor_wallet_address = $3)
+                AND token_address IS NOT DISTINCT FROM $4
+                AND max_rate_per_hour >= $5
+                AND chain_id = $6
+                AND remaining_amount >= $1
+                AND status = 'active'
+                AND expires_at > NOW()
+                ORDER BY expires_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, chain_id, user_wallet_address, vendor_wallet_address, token_address,
+                      approved_amount, remaining_amount, max_rate_per_hour, nonce,
+                      signature, status, expires_at, created_at, updated_at
+            "#
+        )
+        .bind(amount)
+        .bind(&user)
+        .bind(&vendor)
+        .bind(&token)
+        .bind(rate_per_hour)
+        .bind(session.chain_id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        if let Some(permit) = permit {
+            return Ok(permit.into());
+        }
+
+        // Nothing matched; work out why so the caller gets a useful error
+        match self.get_active_permit(session.chain_id, &user, &vendor, token.as_deref()).await? {
+            None => Err(BillingError::PermitNotFound),
+            Some(permit) if permit.max_rate_per_hour < rate_per_hour => Err(BillingError::Conflict(
+                format!(
+                    "Session rate {} exceeds permit max rate {}",
+                    rate_per_hour, permit.max_rate_per_hour
+                )
+            )),
+            Some(_) => Err(BillingError::InsufficientBalance),
+        }
+    }
+
+    /// Return a reservation whose on-chain charge did not go through
+    pub async fn release(&self, permit_id: Uuid, amount: Decimal) -> Result<(), BillingError> {
+        sqlx::query(
+            r#"
+            UPDATE ethereum_permits
+            SET remaining_amount = remaining_amount + $1,
+                status = CASE WHEN status = 'exhausted' THEN 'active' ELSE status END,
+                updated_at = NOW()
+            WHERE id = $2
+            "#
+        )
+        .bind(amount)
+        .bind(permit_id)
+        .execute(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn get_active_permit(
+        &self,
+        chain_id: i64,
+        user_wallet_address: &str,
+        vendor_wallet_address: &str,
+        token_address: Option<&str>,
+    ) -> Result<Option<EthereumPermit>, BillingError> {
+        let permit = sqlx::query_as::<_, EthereumPermitDb>(
+            r#"
+            SELECT id, chain_id, user_wallet_address, vendor_wallet_address, token_address,
+                   approved_amount, remaining_amount, max_rate_per_hour, nonce,
+                   signature, status, expires_at, created_at, updated_at
+            FROM ethereum_permits
+            WHERE user_wallet_address = $1
+            AND (vendor_wallet_address IS NULL OR vendor_wallet_address = $2)
+            AND token_address IS NOT DISTINCT FROM $3
+            AND chain_id = $4
+            AND status = 'active'
+            AND expires_at > NOW()
+            ORDER BY expires_at
+            LIMIT 1
+            "#
+        )
+        .bind(user_wallet_address.to_lowercase())
+        .bind(vendor_wallet_address.to_lowercase())
+        .bind(token_address.map(|t| t.to_lowercase()))
+        .bind(chain_id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(permit.map(|p| p.into()))
+    }
+
+    // Background job to mark permits past their expiry
+    pub async fn check_expired_permits(&self) -> Result<(), BillingError> {
+        sqlx::query(
+            r#"
+            UPDATE ethereum_permits
+            SET status = 'expired', updated_at = NOW()
+            WHERE status = 'active'
+            AND expires_at < NOW()
+            "#
+        )
+        .execute(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(())
+    }
+
+    async fn save_permit(&self, permit: &EthereumPermit) -> Result<(), BillingError> {
+        sqlx::query(
+            r#"
+            INSERT INTO ethereum_permits
+            (id, chain_id, user_wallet_address, vendor_wallet_address, token_address,
+             approved_amount, remaining_amount, max_rate_per_hour, nonce,
+             signature, status, expires_at, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            "#
+        )
+        .bind(permit.id)
+        .bind(permit.chain_id)
+        .bind(&permit.user_wallet_address)
+        .bind(&permit.vendor_wallet_address)
+        .bind(&permit.token_address)
+        .bind(permit.approved_amount)
+        .bind(permit.remaining_amount)
+        .bind(permit.max_rate_per_hour)
+        .bind(permit.nonce)
+        .bind(&permit.signature)
+        .bind(permit.status.to_string())
+        .bind(permit.expires_at)
+        .bind(permit.created_at)
+        .bind(permit.updated_at)
+        .execute(&self.db_pool)
+        .await
+        .map_err(save_error)?;
+
+        Ok(())
+    }
+}
+
+/// A permit whose nonce was already submitted violates
+/// `idx_eth_permits_chain_nonce`; that is a resubmission, not a server fault
+fn save_error(e: sqlx::Error) -> BillingError {
+    match e.as_database_error().and_then(|db_error| db_error.code()) {
+        Some(code) if code == "23505" => {
+            BillingError::Conflict("A permit with this nonce was already submitted".to_string())
+        }
+        _ => BillingError::Database(e),
+    }
+}
+
+fn parse_address(value: &str, field: &str) -> Result<Address, BillingError> {
+    value
+        .parse()
+        .map_err(|e| BillingError::Validation(format!("Invalid {} address: {}", field, e)))
+}
+
+// Permits are matched case-insensitively, so addresses are stored lowercase
+fn format_address(address: Address) -> String {
+    format!("{:?}", address)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn permit_digest(
+    chain_id: u64,
+    verifying_contract: Address,
+    user: Address,
+    vendor: Address,
+    token: Address,
+    max_amount: U256,
+    max_rate_per_hour: U256,
+    expiry: U256,
+    nonce: U256,
+) -> [u8; 32] {
+    let domain_separator = keccak256(abi::encode(&[
+        Token::FixedBytes(keccak256(DOMAIN_TYPE).to_vec()),
+        Token::FixedBytes(keccak256(PERMIT_DOMAIN_NAME).to_vec()),
+        Token::FixedBytes(keccak256(PERMIT_DOMAIN_VERSION).to_vec()),
+        Token::Uint(U256::from(chain_id)),
+        Token::Address(verifying_contract),
+    ]));
+
+    let struct_hash = keccak256(abi::encode(&[
+        Token::FixedBytes(keccak256(PERMIT_TYPE).to_vec()),
+        Token::Address(user),
+        Token::Address(vendor),
+        Token::Address(token),
+        Token::Uint(max_amount),
+        Token::Uint(max_rate_per_hour),
+        Token::Uint(expiry),
+        Token::Uint(nonce),
+    ]));
+
+    let mut message = Vec::with_capacity(66);
+    message.extend_from_slice(&[0x19, 0x01]);
+    message.extend_from_slice(&domain_separator);
+    message.extend_from_slice(&struct_hash);
+
+    keccak256(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::transaction::eip712::{Eip712, TypedData};
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use std::borrow::Cow;
+
+    /// A Postgres error reduced to its SQLSTATE
+    #[derive(Debug)]
+    struct PgError(&'static str);
+
+    impl std::fmt::Display for PgError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "SQLSTATE {}", self.0)
+        }
+    }
+
+    impl std::error::Error for PgError {}
+
+    impl DatabaseError for PgError {
+        fn message(&self) -> &str {
+            "database error"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            match self.0 {
+                "23505" => ErrorKind::UniqueViolation,
+                _ => ErrorKind::Other,
+            }
+        }
+    }
+
+    #[test]
+    fn resubmitted_nonce_is_a_conflict() {
+        let error = save_error(sqlx::Error::Database(Box::new(PgError("23505"))));
+        assert!(matches!(error, BillingError::Conflict(_)));
+    }
+
+    #[test]
+    fn other_save_failures_stay_database_errors() {
+        let error = save_error(sqlx::Error::Database(Box::new(PgError("23503"))));
+        assert!(matches!(error, BillingError::Database(_)));
+
+        let error = save_error(sqlx::Error::PoolTimedOut);
+        assert!(matches!(error, BillingError::Database(_)));
+    }
+
+    #[test]
+    fn permit_digest_matches_eip_712_typed_data() {
+        let address = |value: &str| Address::from_str(value).unwrap();
+        let digest = permit_digest(
+            1,
+            address("0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"),
+            address("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"),
+            address("0x70997970C51812dc3A010C7d01b50e0d17dc79C8"),
+            Address::zero(),
+            U256::exp10(18),
+            U256::exp10(17),
+            U256::from(1_700_000_000u64),
+            U256::from(7),
+        );
+
+        let typed_data: TypedData = serde_json::from_value(serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    {"name": "name", "type": "string"},
+                    {"name": "version", "type": "string"},
+                    {"name": "chainId", "type": "uint256"},
+                    {"name": "verifyingContract", "type": "address"}
+                ],
+                "SpendingPermit": [
+                    {"name": "user", "type": "address"},
+                    {"name": "vendor", "type": "address"},
+                    {"name": "token", "type": "address"},
+                    {"name": "maxAmount", "type": "uint256"},
+                    {"name": "maxRatePerHour", "type": "uint256"},
+                    {"name": "expiry", "type": "uint256"},
+                    {"name": "nonce", "type": "uint256"}
+                ]
+            },
+            "primaryType": "SpendingPermit",
+            "domain": {
+                "name": "PayGo Billing",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "user": "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+                "vendor": "0x70997970C51812dc3A010C7d01b50e0d17dc79C8",
+                "token": "0x0000000000000000000000000000000000000000",
+                "maxAmount": "1000000000000000000",
+                "maxRatePerHour": "100000000000000000",
+                "expiry": 1700000000,
+                "nonce": 7
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(digest, typed_data.encode_eip712().unwrap());
+        assert_eq!(
+            hex::encode(digest),
+            "3b4cc44a0fc100c0c2ae18fa7ddcab2e236e66fe55b47d1678dab328febaa07d"
+        );
+    }
+}
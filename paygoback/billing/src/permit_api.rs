@@ -0,0 +1,110 @@
+// src/permit_api.rs
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::auth::{Access, Principal};
+use crate::permit::{PermitService, SubmitPermitRequest};
+use crate::validation::Validator;
+use crate::error::BillingError;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct PermitDomainQuery {
+    pub chain_id: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/eth/permits/domain",
+    tag = "ethereum",
+    params(PermitDomainQuery),
+    responses(
+        (status = 200, description = "EIP-712 signing domain", body = PermitDomainResponse),
+        (status = 400, description = "No deployment on that chain", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_permit_domain(
+    service: web::Data<Arc<PermitService>>,
+    query: web::Query<PermitDomainQuery>,
+) -> Result<HttpResponse, BillingError> {
+    let domain = service.domain(query.chain_id)?;
+    Ok(HttpResponse::Ok().json(domain))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/eth/permits",
+    tag = "ethereum",
+    request_body = SubmitPermitRequest,
+    responses(
+        (status = 201, description = "Permit stored", body = EthereumPermit),
+        (status = 400, description = "Invalid permit or signature", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Not the permit's wallet", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Permit nonce already submitted", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn submit_permit(
+    service: web::Data<Arc<PermitService>>,
+    principal: Principal,
+    req: web::Json<SubmitPermitRequest>,
+) -> Result<HttpResponse, BillingError> {
+    // Validate inputs
+    Validator::validate_ethereum_address(&req.user)?;
+
+    for address in [&req.vendor, &req.token].into_iter().flatten() {
+        Validator::validate_ethereum_address(address)?;
+    }
+
+    principal.authorize_wallet(&req.user, Access::Write)?;
+
+    let permit = service.submit_permit(req.into_inner()).await?;
+    Ok(HttpResponse::Created().json(permit))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/eth/permits/{id}",
+    tag = "ethereum",
+    params(("id" = Uuid, Path, description = "Permit id")),
+    responses(
+        (status = 200, description = "Permit", body = EthereumPermit),
+        (status = 404, description = "Permit not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn get_permit(
+    service: web::Data<Arc<PermitService>>,
+    principal: Principal,
+    permit_id: web::Path<Uuid>,
+) -> Result<HttpResponse, BillingError> {
+    let permit = service.get_permit(*permit_id).await?;
+    principal.authorize_wallet(&permit.user_wallet_address, Access::Read)?;
+
+    Ok(HttpResponse::Ok().json(permit))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/eth/permits/{id}/revoke",
+    tag = "ethereum",
+    params(("id" = Uuid, Path, description = "Permit id")),
+    responses(
+        (status = 200, description = "Permit revoked", body = EthereumPermit),
+        (status = 404, description = "Permit not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn revoke_permit(
+    service: web::Data<Arc<PermitService>>,
+    principal: Principal,
+    permit_id: web::Path<Uuid>,
+) -> Result<HttpResponse, BillingError> {
+    let permit = service.get_permit(*permit_id).await?;
+    principal.authorize_wallet(&permit.user_wallet_address, Access::Write)?;
+
+    let permit = service.revoke_permit(*permit_id).await?;
+    Ok(HttpResponse::Ok().json(permit))
+}
@@ -0,0 +1,282 @@
+// src/pricing.rs
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::BillingError;
+
+pub const MICROS_PER_HOUR: i64 = 3_600_000_000;
+const MICROS_PER_MINUTE: i64 = 60_000_000;
+const MICROS_PER_SECOND: i64 = 1_000_000;
+
+/// How a vendor prices streaming time. Sessions keep a snapshot of the plan
+/// they started under, so a vendor changing prices never reprices a running
+/// session.
+///
+/// Usage is the session's active time, rounded up to whole
+/// `billing_increment_seconds`. The first `free_minutes` of it cost nothing;
+/// the rest is charged at `rate_per_hour`, or at the rate of the latest tier
+/// that has started. `session_fee` is added once usage starts, a session
+/// that ends is charged at least `minimum_charge`, and no session is
+/// charged more than `max_charge` in total. `meters` price usage reported
+/// by the vendor (see `metering`), which none of the above applies to.
+///
+/// Amounts are in the asset the session settles in, unless `currency`
+/// names a fiat currency (ISO 4217, such as USD) to convert from at
+/// billing time (see `oracle`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PricingPlan {
+    pub rate_per_hour: Decimal,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub tiers: Vec<PriceTier>, // ascending by after_minutes
+    #[serde(default)]
+    pub billing_increment_seconds: u32, // 60 for per-minute pricing, 0 to bill exact time
+    #[serde(default)]
+    pub free_minutes: u32,
+    #[serde(default)]
+    pub session_fee: Decimal,
+    #[serde(default)]
+    pub minimum_charge: Decimal,
+    #[serde(default)]
+    pub max_charge: Option<Decimal>,
+    #[serde(default)]
+    pub meters: Vec<MeterPrice>, // metered usage billed on top of streaming time
+}
+
+/// Per-unit price of a metered service such as API calls or GB transferred
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MeterPrice {
+    pub meter: String,
+    pub unit: String,
+    pub price_per_unit: Decimal,
+}
+
+/// Rate from `after_minutes` of usage on, until the next tier
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PriceTier {
+    pub after_minutes: u32,
+    pub rate_per_hour: Decimal,
+}
+
+impl PricingPlan {
+    /// A plain per-hour rate, as vendors without a plan are billed
+    pub fn flat(rate_per_hour: Decimal) -> Self {
+        Self {
+            rate_per_hour,
+            currency: None,
+            tiers: Vec::new(),
+            billing_increment_seconds: 0,
+            free_minutes: 0,
+            session_fee: Decimal::ZERO,
+            minimum_charge: Decimal::ZERO,
+            max_charge: None,
+            meters: Vec::new(),
+        }
+    }
+
+    pub fn meter(&self, meter: &str) -> Option<&MeterPrice> {
+        self.meters.iter().find(|price| price.meter == meter)
+    }
+
+    /// Highest hourly rate the plan charges at any point, which a spending
+    /// permit's max rate must cover
+    pub fn peak_rate_per_hour(&self) -> Decimal {
+        self.tiers
+            .iter()
+            .map(|tier| tier.rate_per_hour)
+            .fold(self.rate_per_hour, Decimal::max)
+    }
+
+    pub fn validate(&self) -> Result<(), BillingError> {
+        let invalid = |reason: &str| Err(BillingError::Validation(format!("Invalid pricing plan: {}", reason)));
+        let max_rate = Decimal::from(1_000);
+
+        let rates = std::iter::once(self.rate_per_hour).chain(self.tiers.iter().map(|tier| tier.rate_per_hour));
+        for rate in rates {
+            if rate < Decimal::ZERO || rate > max_rate {
+                return invalid("rates must be between 0 and 1000 per hour");
+            }
+        }
+        if let Some(currency) = &self.currency {
+            if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+                return invalid("currency must be a three-letter ISO 4217 code such as USD");
+            }
+        }
+        if self.tiers.windows(2).any(|pair| pair[0].after_minutes >= pair[1].after_minutes) {
+            return invalid("tiers must be in ascending order of after_minutes");
+        }
+        if self.session_fee < Decimal::ZERO || self.minimum_charge < Decimal::ZERO {
+            return invalid("fees and minimum charge can't be negative");
+        }
+        if let Some(max_charge) = self.max_charge {
+            if max_charge <= Decimal::ZERO || max_charge < self.minimum_charge {
+                return invalid("max_charge must be positive and at least minimum_charge");
+            }
+        }
+        for (i, price) in self.meters.iter().enumerate() {
+            if price.meter.is_empty() || price.price_per_unit < Decimal::ZERO {
+                return invalid("meters need a name and a non-negative price");
+            }
+            if self.meters[..i].iter().any(|other| other.meter == price.meter) {
+                return invalid("meter names must be unique");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Exact cost of `usage_micros` of active time, before rounding.
+    /// `ended` applies the minimum charge of a finished session.
+    pub fn cost(&self, usage_micros: i64, ended: bool) -> Decimal {
+        let usage = self.round_up_to_increment(usage_micros.max(0));
+        let free = self.free_minutes as i64 * MICROS_PER_MINUTE;
+
+        // (start, rate) of each pricing period
+        let periods: Vec<(i64, Decimal)> = std::iter::once((0, self.rate_per_hour))
+            .chain(
+                self.tiers
+                    .iter()
+                    .map(|tier| (tier.after_minutes as i64 * MICROS_PER_MINUTE, tier.rate_per_hour)),
+            )
+            .collect();
+
+        let mut rate_micros = Decimal::ZERO;
+        for (i, &(start, rate)) in periods.iter().enumerate() {
+            let end = periods.get(i + 1).map_or(i64::MAX, |&(next, _)| next);
+            let charged = end.min(usage) - start.max(free);
+            if charged > 0 {
+                rate_micros += rate * Decimal::from(charged);
+            }
+        }
+
+        let mut cost = rate_micros / Decimal::from(MICROS_PER_HOUR);
+        if usage > 0 {
+            cost += self.session_fee;
+        }
+        if ended {
+            cost = cost.max(self.minimum_charge);
+        }
+        match self.max_charge {
+            Some(max_charge) => cost.min(max_charge),
+            None => cost,
+        }
+    }
+
+    fn round_up_to_increment(&self, micros: i64) -> i64 {
+        let increment = self.billing_increment_seconds as i64 * MICROS_PER_SECOND;
+        if increment <= 1 {
+            return micros;
+        }
+        micros.saturating_add(increment - 1) / increment * increment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    fn minutes(minutes: i64) -> i64 {
+        minutes * MICROS_PER_MINUTE
+    }
+
+    #[test]
+    fn flat_plan_charges_rate_times_time() {
+        let plan = PricingPlan::flat(dec("6"));
+
+        assert_eq!(plan.cost(minutes(90), false), dec("9"));
+        assert_eq!(plan.cost(36 * MICROS_PER_SECOND, false), dec("0.06"));
+    }
+
+    #[test]
+    fn per_minute_pricing_charges_started_minutes() {
+        let plan = PricingPlan {
+            billing_increment_seconds: 60,
+            ..PricingPlan::flat(dec("6"))
+        };
+
+        assert_eq!(plan.cost(1, false), dec("0.1"));
+        assert_eq!(plan.cost(minutes(1), false), dec("0.1"));
+        assert_eq!(plan.cost(minutes(1) + 1, false), dec("0.2"));
+    }
+
+    #[test]
+    fn free_minutes_are_not_charged() {
+        let plan = PricingPlan {
+            free_minutes: 10,
+            ..PricingPlan::flat(dec("6"))
+        };
+
+        assert_eq!(plan.cost(minutes(10), false), Decimal::ZERO);
+        assert_eq!(plan.cost(minutes(20), false), dec("1"));
+    }
+
+    #[test]
+    fn tiers_change_the_rate_after_their_start() {
+        let plan = PricingPlan {
+            tiers: vec![
+                PriceTier { after_minutes: 60, rate_per_hour: dec("3") },
+                PriceTier { after_minutes: 120, rate_per_hour: dec("1") },
+            ],
+            ..PricingPlan::flat(dec("6"))
+        };
+
+        assert_eq!(plan.cost(minutes(60), false), dec("6"));
+        assert_eq!(plan.cost(minutes(90), false), dec("7.5"));
+        assert_eq!(plan.cost(minutes(180), false), dec("10"));
+    }
+
+    #[test]
+    fn free_minutes_overlapping_a_tier_only_skip_the_free_part() {
+        let plan = PricingPlan {
+            free_minutes: 90,
+            tiers: vec![PriceTier { after_minutes: 60, rate_per_hour: dec("3") }],
+            ..PricingPlan::flat(dec("6"))
+        };
+
+        assert_eq!(plan.cost(minutes(120), false), dec("1.5"));
+    }
+
+    #[test]
+    fn session_fee_minimum_and_cap_apply_to_the_session_total() {
+        let plan = PricingPlan {
+            session_fee: dec("0.5"),
+            minimum_charge: dec("2"),
+            max_charge: Some(dec("5")),
+            ..PricingPlan::flat(dec("6"))
+        };
+
+        assert_eq!(plan.cost(0, false), Decimal::ZERO);
+        assert_eq!(plan.cost(minutes(5), false), dec("1"));
+        assert_eq!(plan.cost(minutes(5), true), dec("2"));
+        assert_eq!(plan.cost(minutes(600), false), dec("5"));
+    }
+
+    #[test]
+    fn validate_rejects_unordered_tiers_and_inconsistent_caps() {
+        let unordered = PricingPlan {
+            tiers: vec![
+                PriceTier { after_minutes: 60, rate_per_hour: dec("3") },
+                PriceTier { after_minutes: 30, rate_per_hour: dec("1") },
+            ],
+            ..PricingPlan::flat(dec("6"))
+        };
+        let cap_below_minimum = PricingPlan {
+            minimum_charge: dec("2"),
+            max_charge: Some(dec("1")),
+            ..PricingPlan::flat(dec("6"))
+        };
+
+        let lowercase_currency = PricingPlan {
+            currency: Some("usd".to_string()),
+            ..PricingPlan::flat(dec("6"))
+        };
+
+        assert!(PricingPlan::flat(dec("6")).validate().is_ok());
+        assert!(unordered.validate().is_err());
+        assert!(cap_below_minimum.validate().is_err());
+        assert!(lowercase_currency.validate().is_err());
+    }
+}
@@ -0,0 +1,290 @@
+// src/rpc.rs
+use async_trait::async_trait;
+use ethers::providers::{Http, Ipc, JsonRpcClient, ProviderError, RpcError, Ws};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+/// A connected JSON-RPC transport, chosen from the endpoint URL scheme:
+/// `http(s)://`, `ws(s)://`, or an IPC socket path (`ipc://` or a bare path).
+#[derive(Debug, Clone)]
+pub enum RpcTransport {
+    Http(Http),
+    Ws(Ws),
+    Ipc(Ipc),
+}
+
+impl RpcTransport {
+    pub async fn connect(url: &str) -> Result<Self, ProviderError> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            let http = Http::from_str(url)
+                .map_err(|e| ProviderError::CustomError(format!("Invalid RPC URL {}: {}", url, e)))?;
+            return Ok(RpcTransport::Http(http));
+        }
+
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            let ws = Ws::connect(url).await?;
+            return Ok(RpcTransport::Ws(ws));
+        }
+
+        let path = url.strip_prefix("ipc://").unwrap_or(url);
+        let ipc = Ipc::connect(path).await?;
+        Ok(RpcTransport::Ipc(ipc))
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcTransport {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            RpcTransport::Http(client) => client.request(method, params).await.map_err(Into::into),
+            RpcTransport::Ws(client) => client.request(method, params).await.map_err(Into::into),
+            RpcTransport::Ipc(client) => client.request(method, params).await.map_err(Into::into),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RpcEndpoint {
+    url: String,
+    transport: RwLock<Option<RpcTransport>>,
+    healthy: AtomicBool,
+}
+
+impl RpcEndpoint {
+    /// Cached transport, (re)connecting if the last one was dropped
+    async fn transport(&self) -> Result<RpcTransport, ProviderError> {
+        if let Some(transport) = self.transport.read().await.as_ref() {
+            return Ok(transport.clone());
+        }
+
+        let mut slot = self.transport.write().await;
+        if let Some(transport) = slot.as_ref() {
+            return Ok(transport.clone());
+        }
+
+        let transport = RpcTransport::connect(&self.url).await?;
+        info!("Connected to RPC endpoint {}", self.url);
+        *slot = Some(transport.clone());
+        Ok(transport)
+    }
+
+    async fn mark_down(&self) {
+        self.healthy.store(false, Ordering::Relaxed);
+        *self.transport.write().await = None;
+    }
+}
+
+#[derive(Debug)]
+struct FailoverInner {
+    endpoints: Vec<RpcEndpoint>,
+    active: AtomicUsize,
+}
+
+/// JSON-RPC client over an ordered list of endpoints. Requests go to the
+/// active endpoint and fail over to the next one on transport errors;
+/// JSON-RPC error responses (reverts, nonce errors) are returned as-is.
+/// Endpoints connect lazily, so an outage at startup only delays the first
+/// successful call instead of disabling the client.
+#[derive(Debug, Clone)]
+pub struct FailoverClient {
+    inner: Arc<FailoverInner>,
+}
+
+impl FailoverClient {
+    pub fn new(urls: &[String]) -> Self {
+        let endpoints = urls
+            .iter()
+            .map(|url| RpcEndpoint {
+                url: url.clone(),
+                transport: RwLock::new(None),
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+
+        Self {
+            inner: Arc::new(FailoverInner {
+                endpoints,
+                active: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Probe every endpoint with `eth_blockNumber`, reconnecting dead ones,
+    /// and move back to the highest-priority healthy endpoint.
+    pub async fn check_health(&self) {
+        for endpoint in &self.inner.endpoints {
+            let result = match endpoint.transport().await {
+                Ok(transport) => transport
+                    .request::<_, serde_json::Value>("eth_blockNumber", ())
+                    .await
+                    .map(|_| ()),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(()) => {
+                    if !endpoint.healthy.swap(true, Ordering::Relaxed) {
+                        info!("RPC endpoint {} recovered", endpoint.url);
+                    }
+                }
+                Err(e) => {
+                    if endpoint.healthy.load(Ordering::Relaxed) {
+                        warn!("RPC endpoint {} failed health check: {}", endpoint.url, e);
+                    }
+                    endpoint.mark_down().await;
+                }
+            }
+        }
+
+        let preferred = self
+            .inner
+            .endpoints
+            .iter()
+            .position(|endpoint| endpoint.healthy.load(Ordering::Relaxed));
+
+        if let Some(index) = preferred {
+            let previous = self.inner.active.swap(index, Ordering::Relaxed);
+            if previous != index {
+                info!("Switched active RPC endpoint to {}", self.inner.endpoints[index].url);
+            }
+        }
+    }
+
+    /// Endpoint indices to try: healthy ones starting from the active
+    /// endpoint, then the ones currently marked down as a last resort.
+    fn attempt_order(&self) -> Vec<usize> {
+        let count = self.inner.endpoints.len();
+        let start = self.inner.active.load(Ordering::Relaxed);
+        let (healthy, down): (Vec<usize>, Vec<usize>) = (0..count)
+            .map(|offset| (start + offset) % count)
+            .partition(|index| self.inner.endpoints[*index].healthy.load(Ordering::Relaxed));
+
+        healthy.into_iter().chain(down).collect()
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for FailoverClient {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut last_error = None;
+
+        for index in self.attempt_order() {
+            let endpoint = &self.inner.endpoints[index];
+
+            let transport = match endpoint.transport().await {
+                Ok(transport) => transport,
+                Err(e) => {
+                    warn!("RPC endpoint {} unavailable: {}", endpoint.url, e);
+                    endpoint.mark_down().await;
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+
+            match transport.request(method, &params).await {
+                Ok(response) => {
+                    endpoint.healthy.store(true, Ordering::Relaxed);
+                    let previous = self.inner.active.swap(index, Ordering::Relaxed);
+                    if previous != index {
+                        warn!("Failed over to RPC endpoint {}", endpoint.url);
+                    }
+                    return Ok(response);
+                }
+                // The node answered; another endpoint would give the same answer
+                Err(e) if e.as_error_response().is_some() => return Err(e),
+                Err(e) => {
+                    warn!("RPC request {} to {} failed: {}", method, endpoint.url, e);
+                    endpoint.mark_down().await;
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            ProviderError::CustomError("No RPC endpoints configured".to_string())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(count: usize) -> FailoverClient {
+        let urls: Vec<String> = (0..count).map(|i| format!("http://rpc-{}.invalid", i)).collect();
+        FailoverClient::new(&urls)
+    }
+
+    fn set_healthy(client: &FailoverClient, index: usize, healthy: bool) {
+        client.inner.endpoints[index].healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn attempts_follow_configured_priority() {
+        assert_eq!(client(3).attempt_order(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn attempts_start_at_the_active_endpoint_and_wrap() {
+        let client = client(3);
+        client.inner.active.store(1, Ordering::Relaxed);
+
+        assert_eq!(client.attempt_order(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn endpoints_marked_down_are_tried_last() {
+        let client = client(4);
+        client.inner.active.store(1, Ordering::Relaxed);
+        set_healthy(&client, 2, false);
+        set_healthy(&client, 0, false);
+
+        assert_eq!(client.attempt_order(), vec![1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn all_endpoints_down_are_still_attempted() {
+        let client = client(2);
+        set_healthy(&client, 0, false);
+        set_healthy(&client, 1, false);
+
+        assert_eq!(client.attempt_order(), vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn request_without_endpoints_fails() {
+        let result = client(0).request::<_, serde_json::Value>("eth_blockNumber", ()).await;
+
+        assert!(matches!(result, Err(ProviderError::CustomError(message)) if message.contains("No RPC endpoints")));
+    }
+
+    #[tokio::test]
+    async fn unreachable_endpoints_are_marked_down() {
+        // Nothing listens on port 1, so every attempt is a transport error
+        let urls = vec!["http://127.0.0.1:1".to_string(), "http://127.0.0.1:1/backup".to_string()];
+        let client = FailoverClient::new(&urls);
+
+        let result = client.request::<_, serde_json::Value>("eth_blockNumber", ()).await;
+
+        assert!(result.is_err());
+        assert!(client.inner.endpoints.iter().all(|endpoint| !endpoint.healthy.load(Ordering::Relaxed)));
+        assert_eq!(client.inner.active.load(Ordering::Relaxed), 0);
+    }
+}
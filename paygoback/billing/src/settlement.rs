@@ -0,0 +1,342 @@
+// src/settlement.rs
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use ethers::types::{Address, U256};
+use uuid::Uuid;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use tracing::{info, warn, error};
+
+use crate::models::*;
+use crate::blockchain::BlockchainClient;
+use crate::chains::ChainRegistry;
+use crate::config::Config;
+use crate::error::BillingError;
+use crate::db;
+use crate::nonce::{SubmissionOutcome, SubmittedTransaction};
+
+/// Settles accrued interval charges on-chain in periodic `billUserBatch`
+/// calls instead of one `billUser` transaction per interval. Each chain's
+/// charges settle in their own batches against that chain's contract.
+pub struct BatchSettler {
+    db_pool: PgPool,
+    chains: Arc<ChainRegistry>,
+    config: Config,
+}
+
+impl BatchSettler {
+    pub fn new(
+        db_pool: PgPool,
+        chains: Arc<ChainRegistry>,
+        config: Config,
+    ) -> Self {
+        Self {
+            db_pool,
+            chains,
+            config,
+        }
+    }
+
+    /// Settle everything accrued since the last period on every chain, one
+    /// batch at a time until each chain's accrued pool is drained, after
+    /// re-broadcasting the chain's stuck batches. A failing chain doesn't
+    /// hold up the others.
+    pub async fn settle_accrued_charges(&self) -> Result<(), BillingError> {
+        let mut result = Ok(());
+
+        for client in self.chains.clients() {
+            if let Err(e) = self.settle_chain(client).await {
+                error!("Settlement on chain {} failed: {:?}", client.chain_id(), e);
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    async fn settle_chain(&self, client: &BlockchainClient) -> Result<(), BillingError> {
+        self.recover_stuck_batches(client).await?;
+
+        loop {
+            match self.settle_next_batch(client).await? {
+                Some(batch) if batch.charge_count == 0 => {
+                    warn!("Batch {} on chain {} had no settleable charges", batch.id, batch.chain_id);
+                }
+                Some(batch) => {
+                    info!(
+                        "Settled batch {} on chain {} covering {} charges across {} user/vendor pairs",
+                        batch.id, batch.chain_id, batch.charge_count, batch.pair_count
+                    );
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    async fn settle_next_batch(&self, client: &BlockchainClient) -> Result<Option<SettlementBatch>, BillingError> {
+        let mut batch = SettlementBatch {
+            id: Uuid::new_v4(),
+            chain_id: client.chain_id() as i64,
+            total_amount: Decimal::ZERO,
+            charge_count: 0,
+            pair_count: 0,
+            tx_hash: None,
+            nonce: None,
+            gas_price_wei: None,
+            status: TransactionStatus::Pending,
+            created_at: Utc::now(),
+            settled_at: None,
+        };
+        let batch_id = batch.id;
+
+        let charges = db::claim_accrued_transactions(
+            &self.db_pool,
+            &batch,
+            self.config.max_batch_charges as i64,
+        )
+        .await?;
+
+        if charges.is_empty() {
+            return Ok(None);
+        }
+
+        // A malformed wallet fails its own charges rather than the whole call
+        let (charges, unsettleable): (Vec<_>, Vec<_>) = charges.into_iter().partition(Self::has_valid_addresses);
+        if !unsettleable.is_empty() {
+            let ids: Vec<Uuid> = unsettleable.iter().map(|charge| charge.id).collect();
+            error!("Failing {} charges with invalid wallet addresses from batch {}: {:?}", ids.len(), batch_id, ids);
+            db::fail_batch_transactions(&self.db_pool, batch_id, &ids).await?;
+        }
+        if charges.is_empty() {
+            db::update_settlement_batch_status(&self.db_pool, batch_id, TransactionStatus::Failed).await?;
+            batch.status = TransactionStatus::Failed;
+            return Ok(Some(batch));
+        }
+
+        let pairs = Self::aggregate_by_pair(&charges);
+        batch.total_amount = pairs.iter().map(|(_, _, amount)| *amount).sum::<Decimal>();
+        batch.charge_count = charges.len() as i32;
+        batch.pair_count = pairs.len() as i32;
+
+        // A send error means nothing reached the mempool, so the charges can
+        // go in the next batch. Anything broadcast comes back pending and the
+        // batch keeps its charges until recover_batch sees it mined or cancelled.
+        let submission = match client.bill_user_batch(&pairs).await {
+            Ok(submission) => submission,
+            Err(e) => {
+                error!("Batch {} was not broadcast, releasing charges: {:?}", batch_id, e);
+                db::update_settlement_batch_status(&self.db_pool, batch_id, TransactionStatus::Failed).await?;
+                db::release_batch_transactions(&self.db_pool, batch_id).await?;
+                return Err(e);
+            }
+        };
+
+        batch.tx_hash = Some(submission.tx_hash.clone());
+        if submission.outcome == SubmissionOutcome::Reverted {
+            self.fail_reverted_batch(batch_id, &submission).await?;
+            batch.status = TransactionStatus::Failed;
+            return Ok(Some(batch));
+        }
+
+        batch.status = submission.status();
+        db::record_batch_settlement(&self.db_pool, batch_id, &submission, batch.status.clone()).await?;
+
+        Ok(Some(batch))
+    }
+
+    /// Re-broadcast batches still unconfirmed past the pending timeout under
+    /// their nonce, or cancel those older than `tx_cancel_after_seconds` and
+    /// return their charges to the accrued pool
+    async fn recover_stuck_batches(&self, client: &BlockchainClient) -> Result<(), BillingError> {
+        let now = Utc::now();
+        let cutoff = now - Duration::seconds(self.config.tx_pending_timeout_seconds as i64);
+        let cancel_before = now - Duration::seconds(self.config.tx_cancel_after_seconds as i64);
+
+        for batch in db::get_stuck_batches(&self.db_pool, client.chain_id() as i64, cutoff).await? {
+            if let Err(e) = self.recover_batch(client, &batch, batch.created_at < cancel_before).await {
+                error!("Failed to recover stuck batch {}: {:?}", batch.id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn recover_batch(&self, client: &BlockchainClient, batch: &PendingBatch, cancel: bool) -> Result<(), BillingError> {
+        let nonce = U256::from(batch.nonce as u64);
+        let gas_price = batch.gas_price_wei
+            .and_then(|price| price.to_u128())
+            .filter(|price| *price > 0)
+            .map(U256::from)
+            .ok_or_else(|| BillingError::Conflict(format!("Batch {} has no recorded gas price", batch.id)))?;
+
+        // The last broadcast may have been mined since it was recorded
+        if let Some(tx_hash) = &batch.tx_hash {
+            let outcome = client.transaction_outcome(tx_hash).await?;
+            if outcome != SubmissionOutcome::Pending {
+                let submission = SubmittedTransaction {
+                    tx_hash: tx_hash.clone(),
+                    nonce,
+                    gas_price,
+                    attempts: 1,
+                    replaced_tx_hashes: Vec::new(),
+                    outcome,
+                };
+                if outcome == SubmissionOutcome::Reverted {
+                    return self.fail_reverted_batch(batch.id, &submission).await;
+                }
+                db::record_batch_settlement(&self.db_pool, batch.id, &submission, TransactionStatus::Confirmed).await?;
+                info!("Batch {} was confirmed under nonce {}", batch.id, nonce);
+                return Ok(());
+            }
+        }
+
+        if cancel {
+            // A mined self transfer spends the nonce whether or not it reverted
+            let submission = client.cancel_transaction(nonce, gas_price).await?;
+            if submission.outcome != SubmissionOutcome::Pending {
+                db::cancel_batch(&self.db_pool, batch.id).await?;
+                info!("Cancelled batch {} under nonce {}; its charges accrue again", batch.id, nonce);
+            } else {
+                db::record_batch_gas_price(&self.db_pool, batch.id, &submission).await?;
+            }
+            return Ok(());
+        }
+
+        let charges = db::get_batch_transactions(&self.db_pool, batch.id).await?;
+        let pairs = Self::aggregate_by_pair(&charges);
+        let submission = client.replace_bill_batch(nonce, gas_price, &pairs).await?;
+        if submission.outcome == SubmissionOutcome::Reverted {
+            return self.fail_reverted_batch(batch.id, &submission).await;
+        }
+        db::record_batch_settlement(&self.db_pool, batch.id, &submission, submission.status()).await?;
+
+        info!("Replaced batch {} under nonce {}", batch.id, nonce);
+
+        Ok(())
+    }
+
+    /// A reverted `billUserBatch` moved no funds: record the failed call and
+    /// return its charges to the accrued pool for the next batch
+    async fn fail_reverted_batch(&self, batch_id: Uuid, submission: &SubmittedTransaction) -> Result<(), BillingError> {
+        error!("Batch {} reverted in {}, releasing charges", batch_id, submission.tx_hash);
+        db::revert_batch(&self.db_pool, batch_id, submission).await
+    }
+
+    fn has_valid_addresses(charge: &BillingTransaction) -> bool {
+        charge.user_wallet_address.parse::<Address>().is_ok()
+            && charge.vendor_wallet_address.parse::<Address>().is_ok()
+    }
+
+    /// Collapse interval charges into one entry per user/vendor pair, keeping
+    /// a stable order so the same charges always produce the same calldata.
+    fn aggregate_by_pair(charges: &[BillingTransaction]) -> Vec<(String, String, Decimal)> {
+        let mut totals: BTreeMap<(String, String), Decimal> = BTreeMap::new();
+
+        for charge in charges {
+            *totals
+                .entry((charge.user_wallet_address.clone(), charge.vendor_wallet_address.clone()))
+                .or_insert(Decimal::ZERO) += charge.amount;
+        }
+
+        totals
+            .into_iter()
+            .map(|((user, vendor), amount)| (user, vendor, amount))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    const ALICE: &str = "0x1111111111111111111111111111111111111111";
+    const BOB: &str = "0x2222222222222222222222222222222222222222";
+    const VENDOR: &str = "0x3333333333333333333333333333333333333333";
+
+    fn charge(user: &str, vendor: &str, amount: &str) -> BillingTransaction {
+        BillingTransaction {
+            id: Uuid::new_v4(),
+            session_id: Uuid::new_v4(),
+            user_wallet_address: user.to_string(),
+            vendor_wallet_address: vendor.to_string(),
+            amount: dec(amount),
+            fee_amount: Decimal::ZERO,
+            net_amount: dec(amount),
+            token_address: None,
+            chain_id: Some(1),
+            duration_minutes: 1,
+            duration_micros: 60_000_000,
+            tx_hash: None,
+            status: TransactionStatus::Accrued,
+            nonce: None,
+            gas_price_wei: None,
+            submission_attempts: 0,
+            replaced_tx_hashes: Vec::new(),
+            batch_id: None,
+            created_at: Utc::now(),
+            fiat_currency: None,
+            fiat_amount: None,
+            exchange_rate: None,
+            exchange_rate_at: None,
+            permit_id: None,
+        }
+    }
+
+    #[test]
+    fn charges_for_the_same_pair_are_summed() {
+        let pairs = BatchSettler::aggregate_by_pair(&[
+            charge(ALICE, VENDOR, "0.10"),
+            charge(ALICE, VENDOR, "0.25"),
+            charge(ALICE, VENDOR, "0.05"),
+        ]);
+
+        assert_eq!(pairs, vec![(ALICE.to_string(), VENDOR.to_string(), dec("0.40"))]);
+    }
+
+    #[test]
+    fn each_user_vendor_pair_gets_one_entry() {
+        let pairs = BatchSettler::aggregate_by_pair(&[
+            charge(BOB, VENDOR, "1"),
+            charge(ALICE, VENDOR, "2"),
+            charge(ALICE, BOB, "3"),
+            charge(BOB, VENDOR, "4"),
+        ]);
+
+        assert_eq!(
+            pairs,
+            vec![
+                (ALICE.to_string(), BOB.to_string(), dec("3")),
+                (ALICE.to_string(), VENDOR.to_string(), dec("2")),
+                (BOB.to_string(), VENDOR.to_string(), dec("5")),
+            ]
+        );
+    }
+
+    #[test]
+    fn pair_order_does_not_depend_on_charge_order() {
+        let charges = [charge(BOB, VENDOR, "1"), charge(ALICE, VENDOR, "2")];
+        let reversed = [charge(ALICE, VENDOR, "2"), charge(BOB, VENDOR, "1")];
+
+        assert_eq!(BatchSettler::aggregate_by_pair(&charges), BatchSettler::aggregate_by_pair(&reversed));
+    }
+
+    #[test]
+    fn no_charges_make_no_pairs() {
+        assert!(BatchSettler::aggregate_by_pair(&[]).is_empty());
+    }
+
+    #[test]
+    fn well_formed_wallets_are_settleable() {
+        assert!(BatchSettler::has_valid_addresses(&charge(ALICE, VENDOR, "1")));
+    }
+
+    #[test]
+    fn malformed_user_or_vendor_wallets_are_not_settleable() {
+        assert!(!BatchSettler::has_valid_addresses(&charge("0x1234", VENDOR, "1")));
+        assert!(!BatchSettler::has_valid_addresses(&charge(ALICE, "not-a-wallet", "1")));
+        assert!(!BatchSettler::has_valid_addresses(&charge("t1abcdefghijklmnopqrstuvwxyz0123456", VENDOR, "1")));
+        assert!(!BatchSettler::has_valid_addresses(&charge(ALICE, "", "1")));
+    }
+}
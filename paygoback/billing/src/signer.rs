@@ -0,0 +1,367 @@
+// src/signer.rs
+use async_trait::async_trait;
+use ethers::{
+    signers::{to_eip155_v, LocalWallet, Signer, WalletError},
+    types::{
+        transaction::{eip2718::TypedTransaction, eip712::Eip712},
+        Address, RecoveryMessage, Signature, H256,
+    },
+    utils::hash_message,
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use thiserror::Error;
+use tracing::info;
+
+use crate::config::{SecretString, SignerConfig};
+use crate::error::BillingError;
+
+#[derive(Error, Debug)]
+pub enum SignerError {
+    #[error(transparent)]
+    Wallet(#[from] WalletError),
+
+    #[error("Remote signer error: {0}")]
+    Remote(String),
+
+    #[error("EIP-712 encoding error: {0}")]
+    Eip712(String),
+}
+
+/// The key that signs billing transactions: a local key (raw or decrypted
+/// from a JSON keystore) or an external signing service that never hands
+/// the key to this process.
+#[derive(Debug, Clone)]
+pub enum ServiceSigner {
+    Local(LocalWallet),
+    Remote(RemoteSigner),
+}
+
+impl ServiceSigner {
+    pub async fn connect(config: &SignerConfig) -> Result<Self, BillingError> {
+        match config {
+            SignerConfig::PrivateKey { private_key } => {
+                let wallet: LocalWallet = private_key
+                    .expose()
+                    .parse()
+                    .map_err(|e| BillingError::Config(format!("Invalid private key: {}", e)))?;
+                Ok(ServiceSigner::Local(wallet))
+            }
+            SignerConfig::Keystore { path, passphrase } => {
+                let wallet = LocalWallet::decrypt_keystore(path, passphrase.expose())
+                    .map_err(|e| BillingError::Config(format!("Cannot decrypt keystore {}: {}", path, e)))?;
+                info!("Loaded signer {:?} from keystore {}", wallet.address(), path);
+                Ok(ServiceSigner::Local(wallet))
+            }
+            SignerConfig::Remote { url, auth_token } => {
+                let signer = RemoteSigner::connect(url, auth_token.clone()).await?;
+                info!("Using remote signer {:?} at {}", signer.address, url);
+                Ok(ServiceSigner::Remote(signer))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for ServiceSigner {
+    type Error = SignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            ServiceSigner::Local(wallet) => Ok(wallet.sign_message(message).await?),
+            ServiceSigner::Remote(remote) => remote.sign_message(message).await,
+        }
+    }
+
+    async fn sign_transaction(&self, message: &TypedTransaction) -> Result<Signature, Self::Error> {
+        match self {
+            ServiceSigner::Local(wallet) => Ok(wallet.sign_transaction(message).await?),
+            ServiceSigner::Remote(remote) => remote.sign_transaction(message).await,
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            ServiceSigner::Local(wallet) => Ok(wallet.sign_typed_data(payload).await?),
+            ServiceSigner::Remote(remote) => remote.sign_typed_data(payload).await,
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            ServiceSigner::Local(wallet) => wallet.address(),
+            ServiceSigner::Remote(remote) => remote.address(),
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            ServiceSigner::Local(wallet) => wallet.chain_id(),
+            ServiceSigner::Remote(remote) => remote.chain_id(),
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            ServiceSigner::Local(wallet) => ServiceSigner::Local(wallet.with_chain_id(chain_id)),
+            ServiceSigner::Remote(remote) => ServiceSigner::Remote(remote.with_chain_id(chain_id)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoteAddressResponse {
+    pub address: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoteSignRequest {
+    pub digest: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoteSignResponse {
+    pub signature: String,
+}
+
+/// Client for an external signer that signs raw 32-byte digests, the
+/// lowest common denominator of KMS/HSM-backed signing services:
+///
+/// - `GET {url}/address` returns `{"address": "0x..."}`
+/// - `POST {url}/sign` with `{"digest": "0x..."}` returns
+///   `{"signature": "0x..."}` (65 bytes `r || s || v`, `v` as 0/1 or 27/28)
+///
+/// Requests carry `Authorization: Bearer <token>` when a token is set.
+/// Transaction hashing and EIP-155 `v` are handled here, and every returned
+/// signature is checked against the signer address.
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    url: String,
+    auth_token: Option<SecretString>,
+    client: reqwest::Client,
+    address: Address,
+    chain_id: u64,
+}
+
+impl RemoteSigner {
+    pub async fn connect(url: &str, auth_token: Option<SecretString>) -> Result<Self, BillingError> {
+        let url = url.trim_end_matches('/').to_string();
+        let client = reqwest::Client::new();
+
+        let mut request = client.get(format!("{}/address", url));
+        if let Some(token) = &auth_token {
+            request = request.bearer_auth(token.expose());
+        }
+
+        let response: RemoteAddressResponse = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| BillingError::Upstream(format!("Remote signer unavailable: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| BillingError::Upstream(format!("Invalid remote signer response: {}", e)))?;
+
+        let address = response
+            .address
+            .parse()
+            .map_err(|e| BillingError::Config(format!("Invalid remote signer address: {}", e)))?;
+
+        Ok(Self {
+            url,
+            auth_token,
+            client,
+            address,
+            chain_id: 1,
+        })
+    }
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(&self, message: S) -> Result<Signature, SignerError> {
+        self.sign_digest(hash_message(message)).await
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, SignerError> {
+        let mut tx = tx.clone();
+        let chain_id = tx.chain_id().map(|id| id.as_u64()).unwrap_or(self.chain_id);
+        tx.set_chain_id(chain_id);
+
+        let mut signature = self.sign_digest(tx.sighash()).await?;
+        signature.v = to_eip155_v((signature.v - 27) as u8, chain_id);
+        Ok(signature)
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(&self, payload: &T) -> Result<Signature, SignerError> {
+        let digest = payload
+            .encode_eip712()
+            .map_err(|e| SignerError::Eip712(e.to_string()))?;
+        self.sign_digest(H256::from(digest)).await
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn with_chain_id<T: Into<u64>>(mut self, chain_id: T) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+
+    /// Signature over `digest` with `v` normalised to 27/28
+    async fn sign_digest(&self, digest: H256) -> Result<Signature, SignerError> {
+        let mut request = self
+            .client
+            .post(format!("{}/sign", self.url))
+            .json(&RemoteSignRequest {
+                digest: format!("{:?}", digest),
+            });
+        if let Some(token) = &self.auth_token {
+            request = request.bearer_auth(token.expose());
+        }
+
+        let response: RemoteSignResponse = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| SignerError::Remote(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| SignerError::Remote(format!("Invalid response: {}", e)))?;
+
+        let mut signature = Signature::from_str(&response.signature)
+            .map_err(|e| SignerError::Remote(format!("Invalid signature: {}", e)))?;
+
+        signature.v = match signature.v {
+            0 | 1 => signature.v + 27,
+            27 | 28 => signature.v,
+            v => return Err(SignerError::Remote(format!("Unexpected recovery id {}", v))),
+        };
+
+        let signer = signature
+            .recover(RecoveryMessage::Hash(digest))
+            .map_err(|e| SignerError::Remote(format!("Unrecoverable signature: {}", e)))?;
+
+        if signer != self.address {
+            return Err(SignerError::Remote(format!(
+                "Signature from {:?} does not match signer {:?}",
+                signer, self.address
+            )));
+        }
+
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{dev::ServerHandle, web, App, HttpResponse, HttpServer, Responder};
+    use ethers::types::TransactionRequest;
+
+    /// Local stand-in for the remote signer interface, backed by an in-process
+    /// wallet, so the remote signing path runs end to end without a real
+    /// signing service
+    pub struct LocalRemoteSigner {
+        pub url: String,
+        handle: ServerHandle,
+    }
+
+    impl LocalRemoteSigner {
+        pub async fn spawn(wallet: LocalWallet) -> std::io::Result<Self> {
+            let wallet = web::Data::new(wallet);
+
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(wallet.clone())
+                    .route("/address", web::get().to(local_signer_address))
+                    .route("/sign", web::post().to(local_signer_sign))
+            })
+            .workers(1)
+            .bind(("127.0.0.1", 0))?;
+
+            let url = format!("http://{}", server.addrs()[0]);
+            let server = server.run();
+            let handle = server.handle();
+            tokio::spawn(server);
+
+            Ok(Self { url, handle })
+        }
+
+        pub async fn stop(self) {
+            self.handle.stop(true).await;
+        }
+    }
+
+    async fn local_signer_address(wallet: web::Data<LocalWallet>) -> impl Responder {
+        HttpResponse::Ok().json(RemoteAddressResponse {
+            address: format!("{:?}", wallet.address()),
+        })
+    }
+
+    async fn local_signer_sign(
+        wallet: web::Data<LocalWallet>,
+        req: web::Json<RemoteSignRequest>,
+    ) -> impl Responder {
+        let digest = match H256::from_str(&req.digest) {
+            Ok(digest) => digest,
+            Err(e) => {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Invalid digest: {}", e)
+                }))
+            }
+        };
+
+        match wallet.sign_hash(digest) {
+            Ok(signature) => HttpResponse::Ok().json(RemoteSignResponse {
+                signature: format!("0x{}", signature),
+            }),
+            Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            })),
+        }
+    }
+
+    const TEST_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    #[actix_web::test]
+    async fn remote_signer_matches_local_wallet() {
+        let wallet: LocalWallet = TEST_KEY.parse().unwrap();
+        let stand_in = LocalRemoteSigner::spawn(wallet.clone()).await.unwrap();
+
+        let config = SignerConfig::Remote {
+            url: stand_in.url.clone(),
+            auth_token: None,
+        };
+        let remote = ServiceSigner::connect(&config).await.unwrap().with_chain_id(10u64);
+        let local = wallet.with_chain_id(10u64);
+        assert_eq!(remote.address(), local.address());
+
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(0x11))
+            .value(1_000u64)
+            .nonce(7u64)
+            .gas(21_000u64)
+            .gas_price(1_000_000_000u64)
+            .into();
+        assert_eq!(
+            remote.sign_transaction(&tx).await.unwrap(),
+            local.sign_transaction(&tx).await.unwrap()
+        );
+
+        assert_eq!(
+            remote.sign_message("paygo").await.unwrap(),
+            local.sign_message("paygo").await.unwrap()
+        );
+
+        stand_in.stop().await;
+    }
+}
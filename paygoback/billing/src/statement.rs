@@ -0,0 +1,635 @@
+// src/statement.rs
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::auth::{Access, Principal};
+use crate::config::Config;
+use crate::error::BillingError;
+use crate::export::{self, ExportFormat, ExportKind};
+use crate::fees::SplitRole;
+use crate::interval::ZCASH_ASSET;
+
+/// Longest range a statement or export may cover
+const MAX_RANGE_DAYS: i64 = 366;
+
+/// Written last into a month's export directory, so an interrupted run is
+/// redone
+const COMPLETE_MARKER: &str = ".complete";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LedgerKind {
+    Charge,
+    Refund, // a charge that was recorded, then failed or was cancelled
+    Payout, // a vendor's or payee's share of a charge
+}
+
+/// One row of the books: a charge, its reversal, or a share of it owed to
+/// a vendor or payee
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LedgerEntry {
+    pub kind: LedgerKind,
+    pub id: Uuid, // the transaction, usage charge or revenue split
+    pub occurred_at: DateTime<Utc>,
+    pub vendor_id: String,
+    pub user_wallet_address: String,
+    pub description: String,
+    pub asset: String,
+    pub amount: Decimal, // gross for charges and refunds, the share for payouts
+    pub fee_amount: Decimal,
+    pub net_amount: Decimal,
+    pub fiat_currency: Option<String>,
+    pub fiat_amount: Option<Decimal>,
+    pub status: String, // of the transaction or usage charge
+    pub settled: bool, // the charge reached the vendor
+    pub role: Option<SplitRole>, // payouts only
+    pub recipient: Option<String>,
+    pub recipient_wallet_address: Option<String>,
+    pub tx_hash: Option<String>,
+}
+
+/// Who a statement is for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementParty {
+    Wallet,
+    Vendor,
+}
+
+/// What a party's entries in one asset add up to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct StatementSummary {
+    pub asset: String,
+    pub charge_count: i64,
+    pub charged: Decimal, // gross
+    pub fees: Decimal,
+    pub net: Decimal,
+    pub refunded: Decimal,
+    pub payee_splits: Decimal, // paid to payees out of the net
+    pub outstanding: Decimal, // charged but not yet settled
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Statement {
+    pub party: StatementParty,
+    pub party_id: String, // wallet address or vendor id
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>, // exclusive
+    pub generated_at: DateTime<Utc>,
+    pub summaries: Vec<StatementSummary>,
+    pub entries: Vec<LedgerEntry>,
+}
+
+/// Which entries to read from the books
+#[derive(Debug, Clone, Default)]
+pub struct LedgerFilter {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub vendor_id: Option<String>,
+    pub user_wallet_address: Option<String>,
+    pub asset: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct TransactionRow {
+    id: Uuid,
+    occurred_at: DateTime<Utc>,
+    updated_at: Option<DateTime<Utc>>,
+    vendor_id: String,
+    user_wallet_address: String,
+    session_code: String,
+    chain_id: Option<i64>,
+    token_address: Option<String>,
+    amount: Decimal,
+    fee_amount: Decimal,
+    net_amount: Decimal,
+    fiat_currency: Option<String>,
+    fiat_amount: Option<Decimal>,
+    status: String,
+    batch_status: Option<String>,
+    tx_hash: Option<String>,
+}
+
+#[derive(Debug, FromRow)]
+struct UsageRow {
+    id: Uuid,
+    occurred_at: DateTime<Utc>,
+    vendor_id: String,
+    user_wallet_address: String,
+    meter: String,
+    amount: Decimal,
+    fee_amount: Decimal,
+    net_amount: Decimal,
+    fiat_currency: Option<String>,
+    fiat_amount: Option<Decimal>,
+    status: String,
+}
+
+#[derive(Debug, FromRow)]
+struct SplitRow {
+    id: Uuid,
+    occurred_at: DateTime<Utc>,
+    vendor_id: String,
+    user_wallet_address: String,
+    role: String,
+    recipient: String,
+    wallet_address: Option<String>,
+    amount: Decimal,
+    chain_id: Option<i64>,
+    token_address: Option<String>,
+    status: String,
+    settled: bool,
+    tx_hash: Option<String>,
+}
+
+/// Reads charges, refunds and payouts out of the billing tables for
+/// statements and accounting exports, and writes each month's exports to
+/// `export_dir`.
+pub struct StatementService {
+    db_pool: PgPool,
+    config: Config,
+}
+
+impl StatementService {
+    pub fn new(db_pool: PgPool, config: Config) -> Self {
+        Self { db_pool, config }
+    }
+
+    /// A wallet's or vendor's statement, for the party itself or an admin
+    pub async fn statement(
+        &self,
+        principal: &Principal,
+        party: StatementParty,
+        party_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Statement, BillingError> {
+        match party {
+            StatementParty::Wallet => principal.authorize_wallet(party_id, Access::Read)?,
+            StatementParty::Vendor => principal.authorize_vendor(Some(party_id), Access::Read)?,
+        }
+        check_range(from, to)?;
+
+        let filter = LedgerFilter {
+            from,
+            to,
+            vendor_id: (party == StatementParty::Vendor).then(|| party_id.to_string()),
+            user_wallet_address: (party == StatementParty::Wallet).then(|| party_id.to_string()),
+            asset: None,
+        };
+        let entries = self.ledger(&filter).await?;
+
+        Ok(Statement {
+            party,
+            party_id: party_id.to_string(),
+            from,
+            to,
+            generated_at: Utc::now(),
+            summaries: summarize(&entries),
+            entries,
+        })
+    }
+
+    /// One kind of entry in an accounting format, for admins and support
+    pub async fn export(
+        &self,
+        principal: &Principal,
+        kind: ExportKind,
+        format: ExportFormat,
+        filter: &LedgerFilter,
+    ) -> Result<String, BillingError> {
+        principal.authorize_vendor(None, Access::Read)?;
+        check_range(filter.from, filter.to)?;
+        if format != ExportFormat::Csv && filter.asset.is_none() {
+            return Err(BillingError::Validation(
+                "IIF and Xero exports take one asset at a time; give asset".to_string(),
+            ));
+        }
+
+        let entries = self.ledger(filter).await?;
+        Ok(export::render(&entries_of(&entries, kind), format))
+    }
+
+    /// Write the previous calendar month's exports and vendor statements
+    /// to `export_dir/YYYY-MM`, unless that month is already complete
+    pub async fn export_previous_month(&self) -> Result<(), BillingError> {
+        let Some(export_dir) = &self.config.export_dir else {
+            return Ok(());
+        };
+
+        let (from, to) = previous_month(Utc::now());
+        let month_dir = Path::new(export_dir).join(from.format("%Y-%m").to_string());
+        if tokio::fs::try_exists(month_dir.join(COMPLETE_MARKER)).await.unwrap_or(false) {
+            return Ok(());
+        }
+
+        let entries = self
+            .ledger(&LedgerFilter {
+                from,
+                to,
+                ..Default::default()
+            })
+            .await?;
+
+        let statements_dir = month_dir.join("statements");
+        tokio::fs::create_dir_all(&statements_dir).await.map_err(export_error)?;
+
+        for kind in [ExportKind::Transactions, ExportKind::Payouts, ExportKind::Refunds] {
+            let entries = entries_of(&entries, kind);
+            write(month_dir.join(format!("{}.csv", kind.as_str())), export::render(&entries, ExportFormat::Csv)).await?;
+
+            // Accounting packages keep one ledger per currency
+            let assets: BTreeSet<&str> = entries.iter().map(|entry| entry.asset.as_str()).collect();
+            for asset in assets {
+                let in_asset: Vec<LedgerEntry> = entries.iter().filter(|entry| entry.asset == asset).cloned().collect();
+                let name = format!("{}-{}", kind.as_str(), file_name(asset));
+                write(month_dir.join(format!("{}.iif", name)), export::render(&in_asset, ExportFormat::Iif)).await?;
+                write(month_dir.join(format!("{}-xero.csv", name)), export::render(&in_asset, ExportFormat::Xero)).await?;
+            }
+        }
+
+        let vendors: BTreeSet<&str> = entries.iter().map(|entry| entry.vendor_id.as_str()).collect();
+        for vendor_id in &vendors {
+            let vendor_entries: Vec<LedgerEntry> =
+                entries.iter().filter(|entry| entry.vendor_id == *vendor_id).cloned().collect();
+            let statement = Statement {
+                party: StatementParty::Vendor,
+                party_id: vendor_id.to_string(),
+                from,
+                to,
+                generated_at: Utc::now(),
+                summaries: summarize(&vendor_entries),
+                entries: vendor_entries,
+            };
+            let json = serde_json::to_string_pretty(&statement)
+                .map_err(|e| BillingError::Config(format!("Failed to encode statement: {}", e)))?;
+
+            write(statements_dir.join(format!("{}.json", file_name(vendor_id))), json).await?;
+            write(
+                statements_dir.join(format!("{}.csv", file_name(vendor_id))),
+                export::render(&statement.entries, ExportFormat::Csv),
+            )
+            .await?;
+        }
+
+        write(month_dir.join(COMPLETE_MARKER), Utc::now().to_rfc3339()).await?;
+
+        info!(
+            "Exported {} ledger entries and {} vendor statements to {}",
+            entries.len(),
+            vendors.len(),
+            month_dir.display()
+        );
+
+        Ok(())
+    }
+
+    /// Charges, refunds and payouts matching `filter`, oldest first
+    async fn ledger(&self, filter: &LedgerFilter) -> Result<Vec<LedgerEntry>, BillingError> {
+        let transactions = sqlx::query_as::<_, TransactionRow>(
+            r#"
+            SELECT t.id, t.created_at AS occurred_at, t.updated_at, s.vendor_id, t.user_wallet_address, s.session_code,
+                   t.chain_id, t.token_address, t.amount, t.fee_amount, t.net_amount,
+                   t.fiat_currency, t.fiat_amount, t.status::text AS status, b.status AS batch_status,
+                   COALESCE(t.tx_hash, b.tx_hash) AS tx_hash
+            FROM billing_transactions t
+            JOIN streaming_sessions s ON s.id = t.session_id
+            LEFT JOIN settlement_batches b ON b.id = t.batch_id
+            WHERE t.created_at >= $1 AND t.created_at < $2
+            AND ($3::text IS NULL OR s.vendor_id = $3)
+            AND ($4::text IS NULL OR t.user_wallet_address = $4)
+            "#
+        )
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(&filter.vendor_id)
+        .bind(&filter.user_wallet_address)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        let usage = sqlx::query_as::<_, UsageRow>(
+            r#"
+            SELECT c.id, c.created_at AS occurred_at, c.vendor_id, p.user_wallet_address, c.meter,
+                   c.amount, c.fee_amount, c.net_amount, c.fiat_currency, c.fiat_amount, c.status
+            FROM usage_charges c
+            JOIN spending_permissions p ON p.id = c.permission_id
+            WHERE c.created_at >= $1 AND c.created_at < $2
+            AND ($3::text IS NULL OR c.vendor_id = $3)
+            AND ($4::text IS NULL OR p.user_wallet_address = $4)
+            "#
+        )
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(&filter.vendor_id)
+        .bind(&filter.user_wallet_address)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        // The platform's share is the fee, already on the charge; splits of
+        // reversed charges were never owed
+        let splits = sqlx::query_as::<_, SplitRow>(
+            r#"
+            SELECT r.id, r.created_at AS occurred_at, r.role, r.recipient, r.wallet_address, r.amount,
+                   COALESCE(s.vendor_id, c.vendor_id) AS vendor_id,
+                   COALESCE(t.user_wallet_address, p.user_wallet_address) AS user_wallet_address,
+                   t.chain_id, t.token_address,
+                   COALESCE(t.status::text, c.status) AS status,
+                   (t.id IS NULL OR t.status = 'confirmed' OR b.status = 'confirmed') AS settled,
+                   COALESCE(t.tx_hash, b.tx_hash) AS tx_hash
+            FROM revenue_splits r
+            LEFT JOIN billing_transactions t ON t.id = r.transaction_id
+            LEFT JOIN streaming_sessions s ON s.id = t.session_id
+            LEFT JOIN settlement_batches b ON b.id = t.batch_id
+            LEFT JOIN usage_charges c ON c.id = r.usage_charge_id
+            LEFT JOIN spending_permissions p ON p.id = c.permission_id
+            WHERE r.role <> 'platform'
+            AND r.created_at >= $1 AND r.created_at < $2
+            AND (t.id IS NULL OR t.status NOT IN ('failed', 'cancelled'))
+            AND ($3::text IS NULL OR COALESCE(s.vendor_id, c.vendor_id) = $3)
+            AND ($4::text IS NULL OR COALESCE(t.user_wallet_address, p.user_wallet_address) = $4)
+            "#
+        )
+        .bind(filter.from)
+        .bind(filter.to)
+        .bind(&filter.vendor_id)
+        .bind(&filter.user_wallet_address)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        let mut entries: Vec<LedgerEntry> = Vec::new();
+
+        for row in transactions {
+            let entry = LedgerEntry {
+                kind: LedgerKind::Charge,
+                id: row.id,
+                occurred_at: row.occurred_at,
+                vendor_id: row.vendor_id,
+                user_wallet_address: row.user_wallet_address,
+                description: format!("Streaming, session {}", row.session_code),
+                asset: self.config.transaction_asset(row.chain_id, row.token_address.as_deref()),
+                amount: row.amount,
+                fee_amount: row.fee_amount,
+                net_amount: row.net_amount,
+                fiat_currency: row.fiat_currency,
+                fiat_amount: row.fiat_amount,
+                settled: row.status == "confirmed" || row.batch_status.as_deref() == Some("confirmed"),
+                status: row.status,
+                role: None,
+                recipient: None,
+                recipient_wallet_address: None,
+                tx_hash: row.tx_hash,
+            };
+            push_with_reversal(&mut entries, entry, row.updated_at);
+        }
+
+        for row in usage {
+            let entry = LedgerEntry {
+                kind: LedgerKind::Charge,
+                id: row.id,
+                occurred_at: row.occurred_at,
+                vendor_id: row.vendor_id,
+                user_wallet_address: row.user_wallet_address,
+                description: format!("{} usage", row.meter),
+                asset: ZCASH_ASSET.to_string(),
+                amount: row.amount,
+                fee_amount: row.fee_amount,
+                net_amount: row.net_amount,
+                fiat_currency: row.fiat_currency,
+                fiat_amount: row.fiat_amount,
+                settled: row.status == "charged",
+                status: row.status,
+                role: None,
+                recipient: None,
+                recipient_wallet_address: None,
+                tx_hash: None,
+            };
+            push_with_reversal(&mut entries, entry, None);
+        }
+
+        entries.extend(splits.into_iter().map(|row| LedgerEntry {
+            kind: LedgerKind::Payout,
+            id: row.id,
+            occurred_at: row.occurred_at,
+            vendor_id: row.vendor_id,
+            user_wallet_address: row.user_wallet_address,
+            description: format!("{} share", row.role),
+            asset: self.config.transaction_asset(row.chain_id, row.token_address.as_deref()),
+            amount: row.amount,
+            fee_amount: Decimal::ZERO,
+            net_amount: row.amount,
+            fiat_currency: None,
+            fiat_amount: None,
+            status: row.status,
+            settled: row.settled,
+            role: Some(if row.role == "payee" { SplitRole::Payee } else { SplitRole::Vendor }),
+            recipient: Some(row.recipient),
+            recipient_wallet_address: row.wallet_address,
+            tx_hash: row.tx_hash,
+        }));
+
+        if let Some(asset) = &filter.asset {
+            entries.retain(|entry| entry.asset.eq_ignore_ascii_case(asset));
+        }
+        entries.sort_by_key(|entry| entry.occurred_at);
+
+        Ok(entries)
+    }
+}
+
+fn check_range(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<(), BillingError> {
+    if from >= to {
+        return Err(BillingError::Validation("from must be before to".to_string()));
+    }
+    if to - from > Duration::days(MAX_RANGE_DAYS) {
+        return Err(BillingError::Validation(format!(
+            "A statement or export covers at most {} days",
+            MAX_RANGE_DAYS
+        )));
+    }
+    Ok(())
+}
+
+/// The calendar month before the one `now` is in, in UTC
+fn previous_month(now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let this_month = NaiveDate::from_ymd_opt(now.year(), now.month(), 1).unwrap_or_default();
+    let last_month = this_month
+        .pred_opt()
+        .and_then(|day| NaiveDate::from_ymd_opt(day.year(), day.month(), 1))
+        .unwrap_or_default();
+
+    (
+        Utc.from_utc_datetime(&last_month.and_hms_opt(0, 0, 0).unwrap_or_default()),
+        Utc.from_utc_datetime(&this_month.and_hms_opt(0, 0, 0).unwrap_or_default()),
+    )
+}
+
+/// Vendor ids and token symbols as safe file names
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+fn export_error(e: std::io::Error) -> BillingError {
+    BillingError::Config(format!("Failed to write exports: {}", e))
+}
+
+async fn write(path: PathBuf, contents: String) -> Result<(), BillingError> {
+    tokio::fs::write(&path, contents).await.map_err(export_error)
+}
+
+/// A charge that failed or was cancelled stays on the ledger as recorded,
+/// followed by a refund reversing it, so every export balances on its own
+fn push_with_reversal(entries: &mut Vec<LedgerEntry>, charge: LedgerEntry, reversed_at: Option<DateTime<Utc>>) {
+    let reversed = matches!(charge.status.as_str(), "failed" | "cancelled");
+    if reversed {
+        let refund = LedgerEntry {
+            kind: LedgerKind::Refund,
+            occurred_at: reversed_at.unwrap_or(charge.occurred_at).max(charge.occurred_at),
+            ..charge.clone()
+        };
+        entries.push(charge);
+        entries.push(refund);
+    } else {
+        entries.push(charge);
+    }
+}
+
+/// The entries an export kind covers: transactions are the charges
+fn entries_of(entries: &[LedgerEntry], kind: ExportKind) -> Vec<LedgerEntry> {
+    let wanted = match kind {
+        ExportKind::Transactions => LedgerKind::Charge,
+        ExportKind::Payouts => LedgerKind::Payout,
+        ExportKind::Refunds => LedgerKind::Refund,
+    };
+    entries.iter().filter(|entry| entry.kind == wanted).cloned().collect()
+}
+
+/// Totals per asset, in the order the assets first appear
+fn summarize(entries: &[LedgerEntry]) -> Vec<StatementSummary> {
+    let mut summaries: Vec<StatementSummary> = Vec::new();
+
+    for entry in entries {
+        let index = match summaries.iter().position(|summary| summary.asset == entry.asset) {
+            Some(index) => index,
+            None => {
+                summaries.push(StatementSummary {
+                    asset: entry.asset.clone(),
+                    ..Default::default()
+                });
+                summaries.len() - 1
+            }
+        };
+        let summary = &mut summaries[index];
+
+        match entry.kind {
+            LedgerKind::Charge => {
+                summary.charge_count += 1;
+                summary.charged += entry.amount;
+                summary.fees += entry.fee_amount;
+                summary.net += entry.net_amount;
+                if !entry.settled {
+                    summary.outstanding += entry.amount;
+                }
+            }
+            LedgerKind::Refund => {
+                // Reversed charges never settle
+                summary.refunded += entry.amount;
+                summary.outstanding -= entry.amount;
+            }
+            LedgerKind::Payout if entry.role == Some(SplitRole::Payee) => summary.payee_splits += entry.amount,
+            LedgerKind::Payout => {}
+        }
+    }
+
+    summaries
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    pub(crate) fn entry(kind: LedgerKind, asset: &str, amount: &str, settled: bool) -> LedgerEntry {
+        let amount = dec(amount);
+        let fee_amount = if kind == LedgerKind::Payout { Decimal::ZERO } else { amount / Decimal::TEN };
+
+        LedgerEntry {
+            kind,
+            id: Uuid::nil(),
+            occurred_at: Utc.with_ymd_and_hms(2024, 1, 15, 10, 30, 0).unwrap(),
+            vendor_id: "vendor-1".to_string(),
+            user_wallet_address: "0xuser".to_string(),
+            description: match kind {
+                LedgerKind::Payout => "payee share".to_string(),
+                _ => "Streaming, session ABC123".to_string(),
+            },
+            asset: asset.to_string(),
+            amount,
+            fee_amount,
+            net_amount: amount - fee_amount,
+            fiat_currency: None,
+            fiat_amount: None,
+            status: "confirmed".to_string(),
+            settled,
+            role: (kind == LedgerKind::Payout).then_some(SplitRole::Payee),
+            recipient: (kind == LedgerKind::Payout).then(|| "referrer-9".to_string()),
+            recipient_wallet_address: None,
+            tx_hash: Some("0xabc".to_string()),
+        }
+    }
+
+    #[test]
+    fn summaries_total_each_asset_separately() {
+        let entries = vec![
+            entry(LedgerKind::Charge, "ZEC", "1", true),
+            entry(LedgerKind::Charge, "USDC", "5", false),
+            entry(LedgerKind::Charge, "ZEC", "2", false),
+            entry(LedgerKind::Charge, "ZEC", "0.5", false),
+            entry(LedgerKind::Refund, "ZEC", "0.5", false),
+            entry(LedgerKind::Payout, "ZEC", "0.2", true),
+        ];
+
+        let summaries = summarize(&entries);
+        assert_eq!(summaries.len(), 2);
+
+        let zec = &summaries[0];
+        assert_eq!(zec.asset, "ZEC");
+        assert_eq!(zec.charge_count, 3);
+        assert_eq!(zec.charged, dec("3.5"));
+        assert_eq!(zec.fees + zec.net, zec.charged);
+        assert_eq!(zec.outstanding, Decimal::from(2));
+        assert_eq!(zec.refunded, dec("0.5"));
+        assert_eq!(zec.payee_splits, dec("0.2"));
+        assert_eq!(summaries[1].charged, Decimal::from(5));
+    }
+
+    #[test]
+    fn previous_month_spans_whole_calendar_months() {
+        let (from, to) = previous_month(Utc.with_ymd_and_hms(2024, 3, 1, 0, 15, 0).unwrap());
+        assert_eq!(from, Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+        assert_eq!(to, Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap());
+
+        let (from, _) = previous_month(Utc.with_ymd_and_hms(2024, 1, 20, 12, 0, 0).unwrap());
+        assert_eq!(from, Utc.with_ymd_and_hms(2023, 12, 1, 0, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn ranges_are_bounded() {
+        let from = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        assert!(check_range(from, from + Duration::days(31)).is_ok());
+        assert!(check_range(from, from).is_err());
+        assert!(check_range(from, from + Duration::days(400)).is_err());
+    }
+}
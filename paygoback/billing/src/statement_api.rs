@@ -0,0 +1,130 @@
+// src/statement_api.rs
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::Principal;
+use crate::error::BillingError;
+use crate::export::{self, ExportFormat, ExportKind};
+use crate::statement::{LedgerFilter, StatementParty, StatementService};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementFormat {
+    #[default]
+    Json,
+    Csv, // the entries only
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StatementQuery {
+    pub wallet_address: Option<String>,
+    pub vendor_id: Option<String>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>, // exclusive
+    #[serde(default)]
+    pub format: StatementFormat,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ExportQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>, // exclusive
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub vendor_id: Option<String>,
+    pub asset: Option<String>, // IIF and Xero imports take one currency at a time
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/statements",
+    tag = "statements",
+    params(StatementQuery),
+    responses(
+        (status = 200, description = "Charges, refunds and payouts in the range with totals per asset", content(
+            ("application/json" = Statement),
+            ("text/csv" = String),
+        )),
+        (status = 400, description = "Not exactly one of wallet_address and vendor_id, or an invalid range", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Not the wallet's owner or the vendor", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn get_statement(
+    service: web::Data<Arc<StatementService>>,
+    principal: Principal,
+    query: web::Query<StatementQuery>,
+) -> Result<HttpResponse, BillingError> {
+    let (party, party_id) = match (&query.wallet_address, &query.vendor_id) {
+        (Some(wallet_address), None) => (StatementParty::Wallet, wallet_address),
+        (None, Some(vendor_id)) => (StatementParty::Vendor, vendor_id),
+        _ => {
+            return Err(BillingError::Validation(
+                "Give exactly one of wallet_address and vendor_id".to_string(),
+            ))
+        }
+    };
+
+    let statement = service.statement(&principal, party, party_id, query.from, query.to).await?;
+
+    match query.format {
+        StatementFormat::Json => Ok(HttpResponse::Ok().json(statement)),
+        StatementFormat::Csv => Ok(HttpResponse::Ok()
+            .content_type(ExportFormat::Csv.content_type())
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"statement-{}.csv\"",
+                    statement.from.format("%Y-%m-%d")
+                ),
+            ))
+            .body(export::render(&statement.entries, ExportFormat::Csv))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/exports/{kind}",
+    tag = "statements",
+    params(("kind" = ExportKind, Path, description = "transactions, payouts or refunds"), ExportQuery),
+    responses(
+        (status = 200, description = "The entries as CSV, a QuickBooks IIF journal or a Xero bank statement", content(
+            ("text/csv" = String),
+            ("text/plain" = String),
+        )),
+        (status = 400, description = "Invalid range, or an IIF or Xero export without asset", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Not an admin or support", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn export_ledger(
+    service: web::Data<Arc<StatementService>>,
+    principal: Principal,
+    kind: web::Path<ExportKind>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse, BillingError> {
+    let filter = LedgerFilter {
+        from: query.from,
+        to: query.to,
+        vendor_id: query.vendor_id.clone(),
+        user_wallet_address: None,
+        asset: query.asset.clone(),
+    };
+    let body = service.export(&principal, *kind, query.format, &filter).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{}-{}.{}\"",
+                kind.as_str(),
+                query.from.format("%Y-%m-%d"),
+                query.format.extension()
+            ),
+        ))
+        .body(body))
+}
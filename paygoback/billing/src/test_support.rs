@@ -0,0 +1,7 @@
+// src/test_support.rs
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+pub fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
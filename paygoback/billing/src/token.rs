@@ -0,0 +1,126 @@
+// src/token.rs
+use ethers::{prelude::*, types::U256};
+use rust_decimal::Decimal;
+
+use crate::error::BillingError;
+
+// Standard ERC-20 surface used for stablecoin billing
+abigen!(
+    Erc20Token,
+    r#"[
+        function decimals() external view returns (uint8)
+        function balanceOf(address owner) external view returns (uint256)
+        function allowance(address owner, address spender) external view returns (uint256)
+        function transferFrom(address from, address to, uint256 amount) external returns (bool)
+    ]"#,
+);
+
+// Largest mantissa a rust_decimal::Decimal can hold (2^96 - 1)
+const MAX_DECIMAL_MANTISSA: u128 = (1u128 << 96) - 1;
+const MAX_DECIMAL_SCALE: u32 = 28;
+
+/// Convert a token amount into its smallest on-chain unit. Amounts are
+/// rounded to the token's precision before they get here, so significant
+/// digits beyond it are an error rather than silently dropped.
+pub fn to_token_units(amount: Decimal, decimals: u8) -> Result<U256, BillingError> {
+    if amount.is_sign_negative() {
+        return Err(BillingError::AmountOverflow(format!(
+            "Negative amount {} cannot be converted to token units",
+            amount
+        )));
+    }
+
+    // 10^78 no longer fits in a U256
+    if decimals > 77 {
+        return Err(BillingError::AmountOverflow(format!(
+            "Token decimals {} out of range",
+            decimals
+        )));
+    }
+
+    let mantissa = U256::from(amount.mantissa() as u128);
+    let scale = amount.scale();
+    let decimals = decimals as u32;
+
+    if decimals >= scale {
+        mantissa
+            .checked_mul(U256::exp10((decimals - scale) as usize))
+            .ok_or_else(|| BillingError::AmountOverflow(format!(
+                "{} does not fit in uint256 with {} decimals",
+                amount, decimals
+            )))
+    } else {
+        let (units, remainder) = mantissa.div_mod(U256::exp10((scale - decimals) as usize));
+        if !remainder.is_zero() {
+            return Err(BillingError::AmountOverflow(format!(
+                "{} has more than {} decimal places",
+                amount, decimals
+            )));
+        }
+        Ok(units)
+    }
+}
+
+/// Convert an on-chain amount in smallest units back into a Decimal.
+/// Precision beyond what Decimal can carry is truncated; values whose
+/// integer part is too large return an explicit overflow error.
+pub fn from_token_units(value: U256, decimals: u8) -> Result<Decimal, BillingError> {
+    let mut value = value;
+    let mut scale = decimals as u32;
+
+    while scale > MAX_DECIMAL_SCALE {
+        value /= U256::from(10);
+        scale -= 1;
+    }
+
+    while value > U256::from(MAX_DECIMAL_MANTISSA) && scale > 0 {
+        value /= U256::from(10);
+        scale -= 1;
+    }
+
+    if value > U256::from(MAX_DECIMAL_MANTISSA) {
+        return Err(BillingError::AmountOverflow(format!(
+            "On-chain amount with {} decimals exceeds the supported range",
+            decimals
+        )));
+    }
+
+    Ok(Decimal::from_i128_with_scale(value.as_u128() as i128, scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    #[test]
+    fn converts_six_decimal_stablecoin_amounts() {
+        assert_eq!(to_token_units(dec("1.5"), 6).unwrap(), U256::from(1_500_000u64));
+        assert_eq!(to_token_units(dec("0.000001"), 6).unwrap(), U256::one());
+        assert_eq!(to_token_units(dec("2.500000000"), 6).unwrap(), U256::from(2_500_000u64));
+        assert_eq!(from_token_units(U256::from(1_500_000u64), 6).unwrap(), dec("1.5"));
+    }
+
+    #[test]
+    fn converts_eighteen_decimal_amounts() {
+        let wei = U256::from_dec_str("1234500000000000000").unwrap();
+        assert_eq!(to_token_units(dec("1.2345"), 18).unwrap(), wei);
+        assert_eq!(to_token_units(dec("0.000000000000000001"), 18).unwrap(), U256::one());
+        assert_eq!(from_token_units(wei, 18).unwrap(), dec("1.2345"));
+
+        let max = U256::from_dec_str("79228162514264337593543950335000000000000000000").unwrap();
+        assert!(from_token_units(max, 18).is_ok());
+        assert!(from_token_units(U256::MAX, 18).is_err());
+    }
+
+    #[test]
+    fn rejects_precision_beyond_the_token_and_negative_amounts() {
+        assert!(matches!(
+            to_token_units(dec("0.0000001"), 6),
+            Err(BillingError::AmountOverflow(_))
+        ));
+        assert!(to_token_units(dec("1.0000000000000000001"), 18).is_err());
+        assert!(to_token_units(dec("-1"), 6).is_err());
+        assert!(to_token_units(Decimal::ONE, 78).is_err());
+    }
+}
@@ -1,7 +1,6 @@
 // src/validation.rs
 use regex::Regex;
 use rust_decimal::Decimal;
-use uuid::Uuid;
 use crate::error::BillingError;
 
 pub struct Validator;
@@ -13,7 +12,7 @@ impl Validator {
             .map_err(|_| BillingError::Config("Invalid regex pattern".to_string()))?;
         
         if !eth_regex.is_match(address) {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Invalid Ethereum address format".to_string()
             ));
         }
@@ -27,7 +26,7 @@ impl Validator {
             .map_err(|_| BillingError::Config("Invalid regex pattern".to_string()))?;
         
         if !zcash_regex.is_match(address) {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Invalid Zcash address format".to_string()
             ));
         }
@@ -41,7 +40,7 @@ impl Validator {
             .map_err(|_| BillingError::Config("Invalid regex pattern".to_string()))?;
         
         if !session_regex.is_match(code) {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Invalid session code format".to_string()
             ));
         }
@@ -52,7 +51,7 @@ impl Validator {
     // Validate vendor ID format
     pub fn validate_vendor_id(vendor_id: &str) -> Result<(), BillingError> {
         if vendor_id.is_empty() || vendor_id.len() > 255 {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Invalid vendor ID length".to_string()
             ));
         }
@@ -62,7 +61,7 @@ impl Validator {
             .map_err(|_| BillingError::Config("Invalid regex pattern".to_string()))?;
         
         if !vendor_regex.is_match(vendor_id) {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Invalid vendor ID format".to_string()
             ));
         }
@@ -73,7 +72,7 @@ impl Validator {
     // Validate amount (must be positive and within reasonable bounds)
     pub fn validate_amount(amount: Decimal) -> Result<(), BillingError> {
         if amount <= Decimal::ZERO {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Amount must be positive".to_string()
             ));
         }
@@ -81,7 +80,7 @@ impl Validator {
         // Set maximum reasonable amount (e.g., 1,000 ZEC)
         let max_amount = Decimal::from(1_000);
         if amount > max_amount {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Amount exceeds maximum limit".to_string()
             ));
         }
@@ -92,7 +91,7 @@ impl Validator {
     // Validate rate per hour
     pub fn validate_rate_per_hour(rate: Decimal) -> Result<(), BillingError> {
         if rate <= Decimal::ZERO {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Rate per hour must be positive".to_string()
             ));
         }
@@ -100,7 +99,7 @@ impl Validator {
         // Set maximum reasonable rate (e.g., 1,000 ZEC per hour)
         let max_rate = Decimal::from(1_000);
         if rate > max_rate {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Rate per hour exceeds maximum limit".to_string()
             ));
         }
@@ -111,14 +110,14 @@ impl Validator {
     // Validate duration in days
     pub fn validate_duration_days(days: i64) -> Result<(), BillingError> {
         if days <= 0 {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Duration must be positive".to_string()
             ));
         }
 
         // Maximum duration of 365 days
         if days > 365 {
-            return Err(BillingError::Config(
+            return Err(BillingError::Validation(
                 "Duration exceeds maximum limit".to_string()
             ));
         }
@@ -126,31 +125,6 @@ impl Validator {
         Ok(())
     }
 
-    // Validate UUID format
-    pub fn validate_uuid(uuid_str: &str) -> Result<Uuid, BillingError> {
-        Uuid::parse_str(uuid_str)
-            .map_err(|_| BillingError::Config("Invalid UUID format".to_string()))
-    }
-
-    // Validate permission ID
-    pub fn validate_permission_id(permission_id: &str) -> Result<Uuid, BillingError> {
-        Self::validate_uuid(permission_id)
-    }
-
-    // Validate streaming duration in seconds
-    pub fn validate_streaming_duration(duration_seconds: u64) -> Result<(), BillingError> {
-        // Maximum session duration of 24 hours in seconds
-        const MAX_DURATION: u64 = 24 * 60 * 60;
-        
-        if duration_seconds > MAX_DURATION {
-            return Err(BillingError::Config(
-                "Streaming duration exceeds maximum limit".to_string()
-            ));
-        }
-
-        Ok(())
-    }
-
     // Sanitize string input (remove potential XSS characters)
     pub fn sanitize_string(input: &str) -> String {
         input
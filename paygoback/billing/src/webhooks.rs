@@ -0,0 +1,708 @@
+// src/webhooks.rs
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::IpAddr;
+use sqlx::{FromRow, PgExecutor, PgPool};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::auth::{Access, Principal};
+use crate::config::WebhookConfig;
+use crate::error::BillingError;
+use crate::events::{BillingEvent, BillingEventKind};
+use crate::models::{BillingTransaction, TransactionStatus};
+
+/// Every event type an endpoint can subscribe to
+pub const EVENT_TYPES: &[&str] = &[
+    "session.started",
+    "session.billed",
+    "session.paused",
+    "session.resumed",
+    "session.ended",
+    "session.failed",
+    "permission.created",
+    "permission.activated",
+    "permission.low_balance",
+    "permission.exhausted",
+    "permission.revoked",
+    "permission.expired",
+    "transaction.pending",
+    "transaction.accrued",
+    "transaction.confirmed",
+    "transaction.failed",
+    "transaction.cancelled",
+    "usage.charged",
+    "usage.charge_failed",
+    "invoice.issued",
+];
+
+/// Header carrying `t=<unix time>,v1=<hex HMAC-SHA256 of "<t>.<body>">`
+const SIGNATURE_HEADER: &str = "PayGo-Signature";
+
+/// Body POSTed to webhook endpoints
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WebhookEvent {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub vendor_id: Option<String>, // also delivered to this vendor's endpoints
+    #[schema(value_type = Object)]
+    pub data: serde_json::Value,
+}
+
+impl WebhookEvent {
+    pub fn new(event_type: &str, vendor_id: Option<&str>, data: &impl Serialize) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            event_type: event_type.to_string(),
+            created_at: Utc::now(),
+            vendor_id: vendor_id.map(str::to_string),
+            data: serde_json::to_value(data).unwrap_or(serde_json::Value::Null),
+        }
+    }
+
+    /// Session lifecycle event, keeping the id clients saw on the event stream
+    pub fn billing(event: &BillingEvent) -> Self {
+        let event_type = match event.kind {
+            BillingEventKind::SessionStarted => "session.started",
+            BillingEventKind::SessionBilled => "session.billed",
+            BillingEventKind::LowBalance => "permission.low_balance",
+            BillingEventKind::SessionPaused => "session.paused",
+            BillingEventKind::SessionResumed => "session.resumed",
+            BillingEventKind::SessionEnded => "session.ended",
+            BillingEventKind::SessionFailed => "session.failed",
+        };
+
+        Self {
+            id: event.id,
+            created_at: event.occurred_at,
+            ..Self::new(event_type, Some(&event.vendor_id), event)
+        }
+    }
+
+    /// Transaction event named after the transaction's current status
+    pub fn transaction(transaction: &BillingTransaction, vendor_id: &str) -> Self {
+        let event_type = match transaction.status {
+            TransactionStatus::Pending => "transaction.pending",
+            TransactionStatus::Accrued => "transaction.accrued",
+            TransactionStatus::Confirmed => "transaction.confirmed",
+            TransactionStatus::Failed => "transaction.failed",
+            TransactionStatus::Cancelled => "transaction.cancelled",
+        };
+
+        Self::new(event_type, Some(vendor_id), transaction)
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateWebhookEndpointRequest {
+    pub url: String,
+    #[serde(default)]
+    pub event_types: Vec<String>, // empty subscribes to every event
+    pub vendor_id: Option<String>, // admins only; defaults to the caller's vendor
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub vendor_id: Option<String>,
+    pub url: String,
+    pub event_types: Vec<String>,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A new endpoint and its signing secret, which is only ever shown here
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedWebhookEndpoint {
+    #[serde(flatten)]
+    pub endpoint: WebhookEndpoint,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct WebhookDeadLetter {
+    pub id: Uuid,
+    pub delivery_id: Uuid,
+    pub endpoint_id: Uuid,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub replayed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, FromRow)]
+struct DueDelivery {
+    id: Uuid,
+    event_id: Uuid,
+    event_type: String,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Outbound webhooks. Events fan out into one delivery row per subscribed
+/// endpoint; `run` POSTs due deliveries with an HMAC signature, retrying
+/// failures with exponential backoff until `max_attempts`, after which the
+/// delivery moves to the dead-letter table to be replayed by hand.
+pub struct WebhookService {
+    db_pool: PgPool,
+    http: reqwest::Client,
+    config: WebhookConfig,
+}
+
+impl WebhookService {
+    pub fn new(db_pool: PgPool, config: WebhookConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(config.timeout_seconds))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build webhook HTTP client");
+
+        Self { db_pool, http, config }
+    }
+
+    pub async fn create_endpoint(
+        &self,
+        principal: &Principal,
+        request: &CreateWebhookEndpointRequest,
+    ) -> Result<CreatedWebhookEndpoint, BillingError> {
+        let vendor_id = request.vendor_id.clone().or_else(|| principal.vendor_id.clone());
+        principal.authorize_vendor(vendor_id.as_deref(), Access::Write)?;
+
+        validate_url(&request.url, self.config.allow_private_urls).await?;
+        if let Some(unknown) = request.event_types.iter().find(|t| !EVENT_TYPES.contains(&t.as_str())) {
+            return Err(BillingError::Validation(format!("Unknown event type {}", unknown)));
+        }
+
+        let secret = generate_secret();
+        let endpoint = sqlx::query_as::<_, WebhookEndpoint>(
+            r#"
+            INSERT INTO webhook_endpoints (id, vendor_id, url, secret, event_types)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, vendor_id, url, event_types, active, created_at
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(&vendor_id)
+        .bind(&request.url)
+        .bind(&secret)
+        .bind(&request.event_types)
+        .fetch_one(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        info!("Registered webhook endpoint {} for {}", endpoint.id, vendor_id.as_deref().unwrap_or("system"));
+
+        Ok(CreatedWebhookEndpoint { endpoint, secret })
+    }
+
+    pub async fn list_endpoints(&self, principal: &Principal) -> Result<Vec<WebhookEndpoint>, BillingError> {
+        let vendor_id = visible_vendor(principal)?;
+
+        let endpoints = sqlx::query_as::<_, WebhookEndpoint>(
+            r#"
+            SELECT id, vendor_id, url, event_types, active, created_at
+            FROM webhook_endpoints
+            WHERE active AND ($1::text IS NULL OR vendor_id = $1)
+            ORDER BY created_at
+            "#
+        )
+        .bind(vendor_id)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(endpoints)
+    }
+
+    /// Deactivate an endpoint; its delivery history is kept
+    pub async fn delete_endpoint(&self, principal: &Principal, endpoint_id: Uuid) -> Result<(), BillingError> {
+        let endpoint = self.get_endpoint(endpoint_id).await?;
+        principal.authorize_vendor(endpoint.vendor_id.as_deref(), Access::Write)?;
+
+        sqlx::query("UPDATE webhook_endpoints SET active = FALSE WHERE id = $1")
+            .bind(endpoint_id)
+            .execute(&self.db_pool)
+            .await
+            .map_err(BillingError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn list_dead_letters(&self, principal: &Principal) -> Result<Vec<WebhookDeadLetter>, BillingError> {
+        let vendor_id = visible_vendor(principal)?;
+
+        let dead_letters = sqlx::query_as::<_, WebhookDeadLetter>(
+            r#"
+            SELECT d.id, d.delivery_id, d.endpoint_id, d.event_id, d.event_type, d.payload,
+                   d.attempts, d.last_status_code, d.last_error, d.replayed_at, d.created_at
+            FROM webhook_dead_letters d
+            JOIN webhook_endpoints e ON e.id = d.endpoint_id
+            WHERE d.replayed_at IS NULL AND ($1::text IS NULL OR e.vendor_id = $1)
+            ORDER BY d.created_at
+            "#
+        )
+        .bind(vendor_id)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(dead_letters)
+    }
+
+    /// Queue a dead-lettered event for delivery again with fresh retries
+    pub async fn replay_dead_letter(
+        &self,
+        principal: &Principal,
+        dead_letter_id: Uuid,
+    ) -> Result<WebhookDeadLetter, BillingError> {
+        let (endpoint_id,): (Uuid,) = sqlx::query_as("SELECT endpoint_id FROM webhook_dead_letters WHERE id = $1")
+            .bind(dead_letter_id)
+            .fetch_optional(&self.db_pool)
+            .await
+            .map_err(BillingError::Database)?
+            .ok_or_else(|| BillingError::NotFound(format!("Dead letter {} not found", dead_letter_id)))?;
+
+        let endpoint = self.get_endpoint(endpoint_id).await?;
+        principal.authorize_vendor(endpoint.vendor_id.as_deref(), Access::Write)?;
+
+        if !endpoint.active {
+            return Err(BillingError::Conflict(format!("Webhook endpoint {} is deactivated", endpoint_id)));
+        }
+
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        // Claiming the dead letter first stops a concurrent replay queueing it twice
+        let dead_letter = sqlx::query_as::<_, WebhookDeadLetter>(
+            r#"
+            UPDATE webhook_dead_letters
+            SET replayed_at = NOW()
+            WHERE id = $1 AND replayed_at IS NULL
+            RETURNING id, delivery_id, endpoint_id, event_id, event_type, payload,
+                      attempts, last_status_code, last_error, replayed_at, created_at
+            "#
+        )
+        .bind(dead_letter_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?
+        .ok_or_else(|| BillingError::Conflict(format!("Dead letter {} was already replayed", dead_letter_id)))?;
+
+        // The original delivery row goes back in the queue, keeping one
+        // delivery per endpoint and event
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'pending', attempts = 0, next_attempt_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(dead_letter.delivery_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(BillingError::Database)?;
+
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        info!("Replaying webhook event {} to endpoint {}", dead_letter.event_id, dead_letter.endpoint_id);
+
+        Ok(dead_letter)
+    }
+
+    /// Deliver due webhooks until the process exits
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            self.config.poll_interval_seconds,
+        ));
+
+        info!("Webhook dispatcher started");
+
+        loop {
+            interval.tick().await;
+
+            match self.deliver_due().await {
+                Ok(0) => {}
+                Ok(count) => info!("Attempted {} webhook deliveries", count),
+                Err(e) => error!("Webhook dispatch failed: {:?}", e),
+            }
+        }
+    }
+
+    /// Attempt one batch of due deliveries
+    pub async fn deliver_due(&self) -> Result<usize, BillingError> {
+        let due = self.claim_due().await?;
+        let count = due.len();
+
+        let attempts = due.into_iter().map(|delivery| async move {
+            let outcome = self.send(&delivery).await;
+            if let Err(e) = self.record_outcome(&delivery, outcome).await {
+                error!("Failed to record webhook delivery {}: {:?}", delivery.id, e);
+            }
+        });
+        futures_util::future::join_all(attempts).await;
+
+        Ok(count)
+    }
+
+    /// Lease due deliveries by pushing `next_attempt_at` past the request
+    /// timeout, so other instances skip them while they are in flight
+    async fn claim_due(&self) -> Result<Vec<DueDelivery>, BillingError> {
+        let lease_seconds = (self.config.timeout_seconds * 2) as f64;
+
+        let due = sqlx::query_as::<_, DueDelivery>(
+            r#"
+            WITH due AS (
+                SELECT d.id
+                FROM webhook_deliveries d
+                JOIN webhook_endpoints e ON e.id = d.endpoint_id
+                WHERE d.status = 'pending' AND d.next_attempt_at <= NOW() AND e.active
+                ORDER BY d.next_attempt_at
+                LIMIT $1
+                FOR UPDATE OF d SKIP LOCKED
+            )
+            UPDATE webhook_deliveries d
+            SET next_attempt_at = NOW() + make_interval(secs => $2)
+            FROM due, webhook_endpoints e
+            WHERE d.id = due.id AND e.id = d.endpoint_id
+            RETURNING d.id, d.event_id, d.event_type, d.payload, d.attempts, e.url, e.secret
+            "#
+        )
+        .bind(self.config.batch_size as i64)
+        .bind(lease_seconds)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(due)
+    }
+
+    /// POST the delivery; `Ok` carries the 2xx status, `Err` the status (if
+    /// any) and reason it failed
+    async fn send(&self, delivery: &DueDelivery) -> Result<u16, (Option<u16>, String)> {
+        // The host may have been re-pointed since the endpoint was registered
+        validate_url(&delivery.url, self.config.allow_private_urls)
+            .await
+            .map_err(|e| (None, e.to_string()))?;
+
+        let timestamp = Utc::now().timestamp();
+        let signature = sign(&delivery.secret, timestamp, &delivery.payload);
+
+        let response = self
+            .http
+            .post(&delivery.url)
+            .header("Content-Type", "application/json")
+            .header("PayGo-Event-Id", delivery.event_id.to_string())
+            .header("PayGo-Event-Type", &delivery.event_type)
+            .header("PayGo-Delivery-Id", delivery.id.to_string())
+            .header(SIGNATURE_HEADER, format!("t={},v1={}", timestamp, signature))
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| (None, e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(status.as_u16())
+        } else {
+            Err((Some(status.as_u16()), format!("Endpoint returned {}", status)))
+        }
+    }
+
+    async fn record_outcome(
+        &self,
+        delivery: &DueDelivery,
+        outcome: Result<u16, (Option<u16>, String)>,
+    ) -> Result<(), BillingError> {
+        let (status_code, reason) = match outcome {
+            Ok(status_code) => {
+                sqlx::query(
+                    r#"
+                    UPDATE webhook_deliveries
+                    SET status = 'delivered', attempts = attempts + 1, last_status_code = $2,
+                        last_error = NULL, delivered_at = NOW()
+                    WHERE id = $1
+                    "#
+                )
+                .bind(delivery.id)
+                .bind(status_code as i32)
+                .execute(&self.db_pool)
+                .await
+                .map_err(BillingError::Database)?;
+
+                return Ok(());
+            }
+            Err(failure) => failure,
+        };
+
+        let attempts = delivery.attempts as u32 + 1;
+        if attempts >= self.config.max_attempts {
+            warn!(
+                "Webhook delivery {} of {} failed {} times, dead-lettering: {}",
+                delivery.id, delivery.event_type, attempts, reason
+            );
+
+            sqlx::query(
+                r#"
+                WITH dead AS (
+                    UPDATE webhook_deliveries
+                    SET status = 'dead', attempts = attempts + 1, last_status_code = $2, last_error = $3
+                    WHERE id = $1
+                    RETURNING id, endpoint_id, event_id, event_type, payload, attempts, last_status_code, last_error
+                )
+                INSERT INTO webhook_dead_letters
+                (delivery_id, endpoint_id, event_id, event_type, payload, attempts, last_status_code, last_error)
+                SELECT id, endpoint_id, event_id, event_type, payload, attempts, last_status_code, last_error
+                FROM dead
+                "#
+            )
+            .bind(delivery.id)
+            .bind(status_code.map(i32::from))
+            .bind(&reason)
+            .execute(&self.db_pool)
+            .await
+            .map_err(BillingError::Database)?;
+
+            return Ok(());
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET attempts = attempts + 1, last_status_code = $2, last_error = $3,
+                next_attempt_at = NOW() + make_interval(secs => $4)
+            WHERE id = $1
+            "#
+        )
+        .bind(delivery.id)
+        .bind(status_code.map(i32::from))
+        .bind(&reason)
+        .bind(backoff_seconds(&self.config, attempts))
+        .execute(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(())
+    }
+
+    async fn get_endpoint(&self, endpoint_id: Uuid) -> Result<WebhookEndpoint, BillingError> {
+        sqlx::query_as::<_, WebhookEndpoint>(
+            r#"
+            SELECT id, vendor_id, url, event_types, active, created_at
+            FROM webhook_endpoints
+            WHERE id = $1
+            "#
+        )
+        .bind(endpoint_id)
+        .fetch_optional(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?
+        .ok_or_else(|| BillingError::NotFound(format!("Webhook endpoint {} not found", endpoint_id)))
+    }
+}
+
+/// Queue an event for every active endpoint subscribed to it: system
+/// endpoints always, the vendor's endpoints when it has one. Called by the
+/// outbox relay; an event relayed twice is only queued once per endpoint.
+pub async fn enqueue<'e>(
+    executor: impl PgExecutor<'e>,
+    event_id: Uuid,
+    event_type: &str,
+    vendor_id: Option<&str>,
+    payload: &str,
+) -> Result<u64, BillingError> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, payload)
+        SELECT id, $1, $2, $3
+        FROM webhook_endpoints
+        WHERE active
+        AND (vendor_id IS NULL OR vendor_id = $4)
+        AND (cardinality(event_types) = 0 OR $2 = ANY(event_types))
+        ON CONFLICT (endpoint_id, event_id) DO NOTHING
+        "#
+    )
+    .bind(event_id)
+    .bind(event_type)
+    .bind(payload)
+    .bind(vendor_id)
+    .execute(executor)
+    .await
+    .map_err(BillingError::Database)?;
+
+    Ok(result.rows_affected())
+}
+
+/// Exponential backoff with ±20% jitter so failed endpoints aren't all
+/// retried in the same tick
+fn backoff_seconds(config: &WebhookConfig, attempts: u32) -> f64 {
+    let delay = config
+        .backoff_base_seconds
+        .saturating_mul(1u64 << attempts.saturating_sub(1).min(20))
+        .min(config.backoff_max_seconds);
+
+    delay as f64 * rand::rng().random_range(0.8..1.2)
+}
+
+/// Vendor whose webhooks the caller may see, or `None` for all of them
+fn visible_vendor(principal: &Principal) -> Result<Option<String>, BillingError> {
+    if principal.authorize_vendor(None, Access::Read).is_ok() {
+        return Ok(None);
+    }
+
+    match &principal.vendor_id {
+        Some(vendor_id) => Ok(Some(vendor_id.clone())),
+        None => Err(BillingError::Forbidden(format!("{} has no webhooks", principal.subject))),
+    }
+}
+
+/// HTTPS to a host that resolves only to public addresses, so endpoints
+/// can't reach the internal network. `allow_private` lifts both checks for
+/// local development.
+async fn validate_url(url: &str, allow_private: bool) -> Result<(), BillingError> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| BillingError::Validation(format!("Invalid webhook URL: {}", e)))?;
+
+    match parsed.scheme() {
+        "https" => {}
+        "http" if allow_private => {}
+        _ => return Err(BillingError::Validation("Webhook URL must use https".to_string())),
+    }
+    if allow_private {
+        return Ok(());
+    }
+
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| BillingError::Validation("Webhook URL has no host".to_string()))?;
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<IpAddr> = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|e| BillingError::Validation(format!("Webhook host {} doesn't resolve: {}", host, e)))?
+        .map(|address| address.ip())
+        .collect();
+
+    if addresses.is_empty() || !addresses.iter().copied().all(is_public) {
+        return Err(BillingError::Validation(format!(
+            "Webhook host {} resolves to a private address",
+            host
+        )));
+    }
+    Ok(())
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)) // carrier-grade NAT
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (first & 0xfe00) == 0xfc00 // unique local
+                    || (first & 0xffc0) == 0xfe80) // link local
+            }
+        },
+    }
+}
+
+fn generate_secret() -> String {
+    let bytes: [u8; 32] = rand::rng().random();
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+/// Hex HMAC-SHA256 of `"<timestamp>.<payload>"`; receivers recompute it
+/// with their secret and reject stale timestamps
+pub fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WebhookConfig {
+        WebhookConfig {
+            max_attempts: 8,
+            backoff_base_seconds: 10,
+            backoff_max_seconds: 3600,
+            timeout_seconds: 10,
+            poll_interval_seconds: 5,
+            batch_size: 100,
+            allow_private_urls: false,
+        }
+    }
+
+    #[test]
+    fn sign_matches_a_known_vector() {
+        assert_eq!(
+            sign("whsec_test", 1700000000, r#"{"id":"evt_1"}"#),
+            "c89214b5b5da833daed6f0b8c5bb6bd58cea9022bd80ccc78230f3942d632925"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_within_jitter_and_caps() {
+        let config = config();
+        for (attempts, delay) in [(1, 10.0), (2, 20.0), (4, 80.0), (9, 2560.0), (12, 3600.0), (64, 3600.0)] {
+            let backoff = backoff_seconds(&config, attempts);
+            assert!(
+                (delay * 0.8..delay * 1.2).contains(&backoff),
+                "attempt {} waited {}",
+                attempts,
+                backoff
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_url_rejects_plain_http_and_private_hosts() {
+        assert!(validate_url("https://93.184.216.34/hooks", false).await.is_ok());
+        assert!(validate_url("https://[2606:4700::1111]/hooks", false).await.is_ok());
+
+        for url in [
+            "http://93.184.216.34/hooks",
+            "https://127.0.0.1/hooks",
+            "https://10.1.2.3/hooks",
+            "https://192.168.0.10/hooks",
+            "https://169.254.169.254/latest/meta-data",
+            "https://100.64.0.1/hooks",
+            "https://[::1]/hooks",
+            "https://[fd00::1]/hooks",
+            "https://[::ffff:127.0.0.1]/hooks",
+            "not a url",
+        ] {
+            assert!(validate_url(url, false).await.is_err(), "accepted {}", url);
+        }
+
+        assert!(validate_url("http://127.0.0.1:8080/hooks", true).await.is_ok());
+        assert!(validate_url("ftp://127.0.0.1/hooks", true).await.is_err());
+    }
+}
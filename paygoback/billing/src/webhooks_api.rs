@@ -0,0 +1,108 @@
+// src/webhooks_api.rs
+use actix_web::{web, HttpResponse};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::auth::Principal;
+use crate::error::BillingError;
+use crate::webhooks::{CreateWebhookEndpointRequest, WebhookService};
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/webhooks/endpoints",
+    tag = "webhooks",
+    request_body = CreateWebhookEndpointRequest,
+    responses(
+        (status = 201, description = "Endpoint registered; the secret is only returned here", body = CreatedWebhookEndpoint),
+        (status = 400, description = "Invalid URL or event type", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "Not the vendor or an admin", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn create_endpoint(
+    service: web::Data<Arc<WebhookService>>,
+    principal: Principal,
+    req: web::Json<CreateWebhookEndpointRequest>,
+) -> Result<HttpResponse, BillingError> {
+    let endpoint = service.create_endpoint(&principal, &req).await?;
+    Ok(HttpResponse::Created().json(endpoint))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/endpoints",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Active endpoints visible to the caller", body = [WebhookEndpoint]),
+        (status = 403, description = "Caller has no webhooks", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn list_endpoints(
+    service: web::Data<Arc<WebhookService>>,
+    principal: Principal,
+) -> Result<HttpResponse, BillingError> {
+    let endpoints = service.list_endpoints(&principal).await?;
+    Ok(HttpResponse::Ok().json(endpoints))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/webhooks/endpoints/{id}",
+    tag = "webhooks",
+    params(("id" = Uuid, Path, description = "Endpoint id")),
+    responses(
+        (status = 204, description = "Endpoint deactivated"),
+        (status = 403, description = "Not the vendor or an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Endpoint not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn delete_endpoint(
+    service: web::Data<Arc<WebhookService>>,
+    principal: Principal,
+    endpoint_id: web::Path<Uuid>,
+) -> Result<HttpResponse, BillingError> {
+    service.delete_endpoint(&principal, *endpoint_id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/dead-letters",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Deliveries that ran out of retries and have not been replayed", body = [WebhookDeadLetter]),
+        (status = 403, description = "Caller has no webhooks", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn list_dead_letters(
+    service: web::Data<Arc<WebhookService>>,
+    principal: Principal,
+) -> Result<HttpResponse, BillingError> {
+    let dead_letters = service.list_dead_letters(&principal).await?;
+    Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/webhooks/dead-letters/{id}/replay",
+    tag = "webhooks",
+    params(("id" = Uuid, Path, description = "Dead letter id")),
+    responses(
+        (status = 202, description = "Event queued for delivery again", body = WebhookDeadLetter),
+        (status = 403, description = "Not the vendor or an admin", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "Dead letter not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Already replayed or endpoint deactivated", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
+pub async fn replay_dead_letter(
+    service: web::Data<Arc<WebhookService>>,
+    principal: Principal,
+    dead_letter_id: web::Path<Uuid>,
+) -> Result<HttpResponse, BillingError> {
+    let dead_letter = service.replay_dead_letter(&principal, *dead_letter_id).await?;
+    Ok(HttpResponse::Accepted().json(dead_letter))
+}
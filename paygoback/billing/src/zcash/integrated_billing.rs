@@ -1,26 +1,39 @@
 // src/zcash/integrated_billing.rs
-use sqlx::PgPool;
+use sqlx::{types::Json, PgConnection, PgPool};
 use redis::Client as RedisClient;
 use std::sync::Arc;
 use chrono::{Utc, Duration};
+use futures_util::{future, stream, StreamExt};
 use uuid::Uuid;
-use rust_decimal::Decimal;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use ethers::types::U256;
 use tracing::{info, warn, error};
 
 use crate::models::*;
 use crate::blockchain::BlockchainClient;
+use crate::chains::ChainRegistry;
+use crate::charging::SessionCharger;
 use crate::config::Config;
 use crate::error::BillingError;
 use crate::db;
 use crate::cache;
-use crate::zcash::zcash_service::ZcashService;
+use crate::interval;
+use crate::metrics::TickReport;
+use crate::nonce::{SubmissionOutcome, SubmittedTransaction};
+use crate::oracle::PriceService;
+use crate::events::{self, BillingEvent, BillingEventKind};
+use crate::outbox;
+use crate::webhooks::WebhookEvent;
+use crate::zcash::zcash_service::{SpendingPermission, ZcashService};
 
 /// Enhanced billing engine that integrates with Zcash spending permissions
 pub struct IntegratedBillingEngine {
     db_pool: PgPool,
     redis_client: RedisClient,
-    blockchain_client: Arc<BlockchainClient>,
+    chains: Arc<ChainRegistry>,
     zcash_service: Arc<ZcashService>,
+    charger: Arc<SessionCharger>,
+    prices: Arc<PriceService>,
     config: Config,
 }
 
@@ -28,15 +41,19 @@ impl IntegratedBillingEngine {
     pub fn new(
         db_pool: PgPool,
         redis_client: RedisClient,
-        blockchain_client: Arc<BlockchainClient>,
+        chains: Arc<ChainRegistry>,
         zcash_service: Arc<ZcashService>,
+        charger: Arc<SessionCharger>,
+        prices: Arc<PriceService>,
         config: Config,
     ) -> Self {
         Self {
             db_pool,
             redis_client,
-            blockchain_client,
+            chains,
             zcash_service,
+            charger,
+            prices,
             config,
         }
     }
@@ -46,6 +63,7 @@ impl IntegratedBillingEngine {
         &self,
         user_wallet_address: String,
         vendor_id: String,
+        chain_id: Option<u64>,
     ) -> Result<CreateSessionResponse, BillingError> {
         // Check if user has an active Zcash spending permission
         let permission_opt = self.zcash_service
@@ -54,11 +72,7 @@ impl IntegratedBillingEngine {
 
         let permission = match permission_opt {
             Some(p) => p,
-            None => {
-                return Err(BillingError::Config(
-                    "No active spending permission found. Please create a permission first.".to_string()
-                ));
-            }
+            None => return Err(BillingError::PermissionNotFound),
         };
 
         // Check if permission has remaining balance
@@ -70,11 +84,11 @@ impl IntegratedBillingEngine {
         let session_code = self.generate_session_code();
         
         // Fetch vendor details
-        let vendor_wallet_address = self.get_vendor_wallet(&vendor_id).await?;
-        let rate_per_hour = self.get_vendor_rate(&vendor_id).await?;
+        let vendor = self.charger.vendor_terms(&vendor_id, chain_id).await?;
+        let rate_per_hour = vendor.pricing_plan.rate_per_hour;
         
-        // Validate rate matches permission rate
-        if rate_per_hour != permission.rate_per_hour {
+        // Validate rate matches permission rate; a fiat rate has no fixed ZEC equivalent
+        if vendor.pricing_plan.currency.is_none() && rate_per_hour != permission.rate_per_hour {
             warn!(
                 "Rate mismatch for user {}. Expected: {}, Got: {}",
                 user_wallet_address, permission.rate_per_hour, rate_per_hour
@@ -86,12 +100,16 @@ impl IntegratedBillingEngine {
             id: Uuid::new_v4(),
             session_code: session_code.clone(),
             user_wallet_address: user_wallet_address.clone(),
-            vendor_wallet_address,
+            vendor_wallet_address: vendor.wallet_address,
             vendor_id,
             start_time: now,
             last_billed_time: now,
             end_time: None,
             rate_per_hour,
+            pricing_plan: Json(vendor.pricing_plan),
+            billed_micros: 0,
+            token_address: vendor.token_address,
+            chain_id: vendor.chain_id,
             total_amount_billed: Decimal::ZERO,
             status: SessionStatus::Active,
             created_at: now,
@@ -128,33 +146,56 @@ impl IntegratedBillingEngine {
         &self,
         session_code: &str,
     ) -> Result<BillingTransaction, BillingError> {
-        let mut session = db::get_session_by_code(&self.db_pool, session_code).await?;
+        let session = db::get_session_by_code(&self.db_pool, session_code).await?;
+
+        // Get the linked permission
+        let permission_id = db::get_session_permission_id(&self.db_pool, session.id)
+            .await?
+            .ok_or(BillingError::PermissionNotFound)?;
+
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        // Locked so a billing tick can't charge the same interval meanwhile
+        let mut session = db::lock_session(&mut *tx, session.id).await?;
         
         if session.status != SessionStatus::Active {
             return Err(BillingError::InvalidSessionCode);
         }
-
-        // Get the linked permission
-        let permission_id = self.get_session_permission_id(session.id).await?;
         
-        // Calculate final duration
+        // Calculate final charge
         let now = Utc::now();
-        let duration = now.signed_duration_since(session.last_billed_time);
-        let hours = Decimal::from(duration.num_seconds()) / Decimal::from(3600);
-        let amount = session.rate_per_hour * hours;
+        let (charge, conversion) = interval::settlement_charge(
+            &self.prices,
+            &session,
+            interval::ZCASH_ASSET,
+            now,
+            true,
+            interval::ZCASH_PRECISION,
+            self.config.billing_rounding,
+        )
+        .await?;
+        let amount = charge.amount;
 
         // Deduct from Zcash permission
-        match self.zcash_service.deduct_streaming_time(permission_id, hours).await {
-            Ok(_) => {
-                info!("Deducted {} hours from permission {}", hours, permission_id);
+        let permission = match self.zcash_service
+            .deduct_streaming_time(&mut tx, permission_id, charge.hours, amount)
+            .await
+        {
+            Ok(permission) => {
+                info!("Deducted {} hours from permission {}", charge.hours, permission_id);
+                permission
             }
+            // Rolled back; the session stays active and can be ended again
+            Err(e @ BillingError::Database(_)) => return Err(e),
             Err(e) => {
                 error!("Failed to deduct from permission: {:?}", e);
-                session.status = SessionStatus::Failed;
-                db::update_session(&self.db_pool, &session).await?;
+                Self::fail_session(&mut tx, &mut session, Some(permission_id)).await?;
+                tx.commit().await.map_err(BillingError::Database)?;
                 return Err(e);
             }
-        }
+        };
+
+        let fees = self.config.fees.apply_to_session(&session, amount, interval::ZCASH_PRECISION, self.config.billing_rounding);
 
         // Create transaction record
         let transaction = BillingTransaction {
@@ -163,304 +204,487 @@ impl IntegratedBillingEngine {
             user_wallet_address: session.user_wallet_address.clone(),
             vendor_wallet_address: session.vendor_wallet_address.clone(),
             amount,
-            duration_minutes: duration.num_minutes(),
+            fee_amount: fees.fee,
+            net_amount: fees.net,
+            token_address: None,
+            chain_id: None,
+            duration_minutes: charge.duration.num_minutes(),
+            duration_micros: charge.duration.num_microseconds().unwrap_or(i64::MAX),
             tx_hash: None, // Zcash permissions don't generate tx hashes per session
             status: TransactionStatus::Confirmed,
+            nonce: None,
+            gas_price_wei: None,
+            submission_attempts: 0,
+            replaced_tx_hashes: Vec::new(),
+            batch_id: None,
             created_at: Utc::now(),
-        };
+            fiat_currency: None,
+            fiat_amount: None,
+            exchange_rate: None,
+            exchange_rate_at: None,
+            permit_id: None,
+        }
+        .with_conversion(conversion.as_ref());
         
-        let saved_transaction = db::create_transaction(&self.db_pool, &transaction).await?;
+        let saved_transaction = db::create_transaction(&mut *tx, &transaction).await?;
+        db::create_revenue_splits(&mut *tx, Some(saved_transaction.id), None, &fees.allocations).await?;
+        outbox::record(&mut *tx, &WebhookEvent::transaction(&saved_transaction, &session.vendor_id)).await?;
         
         // Mark session as completed
         session.status = SessionStatus::Completed;
         session.end_time = Some(now);
+        session.last_billed_time = charge.billed_until;
+        session.billed_micros = charge.billed_micros;
         session.total_amount_billed += amount;
-        db::update_session(&self.db_pool, &session).await?;
+        db::update_session(&mut *tx, &session).await?;
+        outbox::record_billing(
+            &mut *tx,
+            &BillingEvent::for_session(BillingEventKind::SessionEnded, &session)
+                .with_charge(amount)
+                .with_balance(&permission),
+        )
+        .await?;
+
+        tx.commit().await.map_err(BillingError::Database)?;
         
-        info!("Ended session {} with final bill ${}", session_code, amount);
+        info!("Ended session {} with final bill {} ZEC", session_code, amount);
         
         Ok(saved_transaction)
     }
 
-    /// Process active sessions with Zcash permission deduction
-    pub async fn process_active_sessions_with_permissions(&self) -> Result<(), BillingError> {
-        let active_sessions = db::get_active_sessions(&self.db_pool).await?;
-        
-        info!("Processing {} active sessions with Zcash permissions", active_sessions.len());
-        
-        for mut session in active_sessions {
-            let now = Utc::now();
-            let duration = now.signed_duration_since(session.last_billed_time);
-            
-            // Bill if interval has passed
-            if duration.num_seconds() >= self.config.billing_interval_seconds as i64 {
-                // Get the linked permission
-                let permission_id_result = self.get_session_permission_id(session.id).await;
-                
-                match permission_id_result {
-                    Ok(permission_id) => {
-                        let hours = Decimal::from(duration.num_seconds()) / Decimal::from(3600);
-                        
-                        // Try to deduct from permission
-                        match self.zcash_service.deduct_streaming_time(permission_id, hours).await {
-                            Ok(_) => {
-                                let amount = session.rate_per_hour * hours;
-                                
-                                // Create transaction record
-                                let transaction = BillingTransaction {
-                                    id: Uuid::new_v4(),
-                                    session_id: session.id,
-                                    user_wallet_address: session.user_wallet_address.clone(),
-                                    vendor_wallet_address: session.vendor_wallet_address.clone(),
-                                    amount,
-                                    duration_minutes: duration.num_minutes(),
-                                    tx_hash: None,
-                                    status: TransactionStatus::Confirmed,
-                                    created_at: Utc::now(),
-                                };
-                                
-                                let _ = db::create_transaction(&self.db_pool, &transaction).await;
-                                
-                                // Update session
-                                session.last_billed_time = Utc::now();
-                                session.total_amount_billed += amount;
-                                let _ = db::update_session(&self.db_pool, &session).await;
-                                
-                                info!(
-                                    "Billed session {} for ${} from permission",
-                                    session.session_code, amount
-                                );
-                            }
-                            Err(BillingError::InsufficientBalance) => {
-                                warn!(
-                                    "Insufficient balance in permission for session {}",
-                                    session.session_code
-                                );
-                                session.status = SessionStatus::Paused;
-                                let _ = db::update_session(&self.db_pool, &session).await;
-                            }
-                            Err(e) => {
-                                error!("Failed to deduct from permission: {:?}", e);
-                                session.status = SessionStatus::Failed;
-                                let _ = db::update_session(&self.db_pool, &session).await;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to get permission for session {}: {:?}", session.session_code, e);
-                        // Fallback to regular blockchain billing
-                        match self.bill_session_blockchain(&mut session, duration).await {
-                            Ok(transaction) => {
-                                info!(
-                                    "Billed session {} via blockchain fallback for ${}",
-                                    session.session_code, transaction.amount
-                                );
-                            }
-                            Err(e) => {
-                                error!("Blockchain billing also failed: {:?}", e);
-                                session.status = SessionStatus::Failed;
-                                let _ = db::update_session(&self.db_pool, &session).await;
-                            }
-                        }
-                    }
+    /// Process the active sessions in `shards` that are due for billing,
+    /// fetched in keyset pages by id and billed `billing_concurrency` at a
+    /// time. Each session is billed in its own transaction, so one failure
+    /// doesn't stop the tick.
+    pub async fn process_active_sessions_with_permissions(
+        &self,
+        shards: &[u32],
+    ) -> Result<TickReport, BillingError> {
+        let billed_before = Utc::now() - Duration::seconds(self.config.billing_interval_seconds as i64);
+        let page_size = self.config.billing_page_size;
+        let concurrency = self.config.billing_concurrency.max(1);
+        let mut report = TickReport::default();
+        let mut after = None;
+
+        loop {
+            let page = db::get_billable_sessions_page(
+                &self.db_pool,
+                billed_before,
+                shards,
+                self.config.billing_shards,
+                after,
+                page_size,
+            )
+            .await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(last.id);
+            let is_last_page = (page.len() as i64) < page_size;
+
+            report.sessions += page.len() as u64;
+            for session in &page {
+                let lag = billed_before.signed_duration_since(session.last_billed_time).num_seconds();
+                report.max_lag_seconds = report.max_lag_seconds.max(lag);
+            }
+
+            report.failed += stream::iter(page)
+                .map(|session| self.bill_due_session(session))
+                .buffer_unordered(concurrency)
+                .filter(|billed| future::ready(!billed))
+                .count()
+                .await as u64;
+
+            if is_last_page {
+                break;
+            }
+        }
+
+        info!(
+            "Processed {} due sessions in shards {:?} ({} failed)",
+            report.sessions, shards, report.failed
+        );
+
+        Ok(report)
+    }
+
+    /// Bill one listed session, returning whether it went through
+    async fn bill_due_session(&self, mut session: StreamingSession) -> bool {
+        // Sessions without a linked permission are billed on-chain
+        let result = match db::get_session_permission_id(&self.db_pool, session.id).await {
+            Ok(Some(permission_id)) => self.bill_session_with_permission(&mut session, permission_id).await,
+            Ok(None) => self.bill_session_blockchain_fallback(&mut session).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to bill session {}: {:?}", session.session_code, e);
+                false
+            }
+        }
+    }
+
+    /// Re-broadcast on-chain charges that are still pending after their gas
+    /// bumps ran out, using the nonce recorded on the transaction row. Charges
+    /// older than `tx_cancel_after_seconds` are cancelled instead, freeing
+    /// the nonce for the charges queued behind them.
+    pub async fn process_stuck_transactions(&self) -> Result<(), BillingError> {
+        let now = Utc::now();
+        let cutoff = now - Duration::seconds(self.config.tx_pending_timeout_seconds as i64);
+        let cancel_before = now - Duration::seconds(self.config.tx_cancel_after_seconds as i64);
+        let stuck = db::get_stuck_transactions(&self.db_pool, cutoff).await?;
+
+        for transaction in stuck {
+            if transaction.created_at < cancel_before {
+                if let Err(e) = self.cancel_stuck_transaction(transaction.id).await {
+                    error!("Failed to cancel stuck transaction {}: {:?}", transaction.id, e);
                 }
+            } else if let Err(e) = self.replace_stuck_transaction(transaction.id).await {
+                error!("Failed to replace stuck transaction {}: {:?}", transaction.id, e);
             }
         }
-        
+
         Ok(())
     }
 
-    /// Get estimated streaming hours for a user
-    pub async fn get_user_streaming_capacity(
+    /// Replace a pending billing transaction with the same call at a higher gas price
+    pub async fn replace_stuck_transaction(
         &self,
-        user_wallet_address: &str,
-    ) -> Result<StreamingCapacity, BillingError> {
-        let permission_opt = self.zcash_service
-            .get_active_permission_by_wallet(user_wallet_address)
+        transaction_id: Uuid,
+    ) -> Result<BillingTransaction, BillingError> {
+        let transaction = db::get_transaction(&self.db_pool, transaction_id).await?;
+        let (nonce, gas_price) = Self::pending_nonce(&transaction)?;
+        let blockchain_client = self.transaction_client(&transaction)?;
+
+        let submission = blockchain_client
+            .replace_bill_transaction(
+                nonce,
+                gas_price,
+                &transaction.user_wallet_address,
+                &transaction.vendor_wallet_address,
+                transaction.amount,
+                transaction.token_address.as_deref(),
+            )
             .await?;
 
-        match permission_opt {
-            Some(permission) => {
-                let remaining_hours = if permission.rate_per_hour > Decimal::ZERO {
-                    permission.remaining_amount / permission.rate_per_hour
-                } else {
-                    Decimal::ZERO
-                };
-
-                Ok(StreamingCapacity {
-                    has_permission: true,
-                    remaining_balance: permission.remaining_amount,
-                    remaining_hours,
-                    rate_per_hour: permission.rate_per_hour,
-                    expires_at: Some(permission.expires_at),
-                })
-            }
-            None => {
-                Ok(StreamingCapacity {
-                    has_permission: false,
-                    remaining_balance: Decimal::ZERO,
-                    remaining_hours: Decimal::ZERO,
-                    rate_per_hour: Decimal::ZERO,
-                    expires_at: None,
-                })
-            }
-        }
+        let transaction = self.record_submission(transaction_id, &submission, submission.status()).await?;
+
+        info!("Replaced transaction {} under nonce {}", transaction_id, nonce);
+
+        Ok(transaction)
+    }
+
+    /// Cancel a pending billing transaction by spending its nonce on a
+    /// zero-value self transfer
+    pub async fn cancel_stuck_transaction(
+        &self,
+        transaction_id: Uuid,
+    ) -> Result<BillingTransaction, BillingError> {
+        let transaction = db::get_transaction(&self.db_pool, transaction_id).await?;
+        let (nonce, gas_price) = Self::pending_nonce(&transaction)?;
+        let blockchain_client = self.transaction_client(&transaction)?;
+
+        let submission = blockchain_client
+            .cancel_transaction(nonce, gas_price)
+            .await?;
+
+        // A mined self transfer spends the nonce whether or not it reverted
+        let status = if submission.outcome == SubmissionOutcome::Pending {
+            TransactionStatus::Pending
+        } else {
+            TransactionStatus::Cancelled
+        };
+        let transaction = self.record_submission(transaction_id, &submission, status).await?;
+
+        info!("Cancelled transaction {} under nonce {}", transaction_id, nonce);
+
+        Ok(transaction)
     }
 
     // Private helper methods
 
-    async fn link_session_to_permission(
+    /// Charge one interval to the session's permission. The deduction, the
+    /// transaction row, the session update and their events commit together.
+    async fn bill_session_with_permission(
         &self,
-        session_id: Uuid,
+        session: &mut StreamingSession,
         permission_id: Uuid,
     ) -> Result<(), BillingError> {
-        sqlx::query(
-            r#"
-            INSERT INTO session_permissions (session_id, permission_id, created_at)
-            VALUES ($1, $2, NOW())
-            "#
-        )
-        .bind(session_id)
-        .bind(permission_id)
-        .execute(&self.db_pool)
-        .await
-        .map_err(BillingError::Database)?;
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
 
-        Ok(())
-    }
+        // Re-read under lock: another instance or an end request may have
+        // billed the session since it was listed
+        *session = db::lock_session(&mut *tx, session.id).await?;
+        let now = Utc::now();
+        if session.status != SessionStatus::Active
+            || now.signed_duration_since(session.last_billed_time).num_seconds()
+                < self.config.billing_interval_seconds as i64
+        {
+            return Ok(());
+        }
 
-    async fn get_session_permission_id(&self, session_id: Uuid) -> Result<Uuid, BillingError> {
-        let result: (Uuid,) = sqlx::query_as(
-            r#"
-            SELECT permission_id
-            FROM session_permissions
-            WHERE session_id = $1
-            "#
+        let (charge, conversion) = interval::settlement_charge(
+            &self.prices,
+            session,
+            interval::ZCASH_ASSET,
+            now,
+            false,
+            interval::ZCASH_PRECISION,
+            self.config.billing_rounding,
         )
-        .bind(session_id)
-        .fetch_one(&self.db_pool)
-        .await
-        .map_err(BillingError::Database)?;
+        .await?;
+        let amount = charge.amount;
 
-        Ok(result.0)
-    }
+        // Try to deduct from permission
+        let permission = match self.zcash_service
+            .deduct_streaming_time(&mut tx, permission_id, charge.hours, amount)
+            .await
+        {
+            Ok(permission) => permission,
+            Err(BillingError::InsufficientBalance) => {
+                warn!(
+                    "Insufficient balance in permission for session {}",
+                    session.session_code
+                );
+                session.status = SessionStatus::Paused;
+                db::update_session(&mut *tx, session).await?;
+                outbox::record_billing(
+                    &mut *tx,
+                    &BillingEvent::for_session(BillingEventKind::SessionPaused, session).with_permission(permission_id),
+                )
+                .await?;
+                tx.commit().await.map_err(BillingError::Database)?;
+                return Ok(());
+            }
+            // Rolled back; billed again next tick
+            Err(e @ BillingError::Database(_)) => return Err(e),
+            Err(e) => {
+                error!("Failed to deduct from permission: {:?}", e);
+                Self::fail_session(&mut tx, session, Some(permission_id)).await?;
+                tx.commit().await.map_err(BillingError::Database)?;
+                return Ok(());
+            }
+        };
 
-    async fn bill_session_blockchain(
-        &self,
-        session: &mut StreamingSession,
-        duration: Duration,
-    ) -> Result<BillingTransaction, BillingError> {
-        let hours = Decimal::from(duration.num_seconds()) / Decimal::from(3600);
-        let amount = session.rate_per_hour * hours;
-        
-        let balance = self.blockchain_client
-            .get_user_balance(&session.user_wallet_address)
-            .await?;
-        
-        if balance < amount {
-            return Err(BillingError::InsufficientBalance);
-        }
-        
-        let tx_hash = self.blockchain_client
-            .bill_user(
-                &session.user_wallet_address,
-                &session.vendor_wallet_address,
-                amount,
-            )
-            .await?;
-        
+        let fees = self.config.fees.apply_to_session(session, amount, interval::ZCASH_PRECISION, self.config.billing_rounding);
+
+        // Create transaction record
         let transaction = BillingTransaction {
             id: Uuid::new_v4(),
             session_id: session.id,
             user_wallet_address: session.user_wallet_address.clone(),
             vendor_wallet_address: session.vendor_wallet_address.clone(),
             amount,
-            duration_minutes: duration.num_minutes(),
-            tx_hash: Some(tx_hash),
+            fee_amount: fees.fee,
+            net_amount: fees.net,
+            token_address: None,
+            chain_id: None,
+            duration_minutes: charge.duration.num_minutes(),
+            duration_micros: charge.duration.num_microseconds().unwrap_or(i64::MAX),
+            tx_hash: None,
             status: TransactionStatus::Confirmed,
+            nonce: None,
+            gas_price_wei: None,
+            submission_attempts: 0,
+            replaced_tx_hashes: Vec::new(),
+            batch_id: None,
             created_at: Utc::now(),
-        };
-        
-        let saved_transaction = db::create_transaction(&self.db_pool, &transaction).await?;
-        
-        session.last_billed_time = Utc::now();
+            fiat_currency: None,
+            fiat_amount: None,
+            exchange_rate: None,
+            exchange_rate_at: None,
+            permit_id: None,
+        }
+        .with_conversion(conversion.as_ref());
+
+        let saved_transaction = db::create_transaction(&mut *tx, &transaction).await?;
+        db::create_revenue_splits(&mut *tx, Some(saved_transaction.id), None, &fees.allocations).await?;
+        outbox::record(&mut *tx, &WebhookEvent::transaction(&saved_transaction, &session.vendor_id)).await?;
+
+        // Update session
+        session.last_billed_time = charge.billed_until;
+        session.billed_micros = charge.billed_micros;
         session.total_amount_billed += amount;
-        db::update_session(&self.db_pool, session).await?;
-        
-        Ok(saved_transaction)
+        db::update_session(&mut *tx, session).await?;
+
+        outbox::record_billing(
+            &mut *tx,
+            &BillingEvent::for_session(BillingEventKind::SessionBilled, session)
+                .with_charge(amount)
+                .with_balance(&permission),
+        )
+        .await?;
+        if self.crossed_low_balance(&permission, amount) {
+            outbox::record_billing(
+                &mut *tx,
+                &BillingEvent::for_session(BillingEventKind::LowBalance, session).with_balance(&permission),
+            )
+            .await?;
+        }
+
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        info!(
+            "Billed session {} for {} ZEC from permission",
+            session.session_code, amount
+        );
+
+        Ok(())
     }
 
-    fn generate_session_code(&self) -> String {
-        use rand::Rng;
-        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-        let mut rng = rand::rng();
-        
-        (0..12)
-            .map(|_| {
-                let idx = rng.random_range(0..CHARSET.len());
-                CHARSET[idx] as char
-            })
-            .collect()
+    async fn bill_session_blockchain_fallback(&self, session: &mut StreamingSession) -> Result<(), BillingError> {
+        match self.charger.bill_session(session, Utc::now(), false).await {
+            Ok(Some(transaction)) => {
+                info!(
+                    "Billed session {} via blockchain fallback for {}",
+                    session.session_code, transaction.amount
+                );
+                Ok(())
+            }
+            // Billed or ended elsewhere since it was listed
+            Ok(None) => Ok(()),
+            // A stale or disputed price, an unreachable signer or a database
+            // error: nothing was charged, so the session is billed again next tick
+            Err(e @ (BillingError::Upstream(_) | BillingError::Database(_))) => Err(e),
+            Err(e) => {
+                error!("On-chain billing failed for session {}: {:?}", session.session_code, e);
+                let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+                *session = db::lock_session(&mut *tx, session.id).await?;
+                if session.status == SessionStatus::Active {
+                    Self::fail_session(&mut tx, session, None).await?;
+                }
+                tx.commit().await.map_err(BillingError::Database)
+            }
+        }
     }
 
-    async fn get_vendor_wallet(&self, vendor_id: &str) -> Result<String, BillingError> {
-        let url = format!("{}/internal/vendors/{}", self.config.vendor_service_url, vendor_id);
-        let client = reqwest::Client::new();
-        let response = client.get(&url)
-            .bearer_auth(&self.config.vendor_service_token)
-            .send()
-            .await
-            .map_err(|e| BillingError::Config(format!("vendor service request failed: {}", e)))?;
+    async fn fail_session(
+        conn: &mut PgConnection,
+        session: &mut StreamingSession,
+        permission_id: Option<Uuid>,
+    ) -> Result<(), BillingError> {
+        session.status = SessionStatus::Failed;
+        db::update_session(&mut *conn, session).await?;
 
-        if !response.status().is_success() {
-            return Err(BillingError::Config(
-                format!("Vendor Service returned status {} for vendor {}", response.status(), vendor_id)
-            ));
+        let mut event = BillingEvent::for_session(BillingEventKind::SessionFailed, session);
+        if let Some(permission_id) = permission_id {
+            event = event.with_permission(permission_id);
         }
+        outbox::record_billing(&mut *conn, &event).await
+    }
 
-        let vendor: VendorInfo = response.json().await
-            .map_err(|e| BillingError::Config(format!("Invalid vendor JSON: {}", e)))?;
+    /// Store a resubmission's outcome together with its transaction event.
+    /// A cancelled or reverted charge will never take the user's funds, so
+    /// its permit reservation and its interval go back in the same
+    /// transaction and the session's next charge bills that time again.
+    async fn record_submission(
+        &self,
+        transaction_id: Uuid,
+        submission: &SubmittedTransaction,
+        status: TransactionStatus,
+    ) -> Result<BillingTransaction, BillingError> {
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        let dropped = matches!(status, TransactionStatus::Cancelled | TransactionStatus::Failed);
+        db::record_transaction_submission(&mut *tx, transaction_id, submission, status).await?;
+        let transaction = db::get_transaction(&mut *tx, transaction_id).await?;
+
+        let session = if dropped {
+            db::release_permit_reservations(&mut *tx, &[transaction_id]).await?;
+
+            let mut session = db::lock_session(&mut *tx, transaction.session_id).await?;
+            session.last_billed_time -= Duration::microseconds(transaction.duration_micros);
+            session.billed_micros -= transaction.duration_micros;
+            session.total_amount_billed -= transaction.amount;
+            db::update_session(&mut *tx, &session).await?;
+            session
+        } else {
+            db::get_session(&mut *tx, transaction.session_id).await?
+        };
+        outbox::record(&mut *tx, &WebhookEvent::transaction(&transaction, &session.vendor_id)).await?;
 
-        if !vendor.wallet_address.starts_with("0x") || vendor.wallet_address.len() != 42 {
-            return Err(BillingError::Config(format!("Invalid vendor wallet for {}", vendor_id)));
-        }
+        tx.commit().await.map_err(BillingError::Database)?;
 
-        Ok(vendor.wallet_address)
+        Ok(transaction)
     }
 
-    async fn get_vendor_rate(&self, vendor_id: &str) -> Result<Decimal, BillingError> {
-        let url = format!("{}/internal/vendors/{}", self.config.vendor_service_url, vendor_id);
-        let client = reqwest::Client::new();
-        let response = client.get(&url)
-            .bearer_auth(&self.config.vendor_service_token)
-            .send()
-            .await
-            .map_err(|e| BillingError::Config(format!("Vendor service request failed: {}", e)))?;
+    /// Whether this charge took the permission below the low-balance
+    /// threshold, so clients are warned once rather than on every tick
+    fn crossed_low_balance(&self, permission: &SpendingPermission, amount_billed: Decimal) -> bool {
+        let threshold = self.config.low_balance_threshold_hours;
+        let before = permission.remaining_amount + amount_billed;
+
+        match (
+            events::remaining_hours(permission.remaining_amount, permission.rate_per_hour),
+            events::remaining_hours(before, permission.rate_per_hour),
+        ) {
+            (Some(after), Some(before)) => after < threshold && before >= threshold,
+            _ => false,
+        }
+    }
 
-        if !response.status().is_success() {
-            return Err(BillingError::Config(
-                format!("Vendor service returned status {} for vendor {}", response.status(), vendor_id)
-            ));
+    fn pending_nonce(transaction: &BillingTransaction) -> Result<(U256, U256), BillingError> {
+        if !matches!(transaction.status, TransactionStatus::Pending) {
+            return Err(BillingError::Conflict(format!(
+                "Transaction {} is not pending",
+                transaction.id
+            )));
         }
 
-        let vendor: VendorInfo = response.json().await
-            .map_err(|e| BillingError::Config(format!("Invalid vendor JSON: {}", e)))?;
+        let nonce = transaction.nonce.ok_or_else(|| {
+            BillingError::Conflict(format!("Transaction {} was never broadcast", transaction.id))
+        })?;
 
-        if vendor.rate_per_hour <= Decimal::ZERO || vendor.rate_per_hour > Decimal::from(1_000) {
-            return Err(BillingError::Config(
-                format!("Suspicious rate per hour {} for vendor {}", vendor.rate_per_hour, vendor_id)
-            ));
-        }
+        let gas_price = transaction.gas_price_wei
+            .and_then(|price| price.to_u128())
+            .filter(|price| *price > 0)
+            .ok_or_else(|| {
+                BillingError::Conflict(format!("Transaction {} has no recorded gas price", transaction.id))
+            })?;
 
-        Ok(vendor.rate_per_hour)
+        Ok((U256::from(nonce), U256::from(gas_price)))
+    }
+
+    fn transaction_client(&self, transaction: &BillingTransaction) -> Result<Arc<BlockchainClient>, BillingError> {
+        let chain_id = transaction.chain_id.ok_or_else(|| {
+            BillingError::Conflict(format!("Transaction {} was not settled on-chain", transaction.id))
+        })?;
+
+        self.chains.client(chain_id as u64)
+    }
+
+    async fn link_session_to_permission(
+        &self,
+        session_id: Uuid,
+        permission_id: Uuid,
+    ) -> Result<(), BillingError> {
+        sqlx::query(
+            r#"
+            INSERT INTO session_permissions (session_id, permission_id, created_at)
+            VALUES ($1, $2, NOW())
+            "#
+        )
+        .bind(session_id)
+        .bind(permission_id)
+        .execute(&self.db_pool)
+        .await
+        .map_err(BillingError::Database)?;
+
+        Ok(())
     }
-}
 
-#[derive(Debug, serde::Serialize)]
-pub struct StreamingCapacity {
-    pub has_permission: bool,
-    pub remaining_balance: Decimal,
-    pub remaining_hours: Decimal,
-    pub rate_per_hour: Decimal,
-    pub expires_at: Option<chrono::DateTime<Utc>>,
-}
\ No newline at end of file
+    fn generate_session_code(&self) -> String {
+        use rand::Rng;
+        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let mut rng = rand::rng();
+        
+        (0..12)
+            .map(|_| {
+                let idx = rng.random_range(0..CHARSET.len());
+                CHARSET[idx] as char
+            })
+            .collect()
+    }
+}
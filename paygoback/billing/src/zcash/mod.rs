@@ -2,9 +2,6 @@
 pub mod zcash_service;
 pub mod zcash_api;
 pub mod integrated_billing;
-pub mod zcash_config;
 
-pub use zcash_service::{ZcashService, SpendingPermission, PermissionStatus};
-pub use zcash_api::configure_zcash_routes;
+pub use zcash_service::ZcashService;
 pub use integrated_billing::IntegratedBillingEngine;
-pub use zcash_config::ZcashConfig;
\ No newline at end of file
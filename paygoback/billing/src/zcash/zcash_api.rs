@@ -1,37 +1,19 @@
 // src/zcash/zcash_api.rs
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpResponse};
 use std::sync::Arc;
 use uuid::Uuid;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::zcash::zcash_service::{
-    ZcashService, CreatePermissionRequest, PermissionStatus,
+    ZcashService, CreatePermissionRequest,
 };
+use crate::auth::{Access, Principal};
 use crate::validation::Validator;
 use crate::error::BillingError;
 
-pub fn configure_zcash_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(
-        web::scope("/")
-            .route("/test", web::get().to(test_endpoint))
-            .route("/permissions", web::post().to(create_permission))
-            .route("/permissions/{id}/verify", web::post().to(verify_permission))
-            .route("/permissions/{id}", web::get().to(get_permission_status))
-            .route("/permissions/{id}/revoke", web::post().to(revoke_permission))
-            .route("/balance/{address}", web::get().to(get_wallet_balance))
-            .route("/permissions/wallet/{address}", web::get().to(get_active_permission))
-    );
-}
-
-async fn test_endpoint() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({
-        "message": "Zcash routes are working!",
-        "service": "paygo-zcash"
-    }))
-}
-
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreatePermissionApiRequest {
     user_wallet_address: String,
     requested_amount: f64,
@@ -39,51 +21,43 @@ pub struct CreatePermissionApiRequest {
     duration_days: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct VerifyPermissionRequest {
-    // Optional: add transaction ID for verification
-    transaction_id: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
 pub struct BalanceQuery {
     rate_per_hour: Option<f64>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/zcash/permissions",
+    tag = "zcash",
+    request_body = CreatePermissionApiRequest,
+    responses(
+        (status = 201, description = "Permission created, awaiting payment", body = CreatePermissionResponse),
+        (status = 400, description = "Invalid request", body = Problem, content_type = "application/problem+json"),
+        (status = 403, description = "No wallet ownership proof", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = [], "wallet_proof" = []), ("api_key" = [], "wallet_proof" = []))
+)]
 pub async fn create_permission(
     service: web::Data<Arc<ZcashService>>,
+    principal: Principal,
     req: web::Json<CreatePermissionApiRequest>,
-) -> impl Responder {
+) -> Result<HttpResponse, BillingError> {
     // Validate inputs
-    if let Err(e) = Validator::validate_zcash_address(&req.user_wallet_address) {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid wallet address: {:?}", e)
-        }));
-    }
+    Validator::validate_zcash_address(&req.user_wallet_address)?;
+
+    principal.require_wallet_proof(&req.user_wallet_address)?;
 
     let requested_amount = Decimal::from_f64_retain(req.requested_amount)
         .unwrap_or(Decimal::ZERO);
     let rate_per_hour = Decimal::from_f64_retain(req.rate_per_hour)
         .unwrap_or(Decimal::ZERO);
 
-    if let Err(e) = Validator::validate_amount(requested_amount) {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid amount: {:?}", e)
-        }));
-    }
+    Validator::validate_amount(requested_amount)?;
+    Validator::validate_rate_per_hour(rate_per_hour)?;
 
-    if let Err(e) = Validator::validate_rate_per_hour(rate_per_hour) {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid rate: {:?}", e)
-        }));
-    }
-
-    let duration_days = req.duration_days.unwrap_or(30);
-    if let Err(e) = Validator::validate_duration_days(duration_days) {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid duration: {:?}", e)
-        }));
-    }
+    let duration_days = req.duration_days.unwrap_or(service.default_permission_duration_days());
+    Validator::validate_duration_days(duration_days)?;
 
     let request = CreatePermissionRequest {
         user_wallet_address: req.user_wallet_address.clone(),
@@ -92,107 +66,142 @@ pub async fn create_permission(
         duration_days,
     };
 
-    match service.create_spending_permission(request).await {
-        Ok(response) => HttpResponse::Created().json(response),
-        Err(BillingError::Config(msg)) => HttpResponse::BadRequest().json(serde_json::json!({
-            "error": msg
-        })),
-        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Internal server error"
-        })),
-    }
+    let response = service.create_spending_permission(request).await?;
+    Ok(HttpResponse::Created().json(response))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/zcash/permissions/{id}/verify",
+    tag = "zcash",
+    params(("id" = Uuid, Path, description = "Permission id")),
+    responses(
+        (status = 200, description = "Payment received, permission active", body = SpendingPermission),
+        (status = 404, description = "Permission not found", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Permission not pending or payment incomplete", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
 pub async fn verify_permission(
     service: web::Data<Arc<ZcashService>>,
+    principal: Principal,
     permission_id: web::Path<Uuid>,
-    _req: web::Json<VerifyPermissionRequest>,
-) -> impl Responder {
-    match service.verify_and_activate_permission(*permission_id).await {
-        Ok(permission) => HttpResponse::Ok().json(permission),
-        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("{:?}", e)
-        })),
-    }
+) -> Result<HttpResponse, BillingError> {
+    authorize_permission(&service, &principal, *permission_id, Access::Write).await?;
+
+    let permission = service.verify_and_activate_permission(*permission_id).await?;
+    Ok(HttpResponse::Ok().json(permission))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/zcash/permissions/{id}",
+    tag = "zcash",
+    params(("id" = Uuid, Path, description = "Permission id")),
+    responses(
+        (status = 200, description = "Permission status", body = PermissionStatusResponse),
+        (status = 404, description = "Permission not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
 pub async fn get_permission_status(
     service: web::Data<Arc<ZcashService>>,
+    principal: Principal,
     permission_id: web::Path<Uuid>,
-) -> impl Responder {
-    match service.get_permission_status(*permission_id).await {
-        Ok(status) => HttpResponse::Ok().json(status),
-        Err(e) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("{:?}", e)
-        })),
-    }
+) -> Result<HttpResponse, BillingError> {
+    authorize_permission(&service, &principal, *permission_id, Access::Read).await?;
+
+    let status = service.get_permission_status(*permission_id).await?;
+    Ok(HttpResponse::Ok().json(status))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/zcash/permissions/{id}/revoke",
+    tag = "zcash",
+    params(("id" = Uuid, Path, description = "Permission id")),
+    responses(
+        (status = 200, description = "Permission revoked", body = SpendingPermission),
+        (status = 404, description = "Permission not found", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
 pub async fn revoke_permission(
     service: web::Data<Arc<ZcashService>>,
+    principal: Principal,
     permission_id: web::Path<Uuid>,
-) -> impl Responder {
-    match service.revoke_permission(*permission_id).await {
-        Ok(permission) => HttpResponse::Ok().json(permission),
-        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("{:?}", e)
-        })),
-    }
+) -> Result<HttpResponse, BillingError> {
+    authorize_permission(&service, &principal, *permission_id, Access::Write).await?;
+
+    let permission = service.revoke_permission(*permission_id).await?;
+    Ok(HttpResponse::Ok().json(permission))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/zcash/balance/{address}",
+    tag = "zcash",
+    params(("address" = String, Path, description = "Zcash address"), BalanceQuery),
+    responses(
+        (status = 200, description = "Balance and streaming capacity", body = WalletBalanceResponse),
+        (status = 400, description = "Invalid address or rate", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
 pub async fn get_wallet_balance(
     service: web::Data<Arc<ZcashService>>,
+    principal: Principal,
     address: web::Path<String>,
     query: web::Query<BalanceQuery>,
-) -> impl Responder {
+) -> Result<HttpResponse, BillingError> {
     // Validate address
-    if let Err(e) = Validator::validate_zcash_address(&address) {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid wallet address: {:?}", e)
-        }));
-    }
+    Validator::validate_zcash_address(&address)?;
+
+    principal.authorize_wallet(&address, Access::Read)?;
 
     let rate = Decimal::from_f64_retain(query.rate_per_hour.unwrap_or(10.0))
         .unwrap_or(Decimal::from(10));
 
-    if let Err(e) = Validator::validate_rate_per_hour(rate) {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid rate: {:?}", e)
-        }));
-    }
+    Validator::validate_rate_per_hour(rate)?;
 
-    match service.get_wallet_balance(&address, rate).await {
-        Ok(balance) => HttpResponse::Ok().json(balance),
-        Err(BillingError::Config(msg)) => HttpResponse::BadRequest().json(serde_json::json!({
-            "error": msg
-        })),
-        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Internal server error"
-        })),
-    }
+    let balance = service.get_wallet_balance(&address, rate).await?;
+    Ok(HttpResponse::Ok().json(balance))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/zcash/permissions/wallet/{address}",
+    tag = "zcash",
+    params(("address" = String, Path, description = "Zcash address")),
+    responses(
+        (status = 200, description = "Active permission", body = SpendingPermission),
+        (status = 404, description = "No active permission", body = Problem, content_type = "application/problem+json"),
+    ),
+    security(("bearer_jwt" = []), ("api_key" = []))
+)]
 pub async fn get_active_permission(
     service: web::Data<Arc<ZcashService>>,
+    principal: Principal,
     address: web::Path<String>,
-) -> impl Responder {
+) -> Result<HttpResponse, BillingError> {
     // Validate address
-    if let Err(e) = Validator::validate_zcash_address(&address) {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": format!("Invalid wallet address: {:?}", e)
-        }));
-    }
+    Validator::validate_zcash_address(&address)?;
+
+    principal.authorize_wallet(&address, Access::Read)?;
 
-    match service.get_active_permission_by_wallet(&address).await {
-        Ok(Some(permission)) => HttpResponse::Ok().json(permission),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
-            "message": "No active permission found"
-        })),
-        Err(BillingError::Config(msg)) => HttpResponse::BadRequest().json(serde_json::json!({
-            "error": msg
-        })),
-        Err(_) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "Internal server error"
-        })),
+    match service.get_active_permission_by_wallet(&address).await? {
+        Some(permission) => Ok(HttpResponse::Ok().json(permission)),
+        None => Err(BillingError::PermissionNotFound),
     }
-}
\ No newline at end of file
+}
+
+/// Permissions are only visible to the wallet that granted them
+async fn authorize_permission(
+    service: &ZcashService,
+    principal: &Principal,
+    permission_id: Uuid,
+    access: Access,
+) -> Result<(), BillingError> {
+    let permission = service.get_permission(permission_id).await?;
+    principal.authorize_wallet(&permission.user_wallet_address, access)
+}
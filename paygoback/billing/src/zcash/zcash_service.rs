@@ -4,11 +4,14 @@ use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc, Duration};
 use uuid::Uuid;
-use sqlx::{PgPool, prelude::FromRow};
-use tracing::{info, warn, error};
-use std::collections::HashMap;
+use sqlx::{PgConnection, PgExecutor, PgPool, prelude::FromRow};
+use tracing::{info, warn};
+use utoipa::ToSchema;
 
+use crate::config::ZcashConfig;
 use crate::error::BillingError;
+use crate::outbox;
+use crate::webhooks::WebhookEvent;
 
 // Zcash RPC request/response structures
 #[derive(Debug, Serialize)]
@@ -23,7 +26,6 @@ struct ZcashRpcRequest {
 struct ZcashRpcResponse<T> {
     result: Option<T>,
     error: Option<ZcashRpcError>,
-    id: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,10 +38,9 @@ struct ZcashRpcError {
 struct ZcashBalance {
     transparent: String,
     private: String,
-    total: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct SpendingPermission {
     pub id: Uuid,
     pub user_wallet_address: String,
@@ -54,7 +55,7 @@ pub struct SpendingPermission {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 pub enum PermissionStatus {
     Pending,
     Approved,
@@ -135,7 +136,7 @@ pub struct CreatePermissionRequest {
     pub duration_days: i64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CreatePermissionResponse {
     pub permission_id: Uuid,
     pub max_streaming_hours: Decimal,
@@ -144,7 +145,7 @@ pub struct CreatePermissionResponse {
     pub amount_to_pay: Decimal,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct PermissionStatusResponse {
     pub permission_id: Uuid,
     pub status: PermissionStatus,
@@ -154,7 +155,7 @@ pub struct PermissionStatusResponse {
     pub expires_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct WalletBalanceResponse {
     pub wallet_address: String,
     pub transparent_balance: Decimal,
@@ -170,27 +171,30 @@ pub struct ZcashService {
     rpc_user: String,
     rpc_password: String,
     service_wallet_address: String,
+    min_confirmations: u32,
+    default_permission_duration_days: i64,
     db_pool: PgPool,
 }
 
 impl ZcashService {
-    pub fn new(
-        rpc_url: String,
-        rpc_user: String,
-        rpc_password: String,
-        service_wallet_address: String,
-        db_pool: PgPool,
-    ) -> Self {
+    pub fn new(config: &ZcashConfig, db_pool: PgPool) -> Self {
         Self {
             http_client: Client::new(),
-            rpc_url,
-            rpc_user,
-            rpc_password,
-            service_wallet_address,
+            rpc_url: config.rpc_url.clone(),
+            rpc_user: config.rpc_user.clone(),
+            rpc_password: config.rpc_password.expose().to_string(),
+            service_wallet_address: config.service_wallet_address.clone(),
+            min_confirmations: config.min_confirmations,
+            default_permission_duration_days: config.default_permission_duration_days,
             db_pool,
         }
     }
 
+    /// How long a permission lasts when the request doesn't say
+    pub fn default_permission_duration_days(&self) -> i64 {
+        self.default_permission_duration_days
+    }
+
     // Create a spending permission request
     pub async fn create_spending_permission(
         &self,
@@ -217,8 +221,19 @@ impl ZcashService {
             updated_at: Utc::now(),
         };
 
+        let response = CreatePermissionResponse {
+            permission_id: permission.id,
+            max_streaming_hours: max_hours,
+            expires_at: permission.expires_at,
+            payment_address: self.service_wallet_address.clone(),
+            amount_to_pay: request.requested_amount,
+        };
+
         // Save to database
-        self.save_permission(&permission).await?;
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+        Self::save_permission(&mut *tx, &permission).await?;
+        outbox::record(&mut *tx, &WebhookEvent::new("permission.created", None, &response)).await?;
+        tx.commit().await.map_err(BillingError::Database)?;
 
         info!(
             "Created spending permission {} for user {} - {} ZEC for {} hours",
@@ -228,13 +243,7 @@ impl ZcashService {
             max_hours
         );
 
-        Ok(CreatePermissionResponse {
-            permission_id: permission.id,
-            max_streaming_hours: max_hours,
-            expires_at: permission.expires_at,
-            payment_address: self.service_wallet_address.clone(),
-            amount_to_pay: request.requested_amount,
-        })
+        Ok(response)
     }
 
     // Verify payment and activate permission
@@ -245,7 +254,7 @@ impl ZcashService {
         let mut permission = self.get_permission(permission_id).await?;
 
         if permission.status != PermissionStatus::Pending {
-            return Err(BillingError::Config(
+            return Err(BillingError::Conflict(
                 "Permission is not in pending status".to_string()
             ));
         }
@@ -260,8 +269,11 @@ impl ZcashService {
         if received_amount >= permission.approved_amount {
             permission.status = PermissionStatus::Active;
             permission.updated_at = Utc::now();
-            
-            self.update_permission(&permission).await?;
+
+            let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+            Self::update_permission(&mut *tx, &permission).await?;
+            outbox::record(&mut *tx, &WebhookEvent::new("permission.activated", None, &permission)).await?;
+            tx.commit().await.map_err(BillingError::Database)?;
 
             info!(
                 "Activated spending permission {} for user {}",
@@ -271,7 +283,7 @@ impl ZcashService {
 
             Ok(permission)
         } else {
-            Err(BillingError::Config(format!(
+            Err(BillingError::Conflict(format!(
                 "Insufficient payment received. Expected: {}, Got: {}",
                 permission.approved_amount, received_amount
             )))
@@ -335,16 +347,21 @@ impl ZcashService {
         })
     }
 
-    // Deduct streaming time from permission
+    // Deduct streaming time, charged as the already-rounded `amount_to_deduct`,
+    // from permission inside the caller's transaction, which must be committed on `PermissionExpired` and `InsufficientBalance`
+    // too so the status change sticks
     pub async fn deduct_streaming_time(
         &self,
+        conn: &mut PgConnection,
         permission_id: Uuid,
         hours_used: Decimal,
+        amount_to_deduct: Decimal,
     ) -> Result<SpendingPermission, BillingError> {
-        let mut permission = self.get_permission(permission_id).await?;
+        // Locked so concurrent charges against one permission serialize
+        let mut permission = Self::lock_permission(&mut *conn, permission_id).await?;
 
         if permission.status != PermissionStatus::Active {
-            return Err(BillingError::Config(
+            return Err(BillingError::Conflict(
                 "Permission is not active".to_string()
             ));
         }
@@ -352,15 +369,15 @@ impl ZcashService {
         // Check if permission has expired
         if Utc::now() > permission.expires_at {
             permission.status = PermissionStatus::Expired;
-            self.update_permission(&permission).await?;
-            return Err(BillingError::Config("Permission has expired".to_string()));
+            Self::update_permission(&mut *conn, &permission).await?;
+            outbox::record(&mut *conn, &WebhookEvent::new("permission.expired", None, &permission)).await?;
+            return Err(BillingError::PermissionExpired);
         }
 
-        let amount_to_deduct = hours_used * permission.rate_per_hour;
-
         if amount_to_deduct > permission.remaining_amount {
             permission.status = PermissionStatus::Exhausted;
-            self.update_permission(&permission).await?;
+            Self::update_permission(&mut *conn, &permission).await?;
+            outbox::record(&mut *conn, &WebhookEvent::new("permission.exhausted", None, &permission)).await?;
             return Err(BillingError::InsufficientBalance);
         }
 
@@ -373,10 +390,13 @@ impl ZcashService {
             permission.status = PermissionStatus::Exhausted;
         }
 
-        self.update_permission(&permission).await?;
+        Self::update_permission(&mut *conn, &permission).await?;
+        if permission.status == PermissionStatus::Exhausted {
+            outbox::record(&mut *conn, &WebhookEvent::new("permission.exhausted", None, &permission)).await?;
+        }
 
         info!(
-            "Deducted {} hours (${}) from permission {}. Remaining: ${}",
+            "Deducted {} hours ({} ZEC) from permission {}. Remaining: {} ZEC",
             hours_used,
             amount_to_deduct,
             permission_id,
@@ -386,6 +406,16 @@ impl ZcashService {
         Ok(permission)
     }
 
+    // Deduct metered usage, which uses no streaming time
+    pub async fn deduct_usage(
+        &self,
+        conn: &mut PgConnection,
+        permission_id: Uuid,
+        amount: Decimal,
+    ) -> Result<SpendingPermission, BillingError> {
+        self.deduct_streaming_time(conn, permission_id, Decimal::ZERO, amount).await
+    }
+
     // Revoke a permission
     pub async fn revoke_permission(
         &self,
@@ -396,7 +426,10 @@ impl ZcashService {
         permission.status = PermissionStatus::Revoked;
         permission.updated_at = Utc::now();
 
-        self.update_permission(&permission).await?;
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+        Self::update_permission(&mut *tx, &permission).await?;
+        outbox::record(&mut *tx, &WebhookEvent::new("permission.revoked", None, &permission)).await?;
+        tx.commit().await.map_err(BillingError::Database)?;
 
         info!("Revoked permission {}", permission_id);
 
@@ -429,6 +462,23 @@ impl ZcashService {
         Ok(permission.map(|p| p.into()))
     }
 
+    // Check a message signed with a transparent address's key (`signmessage`)
+    pub async fn verify_message(
+        &self,
+        address: &str,
+        signature: &str,
+        message: &str,
+    ) -> Result<bool, BillingError> {
+        self.call_zcash_rpc(
+            "verifymessage",
+            vec![
+                serde_json::json!(address),
+                serde_json::json!(signature),
+                serde_json::json!(message),
+            ],
+        ).await
+    }
+
     // Private helper methods
 
     async fn call_zcash_rpc<T: for<'de> Deserialize<'de>>(
@@ -493,7 +543,7 @@ impl ZcashService {
             if address.starts_with("t1") && address.len() == 35 {
                 return Ok(());
             } else {
-                return Err(BillingError::Blockchain(
+                return Err(BillingError::Validation(
                     "Invalid Zcash address".to_string()
                 ));
             }
@@ -505,7 +555,7 @@ impl ZcashService {
         ).await?;
 
         if !result["isvalid"].as_bool().unwrap_or(false) {
-            return Err(BillingError::Blockchain(
+            return Err(BillingError::Validation(
                 "Invalid Zcash address".to_string()
             ));
         }
@@ -519,7 +569,6 @@ impl ZcashService {
             return Ok(ZcashBalance {
                 transparent: "100000000".to_string(), // 1 ZEC
                 private: "50000000".to_string(),     // 0.5 ZEC
-                total: "150000000".to_string(),       // 1.5 ZEC total
             });
         }
 
@@ -531,7 +580,6 @@ impl ZcashService {
         Ok(ZcashBalance {
             transparent: result["transparent"].as_str().unwrap_or("0").to_string(),
             private: result["private"].as_str().unwrap_or("0").to_string(),
-            total: result["total"].as_str().unwrap_or("0").to_string(),
         })
     }
 
@@ -544,7 +592,7 @@ impl ZcashService {
         // Get transactions for the service wallet
         let transactions: Vec<serde_json::Value> = self.call_zcash_rpc(
             "z_listreceivedbyaddress",
-            vec![serde_json::json!(to_address), serde_json::json!(1)],
+            vec![serde_json::json!(to_address), serde_json::json!(self.min_confirmations)],
         ).await?;
 
         let mut total_received = Decimal::ZERO;
@@ -615,8 +663,8 @@ impl ZcashService {
         Ok(false)
     }
 
-    async fn save_permission(
-        &self,
+    async fn save_permission<'e>(
+        executor: impl PgExecutor<'e>,
         permission: &SpendingPermission,
     ) -> Result<(), BillingError> {
         sqlx::query(
@@ -639,14 +687,14 @@ impl ZcashService {
         .bind(permission.expires_at)
         .bind(permission.created_at)
         .bind(permission.updated_at)
-        .execute(&self.db_pool)
+        .execute(executor)
         .await
         .map_err(BillingError::Database)?;
 
         Ok(())
     }
 
-    async fn get_permission(
+    pub async fn get_permission(
         &self,
         permission_id: Uuid,
     ) -> Result<SpendingPermission, BillingError> {
@@ -666,11 +714,35 @@ impl ZcashService {
 
         permission
             .map(|p| p.into())
-            .ok_or_else(|| BillingError::Config("Permission not found".to_string()))
+            .ok_or(BillingError::PermissionNotFound)
     }
 
-    async fn update_permission(
-        &self,
+    async fn lock_permission(
+        conn: &mut PgConnection,
+        permission_id: Uuid,
+    ) -> Result<SpendingPermission, BillingError> {
+        let permission = sqlx::query_as::<_, SpendingPermissionDb>(
+            r#"
+            SELECT id, user_wallet_address, approved_amount, remaining_amount,
+                   rate_per_hour, max_streaming_hours, used_streaming_hours,
+                   status, expires_at, created_at, updated_at
+            FROM spending_permissions
+            WHERE id = $1
+            FOR UPDATE
+            "#
+        )
+        .bind(permission_id)
+        .fetch_optional(conn)
+        .await
+        .map_err(BillingError::Database)?;
+
+        permission
+            .map(|p| p.into())
+            .ok_or(BillingError::PermissionNotFound)
+    }
+
+    async fn update_permission<'e>(
+        executor: impl PgExecutor<'e>,
         permission: &SpendingPermission,
     ) -> Result<(), BillingError> {
         sqlx::query(
@@ -688,27 +760,40 @@ impl ZcashService {
         .bind(permission.status.to_string())
         .bind(permission.updated_at)
         .bind(permission.id)
-        .execute(&self.db_pool)
+        .execute(executor)
         .await
         .map_err(BillingError::Database)?;
 
         Ok(())
     }
 
-    // Background job to check and update expired permissions
-    pub async fn check_expired_permissions(&self) -> Result<(), BillingError> {
-        sqlx::query(
+    // Background job to check and update expired permissions, returning
+    // the ones it expired
+    pub async fn check_expired_permissions(&self) -> Result<Vec<SpendingPermission>, BillingError> {
+        let mut tx = self.db_pool.begin().await.map_err(BillingError::Database)?;
+
+        let expired = sqlx::query_as::<_, SpendingPermissionDb>(
             r#"
             UPDATE spending_permissions
             SET status = 'expired', updated_at = NOW()
             WHERE status = 'active'
             AND expires_at < NOW()
+            RETURNING id, user_wallet_address, approved_amount, remaining_amount,
+                      rate_per_hour, max_streaming_hours, used_streaming_hours,
+                      status, expires_at, created_at, updated_at
             "#
         )
-        .execute(&self.db_pool)
+        .fetch_all(&mut *tx)
         .await
         .map_err(BillingError::Database)?;
 
-        Ok(())
+        let expired: Vec<SpendingPermission> = expired.into_iter().map(Into::into).collect();
+        for permission in &expired {
+            outbox::record(&mut *tx, &WebhookEvent::new("permission.expired", None, permission)).await?;
+        }
+
+        tx.commit().await.map_err(BillingError::Database)?;
+
+        Ok(expired)
     }
 }
\ No newline at end of file